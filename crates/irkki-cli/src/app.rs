@@ -5,15 +5,19 @@ use ratatui::{
     crossterm::event::{self, Event, KeyCode, KeyEventKind},
 };
 use std::{
-    io,
-    sync::mpsc::{self, Receiver},
-    time::Duration,
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+use crate::buffers::BufferTree;
 use crate::chat_view::{Model as ChatModel, view as chat_view};
+use crate::ignore::{Ignore, IgnoreList};
 use crate::start_view::{Model as StartModel, StartSelection, view as start_view};
 use crate::wizard_view::{Model as WizardModel, view as wizard_view};
-use irkki_core::{IRCClient, IRCEvent};
+use irkki_core::{
+    BouncerNetwork, ConnectionOptions, DccRequest, IRCEvent, NetworkEvent, NetworkManager,
+    ReplyKind, Source, TypingState,
+};
 
 pub enum CurrentScreen {
     Start,
@@ -33,27 +37,76 @@ pub struct App {
     input: String,
     /// Position of cursor in the editor area.
     character_index: usize,
-    /// History of recorded messages
-    messages: Vec<String>,
-    users: Vec<String>,
     nickname: String,
     server: String,
     port: u16,
     current_screen: CurrentScreen,
     start_selection: StartSelection,
     wizard_step: WizardStep,
-    incoming: Option<Receiver<IRCEvent>>,
-    irc_client: Option<IRCClient>,
+    /// The connections to the networks, each known by the name of its server.
+    networks: NetworkManager,
+    /// The buffers of every network, the selected one is shown and gets what we type.
+    buffers: BufferTree,
+    /// Where each network is connected to.
+    servers: HashMap<String, Server>,
+    /// Our nickname on each network, once it has changed from the one we asked for.
+    nicknames: HashMap<String, String>,
+    /// The latest message seen on each network that was disconnected, for the bouncer to play
+    /// back only what came after it when connecting again.
+    last_seen: HashMap<String, SystemTime>,
+    /// Who is typing and where.
+    typing: Vec<Typing>,
+    /// When we last told the selected buffer that we are typing.
+    typing_sent: Option<Instant>,
+    /// The latest messages with a msgid, to quote in replies and reactions.
+    recent: VecDeque<RecentMessage>,
+    /// Who `/ignore` hides the messages of, on every network.
+    ignored: IgnoreList,
+}
+
+/// Where a network is connected to.
+struct Server {
+    host: String,
+    port: u16,
+    /// The id of the bouncer network the connection is bound to.
+    bouncer_network: Option<String>,
+}
+
+/// Someone typing in a buffer, and when they last said so.
+struct Typing {
+    network: String,
+    buffer: String,
+    nickname: String,
+    since: Instant,
+}
+
+/// A message that can be replied or reacted to.
+struct RecentMessage {
+    network: String,
+    msgid: String,
+    /// Where a reply goes: the channel, or the sender of a private message.
+    target: String,
+    /// The message as `<nick> text`.
+    quote: String,
 }
 
 const INPUT_CHARACTER_START: usize = 3;
+/// How many messages are kept for quoting replies and reactions.
+const RECENT_MESSAGES: usize = 256;
+/// How long someone is shown as typing after their last notification.
+const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+/// How often we tell others that we are still typing.
+const TYPING_INTERVAL: Duration = Duration::from_secs(3);
 
 impl App {
     pub fn new() -> Self {
+        let ignored = IgnoreList::default();
+        let mut networks = NetworkManager::new();
+        let list = ignored.clone();
+        networks.add_plugin(move |_| Box::new(Ignore::new(list.clone())));
+
         Self {
             input: String::new(),
-            messages: Vec::new(),
-            users: Vec::new(),
             character_index: 0,
             nickname: String::from("anonguest4523"),
             server: String::from("irc.eu.libera.chat"),
@@ -61,8 +114,15 @@ impl App {
             current_screen: CurrentScreen::Start,
             start_selection: StartSelection::Start,
             wizard_step: WizardStep::Nickname,
-            incoming: None,
-            irc_client: None,
+            networks,
+            buffers: BufferTree::new(),
+            servers: HashMap::new(),
+            nicknames: HashMap::new(),
+            last_seen: HashMap::new(),
+            typing: Vec::new(),
+            typing_sent: None,
+            recent: VecDeque::new(),
+            ignored,
         }
     }
 
@@ -125,32 +185,203 @@ impl App {
     }
 
     fn submit_message(&mut self) {
-        let message = self.input.trim().to_string();
+        // Pasted text keeps its lines and indentation, irkki-core trims what is not needed.
+        let message = self.input.clone();
 
         debug!("Submitting message: {}", message);
 
-        if !message.is_empty() {
-            if let Some(client) = &mut self.irc_client {
-                if let Err(error) = client.send_message(&message) {
-                    self.messages
-                        .push(format!("Failed to send message: {error}"));
-                    error!("Failed to send message: {}", error);
-                } else {
-                    self.messages
-                        .push(format!("<{}> {}", self.nickname, message));
+        if let Some(command) = ["/reply ", "/react "]
+            .into_iter()
+            .find(|command| message.trim_start().starts_with(command))
+        {
+            let text = &message.trim_start()[command.len()..];
+            self.respond_to_latest(command.trim(), text);
+            self.input.clear();
+            self.reset_cursor();
+            return;
+        }
 
-                    if message == "/quit" {
-                        self.current_screen = CurrentScreen::Start;
-                    }
-                }
-            } else {
-                self.messages
-                    .push("Not connected to an IRC server.".to_string());
-                error!("Failed to send message: Not connected to an IRC server.");
+        if let Some(arguments) = message.trim_start().strip_prefix("/connect ") {
+            match Self::parse_connect(arguments) {
+                Some((server, port)) => self.connect(server, port),
+                None => self
+                    .buffers
+                    .push_selected("Usage: /connect <server> [port]".to_string()),
             }
+            self.input.clear();
+            self.reset_cursor();
+            return;
+        }
 
+        let mut words = message.split_whitespace();
+        if let Some(command @ ("/ignore" | "/unignore")) = words.next() {
+            let line = match (command, words.next()) {
+                ("/ignore", Some(nickname)) if self.ignored.add(nickname) => {
+                    format!("Ignoring {nickname}.")
+                }
+                ("/ignore", Some(nickname)) => format!("Already ignoring {nickname}."),
+                ("/ignore", None) => match self.ignored.nicknames() {
+                    nicknames if nicknames.is_empty() => "Not ignoring anyone.".to_string(),
+                    nicknames => format!("Ignoring {}.", nicknames.join(", ")),
+                },
+                (_, Some(nickname)) if self.ignored.remove(nickname) => {
+                    format!("No longer ignoring {nickname}.")
+                }
+                (_, Some(nickname)) => format!("Not ignoring {nickname}."),
+                (_, None) => "Usage: /unignore <nickname>".to_string(),
+            };
+            self.buffers.push_selected(line);
             self.input.clear();
             self.reset_cursor();
+            return;
+        }
+
+        if !message.trim().is_empty() {
+            self.typing_sent = None;
+            self.send(&message);
+            self.input.clear();
+            self.reset_cursor();
+        }
+    }
+
+    /// Sends a line to the network of the selected buffer. Text goes to the channel or the
+    /// conversation that is shown, commands are left to irkki-core.
+    fn send(&mut self, message: &str) {
+        let Some((network, buffer)) = self
+            .buffers
+            .selected()
+            .map(|(network, buffer)| (network.to_string(), buffer.map(str::to_string)))
+        else {
+            return;
+        };
+        let Some(client) = self.networks.client_mut(&network) else {
+            self.buffers
+                .push_selected("Not connected to an IRC server.".to_string());
+            error!("Failed to send message: Not connected to {}.", network);
+            return;
+        };
+
+        let is_command = message.trim_start().starts_with('/');
+        let result = match &buffer {
+            Some(target) if !is_command => client.send_message(format!("/msg {target} {message}")),
+            _ => client.send_message(message),
+        };
+        if let Err(error) = result {
+            self.buffers
+                .push_selected(format!("Failed to send message: {error}"));
+            error!("Failed to send message: {}", error);
+            return;
+        }
+
+        // With echo-message the server sends our messages back, as it delivered them.
+        let is_message =
+            !is_command || message.starts_with("/msg") || message.starts_with("/notice");
+        if !(is_message && client.capabilities().is_enabled("echo-message")) {
+            let nickname = self.nickname_on(&network);
+            for line in message.trim_end().lines() {
+                self.buffers
+                    .push_selected(format!("<{}> {}", nickname, line));
+            }
+        }
+
+        // The network is removed once its connection closes, the screen changes with the last.
+        if message.trim() == "/quit" && self.networks.networks().count() == 1 {
+            self.current_screen = CurrentScreen::Start;
+        }
+    }
+
+    /// The server and port of `/connect <server> [port]`.
+    fn parse_connect(arguments: &str) -> Option<(String, u16)> {
+        let mut words = arguments.split_whitespace();
+        let server = words.next()?.to_string();
+        let port = match words.next() {
+            Some(port) => port.parse().ok()?,
+            None => 6667,
+        };
+        words.next().is_none().then_some((server, port))
+    }
+
+    /// Replies or reacts to the latest message in the selected buffer that has a msgid, for
+    /// `/reply` and `/react`.
+    fn respond_to_latest(&mut self, command: &str, text: &str) {
+        let selected = self.buffers.selected();
+        let latest = self.recent.iter().rev().find(|recent| {
+            selected.is_some_and(|(network, buffer)| {
+                recent.network == network
+                    && buffer.is_none_or(|buffer| recent.target.eq_ignore_ascii_case(buffer))
+            })
+        });
+        let Some((latest, client)) = latest.and_then(|latest| {
+            self.networks
+                .client_mut(&latest.network)
+                .map(|client| (latest, client))
+        }) else {
+            self.buffers
+                .push_selected("There is no message to respond to.".to_string());
+            return;
+        };
+        if text.trim().is_empty() {
+            return;
+        }
+
+        let result = if command == "/reply" {
+            client.send_reply(&latest.target, &latest.msgid, text)
+        } else {
+            client.send_reaction(&latest.target, &latest.msgid, text.trim())
+        };
+        match result {
+            Err(error) => {
+                self.buffers
+                    .push_selected(format!("Failed to send {command}: {error}"));
+                error!("Failed to send {}: {}", command, error);
+            }
+            Ok(_) if !client.capabilities().is_enabled("echo-message") => {
+                let nickname = self.nickname_on(&latest.network);
+                let line = if command == "/reply" {
+                    format!("<{}> {}", nickname, text.trim())
+                } else {
+                    format!("{} reacted {}", nickname, text.trim())
+                };
+                let (network, target) = (latest.network.clone(), latest.target.clone());
+                let quote = format!("  ↳ {}", latest.quote);
+                self.buffers.push(&network, Some(&target), quote);
+                self.buffers.push(&network, Some(&target), line);
+            }
+            Ok(_) => {}
+        }
+    }
+
+    /// Tells the selected channel or conversation that we are typing, at most every
+    /// [`TYPING_INTERVAL`], or that we stopped when the input is cleared.
+    fn notify_typing(&mut self) {
+        let Some((network, Some(target))) = self.buffers.selected() else {
+            return;
+        };
+        let Some(client) = self.networks.client_mut(network) else {
+            return;
+        };
+        if self.input.starts_with('/') {
+            return;
+        }
+
+        let state = if self.input.is_empty() {
+            if self.typing_sent.take().is_none() {
+                return;
+            }
+            TypingState::Done
+        } else if self
+            .typing_sent
+            .is_none_or(|sent| sent.elapsed() >= TYPING_INTERVAL)
+        {
+            self.typing_sent = Some(Instant::now());
+            TypingState::Active
+        } else {
+            return;
+        };
+
+        // Servers without client tags cannot relay it, which is fine to ignore.
+        if let Err(error) = client.send_typing(target, state) {
+            debug!("Not sending typing notification: {}", error);
         }
     }
 
@@ -159,29 +390,52 @@ impl App {
             self.drain_incoming();
             terminal.draw(|frame| self.draw(frame))?;
 
-            if event::poll(Duration::from_millis(50))?
-                && let Event::Key(key) = event::read()?
-                && key.kind == KeyEventKind::Press
-            {
-                let should_exit = match self.current_screen {
-                    CurrentScreen::Start => self.handle_start_input(key.code),
-                    CurrentScreen::Wizard => {
-                        self.handle_wizard_input(key.code);
-                        false
-                    }
-                    CurrentScreen::Chat => {
-                        self.handle_chat_input(key.code);
-                        false
-                    }
-                };
+            if !event::poll(Duration::from_millis(50))? {
+                continue;
+            }
+
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => {
+                    let should_exit = match self.current_screen {
+                        CurrentScreen::Start => self.handle_start_input(key.code),
+                        CurrentScreen::Wizard => {
+                            self.handle_wizard_input(key.code);
+                            false
+                        }
+                        CurrentScreen::Chat => {
+                            self.handle_chat_input(key.code);
+                            false
+                        }
+                    };
 
-                if should_exit {
-                    return Ok(());
+                    if should_exit {
+                        return Ok(());
+                    }
                 }
+                Event::Paste(text) => self.paste(&text),
+                _ => {}
             }
         }
     }
 
+    /// Inserts pasted text at the cursor. The chat input keeps the lines, so a pasted snippet is
+    /// sent as one multiline message, while the wizard only takes the first line.
+    fn paste(&mut self, text: &str) {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        let text = match self.current_screen {
+            CurrentScreen::Chat => text.as_str(),
+            CurrentScreen::Wizard => text.lines().next().unwrap_or_default(),
+            CurrentScreen::Start => return,
+        };
+
+        for c in text.chars() {
+            self.enter_char(c);
+        }
+        if matches!(self.current_screen, CurrentScreen::Chat) {
+            self.notify_typing();
+        }
+    }
+
     fn draw(&self, frame: &mut Frame) {
         match self.current_screen {
             CurrentScreen::Start => {
@@ -199,11 +453,30 @@ impl App {
                 wizard_view(&model, frame);
             }
             CurrentScreen::Chat => {
+                let selected = self.buffers.selected();
+                let typing = self
+                    .typing
+                    .iter()
+                    .filter(|typing| {
+                        selected.is_some_and(|(network, buffer)| {
+                            typing.network == network
+                                && buffer.is_some_and(|buffer| typing.buffer == buffer)
+                        })
+                    })
+                    .map(|typing| typing.nickname.clone())
+                    .collect();
                 let model = ChatModel {
                     input: self.input.clone(),
                     character_index: self.character_index,
-                    messages: self.messages.clone(),
-                    users: self.users.clone(),
+                    messages: self
+                        .buffers
+                        .selected_buffer()
+                        .map(|buffer| buffer.messages.clone())
+                        .unwrap_or_default(),
+                    users: self.users(),
+                    buffers: self.buffers.entries(),
+                    selected_buffer: self.buffers.selected_row(),
+                    typing,
                 };
                 chat_view(&model, frame);
             }
@@ -247,14 +520,54 @@ impl App {
     fn handle_chat_input(&mut self, key: KeyCode) {
         match key {
             KeyCode::Enter => self.submit_message(),
-            KeyCode::Char(to_insert) => self.enter_char(to_insert),
-            KeyCode::Backspace => self.delete_char(),
+            KeyCode::Char(to_insert) => {
+                self.enter_char(to_insert);
+                self.notify_typing();
+            }
+            KeyCode::Backspace => {
+                self.delete_char();
+                self.notify_typing();
+            }
             KeyCode::Left => self.move_cursor_left(),
             KeyCode::Right => self.move_cursor_right(),
+            KeyCode::Tab => self.buffers.select_next(),
+            KeyCode::BackTab => self.buffers.select_previous(),
             _ => {}
         }
     }
 
+    /// The members of the selected channel, those with the highest status first.
+    fn users(&self) -> Vec<String> {
+        let Some((network, Some(channel))) = self.buffers.selected() else {
+            return Vec::new();
+        };
+        let Some(client) = self.networks.client(network) else {
+            return Vec::new();
+        };
+        let channels = client.channels();
+        let Some(channel) = channels.channel(channel) else {
+            return Vec::new();
+        };
+
+        let mut members: Vec<_> = channel.members().collect();
+        members.sort_by_cached_key(|member| {
+            let rank = member
+                .prefixes
+                .chars()
+                .next()
+                .and_then(|prefix| "~&@%+".find(prefix))
+                .unwrap_or(5);
+            (rank, member.nickname.to_lowercase())
+        });
+        members
+            .into_iter()
+            .map(|member| {
+                let prefix = member.prefixes.chars().next();
+                prefix.into_iter().chain(member.nickname.chars()).collect()
+            })
+            .collect()
+    }
+
     fn wizard_prompt(&self) -> String {
         match self.wizard_step {
             WizardStep::Nickname => format!("Enter your nickname ({}):", self.nickname),
@@ -296,56 +609,515 @@ impl App {
     }
 
     fn start_irc_connection(&mut self) {
-        let server = self.server.clone();
-        let port = self.port;
-        let (sender, receiver) = mpsc::channel::<IRCEvent>();
-        self.incoming = Some(receiver);
-
-        let connect_result = IRCClient::connect(self.nickname.clone(), server.clone(), port);
-        let mut client = match connect_result {
-            Ok(client) => client,
-            Err(error) => {
-                self.messages
-                    .push(format!("Failed to connect to {server}:{port}: {error}"));
-                return;
-            }
-        };
+        self.connect(self.server.clone(), self.port);
+    }
 
-        let listen_result = client.start_listening(move |event| {
-            sender
-                .send(event)
-                .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e.to_string()))
-        });
-        if let Err(error) = listen_result {
-            self.messages
-                .push(format!("Failed to start listener: {error}"));
+    /// Connects to another network, which is known by the name of its server.
+    fn connect(&mut self, server: String, port: u16) {
+        self.buffers.add_network(&server);
+        let options = ConnectionOptions::new(self.nickname.clone());
+        self.connect_to(server.clone(), options, server, port);
+    }
+
+    /// Opens a connection for each network of the soju bouncer that `network` is connected to,
+    /// bound to it and known by the name of the network.
+    fn connect_bouncer_networks(&mut self, network: &str, networks: Vec<BouncerNetwork>) {
+        let Some(server) = self
+            .servers
+            .get(network)
+            .filter(|server| server.bouncer_network.is_none())
+        else {
             return;
-        }
+        };
+        let (host, port) = (server.host.clone(), server.port);
+
+        for bouncer_network in networks {
+            let is_open = self.servers.values().any(|server| {
+                server.host == host
+                    && server.port == port
+                    && server.bouncer_network.as_ref() == Some(&bouncer_network.id)
+            });
+            if is_open {
+                continue;
+            }
 
-        self.irc_client = Some(client);
+            let name = bouncer_network.name().to_string();
+            self.buffers
+                .push(&name, None, format!("Connecting to {name} through {host}."));
+            let mut options = ConnectionOptions::new(self.nickname.clone());
+            options.bouncer_network = Some(bouncer_network.id);
+            self.connect_to(name, options, host.clone(), port);
+        }
     }
 
-    fn drain_incoming(&mut self) {
-        let receiver = match &self.incoming {
-            Some(receiver) => receiver,
-            None => return,
+    fn connect_to(
+        &mut self,
+        network: String,
+        mut options: ConnectionOptions,
+        host: String,
+        port: u16,
+    ) {
+        options.playback_since = self.last_seen.get(&network).copied();
+        let server = Server {
+            host: host.clone(),
+            port,
+            bouncer_network: options.bouncer_network.clone(),
         };
+        match self.networks.connect(&network, options, &host, port) {
+            Ok(()) => {
+                self.servers.insert(network, server);
+            }
+            Err(error) => self.buffers.push(
+                &network,
+                None,
+                format!("Failed to connect to {host}:{port}: {error}"),
+            ),
+        }
+    }
 
-        while let Ok(event) = receiver.try_recv() {
+    fn drain_incoming(&mut self) {
+        let events: Vec<NetworkEvent> = self.networks.try_events().collect();
+        for event in events {
             match event {
-                IRCEvent::Users(u) => {
-                    self.users.extend(u);
+                NetworkEvent::Event { network, event } => self.show_event(&network, event),
+                NetworkEvent::Disconnected { network } => {
+                    if let Some(seen) = self
+                        .networks
+                        .remove(&network)
+                        .and_then(|client| client.last_seen())
+                    {
+                        self.last_seen.insert(network.clone(), seen);
+                    }
+                    self.typing.retain(|typing| typing.network != network);
+                    self.buffers
+                        .push(&network, None, format!("Disconnected from {network}."));
                 }
-                IRCEvent::Message(message) => {
-                    self.messages
-                        .push(format!("{} {}", message.command, message.params.join(" ")));
+            }
+        }
+        self.typing
+            .retain(|typing| typing.since.elapsed() < TYPING_TIMEOUT);
+    }
+
+    /// Our nickname on a network.
+    fn nickname_on(&self, network: &str) -> String {
+        self.nicknames
+            .get(network)
+            .unwrap_or(&self.nickname)
+            .clone()
+    }
+
+    fn show_event(&mut self, network: &str, event: irkki_core::Event) {
+        let irkki_core::Event {
+            time,
+            msgid,
+            reply_to,
+            kind,
+            ..
+        } = event;
+        let quoted = reply_to
+            .as_deref()
+            .and_then(|msgid| self.quote(network, msgid));
+        let buffer = Self::buffer_of(&kind, &self.nickname_on(network));
+        let buffer = buffer.as_deref();
+
+        match kind {
+            IRCEvent::Typing { source, state, .. } => {
+                let buffer = buffer.unwrap_or_default();
+                self.typing.retain(|typing| {
+                    !(typing.network == network
+                        && typing.buffer == buffer
+                        && typing.nickname == source.nickname)
+                });
+                if state == TypingState::Active {
+                    self.typing.push(Typing {
+                        network: network.to_string(),
+                        buffer: buffer.to_string(),
+                        nickname: source.nickname,
+                        since: Instant::now(),
+                    });
                 }
-                IRCEvent::MessageOfTheDay(motd) => {
-                    self.messages.push("Message of the Day:".to_string());
-                    self.messages.extend(motd);
+            }
+            IRCEvent::React {
+                source, reaction, ..
+            } => {
+                let line = match quoted {
+                    Some(quoted) => {
+                        format!("{} reacted {} to {}", source.nickname, reaction, quoted)
+                    }
+                    None => format!("{} reacted {}", source.nickname, reaction),
+                };
+                self.buffers.push(
+                    network,
+                    buffer,
+                    format!("[{}] {}", Self::format_time(time), line),
+                );
+            }
+            // The users of a channel are read from the channel state of the client.
+            IRCEvent::Users(_) => {}
+            IRCEvent::Message(message) => {
+                self.buffers.push(
+                    network,
+                    None,
+                    format!("{} {}", message.command, message.params.join(" ")),
+                );
+            }
+            IRCEvent::MessageOfTheDay(motd) => {
+                self.buffers
+                    .push(network, None, "Message of the Day:".to_string());
+                for line in motd {
+                    self.buffers.push(network, None, line);
+                }
+            }
+            IRCEvent::Raw(raw) => self.buffers.push(network, None, raw),
+            IRCEvent::Batch(batch) => {
+                for event in batch.events {
+                    self.show_event(network, event);
                 }
-                IRCEvent::Raw(raw) => self.messages.push(raw),
             }
+            event => {
+                match &event {
+                    IRCEvent::Registered { nickname } => {
+                        self.nicknames.insert(network.to_string(), nickname.clone());
+                    }
+                    IRCEvent::Nick { source, nickname }
+                        if source.nickname == self.nickname_on(network) =>
+                    {
+                        self.nicknames.insert(network.to_string(), nickname.clone());
+                    }
+                    IRCEvent::PrivMsg { source, .. } | IRCEvent::Action { source, .. } => {
+                        self.typing.retain(|typing| {
+                            !(typing.network == network && typing.nickname == source.nickname)
+                        });
+                    }
+                    _ => {}
+                }
+                if let (Some(msgid), Some(buffer)) = (msgid, buffer) {
+                    self.remember(network, msgid, buffer, &event);
+                }
+                if let Some(quoted) = quoted {
+                    self.buffers
+                        .push(network, buffer, format!("  ↳ {}", quoted));
+                }
+                let bouncer_networks = match &event {
+                    IRCEvent::BouncerNetworks(networks) => networks.clone(),
+                    IRCEvent::BouncerNetwork(network) => vec![network.clone()],
+                    _ => Vec::new(),
+                };
+                // Joining a channel from the server buffer shows the channel.
+                let joined = match &event {
+                    IRCEvent::Join {
+                        source, channel, ..
+                    } if source.nickname == self.nickname_on(network) => Some(channel.clone()),
+                    _ => None,
+                };
+                if let Some(line) = Self::format_event(event) {
+                    self.buffers.push(
+                        network,
+                        buffer,
+                        format!("[{}] {}", Self::format_time(time), line),
+                    );
+                }
+                if let Some(channel) = joined
+                    && self.buffers.selected() == Some((network, None))
+                {
+                    self.buffers.select(network, &channel);
+                }
+                if !bouncer_networks.is_empty() {
+                    self.connect_bouncer_networks(network, bouncer_networks);
+                }
+            }
+        }
+    }
+
+    /// The buffer an event is shown in: a channel, the nickname of a private conversation, or
+    /// `None` for the server buffer.
+    fn buffer_of(event: &IRCEvent, nickname: &str) -> Option<String> {
+        let is_channel = |target: &str| target.starts_with(['#', '&']);
+        // Our own messages are shown in the conversation with the one we sent them to.
+        let conversation = |source: &Source, target: &String| {
+            if is_channel(target) || source.nickname.eq_ignore_ascii_case(nickname) {
+                target.clone()
+            } else {
+                source.nickname.clone()
+            }
+        };
+
+        match event {
+            IRCEvent::PrivMsg { source, target, .. }
+            | IRCEvent::Action { source, target, .. }
+            | IRCEvent::Typing { source, target, .. }
+            | IRCEvent::React { source, target, .. } => Some(conversation(source, target)),
+            // Notices from the server itself go to the server buffer.
+            IRCEvent::Notice {
+                source: Some(source),
+                target,
+                ..
+            } if target != "*" && !source.nickname.contains('.') => {
+                Some(conversation(source, target))
+            }
+            IRCEvent::Join { channel, .. }
+            | IRCEvent::Part { channel, .. }
+            | IRCEvent::Kick { channel, .. }
+            | IRCEvent::Topic { channel, .. } => Some(channel.clone()),
+            IRCEvent::Mode { target, .. } if is_channel(target) => Some(target.clone()),
+            _ => None,
         }
     }
+
+    /// Keeps a message to quote when someone replies or reacts to it.
+    fn remember(&mut self, network: &str, msgid: String, target: &str, event: &IRCEvent) {
+        let quote = match event {
+            IRCEvent::PrivMsg { source, text, .. } => format!("<{}> {}", source.nickname, text),
+            IRCEvent::Action { source, text, .. } => format!("* {} {}", source.nickname, text),
+            _ => return,
+        };
+
+        if self.recent.len() == RECENT_MESSAGES {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(RecentMessage {
+            network: network.to_string(),
+            msgid,
+            target: target.to_string(),
+            quote,
+        });
+    }
+
+    fn quote(&self, network: &str, msgid: &str) -> Option<String> {
+        self.recent
+            .iter()
+            .find(|recent| recent.network == network && recent.msgid == msgid)
+            .map(|recent| recent.quote.clone())
+    }
+
+    /// Formats the time of an event as `HH:MM` in UTC.
+    fn format_time(time: SystemTime) -> String {
+        let seconds = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        format!("{:02}:{:02}", seconds / 3600 % 24, seconds / 60 % 60)
+    }
+
+    fn format_event(event: IRCEvent) -> Option<String> {
+        let line = match event {
+            IRCEvent::Registered { nickname } => format!("Registered as {}", nickname),
+            IRCEvent::RegistrationFailed(error) => format!("Registration failed: {}", error.text),
+            IRCEvent::LoggedIn { account } => format!("Logged in as {}", account),
+            IRCEvent::PrivMsg { source, text, .. } => format!("<{}> {}", source.nickname, text),
+            IRCEvent::Notice { source, text, .. } => match source {
+                Some(source) => format!("-{}- {}", source.nickname, text),
+                None => text,
+            },
+            IRCEvent::Action { source, text, .. } => format!("* {} {}", source.nickname, text),
+            IRCEvent::Ctcp {
+                source, command, ..
+            } => format!("{} requested CTCP {}", source.nickname, command),
+            IRCEvent::Dcc {
+                source, request, ..
+            } => match request {
+                DccRequest::Send { filename, size, .. } => match size {
+                    Some(size) => format!(
+                        "{} offers the file {} ({} bytes) over DCC",
+                        source.nickname, filename, size
+                    ),
+                    None => format!("{} offers the file {} over DCC", source.nickname, filename),
+                },
+                DccRequest::Chat { .. } => format!("{} offers a DCC chat", source.nickname),
+                request => format!("DCC {} from {}", request, source.nickname),
+            },
+            IRCEvent::CtcpReply {
+                source,
+                command,
+                params,
+                ..
+            } => format!(
+                "CTCP {} reply from {}: {}",
+                command, source.nickname, params
+            ),
+            IRCEvent::Join {
+                source,
+                channel,
+                account,
+                ..
+            } => match account {
+                Some(account) => format!(
+                    "--> {} ({}) [{}] has joined {}",
+                    source.nickname, source, account, channel
+                ),
+                None => format!(
+                    "--> {} ({}) has joined {}",
+                    source.nickname, source, channel
+                ),
+            },
+            IRCEvent::Part {
+                source,
+                channel,
+                reason,
+            } => format!(
+                "<-- {} has left {} ({})",
+                source.nickname,
+                channel,
+                reason.unwrap_or_default()
+            ),
+            IRCEvent::Quit { source, reason } => format!(
+                "<-- {} has quit ({})",
+                source.nickname,
+                reason.unwrap_or_default()
+            ),
+            IRCEvent::Kick {
+                source,
+                channel,
+                nickname,
+                reason,
+            } => format!(
+                "<-- {} was kicked from {} by {} ({})",
+                nickname,
+                channel,
+                source.nickname,
+                reason.unwrap_or_default()
+            ),
+            IRCEvent::Nick { source, nickname } => {
+                format!("{} is now known as {}", source.nickname, nickname)
+            }
+            IRCEvent::Topic {
+                source,
+                channel,
+                topic,
+            } => match source {
+                Some(source) => format!(
+                    "{} changed the topic of {} to: {}",
+                    source.nickname, channel, topic
+                ),
+                None => format!("Topic for {}: {}", channel, topic),
+            },
+            IRCEvent::Mode {
+                source,
+                target,
+                modes,
+                args,
+            } => format!(
+                "{} sets mode {} {} on {}",
+                source.map(|source| source.nickname).unwrap_or_default(),
+                modes,
+                args.join(" "),
+                target
+            ),
+            // With invite-notify the invites of others in our channels come in too.
+            IRCEvent::Invite {
+                source,
+                nickname,
+                channel,
+            } => format!("{} invites {} to {}", source.nickname, nickname, channel),
+            IRCEvent::Away { source, message } => match message {
+                Some(message) => format!("{} is away: {}", source.nickname, message),
+                None => format!("{} is back", source.nickname),
+            },
+            IRCEvent::Account { source, account } => match account {
+                Some(account) => format!("{} is logged in as {}", source.nickname, account),
+                None => format!("{} has logged out", source.nickname),
+            },
+            IRCEvent::HostChange {
+                source,
+                username,
+                host,
+            } => format!(
+                "{} has changed host to {}@{}",
+                source.nickname, username, host
+            ),
+            IRCEvent::RealnameChange { source, realname } => {
+                format!(
+                    "{} has changed their real name to {}",
+                    source.nickname, realname
+                )
+            }
+            IRCEvent::Online { source } => format!("{} ({}) is online", source.nickname, source),
+            IRCEvent::Offline { nickname } => format!("{} is offline", nickname),
+            IRCEvent::MonitorListFull { limit, nicknames } => format!(
+                "Cannot watch {}, the server allows {} nicknames",
+                nicknames.join(", "),
+                limit
+            ),
+            IRCEvent::Who { mask, users } => {
+                let users: Vec<String> = users
+                    .into_iter()
+                    .map(|user| match user.account {
+                        Some(account) => format!("{} ({})", user.nickname, account),
+                        None => user.nickname,
+                    })
+                    .collect();
+                format!("Who {}: {}", mask, users.join(", "))
+            }
+            IRCEvent::Whois(info) => {
+                let mut line = format!(
+                    "{} ({}@{}): {}",
+                    info.nickname,
+                    info.username.unwrap_or_default(),
+                    info.host.unwrap_or_default(),
+                    info.realname.unwrap_or_default()
+                );
+                if let Some(account) = info.account {
+                    line.push_str(&format!(" | account {}", account));
+                }
+                if !info.channels.is_empty() {
+                    line.push_str(&format!(" | {}", info.channels.join(" ")));
+                }
+                if let Some(server) = info.server {
+                    line.push_str(&format!(" | {}", server));
+                }
+                line
+            }
+            IRCEvent::ServerError(error) if error.params.is_empty() => {
+                format!("Error: {}", error.text)
+            }
+            IRCEvent::ServerError(error) => {
+                format!("Error: {} ({})", error.text, error.params.join(" "))
+            }
+            IRCEvent::Error { message } => format!("Error: {}", message),
+            IRCEvent::StandardReply(reply) => {
+                let kind = match reply.kind {
+                    ReplyKind::Fail => "Error",
+                    ReplyKind::Warn => "Warning",
+                    ReplyKind::Note => "Note",
+                };
+                format!(
+                    "{}: {} ({} {})",
+                    kind, reply.description, reply.command, reply.code
+                )
+            }
+            IRCEvent::Netsplit {
+                server,
+                remote,
+                users,
+            } => format!(
+                "Netsplit between {} and {}, {} users quit",
+                server,
+                remote,
+                users.len()
+            ),
+            IRCEvent::Netjoin {
+                server,
+                remote,
+                joins,
+            } => format!(
+                "Netjoin between {} and {}, {} users rejoined",
+                server,
+                remote,
+                joins.len()
+            ),
+            IRCEvent::BouncerNetwork(network) => match network.state() {
+                Some(state) => format!("Bouncer network {} is {}", network.name(), state),
+                None => format!("Bouncer network {}", network.name()),
+            },
+            IRCEvent::BouncerNetworks(networks) => {
+                let names: Vec<&str> = networks.iter().map(BouncerNetwork::name).collect();
+                format!("Bouncer networks: {}", names.join(", "))
+            }
+            IRCEvent::BouncerNetworkRemoved { id } => {
+                format!("Bouncer network {} was removed", id)
+            }
+            _ => return None,
+        };
+
+        Some(line)
+    }
 }
@@ -0,0 +1,267 @@
+/// The messages of a channel, of a private conversation or of the server itself.
+pub struct Buffer {
+    pub name: String,
+    pub messages: Vec<String>,
+    /// Whether messages have arrived since the buffer was last looked at.
+    pub unread: bool,
+}
+
+impl Buffer {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            messages: Vec::new(),
+            unread: false,
+        }
+    }
+}
+
+struct Network {
+    /// The server buffer, named after the network.
+    server: Buffer,
+    /// Channels and private conversations, in the order they were opened.
+    buffers: Vec<Buffer>,
+}
+
+/// A row of the buffer tree as it is drawn.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    /// Whether the row is a channel or conversation under its network.
+    pub nested: bool,
+    pub unread: bool,
+}
+
+/// The buffers of every network, each network with its channels and private conversations
+/// under it.
+#[derive(Default)]
+pub struct BufferTree {
+    networks: Vec<Network>,
+    /// The index of the network and of the buffer in it, `None` for the server buffer.
+    selected: (usize, Option<usize>),
+}
+
+impl BufferTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a network with its server buffer and selects it. A network that is already there
+    /// keeps its buffers.
+    pub fn add_network(&mut self, network: &str) {
+        let index = match self.network_index(network) {
+            Some(index) => index,
+            None => {
+                self.networks.push(Network {
+                    server: Buffer::new(network),
+                    buffers: Vec::new(),
+                });
+                self.networks.len() - 1
+            }
+        };
+        self.selected = (index, None);
+    }
+
+    /// Adds a line to the buffer `name` of a network, or to its server buffer when there is no
+    /// name. The buffer is opened when it is not there yet.
+    pub fn push(&mut self, network: &str, name: Option<&str>, line: String) {
+        if self.network_index(network).is_none() {
+            self.networks.push(Network {
+                server: Buffer::new(network),
+                buffers: Vec::new(),
+            });
+        }
+        let network_index = self.network_index(network).unwrap_or_default();
+        let network = &mut self.networks[network_index];
+
+        let buffer_index = name.map(|name| {
+            match network
+                .buffers
+                .iter()
+                .position(|buffer| buffer.name.eq_ignore_ascii_case(name))
+            {
+                Some(index) => index,
+                None => {
+                    network.buffers.push(Buffer::new(name));
+                    network.buffers.len() - 1
+                }
+            }
+        });
+
+        let buffer = match buffer_index {
+            Some(index) => &mut network.buffers[index],
+            None => &mut network.server,
+        };
+        buffer.messages.push(line);
+        buffer.unread |= (network_index, buffer_index) != self.selected;
+    }
+
+    /// Adds a line to the selected buffer.
+    pub fn push_selected(&mut self, line: String) {
+        if let Some(buffer) = self.buffer_mut(self.selected) {
+            buffer.messages.push(line);
+        }
+    }
+
+    /// The network and the name of the selected buffer, the name is `None` for a server buffer.
+    pub fn selected(&self) -> Option<(&str, Option<&str>)> {
+        let (network, buffer) = self.selected;
+        let network = self.networks.get(network)?;
+        let name = match buffer {
+            Some(index) => Some(network.buffers.get(index)?.name.as_str()),
+            None => None,
+        };
+        Some((network.server.name.as_str(), name))
+    }
+
+    pub fn selected_buffer(&self) -> Option<&Buffer> {
+        let (network, buffer) = self.selected;
+        let network = self.networks.get(network)?;
+        match buffer {
+            Some(index) => network.buffers.get(index),
+            None => Some(&network.server),
+        }
+    }
+
+    /// The row of the selected buffer in [`BufferTree::entries`].
+    pub fn selected_row(&self) -> usize {
+        let (network, buffer) = self.selected;
+        let before: usize = self.networks[..network.min(self.networks.len())]
+            .iter()
+            .map(|network| network.buffers.len() + 1)
+            .sum();
+        before + buffer.map_or(0, |index| index + 1)
+    }
+
+    pub fn select_next(&mut self) {
+        let rows = self.rows();
+        if !rows.is_empty() {
+            self.select_at(rows[(self.selected_row() + 1) % rows.len()]);
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        let rows = self.rows();
+        if !rows.is_empty() {
+            self.select_at(rows[(self.selected_row() + rows.len() - 1) % rows.len()]);
+        }
+    }
+
+    pub fn entries(&self) -> Vec<Entry> {
+        self.networks
+            .iter()
+            .flat_map(|network| {
+                std::iter::once((&network.server, false))
+                    .chain(network.buffers.iter().map(|buffer| (buffer, true)))
+            })
+            .map(|(buffer, nested)| Entry {
+                name: buffer.name.clone(),
+                nested,
+                unread: buffer.unread,
+            })
+            .collect()
+    }
+
+    /// Selects the buffer `name` of a network, when it is open.
+    pub fn select(&mut self, network: &str, name: &str) {
+        let Some(index) = self.network_index(network) else {
+            return;
+        };
+        if let Some(buffer) = self.networks[index]
+            .buffers
+            .iter()
+            .position(|buffer| buffer.name.eq_ignore_ascii_case(name))
+        {
+            self.select_at((index, Some(buffer)));
+        }
+    }
+
+    fn select_at(&mut self, selected: (usize, Option<usize>)) {
+        self.selected = selected;
+        if let Some(buffer) = self.buffer_mut(selected) {
+            buffer.unread = false;
+        }
+    }
+
+    /// Every buffer in the order of the rows of the tree.
+    fn rows(&self) -> Vec<(usize, Option<usize>)> {
+        self.networks
+            .iter()
+            .enumerate()
+            .flat_map(|(index, network)| {
+                std::iter::once((index, None))
+                    .chain((0..network.buffers.len()).map(move |buffer| (index, Some(buffer))))
+            })
+            .collect()
+    }
+
+    fn buffer_mut(&mut self, (network, buffer): (usize, Option<usize>)) -> Option<&mut Buffer> {
+        let network = self.networks.get_mut(network)?;
+        match buffer {
+            Some(index) => network.buffers.get_mut(index),
+            None => Some(&mut network.server),
+        }
+    }
+
+    fn network_index(&self, network: &str) -> Option<usize> {
+        self.networks
+            .iter()
+            .position(|candidate| candidate.server.name.eq_ignore_ascii_case(network))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(name: &str, nested: bool, unread: bool) -> Entry {
+        Entry {
+            name: name.to_string(),
+            nested,
+            unread,
+        }
+    }
+
+    #[test]
+    fn buffers_are_grouped_under_their_network() {
+        let mut tree = BufferTree::new();
+        tree.add_network("libera");
+        tree.add_network("work");
+        tree.push("libera", Some("#rust"), "<alice> hi".to_string());
+        tree.push("work", None, "Registered as nick".to_string());
+        tree.push("libera", Some("#Rust"), "<bob> hello".to_string());
+        tree.push("libera", Some("alice"), "<alice> psst".to_string());
+
+        assert_eq!(
+            tree.entries(),
+            vec![
+                entry("libera", false, false),
+                entry("#rust", true, true),
+                entry("alice", true, true),
+                entry("work", false, false),
+            ]
+        );
+        assert_eq!(tree.selected(), Some(("work", None)));
+        assert_eq!(tree.selected_row(), 3);
+    }
+
+    #[test]
+    fn selecting_a_buffer_marks_it_as_read() {
+        let mut tree = BufferTree::new();
+        tree.add_network("libera");
+        tree.push("libera", Some("#rust"), "<alice> hi".to_string());
+
+        tree.select_next();
+        tree.push_selected("Not connected to an IRC server.".to_string());
+
+        assert_eq!(tree.selected(), Some(("libera", Some("#rust"))));
+        let buffer = tree.selected_buffer().unwrap();
+        assert_eq!(buffer.messages.len(), 2);
+        assert!(!buffer.unread);
+
+        tree.select_next();
+        assert_eq!(tree.selected(), Some(("libera", None)));
+        tree.select_previous();
+        assert_eq!(tree.selected(), Some(("libera", Some("#rust"))));
+    }
+}
@@ -5,13 +5,19 @@ use ratatui::{
     widgets::{Block, Paragraph},
 };
 
-use crate::widget::{Messages, Users};
+use crate::buffers::Entry;
+use crate::widget::{Buffers, Messages, Users};
 
 pub struct Model {
     pub input: String,
     pub character_index: usize,
     pub messages: Vec<String>,
     pub users: Vec<String>,
+    /// The buffers of every network, and the row of the one that is shown.
+    pub buffers: Vec<Entry>,
+    pub selected_buffer: usize,
+    /// The nicknames of those who are typing.
+    pub typing: Vec<String>,
 }
 
 pub fn view(model: &Model, frame: &mut Frame) {
@@ -22,9 +28,15 @@ pub fn view(model: &Model, frame: &mut Frame) {
     let inner_layout =
         Layout::vertical([Constraint::Min(1), Constraint::Length(3)]).split(outer_layout[0]);
 
-    let input = Paragraph::new(format!("> {}", model.input.as_str()))
+    let title = match &model.typing[..] {
+        [] => "Input".to_string(),
+        [nickname] => format!("Input - {nickname} is typing…"),
+        nicknames => format!("Input - {} are typing…", nicknames.join(", ")),
+    };
+    // Lines of pasted text are shown on one row, with a mark where each one ends.
+    let input = Paragraph::new(format!("> {}", model.input.replace('\n', "↵")))
         .style(Style::default().fg(Color::LightGreen))
-        .block(Block::bordered().title("Input"));
+        .block(Block::bordered().title(title));
     frame.render_widget(input, inner_layout[1]);
 
     let space = 1;
@@ -38,8 +50,13 @@ pub fn view(model: &Model, frame: &mut Frame) {
     let messages = Messages::new(model.messages.iter().map(String::as_str).collect());
     frame.render_widget(messages, inner_layout[0]);
 
+    let side_layout = Layout::vertical([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(outer_layout[1]);
+    let buffers = Buffers::new(&model.buffers, model.selected_buffer);
+    frame.render_widget(buffers, side_layout[0]);
+
     let users = Users::new(model.users.iter().map(String::as_str).collect());
-    frame.render_widget(users, outer_layout[1]);
+    frame.render_widget(users, side_layout[1]);
 }
 
 #[cfg(test)]
@@ -70,6 +87,13 @@ mod test {
             character_index: 2,
             messages: vec!["Message 1".to_string(), "Message 2".to_string()],
             users: vec!["Alice".to_string(), "Bob".to_string()],
+            buffers: vec![Entry {
+                name: "libera".to_string(),
+                nested: false,
+                unread: false,
+            }],
+            selected_buffer: 0,
+            typing: vec![],
         };
         let (buffer, cursor) = render(&model);
 
@@ -80,6 +104,8 @@ mod test {
         assert!(rows.iter().any(|r| r.contains("Chat")));
         assert!(rows.iter().any(|r| r.contains("Input")));
         assert!(rows.iter().any(|r| r.contains("> hello")));
+        assert!(rows.iter().any(|r| r.contains("Buffer")));
+        assert!(rows.iter().any(|r| r.contains("libera")));
         assert!(rows.iter().any(|r| r.contains("Users")));
         assert!(rows.iter().any(|r| r.contains("Alice")));
         assert!(rows.iter().any(|r| r.contains("0: Message 1")));
@@ -98,6 +124,26 @@ mod test {
         assert_eq!(cursor, Position::new(5, input_y));
     }
 
+    #[test]
+    fn render_shows_who_is_typing() {
+        let model = Model {
+            input: String::new(),
+            character_index: 0,
+            messages: vec![],
+            users: vec![],
+            buffers: vec![],
+            selected_buffer: 0,
+            typing: vec!["Alice".to_string()],
+        };
+        let (buffer, _cursor) = render(&model);
+
+        let rows: Vec<String> = (0..15)
+            .map(|y| (0..40).map(|x| buffer[(x, y)].symbol()).collect::<String>())
+            .collect();
+
+        assert!(rows.iter().any(|r| r.contains("Alice is typing…")));
+    }
+
     #[test]
     fn render_sets_cursor_from_character_index() {
         let model = Model {
@@ -105,6 +151,9 @@ mod test {
             character_index: 4,
             messages: vec![],
             users: vec![],
+            buffers: vec![],
+            selected_buffer: 0,
+            typing: vec![],
         };
         let (_buffer, cursor) = render(&model);
 
@@ -0,0 +1,185 @@
+use irkki_core::{Event, IRCEvent, Plugin, PluginContext};
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+
+/// The nicknames whose messages are hidden, shared by the [`Ignore`] plugins of every network.
+#[derive(Clone, Default)]
+pub struct IgnoreList {
+    nicknames: Arc<Mutex<BTreeSet<String>>>,
+}
+
+impl IgnoreList {
+    /// Adds a nickname, returns whether it was not ignored yet.
+    pub fn add(&self, nickname: &str) -> bool {
+        self.nicknames
+            .lock()
+            .is_ok_and(|mut nicknames| nicknames.insert(nickname.to_lowercase()))
+    }
+
+    /// Removes a nickname, returns whether it was ignored.
+    pub fn remove(&self, nickname: &str) -> bool {
+        self.nicknames
+            .lock()
+            .is_ok_and(|mut nicknames| nicknames.remove(&nickname.to_lowercase()))
+    }
+
+    pub fn nicknames(&self) -> Vec<String> {
+        self.nicknames
+            .lock()
+            .map(|nicknames| nicknames.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn contains(&self, nickname: &str) -> bool {
+        self.nicknames
+            .lock()
+            .is_ok_and(|nicknames| nicknames.contains(&nickname.to_lowercase()))
+    }
+}
+
+/// Drops the messages, notices, CTCP requests, typing notifications and reactions of the
+/// nicknames on the ignore list before they reach a buffer, also inside batches such as
+/// chathistory and bouncer playback.
+pub struct Ignore {
+    list: IgnoreList,
+}
+
+impl Ignore {
+    pub fn new(list: IgnoreList) -> Self {
+        Self { list }
+    }
+
+    fn filter(&self, mut event: Event) -> Option<Event> {
+        let source = match &mut event.kind {
+            IRCEvent::Batch(batch) => {
+                batch.events = std::mem::take(&mut batch.events)
+                    .into_iter()
+                    .filter_map(|event| self.filter(event))
+                    .collect();
+                return Some(event);
+            }
+            IRCEvent::PrivMsg { source, .. }
+            | IRCEvent::Action { source, .. }
+            | IRCEvent::Ctcp { source, .. }
+            | IRCEvent::Dcc { source, .. }
+            | IRCEvent::Typing { source, .. }
+            | IRCEvent::React { source, .. }
+            | IRCEvent::Notice {
+                source: Some(source),
+                ..
+            } => source,
+            _ => return Some(event),
+        };
+
+        if self.list.contains(&source.nickname) {
+            None
+        } else {
+            Some(event)
+        }
+    }
+}
+
+impl Plugin for Ignore {
+    fn on_event(&mut self, event: Event, _context: &mut PluginContext) -> Option<Event> {
+        self.filter(event)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use irkki_core::{Batch, Parser, Source};
+
+    fn event(line: &str) -> Event {
+        Event::new(IRCEvent::from_message(
+            Parser::new(line).parse_message().unwrap(),
+        ))
+    }
+
+    #[test]
+    fn messages_of_ignored_nicknames_are_dropped() {
+        let list = IgnoreList::default();
+        let mut ignore = Ignore::new(list.clone());
+        let mut context = PluginContext::new("nick");
+        assert!(list.add("Alice"));
+        assert!(!list.add("alice"));
+
+        let dropped = ignore.on_event(event(":alice!a@host PRIVMSG #rust :hi"), &mut context);
+        let kept = ignore.on_event(event(":bob!b@host PRIVMSG #rust :hi"), &mut context);
+        let joined = ignore.on_event(event(":alice!a@host JOIN #rust"), &mut context);
+
+        assert!(dropped.is_none());
+        assert!(matches!(
+            kept.map(|event| event.kind),
+            Some(IRCEvent::PrivMsg { source, .. }) if source == Source::parse("bob!b@host")
+        ));
+        assert!(joined.is_some());
+        assert!(list.remove("ALICE"));
+        assert!(list.nicknames().is_empty());
+    }
+
+    #[test]
+    fn typing_and_reactions_of_ignored_nicknames_are_dropped() {
+        let list = IgnoreList::default();
+        let mut ignore = Ignore::new(list.clone());
+        let mut context = PluginContext::new("nick");
+        list.add("alice");
+
+        let typing = ignore.on_event(
+            event("@+typing=active :alice!a@host TAGMSG #rust"),
+            &mut context,
+        );
+        let reaction = ignore.on_event(
+            event("@+draft/react=+1;+draft/reply=abc :alice!a@host TAGMSG #rust"),
+            &mut context,
+        );
+        let other = ignore.on_event(
+            event("@+typing=active :bob!b@host TAGMSG #rust"),
+            &mut context,
+        );
+
+        assert!(typing.is_none());
+        assert!(reaction.is_none());
+        assert!(matches!(
+            other.map(|event| event.kind),
+            Some(IRCEvent::Typing { .. })
+        ));
+    }
+
+    #[test]
+    fn ignored_nicknames_are_dropped_from_batches() {
+        let list = IgnoreList::default();
+        let mut ignore = Ignore::new(list.clone());
+        let mut context = PluginContext::new("nick");
+        list.add("alice");
+        let nested = Batch {
+            kind: "chathistory".to_string(),
+            params: vec!["#irc".to_string()],
+            events: vec![event(":alice!a@host PRIVMSG #irc :old")],
+        };
+        let batch = Batch {
+            kind: "chathistory".to_string(),
+            params: vec!["#rust".to_string()],
+            events: vec![
+                event(":alice!a@host PRIVMSG #rust :hi"),
+                event(":bob!b@host PRIVMSG #rust :hello"),
+                Event::new(IRCEvent::Batch(nested)),
+            ],
+        };
+
+        let kept = ignore.on_event(Event::new(IRCEvent::Batch(batch)), &mut context);
+
+        let Some(IRCEvent::Batch(batch)) = kept.map(|event| event.kind) else {
+            panic!("the batch was dropped");
+        };
+        assert_eq!(batch.events.len(), 2);
+        assert!(matches!(
+            &batch.events[0].kind,
+            IRCEvent::PrivMsg { source, .. } if source.nickname == "bob"
+        ));
+        assert!(matches!(
+            &batch.events[1].kind,
+            IRCEvent::Batch(nested) if nested.events.is_empty()
+        ));
+    }
+}
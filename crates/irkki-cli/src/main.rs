@@ -1,13 +1,20 @@
 use color_eyre::Result;
 
 mod app;
+mod buffers;
 mod chat_view;
+mod ignore;
 mod start_view;
 mod widget;
 mod wizard_view;
 
 use app::App;
 use flexi_logger::{FileSpec, Logger};
+use ratatui::crossterm::{
+    event::{DisableBracketedPaste, EnableBracketedPaste},
+    execute,
+};
+use std::io::stdout;
 
 fn main() -> Result<()> {
     Logger::try_with_env()?
@@ -18,7 +25,10 @@ fn main() -> Result<()> {
 
     color_eyre::install()?;
     let terminal = ratatui::init();
+    // Pasted text arrives in one piece instead of as key presses, so its newlines do not send it.
+    execute!(stdout(), EnableBracketedPaste)?;
     let app_result = App::new().run(terminal);
+    let _ = execute!(stdout(), DisableBracketedPaste);
     ratatui::restore();
     app_result
 }
@@ -0,0 +1,103 @@
+use ratatui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, List, ListItem, Widget},
+};
+
+use crate::buffers::Entry;
+
+pub struct Buffers<'a> {
+    pub entries: &'a [Entry],
+    pub selected: usize,
+}
+
+impl<'a> Buffers<'a> {
+    pub fn new(entries: &'a [Entry], selected: usize) -> Self {
+        Self { entries, selected }
+    }
+}
+
+impl Widget for Buffers<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let entries: Vec<ListItem> = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let name = if entry.nested {
+                    format!("  {}", entry.name)
+                } else {
+                    entry.name.clone()
+                };
+                let mut style = Style::default();
+                if entry.unread {
+                    style = style.fg(Color::LightYellow);
+                }
+                if i == self.selected {
+                    style = style.add_modifier(Modifier::REVERSED);
+                }
+                ListItem::new(Line::from(Span::styled(name, style)))
+            })
+            .collect();
+
+        let widget = List::new(entries)
+            .style(Style::default().fg(Color::LightGreen))
+            .block(Block::bordered().title("Buffers"));
+
+        widget.render(area, buf);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let entries = [
+            Entry {
+                name: "libera".to_string(),
+                nested: false,
+                unread: false,
+            },
+            Entry {
+                name: "#rust".to_string(),
+                nested: true,
+                unread: true,
+            },
+            Entry {
+                name: "work".to_string(),
+                nested: false,
+                unread: false,
+            },
+        ];
+        let widget = Buffers::new(&entries, 2);
+        let area = Rect::new(0, 0, 20, 5);
+
+        let mut buffer = Buffer::empty(area);
+        widget.render(area, &mut buffer);
+
+        let row = |y| -> String {
+            (0..area.width)
+                .map(|x| buffer[(x, y)].symbol())
+                .collect::<String>()
+        };
+
+        assert_eq!(row(0), "┌Buffers───────────┐");
+        assert_eq!(row(1), "│libera            │");
+        assert_eq!(row(2), "│  #rust           │");
+        assert_eq!(row(3), "│work              │");
+        assert_eq!(row(4), "└──────────────────┘");
+
+        assert_eq!(buffer[(1, 1)].style().fg, Some(Color::LightGreen));
+        assert_eq!(buffer[(3, 2)].style().fg, Some(Color::LightYellow));
+        assert!(
+            buffer[(1, 3)]
+                .style()
+                .add_modifier
+                .contains(Modifier::REVERSED)
+        );
+    }
+}
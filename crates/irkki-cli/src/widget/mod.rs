@@ -1,9 +1,11 @@
+mod buffers;
 mod button;
 mod header;
 mod messages;
 mod prompt;
 mod users;
 
+pub(crate) use buffers::Buffers;
 pub(crate) use button::Button;
 pub(crate) use header::{Header, SimpleHeader};
 pub(crate) use messages::Messages;
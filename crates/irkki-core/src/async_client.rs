@@ -0,0 +1,608 @@
+use futures_core::Stream;
+use log::{debug, error, info};
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::TcpStream;
+use tokio::sync::{Notify, mpsc};
+use tokio::time;
+
+use crate::{
+    BouncerNetwork, Capabilities, Channels, ConnectionOptions, DccRequest, Error, Event,
+    FloodControl, HistoryRequest, HistoryTarget, IRCEvent, ISON_INTERVAL, NotifyList, Plugin,
+    PluginContext, Plugins, Priority, Protocol, Pushed, Response, SendQueue, TypingState, User,
+    WhoisInfo,
+};
+
+const EVENT_BUFFER: usize = 256;
+const OUTGOING_BUFFER: usize = 256;
+
+/// An IRC client for the tokio runtime.
+///
+/// Every connection runs as a reader and a writer task instead of OS threads, so a process can
+/// keep hundreds of connections open. The client is a cheap handle that can be cloned and used
+/// from several tasks, while the events arrive on the [`EventStream`] returned by
+/// [`AsyncIRCClient::connect`].
+#[derive(Clone)]
+pub struct AsyncIRCClient {
+    protocol: Arc<Mutex<Protocol>>,
+    lines: mpsc::Sender<(String, Priority)>,
+    /// The lines the writer task paces with flood control. While it is full the writer stops
+    /// taking lines, so that sending waits.
+    queue: Arc<Mutex<SendQueue>>,
+    /// Wakes the writer task when the flood control settings change.
+    queue_changed: Arc<Notify>,
+    plugins: Arc<Mutex<Plugins>>,
+}
+
+/// The events received on a connection. Ends when the connection is closed.
+pub struct EventStream {
+    events: mpsc::Receiver<Event>,
+}
+
+impl AsyncIRCClient {
+    pub async fn connect(
+        nickname: impl Into<String>,
+        server: impl AsRef<str>,
+        port: u16,
+    ) -> Result<(Self, EventStream), Error> {
+        Self::connect_with_flood_control(nickname, server, port, FloodControl::default()).await
+    }
+
+    pub async fn connect_with_flood_control(
+        nickname: impl Into<String>,
+        server: impl AsRef<str>,
+        port: u16,
+        flood_control: FloodControl,
+    ) -> Result<(Self, EventStream), Error> {
+        Self::connect_with_options(
+            ConnectionOptions::new(nickname),
+            server,
+            port,
+            flood_control,
+        )
+        .await
+    }
+
+    pub async fn connect_with_options(
+        options: ConnectionOptions,
+        server: impl AsRef<str>,
+        port: u16,
+        flood_control: FloodControl,
+    ) -> Result<(Self, EventStream), Error> {
+        let stream = TcpStream::connect((server.as_ref(), port)).await?;
+        let (reader, writer) = stream.into_split();
+        Self::with_options(options, reader, writer, flood_control).await
+    }
+
+    /// Registers with the server over any pair of async byte streams, for example the halves of
+    /// a TLS or Unix socket stream, or a `tokio::io::duplex` in tests.
+    pub async fn with_transport<R, W>(
+        nickname: impl Into<String>,
+        reader: R,
+        writer: W,
+        flood_control: FloodControl,
+    ) -> Result<(Self, EventStream), Error>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        Self::with_options(
+            ConnectionOptions::new(nickname),
+            reader,
+            writer,
+            flood_control,
+        )
+        .await
+    }
+
+    /// Like [`AsyncIRCClient::with_transport`], using the given nickname fallbacks, SASL
+    /// credentials and channels.
+    pub async fn with_options<R, W>(
+        options: ConnectionOptions,
+        reader: R,
+        writer: W,
+        flood_control: FloodControl,
+    ) -> Result<(Self, EventStream), Error>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (line_sender, line_receiver) = mpsc::channel(OUTGOING_BUFFER);
+        let (event_sender, event_receiver) = mpsc::channel(EVENT_BUFFER);
+        let queue = Arc::new(Mutex::new(SendQueue::new(flood_control)));
+        let queue_changed = Arc::new(Notify::new());
+        tokio::spawn(Self::write_loop(
+            BufWriter::new(writer),
+            line_receiver,
+            Arc::clone(&queue),
+            Arc::clone(&queue_changed),
+        ));
+
+        let client = Self {
+            protocol: Arc::new(Mutex::new(Protocol::with_options(options))),
+            lines: line_sender,
+            queue,
+            queue_changed,
+            plugins: Arc::default(),
+        };
+        client
+            .run(|protocol| {
+                protocol.register();
+                Ok(())
+            })
+            .await?;
+
+        tokio::spawn(Self::read_loop(
+            BufReader::new(reader),
+            client.clone(),
+            event_sender,
+        ));
+
+        Ok((
+            client,
+            EventStream {
+                events: event_receiver,
+            },
+        ))
+    }
+
+    /// Changes the burst and rate used to pace outgoing lines. Takes effect immediately, also
+    /// for lines that are already queued.
+    pub fn set_flood_control(&self, flood_control: FloodControl) {
+        if let Ok(mut queue) = self.queue.lock() {
+            queue.set_config(flood_control);
+        }
+        self.queue_changed.notify_one();
+    }
+
+    /// Number of lines that are waiting in the send queue.
+    pub fn queued_lines(&self) -> usize {
+        self.queue.lock().map_or(0, |queue| queue.len())
+    }
+
+    /// Handles a line typed by the user, see [`Protocol::send_message`]. Waits while the queue
+    /// of outgoing lines is full. The returned response can be awaited for the server's reply.
+    pub async fn send_message(
+        &self,
+        message: impl AsRef<str>,
+    ) -> Result<Response<Vec<Event>>, Error> {
+        self.run(|protocol| protocol.send_message(message)).await
+    }
+
+    /// Replies to the message `msgid`, see [`Protocol::send_reply`].
+    pub async fn send_reply(
+        &self,
+        target: impl AsRef<str>,
+        msgid: impl AsRef<str>,
+        text: impl AsRef<str>,
+    ) -> Result<Response<Vec<Event>>, Error> {
+        self.run(|protocol| protocol.send_reply(target, msgid, text))
+            .await
+    }
+
+    /// Reacts to the message `msgid`, see [`Protocol::send_reaction`].
+    pub async fn send_reaction(
+        &self,
+        target: impl AsRef<str>,
+        msgid: impl AsRef<str>,
+        reaction: impl AsRef<str>,
+    ) -> Result<Response<Vec<Event>>, Error> {
+        self.run(|protocol| protocol.send_reaction(target, msgid, reaction))
+            .await
+    }
+
+    /// Tells `target` whether we are typing, see [`Protocol::send_typing`].
+    pub async fn send_typing(
+        &self,
+        target: impl AsRef<str>,
+        state: TypingState,
+    ) -> Result<(), Error> {
+        self.run(|protocol| protocol.send_typing(target, state))
+            .await
+    }
+
+    /// Sends a DCC request, see [`Protocol::send_dcc`].
+    pub async fn send_dcc(
+        &self,
+        target: impl AsRef<str>,
+        request: &DccRequest,
+    ) -> Result<(), Error> {
+        self.run(|protocol| {
+            protocol.send_dcc(target, request);
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn quit(&self) -> Result<(), Error> {
+        self.run(|protocol| {
+            protocol.quit();
+            Ok(())
+        })
+        .await
+    }
+
+    /// Asks the server about a user and waits for the replies, see [`Protocol::whois`].
+    pub async fn whois(
+        &self,
+        nickname: impl AsRef<str>,
+        timeout: Duration,
+    ) -> Result<WhoisInfo, Error> {
+        let response = self.run(|protocol| Ok(protocol.whois(nickname))).await?;
+        time::timeout(timeout, response)
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+
+    /// Asks the server about the users matching a mask and waits for the replies, see
+    /// [`Protocol::who`].
+    pub async fn who(&self, mask: impl AsRef<str>, timeout: Duration) -> Result<Vec<User>, Error> {
+        let response = self.run(|protocol| Ok(protocol.who(mask))).await?;
+        time::timeout(timeout, response)
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+
+    /// Requests message history and waits for it, see [`Protocol::chathistory`].
+    pub async fn chathistory(
+        &self,
+        request: HistoryRequest,
+        limit: usize,
+        timeout: Duration,
+    ) -> Result<Vec<Event>, Error> {
+        let response = self
+            .run(|protocol| Ok(protocol.chathistory(request, limit)))
+            .await?;
+        time::timeout(timeout, response)
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+
+    /// Lists the conversations with history and waits for the list, see
+    /// [`Protocol::chathistory_targets`].
+    pub async fn chathistory_targets(
+        &self,
+        start: SystemTime,
+        end: SystemTime,
+        limit: usize,
+        timeout: Duration,
+    ) -> Result<Vec<HistoryTarget>, Error> {
+        let response = self
+            .run(|protocol| Ok(protocol.chathistory_targets(start, end, limit)))
+            .await?;
+        time::timeout(timeout, response)
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+
+    /// Lists the networks of a soju bouncer and waits for the list, see
+    /// [`Protocol::list_bouncer_networks`].
+    pub async fn list_bouncer_networks(
+        &self,
+        timeout: Duration,
+    ) -> Result<Vec<BouncerNetwork>, Error> {
+        let response = self
+            .run(|protocol| Ok(protocol.list_bouncer_networks()))
+            .await?;
+        time::timeout(timeout, response)
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+
+    /// Adds a network to a soju bouncer and waits for its id, see
+    /// [`Protocol::add_bouncer_network`].
+    pub async fn add_bouncer_network(
+        &self,
+        attributes: &[(&str, &str)],
+        timeout: Duration,
+    ) -> Result<String, Error> {
+        let response = self
+            .run(|protocol| Ok(protocol.add_bouncer_network(attributes)))
+            .await?;
+        time::timeout(timeout, response)
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+
+    pub async fn change_bouncer_network(
+        &self,
+        id: impl AsRef<str>,
+        attributes: &[(&str, &str)],
+    ) -> Result<(), Error> {
+        self.run(|protocol| protocol.change_bouncer_network(id, attributes))
+            .await
+    }
+
+    pub async fn delete_bouncer_network(&self, id: impl AsRef<str>) -> Result<(), Error> {
+        self.run(|protocol| protocol.delete_bouncer_network(id))
+            .await
+    }
+
+    /// Asks ZNC's `*playback` for what `target` got after `since`, see [`Protocol::play_back`].
+    pub async fn play_back(&self, target: impl AsRef<str>, since: SystemTime) -> Result<(), Error> {
+        self.run(|protocol| protocol.play_back(target, since)).await
+    }
+
+    /// Adds nicknames to the notify list, see [`Protocol::notify_add`].
+    pub async fn notify_add<I, S>(&self, nicknames: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.run(|protocol| protocol.notify_add(nicknames)).await
+    }
+
+    pub async fn notify_remove<I, S>(&self, nicknames: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.run(|protocol| {
+            protocol.notify_remove(nicknames);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Asks the server which nicknames of the notify list are online, see
+    /// [`Protocol::notify_status`].
+    pub async fn notify_status(&self) -> Result<(), Error> {
+        self.run(|protocol| {
+            protocol.notify_status();
+            Ok(())
+        })
+        .await
+    }
+
+    /// Lists the nicknames the server monitors for us, see [`Protocol::monitor_list`].
+    pub async fn monitor_list(&self, timeout: Duration) -> Result<Vec<String>, Error> {
+        let response = self.run(|protocol| Ok(protocol.monitor_list())).await?;
+        time::timeout(timeout, response)
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+
+    /// A snapshot of the notify list and who on it is online.
+    pub fn notify_list(&self) -> NotifyList {
+        self.protocol
+            .lock()
+            .map(|protocol| protocol.notify_list().clone())
+            .unwrap_or_default()
+    }
+
+    /// A snapshot of the capabilities the server offers and has enabled.
+    pub fn capabilities(&self) -> Capabilities {
+        self.protocol
+            .lock()
+            .map(|protocol| protocol.capabilities().clone())
+            .unwrap_or_default()
+    }
+
+    /// A snapshot of the channels we are in and the users in them.
+    pub fn channels(&self) -> Channels {
+        self.protocol
+            .lock()
+            .map(|protocol| protocol.channels().clone())
+            .unwrap_or_default()
+    }
+
+    /// A snapshot of the networks of the soju bouncer.
+    pub fn bouncer_networks(&self) -> Vec<BouncerNetwork> {
+        self.protocol
+            .lock()
+            .map(|protocol| protocol.bouncer_networks().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// The server-time of the latest message, see [`Protocol::last_seen`].
+    pub fn last_seen(&self) -> Option<SystemTime> {
+        self.protocol
+            .lock()
+            .ok()
+            .and_then(|protocol| protocol.last_seen())
+    }
+
+    /// Adds a plugin, which sees the events and the outgoing lines from now on, after the plugins
+    /// that were added before it. See [`Plugin`] for the order.
+    pub fn add_plugin(&self, plugin: Box<dyn Plugin>) {
+        if let Ok(mut plugins) = self.plugins.lock() {
+            plugins.add(plugin);
+        }
+    }
+
+    /// Runs a command on the protocol state and hands the lines it produced to the writer task,
+    /// through the plugins.
+    async fn run<F, T>(&self, command: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Protocol) -> Result<T, Error>,
+    {
+        let (value, lines): (T, Vec<_>) = {
+            let mut protocol = self
+                .protocol
+                .lock()
+                .map_err(|_| io::Error::other("Protocol lock poisoned"))?;
+            let mut plugins = self
+                .plugins
+                .lock()
+                .map_err(|_| io::Error::other("Plugin lock poisoned"))?;
+            let value = command(&mut protocol)?;
+            (
+                value,
+                std::iter::from_fn(|| protocol.poll_outgoing())
+                    .filter_map(|(line, priority)| {
+                        plugins.on_outgoing(line).map(|line| (line, priority))
+                    })
+                    .collect(),
+            )
+        };
+
+        for line in lines {
+            self.lines
+                .send(line)
+                .await
+                .map_err(|_| Error::NotConnected)?;
+        }
+
+        Ok(value)
+    }
+
+    async fn read_loop<R>(reader: BufReader<R>, client: AsyncIRCClient, events: mpsc::Sender<Event>)
+    where
+        R: AsyncRead + Unpin,
+    {
+        info!("Started listening for IRC messages.");
+
+        // The notify list is polled with ISON here, as the reader task lives as long as the
+        // connection.
+        let mut poll = time::interval_at(time::Instant::now() + ISON_INTERVAL, ISON_INTERVAL);
+        let mut lines = reader.lines();
+        loop {
+            let read = tokio::select! {
+                read = lines.next_line() => read,
+                _ = poll.tick() => {
+                    let polled = client.run(|protocol| {
+                        protocol.poll_notify_list();
+                        Ok(())
+                    });
+                    if polled.await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            };
+
+            match read {
+                Ok(None) => {
+                    info!("Connection closed by server.");
+                    break;
+                }
+                Ok(Some(line)) => {
+                    let mut received = Vec::new();
+                    let mut context = None;
+                    let handled = client
+                        .run(|protocol| {
+                            received = protocol.handle_line(&line);
+                            context = Some(PluginContext::new(protocol.nickname()));
+                            Ok(())
+                        })
+                        .await;
+                    let (Ok(()), Some(mut context)) = (handled, context) else {
+                        break;
+                    };
+
+                    let received: Vec<Event> = match client.plugins.lock() {
+                        Ok(mut plugins) => received
+                            .into_iter()
+                            .filter_map(|event| plugins.on_event(event, &mut context))
+                            .collect(),
+                        Err(_) => break,
+                    };
+                    if context.has_replies()
+                        && client
+                            .run(|protocol| {
+                                context.send_replies(protocol);
+                                Ok(())
+                            })
+                            .await
+                            .is_err()
+                    {
+                        break;
+                    }
+                    let failed = received.iter().find_map(|event| match &event.kind {
+                        IRCEvent::RegistrationFailed(error) => Some(error.clone()),
+                        _ => None,
+                    });
+                    for event in received {
+                        if events.send(event).await.is_err() {
+                            return;
+                        }
+                    }
+                    if let Some(error) = failed {
+                        error!("Stopped listening: {}", Error::from(error));
+                        break;
+                    }
+                }
+                Err(error) => {
+                    error!("Failed to read line: {}", error);
+                    break;
+                }
+            }
+        }
+    }
+
+    async fn write_loop<W>(
+        mut writer: W,
+        mut lines: mpsc::Receiver<(String, Priority)>,
+        queue: Arc<Mutex<SendQueue>>,
+        queue_changed: Arc<Notify>,
+    ) where
+        W: AsyncWrite + Unpin,
+    {
+        let mut closed = false;
+        loop {
+            let Ok((line, next_send_in, full)) = queue.lock().map(|mut queue| {
+                let line = queue.pop();
+                let full = queue.len() >= queue.config().capacity.max(1);
+                (line, queue.next_send_in(), full)
+            }) else {
+                return;
+            };
+
+            if let Some(line) = line {
+                debug!("Sending line: {}", line);
+                if let Err(error) = Self::write_line(&mut writer, &line).await {
+                    error!("Failed to send line: {}", error);
+                    return;
+                }
+                continue;
+            }
+            if closed && next_send_in.is_none() {
+                return;
+            }
+
+            // A full queue leaves the lines in the channel, which makes the senders wait.
+            tokio::select! {
+                received = lines.recv(), if !closed && !full => match received {
+                    Some((line, priority)) => {
+                        let Ok(mut queue) = queue.lock() else {
+                            return;
+                        };
+                        if let Pushed::Rejected = queue.push(line, priority) {
+                            error!("Send queue is full, a line was dropped.");
+                        }
+                    }
+                    None => closed = true,
+                },
+                _ = time::sleep(next_send_in.unwrap_or_default()), if next_send_in.is_some() => {}
+                _ = queue_changed.notified() => {}
+            }
+        }
+    }
+
+    async fn write_line<W>(writer: &mut W, line: &str) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        writer.write_all(line.as_bytes()).await?;
+        writer.write_all(b"\r\n").await?;
+        writer.flush().await
+    }
+}
+
+impl EventStream {
+    pub async fn next(&mut self) -> Option<Event> {
+        self.events.recv().await
+    }
+}
+
+impl Stream for EventStream {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        self.events.poll_recv(cx)
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use crate::{BOUNCER_NETWORKS_BATCH, Event, IRCEvent, MULTILINE_BATCH, Message};
+
+/// Messages the server sent together in a `BATCH +reference type` … `BATCH -reference` block,
+/// such as bouncer playback or chathistory replies.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Batch {
+    /// The type of the batch, e.g. `chathistory`.
+    pub kind: String,
+    pub params: Vec<String>,
+    /// The events of the batched messages, in order. Nested batches are in here as
+    /// [`IRCEvent::Batch`] events.
+    pub events: Vec<Event>,
+}
+
+impl Batch {
+    /// Turns the batch into its event, with netsplits, netjoins and bouncer network lists getting
+    /// their own and multiline messages joined into one.
+    fn into_event(self) -> IRCEvent {
+        match (self.kind.as_str(), &self.params[..]) {
+            (MULTILINE_BATCH, _) => self.join_lines().unwrap_or(IRCEvent::Batch(self)),
+            (BOUNCER_NETWORKS_BATCH, _) => IRCEvent::BouncerNetworks(
+                self.events
+                    .into_iter()
+                    .filter_map(|event| match event.kind {
+                        IRCEvent::BouncerNetwork(network) => Some(network),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            ("netsplit", [server, remote, ..]) => IRCEvent::Netsplit {
+                server: server.clone(),
+                remote: remote.clone(),
+                users: self
+                    .events
+                    .into_iter()
+                    .filter_map(|event| match event.kind {
+                        IRCEvent::Quit { source, .. } => Some(source),
+                        _ => None,
+                    })
+                    .collect(),
+            },
+            ("netjoin", [server, remote, ..]) => IRCEvent::Netjoin {
+                server: server.clone(),
+                remote: remote.clone(),
+                joins: self
+                    .events
+                    .into_iter()
+                    .filter_map(|event| match event.kind {
+                        IRCEvent::Join {
+                            source, channel, ..
+                        } => Some((source, channel)),
+                        _ => None,
+                    })
+                    .collect(),
+            },
+            _ => IRCEvent::Batch(self),
+        }
+    }
+
+    /// Joins the messages of a multiline batch into the first one, one line each.
+    fn join_lines(&self) -> Option<IRCEvent> {
+        let (first, rest) = self.events.split_first()?;
+        let mut joined = first.kind.clone();
+        let (IRCEvent::PrivMsg { text, .. } | IRCEvent::Notice { text, .. }) = &mut joined else {
+            return None;
+        };
+
+        for event in rest {
+            let (IRCEvent::PrivMsg { text: line, .. } | IRCEvent::Notice { text: line, .. }) =
+                &event.kind
+            else {
+                return None;
+            };
+            text.push('\n');
+            text.push_str(line);
+        }
+        Some(joined)
+    }
+}
+
+struct OpenBatch {
+    /// The batch this one is nested in.
+    parent: Option<String>,
+    /// The time, msgid and label of the BATCH that started it, which its event is stamped with.
+    time: SystemTime,
+    msgid: Option<String>,
+    label: Option<String>,
+    batch: Batch,
+}
+
+/// The batches that have been started and not yet ended.
+#[derive(Default)]
+pub(crate) struct Batches {
+    open: HashMap<String, OpenBatch>,
+}
+
+impl Batches {
+    /// Handles a BATCH command received at `time`. Returns the event of a batch that has ended,
+    /// unless it is nested in a batch that is still open.
+    pub(crate) fn handle(&mut self, message: &Message, time: SystemTime) -> Option<Event> {
+        let reference = message.params.first()?;
+
+        if let Some(reference) = reference.strip_prefix('+') {
+            let kind = message.params.get(1)?;
+            self.open.insert(
+                reference.to_string(),
+                OpenBatch {
+                    parent: message.tag("batch").map(str::to_string),
+                    time,
+                    msgid: message.tag("msgid").map(str::to_string),
+                    label: message.tag("label").map(str::to_string),
+                    batch: Batch {
+                        kind: kind.clone(),
+                        params: message.params[2..].to_vec(),
+                        events: Vec::new(),
+                    },
+                },
+            );
+            None
+        } else if let Some(reference) = reference.strip_prefix('-') {
+            let open = self.open.remove(reference)?;
+            let event = Event {
+                time: open.time,
+                msgid: open.msgid,
+                reply_to: None,
+                label: open.label,
+                account: None,
+                kind: open.batch.into_event(),
+            };
+            self.add(open.parent.as_deref(), event)
+        } else {
+            None
+        }
+    }
+
+    /// The type of the open batch `reference`.
+    pub(crate) fn kind(&self, reference: Option<&str>) -> Option<&str> {
+        let open = self.open.get(reference?)?;
+        Some(open.batch.kind.as_str())
+    }
+
+    /// Appends text to the last message of the open batch `reference`, for a line with the
+    /// `draft/multiline-concat` tag. Returns whether there was such a message.
+    pub(crate) fn concat(&mut self, reference: Option<&str>, text: &str) -> bool {
+        let last = reference
+            .and_then(|reference| self.open.get_mut(reference))
+            .and_then(|open| open.batch.events.last_mut());
+
+        match last.map(|event| &mut event.kind) {
+            Some(IRCEvent::PrivMsg { text: line, .. } | IRCEvent::Notice { text: line, .. }) => {
+                line.push_str(text);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Adds an event to the open batch `reference`. Gives the event back when there is no such
+    /// batch, so it can be delivered on its own.
+    pub(crate) fn add(&mut self, reference: Option<&str>, event: Event) -> Option<Event> {
+        match reference.and_then(|reference| self.open.get_mut(reference)) {
+            Some(open) => {
+                open.batch.events.push(event);
+                None
+            }
+            None => Some(event),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Parser, Source};
+
+    fn message(line: &str) -> Message {
+        Parser::new(line).parse_message().unwrap()
+    }
+
+    fn event(kind: IRCEvent) -> Event {
+        Event {
+            time: SystemTime::UNIX_EPOCH,
+            ..Event::new(kind)
+        }
+    }
+
+    fn batch(batches: &mut Batches, line: &str) -> Option<Event> {
+        batches.handle(&message(line), SystemTime::UNIX_EPOCH)
+    }
+
+    #[test]
+    fn nested_batch_is_delivered_inside_its_parent() {
+        let mut batches = Batches::default();
+
+        assert!(batch(&mut batches, "BATCH +outer chathistory #rust").is_none());
+        assert!(batch(&mut batches, "@batch=outer BATCH +inner example").is_none());
+        assert!(
+            batches
+                .add(Some("inner"), event(IRCEvent::Raw("one".to_string())))
+                .is_none()
+        );
+        assert!(batch(&mut batches, "BATCH -inner").is_none());
+        let outer = batch(&mut batches, "BATCH -outer").unwrap();
+
+        let IRCEvent::Batch(outer) = outer.kind else {
+            panic!("Expected a batch");
+        };
+        assert_eq!(outer.kind, "chathistory");
+        assert_eq!(outer.params, vec!["#rust"]);
+        assert!(matches!(
+            &outer.events[..],
+            [Event { kind: IRCEvent::Batch(inner), .. }] if inner.events.len() == 1
+        ));
+    }
+
+    #[test]
+    fn netsplit_collects_the_users_who_quit() {
+        let mut batches = Batches::default();
+
+        batch(&mut batches, "BATCH +split netsplit irc.hub other.host");
+        for nick in ["alice", "bob"] {
+            batches.add(
+                Some("split"),
+                event(IRCEvent::Quit {
+                    source: Source::parse(nick),
+                    reason: Some("irc.hub other.host".to_string()),
+                }),
+            );
+        }
+        let split = batch(&mut batches, "BATCH -split").unwrap();
+
+        assert_eq!(
+            split.kind,
+            IRCEvent::Netsplit {
+                server: "irc.hub".to_string(),
+                remote: "other.host".to_string(),
+                users: vec![Source::parse("alice"), Source::parse("bob")],
+            }
+        );
+    }
+
+    #[test]
+    fn multiline_batch_is_joined_into_one_message() {
+        let mut batches = Batches::default();
+        let privmsg = |text: &str| {
+            event(IRCEvent::PrivMsg {
+                source: Source::parse("alice"),
+                target: "#rust".to_string(),
+                text: text.to_string(),
+            })
+        };
+
+        batch(&mut batches, "@msgid=m1 BATCH +ml draft/multiline #rust");
+        batches.add(Some("ml"), privmsg("fn main() {"));
+        batches.add(Some("ml"), privmsg("    run"));
+        assert!(batches.concat(Some("ml"), "();"));
+        batches.add(Some("ml"), privmsg("}"));
+        let joined = batch(&mut batches, "BATCH -ml").unwrap();
+
+        assert_eq!(joined.msgid.as_deref(), Some("m1"));
+        assert_eq!(joined.kind, privmsg("fn main() {\n    run();\n}").kind);
+    }
+
+    #[test]
+    fn events_outside_a_batch_are_given_back() {
+        let mut batches = Batches::default();
+
+        let given_back = batches.add(Some("unknown"), event(IRCEvent::Raw("x".to_string())));
+
+        assert!(given_back.is_some());
+    }
+}
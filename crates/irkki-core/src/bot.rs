@@ -0,0 +1,542 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::time::{Duration, Instant};
+
+use crate::{Event, IRCEvent, Plugin, PluginContext, Source};
+
+/// How long the bot keeps quiet after telling a user they may not run a command, so that
+/// repeating it cannot make the bot flood.
+const DENIAL_QUIET: Duration = Duration::from_secs(60);
+
+/// Who may run a command.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Permission {
+    /// Users whose `nick!user@host` matches the mask, such as `*!*@staff.example.com`. A `*`
+    /// matches any run of characters and a `?` any one character, ignoring case.
+    Hostmask(String),
+    /// Users logged in to the account, see [`Event::account`].
+    Account(String),
+}
+
+impl Permission {
+    pub fn allows(&self, source: &Source, account: Option<&str>) -> bool {
+        match self {
+            Permission::Hostmask(mask) => {
+                let hostmask = format!(
+                    "{}!{}@{}",
+                    source.nickname,
+                    source.user.as_deref().unwrap_or("*"),
+                    source.host.as_deref().unwrap_or("*")
+                );
+                mask_matches(mask, &hostmask)
+            }
+            Permission::Account(wanted) => {
+                account.is_some_and(|account| account.eq_ignore_ascii_case(wanted))
+            }
+        }
+    }
+}
+
+fn mask_matches(mask: &str, text: &str) -> bool {
+    let mask: Vec<char> = mask.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    // Where the text continues after the latest `*`, to backtrack to when the rest fails.
+    let (mut m, mut t) = (0, 0);
+    let mut star = None;
+    while t < text.len() {
+        match mask.get(m) {
+            Some('*') => {
+                star = Some((m, t));
+                m += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                m += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_m, star_t)) => {
+                    star = Some((star_m, star_t + 1));
+                    m = star_m + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    mask[m..].iter().all(|&c| c == '*')
+}
+
+/// Splits the arguments of a command on whitespace. Double quotes keep an argument with spaces
+/// together, and a backslash takes the next character as it is: `add "two words" \"one\"`.
+pub fn parse_arguments(text: &str) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut argument: Option<String> = None;
+    let mut quoted = false;
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    argument.get_or_insert_default().push(next);
+                }
+            }
+            '"' => {
+                quoted = !quoted;
+                argument.get_or_insert_default();
+            }
+            c if c.is_whitespace() && !quoted => arguments.extend(argument.take()),
+            c => argument.get_or_insert_default().push(c),
+        }
+    }
+    arguments.extend(argument);
+    arguments
+}
+
+/// A command being run, with what the handler needs to answer it.
+pub struct Invocation<'a> {
+    /// The name of the command, as it was registered.
+    pub command: String,
+    pub source: Source,
+    /// The account the sender is logged in to, when known.
+    pub account: Option<String>,
+    /// Where replies go: the channel the command was sent in, or the sender when it was sent to
+    /// us directly.
+    pub reply_target: String,
+    pub arguments: Vec<String>,
+    /// The text after the name of the command, as it was sent.
+    pub text: String,
+    context: &'a mut PluginContext,
+}
+
+impl Invocation<'_> {
+    pub fn argument(&self, index: usize) -> Option<&str> {
+        self.arguments.get(index).map(String::as_str)
+    }
+
+    /// Replies where the command was sent.
+    pub fn reply(&mut self, text: impl Into<String>) {
+        self.context.send_message(&self.reply_target, text);
+    }
+
+    /// Replies with a notice to the sender only.
+    pub fn notice(&mut self, text: impl Into<String>) {
+        self.context.send_notice(&self.source.nickname, text);
+    }
+
+    /// The context of the plugin, for sending elsewhere.
+    pub fn context(&mut self) -> &mut PluginContext {
+        self.context
+    }
+}
+
+type Handler = Box<dyn FnMut(&mut Invocation) + Send>;
+
+/// A command of a [`Bot`], run when someone sends its name after one of the bot's prefixes.
+pub struct Command {
+    pub name: String,
+    /// What the arguments are, shown by `help`: `<sides> [count]`.
+    pub usage: String,
+    /// What the command does, shown by `help`.
+    pub description: String,
+    /// Who may run the command. Anyone may when it is empty.
+    pub permissions: Vec<Permission>,
+    /// How long the command rests after it was run, in each channel and query on its own.
+    pub cooldown: Option<Duration>,
+    handler: Handler,
+}
+
+impl Command {
+    pub fn new<F>(name: impl Into<String>, handler: F) -> Self
+    where
+        F: FnMut(&mut Invocation) + Send + 'static,
+    {
+        Self {
+            name: name.into(),
+            usage: String::new(),
+            description: String::new(),
+            permissions: Vec::new(),
+            cooldown: None,
+            handler: Box::new(handler),
+        }
+    }
+
+    fn allows(&self, source: &Source, account: Option<&str>) -> bool {
+        self.permissions.is_empty()
+            || self
+                .permissions
+                .iter()
+                .any(|permission| permission.allows(source, account))
+    }
+}
+
+/// Routes PRIVMSGs to the handlers of commands, as a [`Plugin`] of a client.
+///
+/// A command is its name after one of the prefixes, or after our nickname and a `:` or `,`:
+/// `!roll 6` or `irkki: roll 6`. In a query the prefix may be left out. Commands that are not
+/// allowed or are cooling down are answered with a notice to the sender, once until the command
+/// can be run again, and `help` lists the commands the sender may run unless a command of that
+/// name is added. The messages are passed on to the next plugins like any other.
+pub struct Bot {
+    /// What commands start with, `!` by default.
+    pub prefixes: Vec<String>,
+    commands: Vec<Command>,
+    /// Until when each command rests, by the command and where it was run.
+    resting_until: HashMap<(String, String), Instant>,
+    /// Until when a user is not told again why a command cannot run, by the command and the
+    /// nickname of the user.
+    quiet_until: HashMap<(String, String), Instant>,
+}
+
+impl Default for Bot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bot {
+    pub fn new() -> Self {
+        Self {
+            prefixes: vec!["!".to_string()],
+            commands: Vec::new(),
+            resting_until: HashMap::new(),
+            quiet_until: HashMap::new(),
+        }
+    }
+
+    /// Adds a command. One with the same name is replaced.
+    pub fn add_command(&mut self, command: Command) {
+        self.commands
+            .retain(|existing| !existing.name.eq_ignore_ascii_case(&command.name));
+        self.commands.push(command);
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = &Command> {
+        self.commands.iter()
+    }
+
+    /// The name of the command and the text after it, when `text` is a command.
+    fn parse<'t>(&self, text: &'t str, nickname: &str, query: bool) -> Option<(&'t str, &'t str)> {
+        let addressed = text
+            .get(..nickname.len())
+            .filter(|start| start.eq_ignore_ascii_case(nickname))
+            .and_then(|_| text[nickname.len()..].strip_prefix([':', ',']));
+        let command = addressed
+            .or_else(|| {
+                self.prefixes
+                    .iter()
+                    .find_map(|prefix| text.strip_prefix(prefix.as_str()))
+            })
+            .or(query.then_some(text))?
+            .trim_start();
+
+        let (name, rest) = command
+            .split_once(char::is_whitespace)
+            .unwrap_or((command, ""));
+        (!name.is_empty()).then_some((name, rest.trim()))
+    }
+
+    /// Runs a command, or tells the sender why it cannot run.
+    fn run(&mut self, name: &str, mut invocation: Invocation) {
+        let now = Instant::now();
+        self.resting_until.retain(|_, until| *until > now);
+        self.quiet_until.retain(|_, until| *until > now);
+
+        let Some(command) = self
+            .commands
+            .iter_mut()
+            .find(|command| command.name.eq_ignore_ascii_case(name))
+        else {
+            if name.eq_ignore_ascii_case("help") {
+                let help = self.help(&invocation);
+                invocation.reply(help);
+            }
+            return;
+        };
+        invocation.command = command.name.clone();
+        let user = (
+            command.name.to_lowercase(),
+            invocation.source.nickname.to_lowercase(),
+        );
+
+        if !command.allows(&invocation.source, invocation.account.as_deref()) {
+            if let Entry::Vacant(quiet) = self.quiet_until.entry(user) {
+                quiet.insert(now + DENIAL_QUIET);
+                invocation.notice(format!("You may not use {}.", command.name));
+            }
+            return;
+        }
+
+        let key = (
+            command.name.to_lowercase(),
+            invocation.reply_target.to_lowercase(),
+        );
+        if let Some(cooldown) = command.cooldown {
+            if let Some(&until) = self.resting_until.get(&key) {
+                if let Entry::Vacant(quiet) = self.quiet_until.entry(user) {
+                    quiet.insert(until);
+                    let wait = (until - now).as_secs_f64().ceil();
+                    invocation.notice(format!("{} can be used again in {wait}s.", command.name));
+                }
+                return;
+            }
+            self.resting_until.insert(key, now + cooldown);
+        }
+
+        (command.handler)(&mut invocation);
+    }
+
+    /// The commands the sender may run, or how to use the one asked about.
+    fn help(&self, invocation: &Invocation) -> String {
+        let prefix = self.prefixes.first().map_or("", String::as_str);
+        let allowed: Vec<&Command> = self
+            .commands
+            .iter()
+            .filter(|command| command.allows(&invocation.source, invocation.account.as_deref()))
+            .collect();
+
+        let Some(name) = invocation.argument(0) else {
+            let names: Vec<String> = allowed
+                .iter()
+                .map(|command| format!("{prefix}{}", command.name))
+                .collect();
+            if names.is_empty() {
+                return "No commands.".to_string();
+            }
+            return format!(
+                "Commands: {}. Use {prefix}help <command> for more.",
+                names.join(", ")
+            );
+        };
+
+        let name = name.strip_prefix(prefix).unwrap_or(name);
+        match allowed
+            .iter()
+            .find(|command| command.name.eq_ignore_ascii_case(name))
+        {
+            Some(command) => {
+                let mut help = format!("{prefix}{}", command.name);
+                if !command.usage.is_empty() {
+                    help = format!("{help} {}", command.usage);
+                }
+                if !command.description.is_empty() {
+                    help = format!("{help}: {}", command.description);
+                }
+                help
+            }
+            None => format!("No command {prefix}{name}."),
+        }
+    }
+}
+
+impl Plugin for Bot {
+    fn on_event(&mut self, event: Event, context: &mut PluginContext) -> Option<Event> {
+        let IRCEvent::PrivMsg {
+            source,
+            target,
+            text,
+        } = &event.kind
+        else {
+            return Some(event);
+        };
+
+        // Our own messages come back with echo-message.
+        let query = target.eq_ignore_ascii_case(context.nickname());
+        if source.nickname.eq_ignore_ascii_case(context.nickname()) {
+            return Some(event);
+        }
+        let Some((name, rest)) = self.parse(text, context.nickname(), query) else {
+            return Some(event);
+        };
+
+        let invocation = Invocation {
+            command: name.to_string(),
+            source: source.clone(),
+            account: event.account.clone(),
+            reply_target: if query {
+                source.nickname.clone()
+            } else {
+                target.clone()
+            },
+            arguments: parse_arguments(rest),
+            text: rest.to_string(),
+            context,
+        };
+        self.run(name, invocation);
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Parser, Protocol};
+    use std::sync::{Arc, Mutex};
+
+    fn event(line: &str, account: Option<&str>) -> Event {
+        Event {
+            account: account.map(str::to_string),
+            ..Event::new(IRCEvent::from_message(
+                Parser::new(line).parse_message().unwrap(),
+            ))
+        }
+    }
+
+    /// The lines the bot sends in reply to `line`.
+    fn replies(bot: &mut Bot, line: &str, account: Option<&str>) -> Vec<String> {
+        let mut context = PluginContext::new("bot");
+        assert!(bot.on_event(event(line, account), &mut context).is_some());
+
+        let mut protocol = Protocol::new("bot");
+        while protocol.poll_outgoing().is_some() {}
+        context.send_replies(&mut protocol);
+        std::iter::from_fn(|| protocol.poll_outgoing())
+            .map(|(line, _)| line)
+            .collect()
+    }
+
+    fn echo() -> Command {
+        let mut command = Command::new("echo", |invocation| {
+            let text = invocation.arguments.join("|");
+            invocation.reply(text);
+        });
+        command.usage = "<text>".to_string();
+        command.description = "Says the arguments back.".to_string();
+        command
+    }
+
+    #[test]
+    fn arguments_are_split_on_whitespace_and_quotes() {
+        assert_eq!(
+            parse_arguments(r#"add  "two words" \"one\" ''"#),
+            vec!["add", "two words", "\"one\"", "''"]
+        );
+        assert_eq!(parse_arguments(r#"empty "" "#), vec!["empty", ""]);
+        assert!(parse_arguments("   ").is_empty());
+    }
+
+    #[test]
+    fn commands_are_answered_where_they_were_sent() {
+        let mut bot = Bot::new();
+        bot.prefixes.push("?".to_string());
+        bot.add_command(echo());
+
+        assert_eq!(
+            replies(
+                &mut bot,
+                ":alice!a@host PRIVMSG #rust :!echo a \"b c\"",
+                None
+            ),
+            vec!["PRIVMSG #rust :a|b c"]
+        );
+        assert_eq!(
+            replies(&mut bot, ":alice!a@host PRIVMSG #rust :Bot: ECHO hi", None),
+            vec!["PRIVMSG #rust :hi"]
+        );
+        assert_eq!(
+            replies(&mut bot, ":alice!a@host PRIVMSG bot :echo private", None),
+            vec!["PRIVMSG alice :private"]
+        );
+        assert_eq!(
+            replies(&mut bot, ":alice!a@host PRIVMSG bot :?echo x", None),
+            vec!["PRIVMSG alice :x"]
+        );
+        assert!(replies(&mut bot, ":alice!a@host PRIVMSG #rust :echo hi", None).is_empty());
+        assert!(replies(&mut bot, ":alice!a@host PRIVMSG #rust :!unknown", None).is_empty());
+    }
+
+    #[test]
+    fn permissions_check_the_hostmask_or_the_account() {
+        let runs = Arc::new(Mutex::new(0));
+        let counter = Arc::clone(&runs);
+        let mut command = Command::new("op", move |_| *counter.lock().unwrap() += 1);
+        command.permissions = vec![
+            Permission::Hostmask("*!*@*.staff.example.com".to_string()),
+            Permission::Account("admin".to_string()),
+        ];
+        let mut bot = Bot::new();
+        bot.add_command(command);
+
+        replies(
+            &mut bot,
+            ":alice!a@vpn.staff.example.com PRIVMSG #ops :!op",
+            None,
+        );
+        replies(&mut bot, ":bob!b@home.net PRIVMSG #ops :!op", Some("Admin"));
+        let denied = replies(&mut bot, ":eve!e@staff.example.com PRIVMSG #ops :!op", None);
+        let repeated = replies(&mut bot, ":eve!e@staff.example.com PRIVMSG #ops :!op", None);
+
+        assert_eq!(*runs.lock().unwrap(), 2);
+        assert_eq!(denied, vec!["NOTICE eve :You may not use op."]);
+        assert!(repeated.is_empty());
+        assert!(mask_matches("a?c*", "ABCdef"));
+        assert!(!mask_matches("a?c", "abcd"));
+    }
+
+    #[test]
+    fn cooldowns_are_kept_per_channel() {
+        let mut command = echo();
+        command.cooldown = Some(Duration::from_secs(30));
+        let mut bot = Bot::new();
+        bot.add_command(command);
+
+        let first = replies(&mut bot, ":alice!a@host PRIVMSG #rust :!echo 1", None);
+        let second = replies(&mut bot, ":bob!b@host PRIVMSG #rust :!echo 2", None);
+        let repeated = replies(&mut bot, ":bob!b@host PRIVMSG #rust :!echo 2", None);
+        let elsewhere = replies(&mut bot, ":carol!c@host PRIVMSG #irc :!echo 3", None);
+
+        assert_eq!(first, vec!["PRIVMSG #rust :1"]);
+        assert_eq!(second, vec!["NOTICE bob :echo can be used again in 30s."]);
+        assert!(repeated.is_empty());
+        assert_eq!(elsewhere, vec!["PRIVMSG #irc :3"]);
+    }
+
+    #[test]
+    fn rested_commands_are_forgotten() {
+        let mut command = echo();
+        command.cooldown = Some(Duration::from_millis(1));
+        let mut bot = Bot::new();
+        bot.add_command(command);
+
+        replies(&mut bot, ":alice!a@host PRIVMSG #rust :!echo 1", None);
+        replies(&mut bot, ":alice!a@host PRIVMSG #irc :!echo 2", None);
+        assert_eq!(bot.resting_until.len(), 2);
+        std::thread::sleep(Duration::from_millis(10));
+
+        let again = replies(&mut bot, ":alice!a@host PRIVMSG #rust :!echo 3", None);
+
+        assert_eq!(again, vec!["PRIVMSG #rust :3"]);
+        assert_eq!(bot.resting_until.len(), 1);
+    }
+
+    #[test]
+    fn help_lists_the_commands_the_sender_may_run() {
+        let mut bot = Bot::new();
+        bot.add_command(echo());
+        let mut secret = Command::new("secret", |_| {});
+        secret.permissions = vec![Permission::Account("admin".to_string())];
+        bot.add_command(secret);
+
+        assert_eq!(
+            replies(&mut bot, ":alice!a@host PRIVMSG #rust :!help", None),
+            vec!["PRIVMSG #rust :Commands: !echo. Use !help <command> for more."]
+        );
+        assert_eq!(
+            replies(
+                &mut bot,
+                ":alice!a@host PRIVMSG #rust :!help",
+                Some("admin")
+            ),
+            vec!["PRIVMSG #rust :Commands: !echo, !secret. Use !help <command> for more."]
+        );
+        assert_eq!(
+            replies(&mut bot, ":alice!a@host PRIVMSG #rust :!help !echo", None),
+            vec!["PRIVMSG #rust :!echo <text>: Says the arguments back."]
+        );
+        assert_eq!(
+            replies(&mut bot, ":alice!a@host PRIVMSG #rust :!help secret", None),
+            vec!["PRIVMSG #rust :No command !secret."]
+        );
+    }
+}
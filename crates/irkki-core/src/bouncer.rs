@@ -0,0 +1,227 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    Error, IRCEvent, Message, Responder, Response, ServerError, StandardReply, escape_tag_value,
+    parse_tags, response,
+};
+
+/// The capability of soju bouncers for listing, adding and binding to their upstream networks.
+pub(crate) const BOUNCER_NETWORKS_CAP: &str = "soju.im/bouncer-networks";
+pub(crate) const BOUNCER_NETWORKS_BATCH: &str = "soju.im/bouncer-networks";
+/// The capability of ZNC's `*playback` module, with which the buffers are only played back on
+/// request.
+pub(crate) const PLAYBACK_CAP: &str = "znc.in/playback";
+
+/// An upstream network of a soju bouncer, with attributes such as `name`, `host` and `state`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BouncerNetwork {
+    /// The id the bouncer knows the network by, which `BOUNCER BIND` takes.
+    pub id: String,
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl BouncerNetwork {
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes.get(key).map(String::as_str)
+    }
+
+    /// The name of the network, or its host when it has none.
+    pub fn name(&self) -> &str {
+        self.attribute("name")
+            .or_else(|| self.attribute("host"))
+            .unwrap_or(&self.id)
+    }
+
+    /// Whether the bouncer is `connected`, `connecting` or `disconnected` from the network.
+    pub fn state(&self) -> Option<&str> {
+        self.attribute("state")
+    }
+}
+
+/// Formats attributes as `BOUNCER ADDNETWORK` and `CHANGENETWORK` take them, escaped like
+/// message tags: `name=Libera;host=irc.libera.chat`.
+pub(crate) fn format_attributes(attributes: &[(&str, &str)]) -> String {
+    attributes
+        .iter()
+        .map(|(key, value)| format!("{key}={}", escape_tag_value(value)))
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// The `PLAY` command for ZNC's `*playback` module, which plays back what `target` got after
+/// `since`. The target `*` plays back every buffer.
+pub(crate) fn playback_command(target: &str, since: Option<SystemTime>) -> String {
+    let since = since
+        .and_then(|since| since.duration_since(UNIX_EPOCH).ok())
+        .map_or("0".to_string(), |since| {
+            format!("{}.{:03}", since.as_secs(), since.subsec_millis())
+        });
+    format!("PRIVMSG *playback :PLAY {target} {since}")
+}
+
+/// Keeps the networks of a soju bouncer up to date from `BOUNCER NETWORK`, and completes the
+/// requests waiting for the bouncer's replies.
+#[derive(Default)]
+pub(crate) struct Bouncer {
+    networks: BTreeMap<String, BouncerNetwork>,
+    /// The `BOUNCER LISTNETWORKS` requests waiting for their batch.
+    listing: VecDeque<Responder<Vec<BouncerNetwork>>>,
+    /// The `BOUNCER ADDNETWORK` requests waiting for the id of the new network.
+    adding: VecDeque<Responder<String>>,
+}
+
+impl Bouncer {
+    pub(crate) fn networks(&self) -> impl Iterator<Item = &BouncerNetwork> {
+        self.networks.values()
+    }
+
+    pub(crate) fn expect_listing(&mut self) -> Response<Vec<BouncerNetwork>> {
+        let (responder, response) = response();
+        self.listing.push_back(responder);
+        response
+    }
+
+    pub(crate) fn expect_added(&mut self) -> Response<String> {
+        let (responder, response) = response();
+        self.adding.push_back(responder);
+        response
+    }
+
+    /// Handles a `BOUNCER` message. A network update only carries the attributes that changed,
+    /// where an empty value removes one, so the event has the network as it is known now.
+    pub(crate) fn handle(&mut self, message: &Message) -> Option<IRCEvent> {
+        let param = |i: usize| message.params.get(i).map(String::as_str);
+
+        match (param(0)?, param(1)?) {
+            ("NETWORK", id) if param(2) == Some("*") => {
+                self.networks.remove(id);
+                Some(IRCEvent::BouncerNetworkRemoved { id: id.to_string() })
+            }
+            ("NETWORK", id) => {
+                let network =
+                    self.networks
+                        .entry(id.to_string())
+                        .or_insert_with(|| BouncerNetwork {
+                            id: id.to_string(),
+                            attributes: BTreeMap::new(),
+                        });
+                for (key, value) in parse_tags(param(2)?) {
+                    if value.is_empty() {
+                        network.attributes.remove(&key);
+                    } else {
+                        network.attributes.insert(key, value);
+                    }
+                }
+                Some(IRCEvent::BouncerNetwork(network.clone()))
+            }
+            ("ADDNETWORK", id) => {
+                if let Some(responder) = self.adding.pop_front() {
+                    responder.complete(Ok(id.to_string()));
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Completes the oldest listing with the networks of a `soju.im/bouncer-networks` batch.
+    pub(crate) fn complete(&mut self, event: &IRCEvent) {
+        if let IRCEvent::BouncerNetworks(networks) = event
+            && let Some(responder) = self.listing.pop_front()
+        {
+            responder.complete(Ok(networks.clone()));
+        }
+    }
+
+    /// Fails the request a `FAIL BOUNCER <code> <subcommand>` is about.
+    pub(crate) fn fail(&mut self, reply: &StandardReply) {
+        let error = || Error::Protocol(ServerError::from(reply.clone()));
+        match reply.context.first().map(String::as_str) {
+            Some("LISTNETWORKS") => {
+                if let Some(responder) = self.listing.pop_front() {
+                    responder.complete(Err(error()));
+                }
+            }
+            Some("ADDNETWORK") => {
+                if let Some(responder) = self.adding.pop_front() {
+                    responder.complete(Err(error()));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+    use std::time::Duration;
+
+    fn message(line: &str) -> Message {
+        Parser::new(line).parse_message().unwrap()
+    }
+
+    #[test]
+    fn network_updates_are_merged() {
+        let mut bouncer = Bouncer::default();
+
+        bouncer.handle(&message(
+            ":soju BOUNCER NETWORK 42 name=Libera\\sChat;host=irc.libera.chat;state=connecting",
+        ));
+        let event = bouncer.handle(&message(":soju BOUNCER NETWORK 42 state=connected;host="));
+
+        let Some(IRCEvent::BouncerNetwork(network)) = event else {
+            panic!("Expected a bouncer network, got {event:?}");
+        };
+        assert_eq!(network.id, "42");
+        assert_eq!(network.name(), "Libera Chat");
+        assert_eq!(network.state(), Some("connected"));
+        assert_eq!(network.attribute("host"), None);
+
+        assert_eq!(
+            bouncer.handle(&message(":soju BOUNCER NETWORK 42 *")),
+            Some(IRCEvent::BouncerNetworkRemoved {
+                id: "42".to_string()
+            })
+        );
+        assert_eq!(bouncer.networks().count(), 0);
+    }
+
+    #[test]
+    fn added_network_completes_with_its_id() {
+        let mut bouncer = Bouncer::default();
+        let added = bouncer.expect_added();
+        let failed = bouncer.expect_added();
+
+        bouncer.handle(&message(":soju BOUNCER ADDNETWORK 7"));
+        let reply = StandardReply::from_message(&message(
+            ":soju FAIL BOUNCER INVALID_ATTRIBUTE ADDNETWORK host :Missing host",
+        ))
+        .unwrap();
+        bouncer.fail(&reply);
+
+        assert_eq!(added.try_take().unwrap().unwrap(), "7");
+        assert!(matches!(
+            failed.try_take().unwrap(),
+            Err(Error::Protocol(error)) if error.code == "INVALID_ATTRIBUTE"
+        ));
+    }
+
+    #[test]
+    fn attributes_and_playback_are_formatted() {
+        assert_eq!(
+            format_attributes(&[("name", "Work IRC"), ("host", "irc.example.com")]),
+            "name=Work\\sIRC;host=irc.example.com"
+        );
+        assert_eq!(playback_command("*", None), "PRIVMSG *playback :PLAY * 0");
+        assert_eq!(
+            playback_command(
+                "#rust",
+                Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_250))
+            ),
+            "PRIVMSG *playback :PLAY #rust 1700000000.250"
+        );
+    }
+}
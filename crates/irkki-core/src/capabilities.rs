@@ -0,0 +1,110 @@
+use std::collections::{HashMap, HashSet};
+
+/// Capabilities offered by the server and the ones that have been enabled for this connection,
+/// kept up to date from the CAP LS, ACK, NEW and DEL replies.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    available: HashMap<String, Option<String>>,
+    enabled: HashSet<String>,
+}
+
+impl Capabilities {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Updates the capabilities from the params of a CAP message, e.g.
+    /// `CAP nick LS * :multi-prefix sasl=PLAIN` or `CAP nick ACK :batch`.
+    pub fn update(&mut self, params: &[String]) {
+        let Some(subcommand) = params.get(1) else {
+            return;
+        };
+        let Some(caps) = params.last().filter(|_| params.len() > 2) else {
+            return;
+        };
+
+        match subcommand.as_str() {
+            "LS" | "NEW" => {
+                for (name, value) in Self::split(caps) {
+                    self.available
+                        .insert(name.to_string(), value.map(String::from));
+                }
+            }
+            "ACK" => {
+                for (name, _) in Self::split(caps) {
+                    if let Some(name) = name.strip_prefix('-') {
+                        self.enabled.remove(name);
+                    } else {
+                        self.enabled.insert(name.to_string());
+                    }
+                }
+            }
+            "DEL" => {
+                for (name, _) in Self::split(caps) {
+                    self.available.remove(name);
+                    self.enabled.remove(name);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn split(caps: &str) -> impl Iterator<Item = (&str, Option<&str>)> {
+        caps.split_whitespace()
+            .map(|cap| match cap.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (cap, None),
+            })
+    }
+
+    pub fn is_available(&self, name: &str) -> bool {
+        self.available.contains_key(name)
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.enabled.contains(name)
+    }
+
+    /// The value advertised for a capability, e.g. `PLAIN,EXTERNAL` for `sasl=PLAIN,EXTERNAL`.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.available.get(name).and_then(|value| value.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn ls_and_ack_track_available_and_enabled_caps() {
+        let mut caps = Capabilities::new();
+        caps.update(&params(&[
+            "*",
+            "LS",
+            "*",
+            "batch draft/multiline=max-bytes=4096",
+        ]));
+        caps.update(&params(&["*", "LS", "sasl=PLAIN"]));
+        caps.update(&params(&["nick", "ACK", "batch draft/multiline"]));
+
+        assert!(caps.is_available("sasl"));
+        assert_eq!(caps.value("draft/multiline"), Some("max-bytes=4096"));
+        assert!(caps.is_enabled("batch"));
+        assert!(!caps.is_enabled("sasl"));
+    }
+
+    #[test]
+    fn del_and_negative_ack_disable_caps() {
+        let mut caps = Capabilities::new();
+        caps.update(&params(&["nick", "ACK", "batch away-notify"]));
+        caps.update(&params(&["nick", "ACK", "-batch"]));
+        caps.update(&params(&["nick", "DEL", "away-notify"]));
+
+        assert!(!caps.is_enabled("batch"));
+        assert!(!caps.is_enabled("away-notify"));
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::HashMap;
+
+use crate::{ISupport, Message, Source};
+
+/// What we know about a user who shares a channel with us.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct User {
+    pub nickname: String,
+    pub username: Option<String>,
+    pub host: Option<String>,
+    pub realname: Option<String>,
+    /// The account the user is logged in to, when known.
+    pub account: Option<String>,
+    pub away: bool,
+}
+
+/// A user in a channel, with their membership prefixes such as `@` for operators.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Member {
+    pub nickname: String,
+    pub prefixes: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Channel {
+    pub name: String,
+    pub topic: Option<String>,
+    members: HashMap<String, Member>,
+}
+
+impl Channel {
+    pub fn members(&self) -> impl Iterator<Item = &Member> {
+        self.members.values()
+    }
+
+    pub fn member(&self, nickname: &str) -> Option<&Member> {
+        self.members.get(&key(nickname))
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+
+/// The channels we are in and the users in them, kept up to date from JOIN, PART, KICK, QUIT,
+/// NICK, MODE, TOPIC, the NAMES replies and WHO.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Channels {
+    channels: HashMap<String, Channel>,
+    users: HashMap<String, User>,
+}
+
+fn key(name: &str) -> String {
+    name.to_ascii_lowercase()
+}
+
+impl Channels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn channel(&self, name: &str) -> Option<&Channel> {
+        self.channels.get(&key(name))
+    }
+
+    pub fn channels(&self) -> impl Iterator<Item = &Channel> {
+        self.channels.values()
+    }
+
+    pub fn user(&self, nickname: &str) -> Option<&User> {
+        self.users.get(&key(nickname))
+    }
+
+    /// Updates the model from a message, `nickname` being our own nickname.
+    pub(crate) fn handle(&mut self, message: &Message, nickname: &str, isupport: &ISupport) {
+        let source = message.prefix.as_deref().map(Source::parse);
+        let params = &message.params;
+        let is_own = source
+            .as_ref()
+            .is_some_and(|source| source.nickname.eq_ignore_ascii_case(nickname));
+
+        match (message.command.as_str(), source) {
+            ("JOIN", Some(source)) if !params.is_empty() => {
+                if is_own {
+                    self.channels.insert(
+                        key(&params[0]),
+                        Channel {
+                            name: params[0].clone(),
+                            ..Channel::default()
+                        },
+                    );
+                }
+                self.add_member(&params[0], &source.nickname, "");
+                let user = self.user_or_default(&source.nickname);
+                // With extended-join the account and real name come with the JOIN.
+                let account = match params.get(1).map(String::as_str) {
+                    Some("*") => None,
+                    Some(account) => Some(account.to_string()),
+                    None => user.account.clone(),
+                };
+                self.update_user(User {
+                    username: source.user,
+                    host: source.host,
+                    realname: params.get(2).cloned().or(user.realname.clone()),
+                    account,
+                    ..user
+                });
+            }
+            ("PART", Some(source)) if !params.is_empty() => {
+                self.leave(&params[0], &source.nickname, is_own);
+            }
+            ("KICK", Some(_)) if params.len() >= 2 => {
+                let kicked_self = params[1].eq_ignore_ascii_case(nickname);
+                self.leave(&params[0], &params[1], kicked_self);
+            }
+            ("QUIT", Some(source)) => {
+                for channel in self.channels.values_mut() {
+                    channel.members.remove(&key(&source.nickname));
+                }
+                self.users.remove(&key(&source.nickname));
+            }
+            ("NICK", Some(source)) if !params.is_empty() => {
+                self.rename(&source.nickname, &params[0]);
+            }
+            ("AWAY", Some(source)) => {
+                if let Some(user) = self.users.get_mut(&key(&source.nickname)) {
+                    user.away = params.first().is_some_and(|message| !message.is_empty());
+                }
+            }
+            ("ACCOUNT", Some(source)) if !params.is_empty() => {
+                if let Some(user) = self.users.get_mut(&key(&source.nickname)) {
+                    user.account = Some(params[0].clone()).filter(|account| account != "*");
+                }
+            }
+            ("CHGHOST", Some(source)) if params.len() >= 2 => {
+                if let Some(user) = self.users.get_mut(&key(&source.nickname)) {
+                    user.username = Some(params[0].clone());
+                    user.host = Some(params[1].clone());
+                }
+            }
+            ("SETNAME", Some(source)) if !params.is_empty() => {
+                if let Some(user) = self.users.get_mut(&key(&source.nickname)) {
+                    user.realname = Some(params[0].clone());
+                }
+            }
+            ("TOPIC", _) if params.len() >= 2 => self.set_topic(&params[0], &params[1]),
+            // RPL_TOPIC
+            ("332", _) if params.len() >= 3 => self.set_topic(&params[1], &params[2]),
+            // RPL_NAMREPLY
+            ("353", _) if params.len() >= 4 => {
+                let prefixes: String = isupport.prefixes().iter().map(|(_, p)| p).collect();
+                for name in params[3].split_whitespace() {
+                    let nick = name.trim_start_matches(|c| prefixes.contains(c));
+                    let source = Source::parse(nick);
+                    self.add_member(
+                        &params[2],
+                        &source.nickname,
+                        &name[..name.len() - nick.len()],
+                    );
+                    let user = self.user_or_default(&source.nickname);
+                    self.update_user(User {
+                        username: source.user.or(user.username.clone()),
+                        host: source.host.or(user.host.clone()),
+                        ..user
+                    });
+                }
+            }
+            ("MODE", _) if params.len() >= 2 => {
+                self.update_modes(&params[0], &params[1], &params[2..], isupport)
+            }
+            _ => {}
+        }
+    }
+
+    /// Adds what WHO told us about a user who is in one of our channels.
+    pub(crate) fn update_user(&mut self, user: User) {
+        let user_key = key(&user.nickname);
+        let in_a_channel = self
+            .channels
+            .values()
+            .any(|channel| channel.members.contains_key(&user_key));
+
+        if in_a_channel {
+            self.users.insert(user_key, user);
+        }
+    }
+
+    pub(crate) fn user_or_default(&self, nickname: &str) -> User {
+        self.user(nickname).cloned().unwrap_or_else(|| User {
+            nickname: nickname.to_string(),
+            ..User::default()
+        })
+    }
+
+    fn add_member(&mut self, channel: &str, nickname: &str, prefixes: &str) {
+        if let Some(channel) = self.channels.get_mut(&key(channel)) {
+            channel.members.insert(
+                key(nickname),
+                Member {
+                    nickname: nickname.to_string(),
+                    prefixes: prefixes.to_string(),
+                },
+            );
+        }
+    }
+
+    fn leave(&mut self, channel: &str, nickname: &str, is_own: bool) {
+        if is_own {
+            self.channels.remove(&key(channel));
+        } else if let Some(channel) = self.channels.get_mut(&key(channel)) {
+            channel.members.remove(&key(nickname));
+        }
+
+        let channels = &self.channels;
+        self.users.retain(|user, _| {
+            channels
+                .values()
+                .any(|channel| channel.members.contains_key(user))
+        });
+    }
+
+    fn rename(&mut self, old: &str, new: &str) {
+        for channel in self.channels.values_mut() {
+            if let Some(mut member) = channel.members.remove(&key(old)) {
+                member.nickname = new.to_string();
+                channel.members.insert(key(new), member);
+            }
+        }
+        if let Some(mut user) = self.users.remove(&key(old)) {
+            user.nickname = new.to_string();
+            self.users.insert(key(new), user);
+        }
+    }
+
+    fn set_topic(&mut self, channel: &str, topic: &str) {
+        if let Some(channel) = self.channels.get_mut(&key(channel)) {
+            channel.topic = Some(topic.to_string()).filter(|topic| !topic.is_empty());
+        }
+    }
+
+    /// Applies the membership modes in a MODE change, such as `+o nick`, and skips the
+    /// parameters of the other modes.
+    fn update_modes(&mut self, target: &str, modes: &str, args: &[String], isupport: &ISupport) {
+        let Some(channel) = self.channels.get_mut(&key(target)) else {
+            return;
+        };
+        let prefixes = isupport.prefixes();
+        let [list, always, when_set, _] = isupport.channel_modes();
+        let mut args = args.iter();
+        let mut adding = true;
+
+        for mode in modes.chars() {
+            match mode {
+                '+' => adding = true,
+                '-' => adding = false,
+                mode => {
+                    if let Some(rank) = prefixes.iter().position(|(m, _)| *m == mode) {
+                        let Some(member) = args
+                            .next()
+                            .and_then(|nick| channel.members.get_mut(&key(nick)))
+                        else {
+                            continue;
+                        };
+                        let symbol = prefixes[rank].1;
+                        member.prefixes.retain(|c| c != symbol);
+                        if adding {
+                            member.prefixes.push(symbol);
+                        }
+                        // Keep the most powerful prefix first.
+                        let mut symbols: Vec<char> = member.prefixes.chars().collect();
+                        symbols.sort_by_key(|c| prefixes.iter().position(|(_, p)| p == c));
+                        member.prefixes = symbols.into_iter().collect();
+                    } else if list.contains(mode)
+                        || always.contains(mode)
+                        || (adding && when_set.contains(mode))
+                    {
+                        args.next();
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+
+    fn feed(channels: &mut Channels, lines: &[&str]) {
+        let isupport = ISupport::new();
+        for line in lines {
+            let message = Parser::new(line).parse_message().unwrap();
+            channels.handle(&message, "me", &isupport);
+        }
+    }
+
+    #[test]
+    fn names_and_joins_fill_the_channel() {
+        let mut channels = Channels::new();
+
+        feed(
+            &mut channels,
+            &[
+                ":me!u@h JOIN #rust",
+                ":server 353 me = #rust :@alice +bob me",
+                ":carol!c@host JOIN #rust",
+                ":server 332 me #rust :Systems programming",
+            ],
+        );
+
+        let channel = channels.channel("#Rust").unwrap();
+        assert_eq!(channel.len(), 4);
+        assert_eq!(channel.member("alice").unwrap().prefixes, "@");
+        assert_eq!(channel.topic.as_deref(), Some("Systems programming"));
+        assert_eq!(
+            channels.user("carol").unwrap().host.as_deref(),
+            Some("host")
+        );
+    }
+
+    #[test]
+    fn users_leave_and_change_nicknames() {
+        let mut channels = Channels::new();
+
+        feed(
+            &mut channels,
+            &[
+                ":me!u@h JOIN #rust",
+                ":server 353 me = #rust :alice bob carol me",
+                ":alice!a@h PART #rust",
+                ":me!u@h KICK #rust bob :bye",
+                ":carol!c@h NICK caroline",
+            ],
+        );
+
+        let channel = channels.channel("#rust").unwrap();
+        assert!(channel.member("alice").is_none());
+        assert!(channel.member("bob").is_none());
+        assert_eq!(channel.member("caroline").unwrap().nickname, "caroline");
+        assert!(channels.user("alice").is_none());
+        assert!(channels.user("caroline").is_some());
+
+        feed(&mut channels, &[":me!u@h PART #rust"]);
+        assert!(channels.channel("#rust").is_none());
+        assert!(channels.user("caroline").is_none());
+    }
+
+    #[test]
+    fn presence_changes_update_the_user() {
+        let mut channels = Channels::new();
+
+        feed(
+            &mut channels,
+            &[
+                ":me!u@h JOIN #rust",
+                ":alice!a@h JOIN #rust alice_acct :Alice",
+                ":bob!b@h JOIN #rust * :Bob",
+                ":alice!a@h AWAY :lunch",
+                ":alice!a@h CHGHOST al new.host",
+                ":alice!al@new.host SETNAME :Alice L.",
+                ":bob!b@h ACCOUNT bob_acct",
+            ],
+        );
+
+        let alice = channels.user("alice").unwrap();
+        assert_eq!(alice.account.as_deref(), Some("alice_acct"));
+        assert!(alice.away);
+        assert_eq!(alice.username.as_deref(), Some("al"));
+        assert_eq!(alice.host.as_deref(), Some("new.host"));
+        assert_eq!(alice.realname.as_deref(), Some("Alice L."));
+        assert_eq!(
+            channels.user("bob").unwrap().account.as_deref(),
+            Some("bob_acct")
+        );
+    }
+
+    #[test]
+    fn membership_modes_update_prefixes() {
+        let mut channels = Channels::new();
+
+        feed(
+            &mut channels,
+            &[
+                ":me!u@h JOIN #rust",
+                ":server 353 me = #rust :alice bob me",
+                ":op MODE #rust +kov secret alice alice",
+                ":op MODE #rust -o+v alice bob",
+            ],
+        );
+
+        let channel = channels.channel("#rust").unwrap();
+        assert_eq!(channel.member("alice").unwrap().prefixes, "+");
+        assert_eq!(channel.member("bob").unwrap().prefixes, "+");
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::time::SystemTime;
+
+use crate::{
+    Error, Event, IRCEvent, Responder, Response, ServerError, format_server_time,
+    parse_server_time, response,
+};
+
+/// The batch type of the replies to CHATHISTORY requests for messages.
+pub(crate) const HISTORY_BATCH: &str = "chathistory";
+/// The batch type of the replies to CHATHISTORY TARGETS.
+pub(crate) const TARGETS_BATCH: &str = "draft/chathistory-targets";
+
+/// How many msgids are remembered to drop replayed messages that were already seen.
+const SEEN_CAPACITY: usize = 4096;
+
+/// The message a CHATHISTORY request starts or ends at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Selector {
+    MsgId(String),
+    Timestamp(SystemTime),
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Selector::MsgId(msgid) => write!(f, "msgid={msgid}"),
+            Selector::Timestamp(time) => write!(f, "timestamp={}", format_server_time(*time)),
+        }
+    }
+}
+
+/// A CHATHISTORY request for the messages of a channel or a private conversation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HistoryRequest {
+    /// The most recent messages, or only those after `after`. Used to fetch the messages that
+    /// were missed while disconnected.
+    Latest {
+        target: String,
+        after: Option<Selector>,
+    },
+    /// The messages before the selected one, for loading older messages.
+    Before {
+        target: String,
+        selector: Selector,
+    },
+    After {
+        target: String,
+        selector: Selector,
+    },
+    /// The messages around the selected one, such as a message someone replied to.
+    Around {
+        target: String,
+        selector: Selector,
+    },
+    /// The messages between two others, in either direction.
+    Between {
+        target: String,
+        start: Selector,
+        end: Selector,
+    },
+}
+
+impl HistoryRequest {
+    pub fn target(&self) -> &str {
+        match self {
+            HistoryRequest::Latest { target, .. }
+            | HistoryRequest::Before { target, .. }
+            | HistoryRequest::After { target, .. }
+            | HistoryRequest::Around { target, .. }
+            | HistoryRequest::Between { target, .. } => target,
+        }
+    }
+
+    pub(crate) fn command(&self, limit: usize) -> String {
+        match self {
+            HistoryRequest::Latest { target, after } => {
+                let after = after
+                    .as_ref()
+                    .map_or_else(|| "*".to_string(), Selector::to_string);
+                format!("CHATHISTORY LATEST {target} {after} {limit}")
+            }
+            HistoryRequest::Before { target, selector } => {
+                format!("CHATHISTORY BEFORE {target} {selector} {limit}")
+            }
+            HistoryRequest::After { target, selector } => {
+                format!("CHATHISTORY AFTER {target} {selector} {limit}")
+            }
+            HistoryRequest::Around { target, selector } => {
+                format!("CHATHISTORY AROUND {target} {selector} {limit}")
+            }
+            HistoryRequest::Between { target, start, end } => {
+                format!("CHATHISTORY BETWEEN {target} {start} {end} {limit}")
+            }
+        }
+    }
+}
+
+/// A conversation that has history, as listed by CHATHISTORY TARGETS.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HistoryTarget {
+    pub name: String,
+    /// The time of the latest message in the conversation.
+    pub latest: SystemTime,
+}
+
+enum Pending {
+    Messages {
+        target: String,
+        responder: Responder<Vec<Event>>,
+    },
+    Targets(Responder<Vec<HistoryTarget>>),
+}
+
+/// The CHATHISTORY requests waiting for their batch, and the msgids that have been seen.
+#[derive(Default)]
+pub(crate) struct ChatHistory {
+    pending: VecDeque<Pending>,
+    seen: HashSet<String>,
+    seen_order: VecDeque<String>,
+}
+
+impl ChatHistory {
+    pub(crate) fn expect(&mut self, target: &str) -> Response<Vec<Event>> {
+        let (responder, response) = response();
+        self.pending.push_back(Pending::Messages {
+            target: target.to_string(),
+            responder,
+        });
+        response
+    }
+
+    pub(crate) fn expect_targets(&mut self) -> Response<Vec<HistoryTarget>> {
+        let (responder, response) = response();
+        self.pending.push_back(Pending::Targets(responder));
+        response
+    }
+
+    /// Remembers a msgid, and tells whether it was seen before.
+    pub(crate) fn see(&mut self, msgid: &str) -> bool {
+        if self.seen.contains(msgid) {
+            return true;
+        }
+
+        if self.seen_order.len() == SEEN_CAPACITY
+            && let Some(oldest) = self.seen_order.pop_front()
+        {
+            self.seen.remove(&oldest);
+        }
+        self.seen.insert(msgid.to_string());
+        self.seen_order.push_back(msgid.to_string());
+        false
+    }
+
+    /// Completes the request that a finished batch answers, with its events in order. Gives the
+    /// event back when it is not such a batch or no request is waiting for it.
+    pub(crate) fn complete(&mut self, event: Event) -> Option<Event> {
+        let IRCEvent::Batch(batch) = &event.kind else {
+            return Some(event);
+        };
+
+        let position = match batch.kind.as_str() {
+            HISTORY_BATCH => {
+                let target = batch.params.first().map_or("", String::as_str);
+                self.pending.iter().position(|pending| {
+                    matches!(pending, Pending::Messages { target: expected, .. }
+                        if expected.eq_ignore_ascii_case(target))
+                })
+            }
+            TARGETS_BATCH => self
+                .pending
+                .iter()
+                .position(|pending| matches!(pending, Pending::Targets(_))),
+            _ => None,
+        };
+        let Some(pending) = position.and_then(|position| self.pending.remove(position)) else {
+            return Some(event);
+        };
+        let IRCEvent::Batch(batch) = event.kind else {
+            return None;
+        };
+
+        match pending {
+            Pending::Messages { responder, .. } => {
+                let mut events = batch.events;
+                events.sort_by_key(|event| event.time);
+                responder.complete(Ok(events));
+            }
+            Pending::Targets(responder) => {
+                let targets = batch
+                    .events
+                    .iter()
+                    .filter_map(|event| match &event.kind {
+                        IRCEvent::Message(message)
+                            if message.command == "CHATHISTORY" && message.params.len() >= 3 =>
+                        {
+                            Some(HistoryTarget {
+                                name: message.params[1].clone(),
+                                latest: parse_server_time(&message.params[2])?,
+                            })
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                responder.complete(Ok(targets));
+            }
+        }
+        None
+    }
+
+    /// Fails the oldest request, which the server answered with `FAIL CHATHISTORY`.
+    pub(crate) fn fail(&mut self, error: ServerError) {
+        match self.pending.pop_front() {
+            Some(Pending::Messages { responder, .. }) => {
+                responder.complete(Err(Error::Protocol(error)))
+            }
+            Some(Pending::Targets(responder)) => responder.complete(Err(Error::Protocol(error))),
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Batch;
+    use std::time::Duration;
+
+    fn event(seconds: u64, text: &str) -> Event {
+        Event {
+            time: SystemTime::UNIX_EPOCH + Duration::from_secs(seconds),
+            ..Event::new(IRCEvent::Raw(text.to_string()))
+        }
+    }
+
+    fn batch(kind: &str, params: &[&str], events: Vec<Event>) -> Event {
+        Event {
+            time: SystemTime::UNIX_EPOCH,
+            ..Event::new(IRCEvent::Batch(Batch {
+                kind: kind.to_string(),
+                params: params.iter().map(|param| param.to_string()).collect(),
+                events,
+            }))
+        }
+    }
+
+    #[test]
+    fn requests_are_formatted_with_selectors() {
+        let latest = HistoryRequest::Latest {
+            target: "#rust".to_string(),
+            after: None,
+        };
+        let between = HistoryRequest::Between {
+            target: "alice".to_string(),
+            start: Selector::MsgId("abc".to_string()),
+            end: Selector::Timestamp(SystemTime::UNIX_EPOCH),
+        };
+
+        assert_eq!(latest.command(50), "CHATHISTORY LATEST #rust * 50");
+        assert_eq!(
+            between.command(10),
+            "CHATHISTORY BETWEEN alice msgid=abc timestamp=1970-01-01T00:00:00.000Z 10"
+        );
+    }
+
+    #[test]
+    fn batch_completes_the_request_for_its_target_in_order() {
+        let mut history = ChatHistory::default();
+        let rust = history.expect("#rust");
+        let other = history.expect("#other");
+
+        let unrelated = history.complete(batch("netsplit", &[], Vec::new()));
+        let answered = history.complete(batch(
+            HISTORY_BATCH,
+            &["#Rust"],
+            vec![event(2, "second"), event(1, "first")],
+        ));
+
+        assert!(unrelated.is_some());
+        assert!(answered.is_none());
+        let events = rust.try_take().unwrap().unwrap();
+        assert_eq!(events, vec![event(1, "first"), event(2, "second")]);
+        assert!(other.try_take().is_none());
+    }
+
+    #[test]
+    fn seen_msgids_are_remembered() {
+        let mut history = ChatHistory::default();
+
+        assert!(!history.see("abc"));
+        assert!(history.see("abc"));
+    }
+}
@@ -0,0 +1,264 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+
+/// A DCC request, sent as the params of a `DCC` CTCP, e.g.
+/// `\x01DCC SEND report.txt 2130706433 5000 1024\x01`.
+///
+/// A request with port 0 and a token is passive: the one receiving it listens instead, and
+/// answers with the same request carrying its own address and port and the token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DccRequest {
+    /// `DCC CHAT chat <address> <port>`
+    Chat { address: IpAddr, port: u16 },
+    /// `DCC SEND <filename> <address> <port> [<size> [<token>]]`
+    Send {
+        filename: String,
+        address: IpAddr,
+        port: u16,
+        size: Option<u64>,
+        token: Option<String>,
+    },
+    /// `DCC RESUME <filename> <port> <position> [<token>]` asks the sender of an offer to start
+    /// at `position`, where a previous transfer stopped.
+    Resume {
+        filename: String,
+        port: u16,
+        position: u64,
+        token: Option<String>,
+    },
+    /// `DCC ACCEPT <filename> <port> <position> [<token>]` is the sender's answer to a RESUME.
+    Accept {
+        filename: String,
+        port: u16,
+        position: u64,
+        token: Option<String>,
+    },
+}
+
+impl DccRequest {
+    /// Parses the params of a `DCC` CTCP. Filenames with spaces are quoted.
+    pub fn parse(params: &str) -> Option<Self> {
+        let words = words(params);
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+        let token = |word: Option<&&str>| word.map(|token| token.to_string());
+
+        match words.first()?.to_ascii_uppercase().as_str() {
+            "CHAT" => match words[..] {
+                [_, _, address, port, ..] => Some(Self::Chat {
+                    address: parse_address(address)?,
+                    port: port.parse().ok()?,
+                }),
+                _ => None,
+            },
+            "SEND" => match words[..] {
+                [_, filename, address, port, ref rest @ ..] => Some(Self::Send {
+                    filename: filename.to_string(),
+                    address: parse_address(address)?,
+                    port: port.parse().ok()?,
+                    size: rest.first().and_then(|size| size.parse().ok()),
+                    token: token(rest.get(1)),
+                }),
+                _ => None,
+            },
+            command @ ("RESUME" | "ACCEPT") => match words[..] {
+                [_, filename, port, position, ref rest @ ..] => {
+                    let filename = filename.to_string();
+                    let port = port.parse().ok()?;
+                    let position = position.parse().ok()?;
+                    let token = token(rest.first());
+
+                    Some(if command == "RESUME" {
+                        Self::Resume {
+                            filename,
+                            port,
+                            position,
+                            token,
+                        }
+                    } else {
+                        Self::Accept {
+                            filename,
+                            port,
+                            position,
+                            token,
+                        }
+                    })
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Whether the one receiving the request should listen rather than connect.
+    pub fn is_passive(&self) -> bool {
+        match self {
+            Self::Chat { .. } => false,
+            Self::Send { port, token, .. }
+            | Self::Resume { port, token, .. }
+            | Self::Accept { port, token, .. } => *port == 0 && token.is_some(),
+        }
+    }
+}
+
+impl fmt::Display for DccRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (command, filename, rest) = match self {
+            Self::Chat { address, port } => {
+                return write!(f, "CHAT chat {} {port}", format_address(address));
+            }
+            Self::Send {
+                filename,
+                address,
+                port,
+                size,
+                token,
+            } => {
+                let mut rest = format!("{} {port}", format_address(address));
+                if let Some(size) = size {
+                    rest.push_str(&format!(" {size}"));
+                    if let Some(token) = token {
+                        rest.push_str(&format!(" {token}"));
+                    }
+                }
+                ("SEND", filename, rest)
+            }
+            Self::Resume {
+                filename,
+                port,
+                position,
+                token,
+            } => ("RESUME", filename, resume_params(*port, *position, token)),
+            Self::Accept {
+                filename,
+                port,
+                position,
+                token,
+            } => ("ACCEPT", filename, resume_params(*port, *position, token)),
+        };
+
+        if filename.contains(' ') {
+            write!(f, "{command} \"{filename}\" {rest}")
+        } else {
+            write!(f, "{command} {filename} {rest}")
+        }
+    }
+}
+
+fn resume_params(port: u16, position: u64, token: &Option<String>) -> String {
+    match token {
+        Some(token) => format!("{port} {position} {token}"),
+        None => format!("{port} {position}"),
+    }
+}
+
+/// Splits the params into words, keeping quoted words with spaces together.
+fn words(params: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut rest = params.trim_start();
+
+    while !rest.is_empty() {
+        let (word, next) = match rest
+            .strip_prefix('"')
+            .and_then(|quoted| quoted.split_once('"'))
+        {
+            Some(quoted) => quoted,
+            None => rest.split_once(' ').unwrap_or((rest, "")),
+        };
+        words.push(word.to_string());
+        rest = next.trim_start();
+    }
+
+    words
+}
+
+/// IPv4 addresses are sent as one decimal number, IPv6 addresses as they are written.
+fn parse_address(address: &str) -> Option<IpAddr> {
+    match address.parse::<u32>() {
+        Ok(number) => Some(IpAddr::V4(Ipv4Addr::from(number))),
+        Err(_) => address.parse().ok(),
+    }
+}
+
+fn format_address(address: &IpAddr) -> String {
+    match address {
+        IpAddr::V4(address) => u32::from(*address).to_string(),
+        IpAddr::V6(address) => address.to_string(),
+    }
+}
+
+/// The name a received file may be saved under: the last part of the offered name, without
+/// the directories and control characters that could place it outside the download directory.
+/// Returns `None` when nothing usable is left.
+pub fn safe_filename(filename: &str) -> Option<String> {
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = name
+        .chars()
+        .filter(|c| !c.is_control() && *c != ':')
+        .collect();
+    let name = name.trim().trim_start_matches('.');
+
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn send_with_size_and_token_is_parsed() {
+        let request = DccRequest::parse("SEND \"log bundle.tgz\" 2130706433 0 1024 7").unwrap();
+
+        assert_eq!(
+            request,
+            DccRequest::Send {
+                filename: "log bundle.tgz".to_string(),
+                address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                port: 0,
+                size: Some(1024),
+                token: Some("7".to_string()),
+            }
+        );
+        assert!(request.is_passive());
+        assert_eq!(
+            request.to_string(),
+            "SEND \"log bundle.tgz\" 2130706433 0 1024 7"
+        );
+    }
+
+    #[test]
+    fn chat_resume_and_accept_round_trip() {
+        for params in [
+            "CHAT chat 2130706433 5000",
+            "RESUME report.txt 5000 512",
+            "ACCEPT report.txt 0 512 7",
+            "SEND report.txt ::1 5000",
+        ] {
+            assert_eq!(DccRequest::parse(params).unwrap().to_string(), params);
+        }
+    }
+
+    #[test]
+    fn malformed_requests_are_not_parsed() {
+        assert_eq!(DccRequest::parse("SEND report.txt"), None);
+        assert_eq!(DccRequest::parse("SEND report.txt nowhere 5000"), None);
+        assert_eq!(DccRequest::parse("RESUME report.txt 5000 half"), None);
+        assert_eq!(DccRequest::parse("VOICE x 1 2"), None);
+    }
+
+    #[test]
+    fn filenames_cannot_leave_the_download_directory() {
+        assert_eq!(safe_filename("report.txt").as_deref(), Some("report.txt"));
+        assert_eq!(safe_filename("../../etc/passwd").as_deref(), Some("passwd"));
+        assert_eq!(
+            safe_filename("..\\..\\boot.ini").as_deref(),
+            Some("boot.ini")
+        );
+        assert_eq!(
+            safe_filename("C:autoexec.bat").as_deref(),
+            Some("Cautoexec.bat")
+        );
+        assert_eq!(safe_filename("..").as_deref(), None);
+        assert_eq!(safe_filename("logs/").as_deref(), None);
+        assert_eq!(safe_filename("\x07").as_deref(), None);
+    }
+}
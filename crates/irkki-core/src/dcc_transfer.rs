@@ -0,0 +1,725 @@
+use log::{error, info};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{DccRequest, safe_filename};
+
+/// How many bytes of a file are sent or received at a time, with a progress event for each.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// How often a listening socket checks whether the peer has connected.
+const ACCEPT_POLL: Duration = Duration::from_millis(20);
+
+/// Settings of DCC file transfers and chats.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DccConfig {
+    /// Where received files are saved.
+    pub download_dir: PathBuf,
+    /// The address our requests ask the peer to connect to, usually the public address of this
+    /// machine.
+    pub address: IpAddr,
+    /// The local address our listening sockets bind to. With port 0 the system picks a port.
+    pub bind: SocketAddr,
+    /// How long to wait for the peer to connect, for a connection to the peer, for the peer to
+    /// answer an offer and for data during a transfer.
+    pub timeout: Duration,
+    /// The most bytes received for an offer that does not tell the size of the file. A sender
+    /// that reaches it fails the transfer.
+    pub size_limit: u64,
+}
+
+impl DccConfig {
+    pub fn new(download_dir: impl Into<PathBuf>, address: IpAddr) -> Self {
+        Self {
+            download_dir: download_dir.into(),
+            address,
+            bind: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            timeout: Duration::from_secs(120),
+            size_limit: 4 * 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// What happens with the transfers and chats of [`DccTransfers`], by the id they were given.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DccEvent {
+    /// Part of a file was sent or received. `transferred` counts a resumed part too.
+    Progress {
+        id: u64,
+        transferred: u64,
+        size: Option<u64>,
+    },
+    /// A file was sent, or received and saved at `path`.
+    Completed {
+        id: u64,
+        path: PathBuf,
+    },
+    Failed {
+        id: u64,
+        error: String,
+    },
+    ChatConnected {
+        id: u64,
+    },
+    ChatLine {
+        id: u64,
+        text: String,
+    },
+    ChatClosed {
+        id: u64,
+    },
+}
+
+/// One of our file offers, waiting for the peer to connect, resume or answer a passive offer.
+struct Offer {
+    id: u64,
+    path: PathBuf,
+    size: u64,
+    position: Arc<AtomicU64>,
+    /// Set once the peer has connected, or stopped being waited for.
+    done: Arc<AtomicBool>,
+    created: Instant,
+}
+
+/// A download waiting for the ACCEPT of its RESUME.
+struct Download {
+    id: u64,
+    /// Where the file is saved once it is complete.
+    path: PathBuf,
+    offer: DccRequest,
+    /// The size of the part, which the RESUME asked to continue from.
+    position: u64,
+    created: Instant,
+}
+
+/// The peer and the port, or the token of passive requests, which tie RESUME, ACCEPT and the
+/// answers to passive offers to the request they are about.
+type RequestKey = (String, String);
+
+fn key(peer: &str, port: u16, token: Option<&str>) -> RequestKey {
+    let id = token.map_or_else(|| port.to_string(), str::to_string);
+    (peer.to_ascii_lowercase(), id)
+}
+
+/// DCC file transfers and chats, run on threads of their own.
+///
+/// The requests for the peer are returned to the caller, who sends them over IRC with
+/// [`crate::Protocol::send_dcc`]. The requests from the peer are passed in with
+/// [`DccTransfers::accept_file`], [`DccTransfers::accept_chat`] and [`DccTransfers::handle`].
+/// Progress is reported with [`DccEvent`]s on the receiver returned by [`DccTransfers::new`].
+pub struct DccTransfers {
+    config: DccConfig,
+    events: mpsc::Sender<DccEvent>,
+    next_id: u64,
+    offers: HashMap<RequestKey, Offer>,
+    downloads: HashMap<RequestKey, Download>,
+    chats: Arc<Mutex<HashMap<u64, TcpStream>>>,
+}
+
+impl DccTransfers {
+    pub fn new(config: DccConfig) -> (Self, mpsc::Receiver<DccEvent>) {
+        let (events, receiver) = mpsc::channel();
+        let transfers = Self {
+            config,
+            events,
+            next_id: 1,
+            offers: HashMap::new(),
+            downloads: HashMap::new(),
+            chats: Arc::new(Mutex::new(HashMap::new())),
+        };
+        (transfers, receiver)
+    }
+
+    pub fn config(&self) -> &DccConfig {
+        &self.config
+    }
+
+    fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Forgets the offers that are done and the offers and downloads the peer did not answer in
+    /// time, so that a late request about them is ignored.
+    fn prune(&mut self) {
+        let timeout = self.config.timeout;
+        self.offers.retain(|_, offer| {
+            !offer.done.load(Ordering::SeqCst) && offer.created.elapsed() < timeout
+        });
+        self.downloads
+            .retain(|_, download| download.created.elapsed() < timeout);
+    }
+
+    /// Offers a file to `peer` and listens for it to connect. Returns the id of the transfer
+    /// and the SEND request to send to the peer.
+    pub fn offer_file(&mut self, peer: &str, path: &Path) -> io::Result<(u64, DccRequest)> {
+        self.prune();
+        let (filename, size) = Self::describe(path)?;
+        let listener = TcpListener::bind(self.config.bind)?;
+        let port = listener.local_addr()?.port();
+        let id = self.next_id();
+        let position = Arc::new(AtomicU64::new(0));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let offer_position = Arc::clone(&position);
+        let offer_done = Arc::clone(&done);
+        let offer_path = path.to_path_buf();
+        let timeout = self.config.timeout;
+        self.spawn(id, move |events| {
+            let stream = accept(&listener, timeout, None)
+                .and_then(|stream| with_read_timeout(stream, timeout));
+            offer_done.store(true, Ordering::SeqCst);
+            let position = offer_position.load(Ordering::SeqCst);
+            send_file(stream?, &offer_path, size, position, id, events)
+        });
+        self.offers.insert(
+            key(peer, port, None),
+            Offer {
+                id,
+                path: path.to_path_buf(),
+                size,
+                position,
+                done,
+                created: Instant::now(),
+            },
+        );
+
+        let request = DccRequest::Send {
+            filename,
+            address: self.config.address,
+            port,
+            size: Some(size),
+            token: None,
+        };
+        Ok((id, request))
+    }
+
+    /// Offers a file to `peer` passively, for when we cannot be connected to. The peer listens
+    /// and answers with where to connect, which is passed to [`DccTransfers::handle`].
+    pub fn offer_file_passive(&mut self, peer: &str, path: &Path) -> io::Result<(u64, DccRequest)> {
+        self.prune();
+        let (filename, size) = Self::describe(path)?;
+        let id = self.next_id();
+        let token = id.to_string();
+
+        self.offers.insert(
+            key(peer, 0, Some(&token)),
+            Offer {
+                id,
+                path: path.to_path_buf(),
+                size,
+                position: Arc::new(AtomicU64::new(0)),
+                done: Arc::new(AtomicBool::new(false)),
+                created: Instant::now(),
+            },
+        );
+
+        let request = DccRequest::Send {
+            filename,
+            address: self.config.address,
+            port: 0,
+            size: Some(size),
+            token: Some(token),
+        };
+        Ok((id, request))
+    }
+
+    fn describe(path: &Path) -> io::Result<(String, u64)> {
+        let size = fs::metadata(path)?.len();
+        let filename = path
+            .file_name()
+            .and_then(|name| safe_filename(&name.to_string_lossy()))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No file name to send"))?;
+        Ok((filename, size))
+    }
+
+    /// Receives the file a SEND request from `peer` offers into the download directory. A part
+    /// left there by an earlier transfer is resumed.
+    ///
+    /// Existing files are never overwritten: the file is received into `<name>.part` and renamed
+    /// once it is complete, to `<name> (1).<ext>` and so on when the name is taken.
+    ///
+    /// Returns the id of the transfer, and the request to send to the peer when there is one:
+    /// a RESUME, or the answer to a passive offer.
+    pub fn accept_file(
+        &mut self,
+        peer: &str,
+        offer: &DccRequest,
+    ) -> io::Result<(u64, Option<DccRequest>)> {
+        let DccRequest::Send {
+            filename,
+            port,
+            size,
+            token,
+            ..
+        } = offer
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Not a DCC SEND request",
+            ));
+        };
+        let name = safe_filename(filename).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unsafe file name {filename:?}"),
+            )
+        })?;
+        self.prune();
+        let id = self.next_id();
+        let directory = &self.config.download_dir;
+        fs::create_dir_all(directory)?;
+        let path = directory.join(&name);
+
+        let received = fs::metadata(partial_path(&path)).map_or(0, |metadata| metadata.len());
+        if received > 0 && size.is_some_and(|size| received < size) {
+            info!("Resuming {} at {} bytes.", path.display(), received);
+            self.downloads.insert(
+                key(peer, *port, token.as_deref()),
+                Download {
+                    id,
+                    path,
+                    offer: offer.clone(),
+                    position: received,
+                    created: Instant::now(),
+                },
+            );
+            let resume = DccRequest::Resume {
+                filename: filename.clone(),
+                port: *port,
+                position: received,
+                token: token.clone(),
+            };
+            return Ok((id, Some(resume)));
+        }
+
+        let path = free_path(&self.config.download_dir, &name);
+        let reply = self.download(id, path, offer, 0)?;
+        Ok((id, reply))
+    }
+
+    /// Connects to the sender, or listens for it when the offer is passive and returns the
+    /// answer that tells the sender where to connect. The file is received into the part of
+    /// `path`, which a position past the start appends to.
+    fn download(
+        &mut self,
+        id: u64,
+        path: PathBuf,
+        offer: &DccRequest,
+        position: u64,
+    ) -> io::Result<Option<DccRequest>> {
+        let DccRequest::Send {
+            filename,
+            address,
+            port,
+            size,
+            token,
+        } = offer.clone()
+        else {
+            return Ok(None);
+        };
+        let partial = partial_path(&path);
+        let file = if position > 0 {
+            OpenOptions::new().append(true).open(&partial)?
+        } else {
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&partial)?
+        };
+        let (timeout, limit) = (self.config.timeout, self.config.size_limit);
+
+        if !offer.is_passive() {
+            self.spawn(id, move |events| {
+                let stream = with_read_timeout(connect(address, port, timeout)?, timeout)?;
+                receive_file(stream, file, path, size, limit, id, events)
+            });
+            return Ok(None);
+        }
+
+        // The sender of a passive offer tells its address, so no one else gets to send the file.
+        let sender = Some(address).filter(|address| !address.is_unspecified());
+        let listener = TcpListener::bind(self.config.bind)?;
+        let local_port = listener.local_addr()?.port();
+        self.spawn(id, move |events| {
+            let stream = with_read_timeout(accept(&listener, timeout, sender)?, timeout)?;
+            receive_file(stream, file, path, size, limit, id, events)
+        });
+        Ok(Some(DccRequest::Send {
+            filename,
+            address: self.config.address,
+            port: local_port,
+            size,
+            token,
+        }))
+    }
+
+    /// Handles the requests from `peer` about our offers and downloads: a RESUME of an offer,
+    /// the ACCEPT of a RESUME and the answer to a passive offer. Returns the request to send
+    /// back, when there is one.
+    pub fn handle(&mut self, peer: &str, request: &DccRequest) -> io::Result<Option<DccRequest>> {
+        self.prune();
+        match request {
+            DccRequest::Resume {
+                filename,
+                port,
+                position,
+                token,
+            } => {
+                let Some(offer) = self.offers.get(&key(peer, *port, token.as_deref())) else {
+                    return Ok(None);
+                };
+                if *position > offer.size {
+                    return Ok(None);
+                }
+                offer.position.store(*position, Ordering::SeqCst);
+
+                Ok(Some(DccRequest::Accept {
+                    filename: filename.clone(),
+                    port: *port,
+                    position: *position,
+                    token: token.clone(),
+                }))
+            }
+            DccRequest::Accept {
+                port,
+                position,
+                token,
+                ..
+            } => {
+                // Resuming anywhere but at the end of the part would corrupt the file.
+                let key = key(peer, *port, token.as_deref());
+                if let Some(download) = self.downloads.get(&key)
+                    && download.position != *position
+                {
+                    info!(
+                        "Ignored an ACCEPT at {} bytes for a RESUME at {}.",
+                        position, download.position
+                    );
+                    return Ok(None);
+                }
+                let Some(download) = self.downloads.remove(&key) else {
+                    return Ok(None);
+                };
+                self.download(
+                    download.id,
+                    download.path,
+                    &download.offer,
+                    download.position,
+                )
+            }
+            DccRequest::Send {
+                address,
+                port,
+                token: Some(token),
+                ..
+            } if *port != 0 => {
+                let Some(offer) = self.offers.remove(&key(peer, 0, Some(token))) else {
+                    return Ok(None);
+                };
+                let (address, port, timeout) = (*address, *port, self.config.timeout);
+                self.spawn(offer.id, move |events| {
+                    let stream = with_read_timeout(connect(address, port, timeout)?, timeout)?;
+                    let position = offer.position.load(Ordering::SeqCst);
+                    send_file(stream, &offer.path, offer.size, position, offer.id, events)
+                });
+                Ok(None)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Offers a chat and listens for the peer to connect. Returns the id of the chat and the
+    /// CHAT request to send to the peer.
+    pub fn offer_chat(&mut self) -> io::Result<(u64, DccRequest)> {
+        let listener = TcpListener::bind(self.config.bind)?;
+        let port = listener.local_addr()?.port();
+        let id = self.next_id();
+        let chats = Arc::clone(&self.chats);
+        let timeout = self.config.timeout;
+
+        self.spawn(id, move |events| {
+            let stream = accept(&listener, timeout, None)?;
+            chat(stream, id, &chats, events)
+        });
+
+        let request = DccRequest::Chat {
+            address: self.config.address,
+            port,
+        };
+        Ok((id, request))
+    }
+
+    /// Connects to the chat a CHAT request offers, and returns the id of the chat.
+    pub fn accept_chat(&mut self, offer: &DccRequest) -> io::Result<u64> {
+        let DccRequest::Chat { address, port } = *offer else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Not a DCC CHAT request",
+            ));
+        };
+        let id = self.next_id();
+        let chats = Arc::clone(&self.chats);
+        let timeout = self.config.timeout;
+
+        self.spawn(id, move |events| {
+            let stream = connect(address, port, timeout)?;
+            chat(stream, id, &chats, events)
+        });
+        Ok(id)
+    }
+
+    /// Sends a line to a connected chat.
+    pub fn send_chat(&self, id: u64, text: &str) -> io::Result<()> {
+        let chats = self
+            .chats
+            .lock()
+            .map_err(|_| io::Error::other("DCC chat lock poisoned"))?;
+        let mut stream = chats
+            .get(&id)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+
+        stream.write_all(format!("{text}\n").as_bytes())
+    }
+
+    /// Closes a chat. Its reader reports [`DccEvent::ChatClosed`].
+    pub fn close_chat(&self, id: u64) {
+        if let Ok(mut chats) = self.chats.lock()
+            && let Some(stream) = chats.remove(&id)
+        {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Runs a transfer or chat on its own thread and reports its outcome.
+    fn spawn<F>(&self, id: u64, work: F)
+    where
+        F: FnOnce(&mpsc::Sender<DccEvent>) -> io::Result<DccEvent> + Send + 'static,
+    {
+        let events = self.events.clone();
+        thread::spawn(move || {
+            let event = work(&events).unwrap_or_else(|error| {
+                error!("DCC {} failed: {}", id, error);
+                DccEvent::Failed {
+                    id,
+                    error: error.to_string(),
+                }
+            });
+            let _ = events.send(event);
+        });
+    }
+}
+
+/// Where a download is written until it is complete. Only these files are resumed, so a file
+/// that merely has the same name is never appended to.
+fn partial_path(path: &Path) -> PathBuf {
+    let mut partial = path.as_os_str().to_os_string();
+    partial.push(".part");
+    PathBuf::from(partial)
+}
+
+/// The first of `name`, `name (1).ext`, `name (2).ext` and so on in `directory` that is not
+/// taken by a file or by the part of a download.
+fn free_path(directory: &Path, name: &str) -> PathBuf {
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+        _ => (name, String::new()),
+    };
+
+    let mut path = directory.join(name);
+    let mut n = 0;
+    while path.exists() || partial_path(&path).exists() {
+        n += 1;
+        path = directory.join(format!("{stem} ({n}){extension}"));
+    }
+    path
+}
+
+/// Moves a complete download from its part to `path`, or next to it when a file has taken the
+/// name in the meantime, and returns where it was saved.
+fn finish_download(path: PathBuf) -> io::Result<PathBuf> {
+    let partial = partial_path(&path);
+    let path = match (path.exists(), path.parent(), path.file_name()) {
+        (true, Some(directory), Some(name)) => free_path(directory, &name.to_string_lossy()),
+        _ => path,
+    };
+    fs::rename(partial, &path)?;
+    Ok(path)
+}
+
+/// Waits for the peer to connect, for at most `timeout`. When the address of the peer is known,
+/// connections from elsewhere are closed.
+fn accept(
+    listener: &TcpListener,
+    timeout: Duration,
+    peer: Option<IpAddr>,
+) -> io::Result<TcpStream> {
+    listener.set_nonblocking(true)?;
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        match listener.accept() {
+            Ok((_, address))
+                if peer.is_some_and(|peer| peer.to_canonical() != address.ip().to_canonical()) =>
+            {
+                info!("Closed a DCC connection from the unexpected {}.", address);
+            }
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                return Ok(stream);
+            }
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                if Instant::now() >= deadline {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "The peer did not connect",
+                    ));
+                }
+                thread::sleep(ACCEPT_POLL);
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+fn connect(address: IpAddr, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+    TcpStream::connect_timeout(&SocketAddr::new(address, port), timeout)
+}
+
+/// Makes reads from the stream of a transfer fail once the peer has sent nothing for `timeout`,
+/// rather than wait for a stalled peer forever.
+fn with_read_timeout(stream: TcpStream, timeout: Duration) -> io::Result<TcpStream> {
+    stream.set_read_timeout(Some(timeout))?;
+    Ok(stream)
+}
+
+/// Sends the file from `position` on. The receiver acknowledges what it has with the number of
+/// bytes received so far, as 32 bits in network order.
+fn send_file(
+    mut stream: TcpStream,
+    path: &Path,
+    size: u64,
+    position: u64,
+    id: u64,
+    events: &mpsc::Sender<DccEvent>,
+) -> io::Result<DccEvent> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(position))?;
+    let mut sent = position;
+    let mut buffer = vec![0; CHUNK_SIZE];
+
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        stream.write_all(&buffer[..n])?;
+        sent += n as u64;
+        let _ = events.send(DccEvent::Progress {
+            id,
+            transferred: sent,
+            size: Some(size),
+        });
+    }
+
+    // Closing before the last acknowledgement could reset the connection while the peer still
+    // has data to read.
+    let mut ack = [0; 4];
+    while stream.read_exact(&mut ack).is_ok() {
+        if u32::from_be_bytes(ack) == sent as u32 {
+            break;
+        }
+    }
+
+    info!("Sent {} over DCC.", path.display());
+    Ok(DccEvent::Completed {
+        id,
+        path: path.to_path_buf(),
+    })
+}
+
+/// Receives the file after the part already in `file`, up to its size, or up to `limit` bytes
+/// when the offer did not tell the size.
+fn receive_file(
+    mut stream: TcpStream,
+    mut file: File,
+    path: PathBuf,
+    size: Option<u64>,
+    limit: u64,
+    id: u64,
+    events: &mpsc::Sender<DccEvent>,
+) -> io::Result<DccEvent> {
+    let mut received = file.metadata()?.len();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let limit = size.unwrap_or(limit);
+
+    while received < limit {
+        let n = stream.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        // Whatever the sender sends past the end is left out.
+        let n = n.min(usize::try_from(limit - received).unwrap_or(usize::MAX));
+        file.write_all(&buffer[..n])?;
+        received += n as u64;
+        stream.write_all(&(received as u32).to_be_bytes())?;
+        let _ = events.send(DccEvent::Progress {
+            id,
+            transferred: received,
+            size,
+        });
+    }
+
+    if size.is_some_and(|size| received < size) {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("The sender stopped after {received} bytes"),
+        ));
+    }
+    if size.is_none() && received >= limit {
+        return Err(io::Error::new(
+            io::ErrorKind::FileTooLarge,
+            format!("The file reached the limit of {limit} bytes"),
+        ));
+    }
+
+    drop(file);
+    let path = finish_download(path)?;
+    info!("Received {} over DCC.", path.display());
+    Ok(DccEvent::Completed { id, path })
+}
+
+/// Reads the lines of a chat until either side closes it.
+fn chat(
+    stream: TcpStream,
+    id: u64,
+    chats: &Mutex<HashMap<u64, TcpStream>>,
+    events: &mpsc::Sender<DccEvent>,
+) -> io::Result<DccEvent> {
+    chats
+        .lock()
+        .map_err(|_| io::Error::other("DCC chat lock poisoned"))?
+        .insert(id, stream.try_clone()?);
+    let _ = events.send(DccEvent::ChatConnected { id });
+
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let text = line.trim_end_matches('\r').to_string();
+        let _ = events.send(DccEvent::ChatLine { id, text });
+    }
+
+    if let Ok(mut chats) = chats.lock() {
+        chats.remove(&id);
+    }
+    Ok(DccEvent::ChatClosed { id })
+}
@@ -0,0 +1,198 @@
+use std::fmt;
+use std::io;
+
+use crate::Message;
+
+/// Everything that can go wrong in irkki-core.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading from or writing to the connection failed.
+    Transport(io::Error),
+    /// The client is not connected, or the connection has been closed.
+    NotConnected,
+    /// The send queue is full and the line was dropped, see [`crate::OverflowPolicy`].
+    SendQueueFull,
+    /// The server did not reply in time.
+    Timeout,
+    /// The line cannot be sent as given, for example because its tags are too long.
+    InvalidInput(String),
+    /// A line from the server is not a valid IRC message.
+    Parse { line: String, reason: String },
+    /// The server rejected a command.
+    Protocol(ServerError),
+    /// The server did not accept the connection or the nickname during registration, see
+    /// [`crate::IRCEvent::RegistrationFailed`].
+    Registration(ServerError),
+    /// Authentication with the server or services failed.
+    Auth(ServerError),
+    /// The host has an STS policy, so it must be connected to over TLS on `port`.
+    TlsRequired { port: u16 },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Transport(error) => write!(f, "Connection failed: {error}"),
+            Error::NotConnected => write!(f, "Client is not connected."),
+            Error::SendQueueFull => write!(f, "Send queue is full, the line was dropped."),
+            Error::Timeout => write!(f, "The server did not reply in time."),
+            Error::InvalidInput(reason) => write!(f, "Invalid input: {reason}"),
+            Error::Parse { line, reason } => write!(f, "Failed to parse '{line}': {reason}"),
+            Error::Protocol(error) => write!(f, "Command failed: {error}"),
+            Error::Registration(error) => write!(f, "Registration failed: {error}"),
+            Error::Auth(error) => write!(f, "Authentication failed: {error}"),
+            Error::TlsRequired { port } => {
+                write!(f, "The server requires TLS, connect to port {port}.")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Transport(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::NotConnected => Error::NotConnected,
+            _ => Error::Transport(error),
+        }
+    }
+}
+
+impl From<ServerError> for Error {
+    fn from(error: ServerError) -> Self {
+        match error.kind() {
+            ServerErrorKind::NoNicknameGiven
+            | ServerErrorKind::ErroneousNickname
+            | ServerErrorKind::NicknameInUse
+            | ServerErrorKind::NickCollision => Error::Registration(error),
+            ServerErrorKind::PasswordMismatch | ServerErrorKind::SaslFailed => Error::Auth(error),
+            ServerErrorKind::Other => Error::Protocol(error),
+        }
+    }
+}
+
+/// The error numerics the client knows how to react to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServerErrorKind {
+    /// ERR_NONICKNAMEGIVEN (431)
+    NoNicknameGiven,
+    /// ERR_ERRONEUSNICKNAME (432)
+    ErroneousNickname,
+    /// ERR_NICKNAMEINUSE (433)
+    NicknameInUse,
+    /// ERR_NICKCOLLISION (436)
+    NickCollision,
+    /// ERR_PASSWDMISMATCH (464)
+    PasswordMismatch,
+    /// ERR_SASLFAIL (904), and ERR_NICKLOCKED (902), ERR_SASLTOOLONG (905) and
+    /// ERR_SASLABORTED (906), which end a SASL login too.
+    SaslFailed,
+    Other,
+}
+
+/// An error numeric sent by the server, keeping the server's own text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerError {
+    /// The three digit numeric, e.g. `433`.
+    pub code: String,
+    /// The parameters between our nickname and the text, e.g. the rejected nickname.
+    pub params: Vec<String>,
+    /// The human readable text from the server.
+    pub text: String,
+}
+
+impl ServerError {
+    /// Returns the error for numerics in the 400-599 range and the SASL failures, which are the
+    /// ones servers use for errors.
+    pub fn from_message(message: &Message) -> Option<Self> {
+        let code: u16 = message.command.parse().ok()?;
+        if !(400..600).contains(&code) && !(902..=908).contains(&code) {
+            return None;
+        }
+
+        // The first parameter is our own nickname and the last one is the text.
+        let (text, params) = match message.params.split_last() {
+            Some((text, params)) if !params.is_empty() => (text.clone(), params[1..].to_vec()),
+            Some((text, _)) => (text.clone(), Vec::new()),
+            None => (String::new(), Vec::new()),
+        };
+
+        Some(Self {
+            code: message.command.clone(),
+            params,
+            text,
+        })
+    }
+
+    pub fn kind(&self) -> ServerErrorKind {
+        match self.code.as_str() {
+            "431" => ServerErrorKind::NoNicknameGiven,
+            "432" => ServerErrorKind::ErroneousNickname,
+            "433" => ServerErrorKind::NicknameInUse,
+            "436" => ServerErrorKind::NickCollision,
+            "464" => ServerErrorKind::PasswordMismatch,
+            "902" | "904" | "905" | "906" => ServerErrorKind::SaslFailed,
+            _ => ServerErrorKind::Other,
+        }
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code)?;
+        for param in &self.params {
+            write!(f, " {param}")?;
+        }
+        write!(f, " :{}", self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+
+    fn server_error(line: &str) -> Option<ServerError> {
+        ServerError::from_message(&Parser::new(line).parse_message().unwrap())
+    }
+
+    #[test]
+    fn nickname_in_use_keeps_nickname_and_text() {
+        let error = server_error(":server 433 * alice :Nickname is already in use").unwrap();
+
+        assert_eq!(error.kind(), ServerErrorKind::NicknameInUse);
+        assert_eq!(error.params, vec!["alice"]);
+        assert_eq!(error.text, "Nickname is already in use");
+        assert!(matches!(Error::from(error), Error::Registration(_)));
+    }
+
+    #[test]
+    fn other_numerics_are_not_errors() {
+        assert_eq!(server_error(":server 001 nick :Welcome"), None);
+        assert_eq!(server_error(":server PRIVMSG nick :hi"), None);
+    }
+
+    #[test]
+    fn unknown_error_numeric_is_a_protocol_error() {
+        let error = server_error(":server 403 nick #nope :No such channel").unwrap();
+
+        assert_eq!(error.to_string(), "403 #nope :No such channel");
+        assert!(matches!(Error::from(error), Error::Protocol(_)));
+    }
+
+    #[test]
+    fn io_errors_are_transport_errors() {
+        let error = Error::from(io::Error::from(io::ErrorKind::BrokenPipe));
+
+        assert!(matches!(error, Error::Transport(_)));
+        assert!(std::error::Error::source(&error).is_some());
+    }
+}
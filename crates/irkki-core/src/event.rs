@@ -0,0 +1,950 @@
+use std::time::{Duration, SystemTime};
+
+use crate::{
+    Batch, BouncerNetwork, DccRequest, Message, ServerError, StandardReply, User, WhoisInfo,
+};
+
+/// Where a message came from, either a user (`nick!user@host`) or a server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Source {
+    /// The nickname of the user, or the name of the server.
+    pub nickname: String,
+    pub user: Option<String>,
+    pub host: Option<String>,
+}
+
+impl Source {
+    pub fn parse(prefix: &str) -> Self {
+        let (rest, host) = match prefix.split_once('@') {
+            Some((rest, host)) => (rest, Some(host.to_string())),
+            None => (prefix, None),
+        };
+        let (nickname, user) = match rest.split_once('!') {
+            Some((nickname, user)) => (nickname, Some(user.to_string())),
+            None => (rest, None),
+        };
+
+        Self {
+            nickname: nickname.to_string(),
+            user,
+            host,
+        }
+    }
+
+    fn from_prefix(prefix: &Option<String>) -> Option<Self> {
+        prefix.as_deref().map(Self::parse)
+    }
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.nickname)?;
+        if let Some(user) = &self.user {
+            write!(f, "!{user}")?;
+        }
+        if let Some(host) = &self.host {
+            write!(f, "@{host}")?;
+        }
+        Ok(())
+    }
+}
+
+/// What someone is doing in the input box, sent with the `+typing` client tag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TypingState {
+    /// Typing right now.
+    Active,
+    /// Stopped typing, with text left in the input box.
+    Paused,
+    /// Cleared the input box without sending.
+    Done,
+}
+
+impl TypingState {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "active" => Some(Self::Active),
+            "paused" => Some(Self::Paused),
+            "done" => Some(Self::Done),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Active => "active",
+            Self::Paused => "paused",
+            Self::Done => "done",
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub enum IRCEvent {
+    /// A message that has no dedicated event.
+    Message(Message),
+    /// The server has welcomed us, with the nickname we actually got.
+    Registered {
+        nickname: String,
+    },
+    /// The server refused the registration with an error that trying again cannot fix, for
+    /// example ERR_PASSWDMISMATCH, a failed SASL login or the last nickname to try being taken,
+    /// so the client quits. `Error::from` the error tells a
+    /// rejected nickname, [`crate::Error::Registration`], from a failed login,
+    /// [`crate::Error::Auth`].
+    RegistrationFailed(ServerError),
+    /// Services have logged us in to an account.
+    LoggedIn {
+        account: String,
+    },
+    Users(Vec<String>),
+    MessageOfTheDay(Vec<String>),
+    Raw(String),
+    PrivMsg {
+        source: Source,
+        target: String,
+        text: String,
+    },
+    Notice {
+        source: Option<Source>,
+        target: String,
+        text: String,
+    },
+    /// A CTCP ACTION, sent with `/me`.
+    Action {
+        source: Source,
+        target: String,
+        text: String,
+    },
+    /// A CTCP request other than ACTION, e.g. VERSION or PING.
+    Ctcp {
+        source: Source,
+        target: String,
+        command: String,
+        params: String,
+    },
+    /// A DCC request, offering a file or a chat or answering one, see [`crate::DccTransfers`].
+    Dcc {
+        source: Source,
+        target: String,
+        request: DccRequest,
+    },
+    CtcpReply {
+        source: Source,
+        target: String,
+        command: String,
+        params: String,
+    },
+    /// A user joined a channel. With extended-join the account the user is logged in to and
+    /// their real name are known as well.
+    Join {
+        source: Source,
+        channel: String,
+        account: Option<String>,
+        realname: Option<String>,
+    },
+    Part {
+        source: Source,
+        channel: String,
+        reason: Option<String>,
+    },
+    Quit {
+        source: Source,
+        reason: Option<String>,
+    },
+    Kick {
+        source: Source,
+        channel: String,
+        nickname: String,
+        reason: Option<String>,
+    },
+    Nick {
+        source: Source,
+        nickname: String,
+    },
+    /// A topic change, or the current topic (RPL_TOPIC) when there is no source.
+    Topic {
+        source: Option<Source>,
+        channel: String,
+        topic: String,
+    },
+    Mode {
+        source: Option<Source>,
+        target: String,
+        modes: String,
+        args: Vec<String>,
+    },
+    Invite {
+        source: Source,
+        nickname: String,
+        channel: String,
+    },
+    /// A user is away, or back when the message is `None`.
+    Away {
+        source: Source,
+        message: Option<String>,
+    },
+    /// A user logged in to an account, or out when it is `None`.
+    Account {
+        source: Source,
+        account: Option<String>,
+    },
+    /// A user's username or host changed, with chghost.
+    HostChange {
+        source: Source,
+        username: String,
+        host: String,
+    },
+    /// A user changed their real name, with setname.
+    RealnameChange {
+        source: Source,
+        realname: String,
+    },
+    /// A user is typing to `target`, or stopped, from a TAGMSG with the `+typing` tag.
+    Typing {
+        source: Source,
+        target: String,
+        state: TypingState,
+    },
+    /// A user reacted to the message in [`Event::reply_to`], e.g. with an emoji.
+    React {
+        source: Source,
+        target: String,
+        reaction: String,
+    },
+    /// A user on the notify list came online.
+    Online {
+        source: Source,
+    },
+    /// A user on the notify list went offline.
+    Offline {
+        nickname: String,
+    },
+    /// The server's MONITOR list is full, and these nicknames were not added to it.
+    MonitorListFull {
+        limit: usize,
+        nicknames: Vec<String>,
+    },
+    /// The users listed in reply to a WHO.
+    Who {
+        mask: String,
+        users: Vec<User>,
+    },
+    /// The collected replies to a WHOIS.
+    Whois(WhoisInfo),
+    /// Messages the server sent together in a batch.
+    Batch(Batch),
+    /// The link between two servers broke and the users behind `remote` quit.
+    Netsplit {
+        server: String,
+        remote: String,
+        users: Vec<Source>,
+    },
+    /// The servers are linked again and the users behind `remote` rejoined, listed with the
+    /// channel they joined.
+    Netjoin {
+        server: String,
+        remote: String,
+        joins: Vec<(Source, String)>,
+    },
+    /// An error numeric, e.g. ERR_NICKNAMEINUSE.
+    ServerError(ServerError),
+    /// A `FAIL`, `WARN` or `NOTE` about a command.
+    StandardReply(StandardReply),
+    /// The server asked over plaintext to reconnect over TLS on `port`, so the registration
+    /// stops here.
+    StsUpgrade {
+        port: u16,
+    },
+    /// The server's STS policy, seen over TLS: for `duration` the host must only be connected
+    /// to over TLS.
+    StsPolicy {
+        duration: Duration,
+    },
+    /// A network of a soju bouncer was added or changed, with the attributes it has now.
+    BouncerNetwork(BouncerNetwork),
+    /// The networks of a soju bouncer, listed after registering or in answer to
+    /// `BOUNCER LISTNETWORKS`.
+    BouncerNetworks(Vec<BouncerNetwork>),
+    /// A network was removed from a soju bouncer.
+    BouncerNetworkRemoved {
+        id: String,
+    },
+    /// The server is closing the connection.
+    Error {
+        message: String,
+    },
+}
+
+impl IRCEvent {
+    /// Turns a message into its typed event, or [`IRCEvent::Message`] when there is none.
+    pub fn from_message(message: Message) -> Self {
+        let source = Source::from_prefix(&message.prefix);
+        let params = &message.params;
+        let param = |i: usize| params.get(i).cloned();
+
+        let event = match (message.command.as_str(), source) {
+            ("PRIVMSG", Some(source)) if params.len() >= 2 => Some(Self::from_privmsg(
+                source,
+                param(0).unwrap_or_default(),
+                &params[1],
+            )),
+            ("NOTICE", source) if params.len() >= 2 => Some(Self::from_notice(
+                source,
+                param(0).unwrap_or_default(),
+                &params[1],
+            )),
+            // With extended-join: JOIN <channel> <account> :<realname>
+            ("JOIN", Some(source)) if !params.is_empty() => Some(Self::Join {
+                source,
+                channel: params[0].clone(),
+                account: param(1).filter(|account| account != "*"),
+                realname: param(2),
+            }),
+            ("PART", Some(source)) if !params.is_empty() => Some(Self::Part {
+                source,
+                channel: params[0].clone(),
+                reason: param(1),
+            }),
+            ("QUIT", Some(source)) => Some(Self::Quit {
+                source,
+                reason: param(0),
+            }),
+            ("KICK", Some(source)) if params.len() >= 2 => Some(Self::Kick {
+                source,
+                channel: params[0].clone(),
+                nickname: params[1].clone(),
+                reason: param(2),
+            }),
+            ("NICK", Some(source)) if !params.is_empty() => Some(Self::Nick {
+                source,
+                nickname: params[0].clone(),
+            }),
+            ("TOPIC", source) if params.len() >= 2 => Some(Self::Topic {
+                source,
+                channel: params[0].clone(),
+                topic: params[1].clone(),
+            }),
+            // RPL_TOPIC
+            ("332", _) if params.len() >= 3 => Some(Self::Topic {
+                source: None,
+                channel: params[1].clone(),
+                topic: params[2].clone(),
+            }),
+            ("MODE", source) if params.len() >= 2 => Some(Self::Mode {
+                source,
+                target: params[0].clone(),
+                modes: params[1].clone(),
+                args: params[2..].to_vec(),
+            }),
+            ("INVITE", Some(source)) if params.len() >= 2 => Some(Self::Invite {
+                source,
+                nickname: params[0].clone(),
+                channel: params[1].clone(),
+            }),
+            ("AWAY", Some(source)) => Some(Self::Away {
+                source,
+                message: param(0).filter(|message| !message.is_empty()),
+            }),
+            ("ACCOUNT", Some(source)) if !params.is_empty() => Some(Self::Account {
+                source,
+                account: param(0).filter(|account| account != "*"),
+            }),
+            ("CHGHOST", Some(source)) if params.len() >= 2 => Some(Self::HostChange {
+                source,
+                username: params[0].clone(),
+                host: params[1].clone(),
+            }),
+            ("SETNAME", Some(source)) if !params.is_empty() => Some(Self::RealnameChange {
+                source,
+                realname: params[0].clone(),
+            }),
+            // RPL_AWAY
+            ("301", _) if params.len() >= 3 => Some(Self::Away {
+                source: Source::parse(&params[1]),
+                message: Some(params[2].clone()),
+            }),
+            ("TAGMSG", Some(source)) if !params.is_empty() => {
+                Self::from_tagmsg(source, params[0].clone(), &message)
+            }
+            ("FAIL" | "WARN" | "NOTE", _) => {
+                StandardReply::from_message(&message).map(Self::StandardReply)
+            }
+            ("ERROR", _) => Some(Self::Error {
+                message: param(0).unwrap_or_default(),
+            }),
+            _ => None,
+        };
+
+        event.unwrap_or(Self::Message(message))
+    }
+
+    fn from_privmsg(source: Source, target: String, text: &str) -> Self {
+        match Self::parse_ctcp(text) {
+            Some(("ACTION", text)) => Self::Action {
+                source,
+                target,
+                text: text.to_string(),
+            },
+            Some(("DCC", params)) if let Some(request) = DccRequest::parse(params) => Self::Dcc {
+                source,
+                target,
+                request,
+            },
+            Some((command, params)) => Self::Ctcp {
+                source,
+                target,
+                command: command.to_string(),
+                params: params.to_string(),
+            },
+            None => Self::PrivMsg {
+                source,
+                target,
+                text: text.to_string(),
+            },
+        }
+    }
+
+    fn from_tagmsg(source: Source, target: String, message: &Message) -> Option<Self> {
+        if let Some(state) = message.tag("+typing").and_then(TypingState::parse) {
+            return Some(Self::Typing {
+                source,
+                target,
+                state,
+            });
+        }
+
+        message.tag("+draft/react").map(|reaction| Self::React {
+            source,
+            target,
+            reaction: reaction.to_string(),
+        })
+    }
+
+    fn from_notice(source: Option<Source>, target: String, text: &str) -> Self {
+        match (Self::parse_ctcp(text), source) {
+            (Some((command, params)), Some(source)) => Self::CtcpReply {
+                source,
+                target,
+                command: command.to_string(),
+                params: params.to_string(),
+            },
+            (_, source) => Self::Notice {
+                source,
+                target,
+                text: text.to_string(),
+            },
+        }
+    }
+
+    /// Splits `\x01COMMAND params\x01` into the command and its params. The closing `\x01` is
+    /// optional.
+    fn parse_ctcp(text: &str) -> Option<(&str, &str)> {
+        let body = text.strip_prefix('\x01')?;
+        let body = body.strip_suffix('\x01').unwrap_or(body);
+
+        Some(body.split_once(' ').unwrap_or((body, "")))
+    }
+}
+
+impl std::fmt::Debug for IRCEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IRCEvent::Message(msg) => write!(f, "IRCEvent::Message(cmd: {})", msg.command),
+            IRCEvent::Raw(s) => write!(f, "IRCEvent::Raw({})", s),
+            IRCEvent::Registered { nickname } => write!(f, "IRCEvent::Registered({nickname})"),
+            IRCEvent::RegistrationFailed(error) => {
+                write!(f, "IRCEvent::RegistrationFailed({error})")
+            }
+            IRCEvent::LoggedIn { account } => write!(f, "IRCEvent::LoggedIn({account})"),
+            IRCEvent::Users(users) => write!(f, "IRCEvent::Users({})", users.join(", ")),
+            IRCEvent::MessageOfTheDay(motd) => {
+                write!(f, "IRCEvent::MessageOfTheDay({})", motd.join("\n"))
+            }
+            IRCEvent::PrivMsg {
+                source,
+                target,
+                text,
+            } => write!(f, "IRCEvent::PrivMsg({source} -> {target}: {text})"),
+            IRCEvent::Notice {
+                source,
+                target,
+                text,
+            } => match source {
+                Some(source) => write!(f, "IRCEvent::Notice({source} -> {target}: {text})"),
+                None => write!(f, "IRCEvent::Notice({target}: {text})"),
+            },
+            IRCEvent::Action {
+                source,
+                target,
+                text,
+            } => write!(f, "IRCEvent::Action({source} -> {target}: {text})"),
+            IRCEvent::Ctcp {
+                source,
+                target,
+                command,
+                params,
+            } => write!(
+                f,
+                "IRCEvent::Ctcp({source} -> {target}: {command} {params})"
+            ),
+            IRCEvent::Dcc {
+                source,
+                target,
+                request,
+            } => write!(f, "IRCEvent::Dcc({source} -> {target}: {request})"),
+            IRCEvent::CtcpReply {
+                source,
+                target,
+                command,
+                params,
+            } => write!(
+                f,
+                "IRCEvent::CtcpReply({source} -> {target}: {command} {params})"
+            ),
+            IRCEvent::Join {
+                source, channel, ..
+            } => write!(f, "IRCEvent::Join({source} {channel})"),
+            IRCEvent::Part {
+                source, channel, ..
+            } => write!(f, "IRCEvent::Part({source} {channel})"),
+            IRCEvent::Quit { source, .. } => write!(f, "IRCEvent::Quit({source})"),
+            IRCEvent::Kick {
+                channel, nickname, ..
+            } => write!(f, "IRCEvent::Kick({nickname} from {channel})"),
+            IRCEvent::Nick { source, nickname } => {
+                write!(f, "IRCEvent::Nick({} -> {nickname})", source.nickname)
+            }
+            IRCEvent::Topic { channel, topic, .. } => {
+                write!(f, "IRCEvent::Topic({channel}: {topic})")
+            }
+            IRCEvent::Mode {
+                target,
+                modes,
+                args,
+                ..
+            } => write!(f, "IRCEvent::Mode({target} {modes} {})", args.join(" ")),
+            IRCEvent::Invite {
+                nickname, channel, ..
+            } => write!(f, "IRCEvent::Invite({nickname} to {channel})"),
+            IRCEvent::Away { source, message } => match message {
+                Some(message) => write!(f, "IRCEvent::Away({}: {message})", source.nickname),
+                None => write!(f, "IRCEvent::Away({} is back)", source.nickname),
+            },
+            IRCEvent::Account { source, account } => match account {
+                Some(account) => write!(f, "IRCEvent::Account({}: {account})", source.nickname),
+                None => write!(f, "IRCEvent::Account({} logged out)", source.nickname),
+            },
+            IRCEvent::HostChange {
+                source,
+                username,
+                host,
+            } => write!(
+                f,
+                "IRCEvent::HostChange({} to {username}@{host})",
+                source.nickname
+            ),
+            IRCEvent::RealnameChange { source, realname } => {
+                write!(
+                    f,
+                    "IRCEvent::RealnameChange({}: {realname})",
+                    source.nickname
+                )
+            }
+            IRCEvent::Typing {
+                source,
+                target,
+                state,
+            } => write!(
+                f,
+                "IRCEvent::Typing({} -> {target}: {})",
+                source.nickname,
+                state.as_str()
+            ),
+            IRCEvent::React {
+                source,
+                target,
+                reaction,
+            } => write!(
+                f,
+                "IRCEvent::React({} -> {target}: {reaction})",
+                source.nickname
+            ),
+            IRCEvent::Online { source } => write!(f, "IRCEvent::Online({source})"),
+            IRCEvent::Offline { nickname } => write!(f, "IRCEvent::Offline({nickname})"),
+            IRCEvent::MonitorListFull { limit, nicknames } => write!(
+                f,
+                "IRCEvent::MonitorListFull({limit}: {})",
+                nicknames.join(", ")
+            ),
+            IRCEvent::Who { mask, users } => {
+                write!(f, "IRCEvent::Who({mask}: {} users)", users.len())
+            }
+            IRCEvent::Whois(info) => write!(f, "IRCEvent::Whois({})", info.nickname),
+            IRCEvent::Batch(batch) => write!(
+                f,
+                "IRCEvent::Batch({}: {} events)",
+                batch.kind,
+                batch.events.len()
+            ),
+            IRCEvent::Netsplit {
+                server,
+                remote,
+                users,
+            } => write!(
+                f,
+                "IRCEvent::Netsplit({server} {remote}: {} users)",
+                users.len()
+            ),
+            IRCEvent::Netjoin {
+                server,
+                remote,
+                joins,
+            } => write!(
+                f,
+                "IRCEvent::Netjoin({server} {remote}: {} joins)",
+                joins.len()
+            ),
+            IRCEvent::ServerError(error) => write!(f, "IRCEvent::ServerError({error})"),
+            IRCEvent::StandardReply(reply) => write!(f, "IRCEvent::StandardReply({reply})"),
+            IRCEvent::StsUpgrade { port } => write!(f, "IRCEvent::StsUpgrade({port})"),
+            IRCEvent::StsPolicy { duration } => {
+                write!(f, "IRCEvent::StsPolicy({}s)", duration.as_secs())
+            }
+            IRCEvent::BouncerNetwork(network) => {
+                write!(
+                    f,
+                    "IRCEvent::BouncerNetwork({}: {})",
+                    network.id,
+                    network.name()
+                )
+            }
+            IRCEvent::BouncerNetworks(networks) => {
+                write!(f, "IRCEvent::BouncerNetworks({} networks)", networks.len())
+            }
+            IRCEvent::BouncerNetworkRemoved { id } => {
+                write!(f, "IRCEvent::BouncerNetworkRemoved({id})")
+            }
+            IRCEvent::Error { message } => write!(f, "IRCEvent::Error({message})"),
+        }
+    }
+}
+
+/// An event with when it happened, the id of the message it came from, the message it replies
+/// to and the label of the command it answers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    /// The time from the `server-time` tag, or when the line was received if the server did not
+    /// send one. Messages replayed by a bouncer keep their original time this way.
+    pub time: SystemTime,
+    /// The `msgid` tag, which identifies the message on the server.
+    pub msgid: Option<String>,
+    /// The msgid of the message this one replies to, from the `+draft/reply` client tag.
+    pub reply_to: Option<String>,
+    /// The `label` of the command this event replies to, see [`crate::Protocol::send_message`].
+    pub label: Option<String>,
+    /// The account the sender is logged in to, from the `account` tag, or else as far as the
+    /// channels we share with them tell.
+    pub account: Option<String>,
+    pub kind: IRCEvent,
+}
+
+impl Event {
+    /// An event that happens now and came without tags, for example one a plugin or a test
+    /// makes up.
+    pub fn new(kind: IRCEvent) -> Self {
+        Self {
+            time: SystemTime::now(),
+            msgid: None,
+            reply_to: None,
+            label: None,
+            account: None,
+            kind,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+
+    fn event(line: &str) -> IRCEvent {
+        IRCEvent::from_message(Parser::new(line).parse_message().unwrap())
+    }
+
+    fn alice() -> Source {
+        Source::parse("alice!al@example.com")
+    }
+
+    #[test]
+    fn irc_event_debug_formats_message_variant() {
+        let event = IRCEvent::Message(Message {
+            tags: Default::default(),
+            prefix: None,
+            command: "NOTICE".to_string(),
+            params: vec!["#test".to_string(), "hello".to_string()],
+        });
+
+        assert_eq!(format!("{event:?}"), "IRCEvent::Message(cmd: NOTICE)");
+    }
+
+    #[test]
+    fn irc_event_debug_formats_users_variant() {
+        let event = IRCEvent::Users(vec!["alice".to_string(), "bob".to_string()]);
+
+        assert_eq!(format!("{event:?}"), "IRCEvent::Users(alice, bob)");
+    }
+
+    #[test]
+    fn irc_event_debug_formats_raw_variant() {
+        let event = IRCEvent::Raw("Connected".to_string());
+
+        assert_eq!(format!("{event:?}"), "IRCEvent::Raw(Connected)");
+    }
+
+    #[test]
+    fn source_is_split_into_nickname_user_and_host() {
+        assert_eq!(
+            alice(),
+            Source {
+                nickname: "alice".to_string(),
+                user: Some("al".to_string()),
+                host: Some("example.com".to_string()),
+            }
+        );
+        assert_eq!(Source::parse("irc.example.com").user, None);
+        assert_eq!(alice().to_string(), "alice!al@example.com");
+    }
+
+    #[test]
+    fn privmsg_action_and_ctcp() {
+        assert_eq!(
+            event(":alice!al@example.com PRIVMSG #chan :hello there"),
+            IRCEvent::PrivMsg {
+                source: alice(),
+                target: "#chan".to_string(),
+                text: "hello there".to_string(),
+            }
+        );
+        assert_eq!(
+            event(":alice!al@example.com PRIVMSG #chan :\x01ACTION waves\x01"),
+            IRCEvent::Action {
+                source: alice(),
+                target: "#chan".to_string(),
+                text: "waves".to_string(),
+            }
+        );
+        assert_eq!(
+            event(":alice!al@example.com PRIVMSG bob :\x01VERSION\x01"),
+            IRCEvent::Ctcp {
+                source: alice(),
+                target: "bob".to_string(),
+                command: "VERSION".to_string(),
+                params: String::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn dcc_ctcp_becomes_a_dcc_request() {
+        assert_eq!(
+            event(":alice!al@example.com PRIVMSG bob :\x01DCC CHAT chat 2130706433 5000\x01"),
+            IRCEvent::Dcc {
+                source: alice(),
+                target: "bob".to_string(),
+                request: DccRequest::Chat {
+                    address: std::net::Ipv4Addr::LOCALHOST.into(),
+                    port: 5000,
+                },
+            }
+        );
+        assert!(matches!(
+            event(":alice!al@example.com PRIVMSG bob :\x01DCC VOICE x\x01"),
+            IRCEvent::Ctcp { command, .. } if command == "DCC"
+        ));
+    }
+
+    #[test]
+    fn notice_without_prefix_has_no_source() {
+        assert_eq!(
+            event("NOTICE * :*** Checking Ident"),
+            IRCEvent::Notice {
+                source: None,
+                target: "*".to_string(),
+                text: "*** Checking Ident".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn channel_membership_events() {
+        assert_eq!(
+            event(":alice!al@example.com JOIN :#chan"),
+            IRCEvent::Join {
+                source: alice(),
+                channel: "#chan".to_string(),
+                account: None,
+                realname: None,
+            }
+        );
+        assert_eq!(
+            event(":alice!al@example.com PART #chan"),
+            IRCEvent::Part {
+                source: alice(),
+                channel: "#chan".to_string(),
+                reason: None,
+            }
+        );
+        assert_eq!(
+            event(":alice!al@example.com KICK #chan bob :behave"),
+            IRCEvent::Kick {
+                source: alice(),
+                channel: "#chan".to_string(),
+                nickname: "bob".to_string(),
+                reason: Some("behave".to_string()),
+            }
+        );
+        assert_eq!(
+            event(":alice!al@example.com QUIT :Client closed"),
+            IRCEvent::Quit {
+                source: alice(),
+                reason: Some("Client closed".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn topic_from_command_and_numeric() {
+        assert_eq!(
+            event(":server 332 nick #chan :Welcome!"),
+            IRCEvent::Topic {
+                source: None,
+                channel: "#chan".to_string(),
+                topic: "Welcome!".to_string(),
+            }
+        );
+        assert!(matches!(
+            event(":alice!al@example.com TOPIC #chan :New topic"),
+            IRCEvent::Topic {
+                source: Some(_),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn mode_nick_invite_away_and_error() {
+        assert_eq!(
+            event(":SomeOp MODE #channel +oo SomeUser :AnotherUser"),
+            IRCEvent::Mode {
+                source: Some(Source::parse("SomeOp")),
+                target: "#channel".to_string(),
+                modes: "+oo".to_string(),
+                args: vec!["SomeUser".to_string(), "AnotherUser".to_string()],
+            }
+        );
+        assert_eq!(
+            event(":alice!al@example.com NICK alicia"),
+            IRCEvent::Nick {
+                source: alice(),
+                nickname: "alicia".to_string(),
+            }
+        );
+        assert_eq!(
+            event(":alice!al@example.com INVITE bob #chan"),
+            IRCEvent::Invite {
+                source: alice(),
+                nickname: "bob".to_string(),
+                channel: "#chan".to_string(),
+            }
+        );
+        assert_eq!(
+            event(":alice!al@example.com AWAY"),
+            IRCEvent::Away {
+                source: alice(),
+                message: None,
+            }
+        );
+        assert_eq!(
+            event("ERROR :Closing Link"),
+            IRCEvent::Error {
+                message: "Closing Link".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn presence_events() {
+        assert_eq!(
+            event(":alice!al@example.com JOIN #chan * :Alice Liddell"),
+            IRCEvent::Join {
+                source: alice(),
+                channel: "#chan".to_string(),
+                account: None,
+                realname: Some("Alice Liddell".to_string()),
+            }
+        );
+        assert_eq!(
+            event(":alice!al@example.com ACCOUNT alice_acct"),
+            IRCEvent::Account {
+                source: alice(),
+                account: Some("alice_acct".to_string()),
+            }
+        );
+        assert_eq!(
+            event(":alice!al@example.com ACCOUNT *"),
+            IRCEvent::Account {
+                source: alice(),
+                account: None,
+            }
+        );
+        assert_eq!(
+            event(":alice!al@example.com CHGHOST alice new.host"),
+            IRCEvent::HostChange {
+                source: alice(),
+                username: "alice".to_string(),
+                host: "new.host".to_string(),
+            }
+        );
+        assert_eq!(
+            event(":alice!al@example.com SETNAME :Alice L."),
+            IRCEvent::RealnameChange {
+                source: alice(),
+                realname: "Alice L.".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn tagmsg_with_client_tags() {
+        assert_eq!(
+            event("@+typing=active :alice!al@example.com TAGMSG #chan"),
+            IRCEvent::Typing {
+                source: alice(),
+                target: "#chan".to_string(),
+                state: TypingState::Active,
+            }
+        );
+        assert_eq!(
+            event("@+draft/reply=abc;+draft/react=👍 :alice!al@example.com TAGMSG #chan"),
+            IRCEvent::React {
+                source: alice(),
+                target: "#chan".to_string(),
+                reaction: "👍".to_string(),
+            }
+        );
+        assert!(matches!(
+            event("@+example=1 :alice!al@example.com TAGMSG #chan"),
+            IRCEvent::Message(_)
+        ));
+    }
+
+    #[test]
+    fn unknown_command_stays_a_message() {
+        assert!(matches!(
+            event(":server 001 nick :welcome"),
+            IRCEvent::Message(m) if m.command == "001"
+        ));
+    }
+}
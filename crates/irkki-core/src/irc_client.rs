@@ -1,39 +1,130 @@
 use log::{debug, error, info};
-use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
 use std::net::TcpStream;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+#[cfg(feature = "tls")]
+use crate::TlsStream;
+use crate::{
+    BouncerNetwork, Capabilities, Channels, ConnectionOptions, DccRequest, Error, Event,
+    FloodControl, HistoryRequest, HistoryTarget, IRCEvent, ISON_INTERVAL, NotifyList, Plugin,
+    PluginContext, Plugins, Priority, Protocol, Pushed, Response, Security, SendQueue, StsStore,
+    Transport, TypingState, User, WhoisInfo,
+};
+#[cfg(feature = "tls")]
+use rustls::ClientConfig;
+
+/// Outgoing lines waiting for the writer thread, which drains them at the pace allowed by the
+/// flood control settings.
+struct Outgoing {
+    state: Mutex<OutgoingState>,
+    ready: Condvar,
+    /// The plugins every line passes before it is queued.
+    plugins: Arc<Mutex<Plugins>>,
+}
 
-use crate::{Message, Parser};
-
-#[derive(PartialEq)]
-pub enum IRCEvent {
-    Message(Message),
-    Users(Vec<String>),
-    MessageOfTheDay(Vec<String>),
-    Raw(String),
+struct OutgoingState {
+    queue: SendQueue,
+    closed: bool,
+    /// The writer of a new connection, which the writer thread switches to before it writes
+    /// the next line.
+    writer: Option<BoxedWriter>,
 }
 
-impl std::fmt::Debug for IRCEvent {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            IRCEvent::Message(msg) => write!(f, "IRCEvent::Message(cmd: {})", msg.command),
-            IRCEvent::Raw(s) => write!(f, "IRCEvent::Raw({})", s),
-            IRCEvent::Users(users) => write!(f, "IRCEvent::Users({})", users.join(", ")),
-            IRCEvent::MessageOfTheDay(motd) => {
-                write!(f, "IRCEvent::MessageOfTheDay({})", motd.join("\n"))
+impl Outgoing {
+    fn new(flood_control: FloodControl, plugins: Arc<Mutex<Plugins>>) -> Self {
+        Self {
+            state: Mutex::new(OutgoingState {
+                queue: SendQueue::new(flood_control),
+                closed: false,
+                writer: None,
+            }),
+            ready: Condvar::new(),
+            plugins,
+        }
+    }
+
+    fn lock(&self) -> io::Result<MutexGuard<'_, OutgoingState>> {
+        self.state
+            .lock()
+            .map_err(|_| io::Error::other("Send queue lock poisoned"))
+    }
+
+    fn push(&self, line: &str, priority: Priority) -> Result<(), Error> {
+        let mut state = self.lock()?;
+        if state.closed {
+            return Err(Error::NotConnected);
+        }
+
+        let pushed = state.queue.push(line, priority);
+        // The ISON poller waits on the same condition variable as the writer thread.
+        self.ready.notify_all();
+
+        match pushed {
+            Pushed::Rejected => Err(Error::SendQueueFull),
+            Pushed::Queued | Pushed::DroppedOldest(_) => Ok(()),
+        }
+    }
+
+    /// Moves the lines the protocol wants to send into the send queue, through the plugins.
+    fn push_from(&self, protocol: &mut Protocol) -> Result<(), Error> {
+        let mut plugins = self
+            .plugins
+            .lock()
+            .map_err(|_| io::Error::other("Plugin lock poisoned"))?;
+        let mut result = Ok(());
+        while let Some((line, priority)) = protocol.poll_outgoing() {
+            let Some(line) = plugins.on_outgoing(line) else {
+                continue;
+            };
+            if let Err(error) = self.push(&line, priority) {
+                result = Err(error);
             }
         }
+        result
+    }
+
+    /// Switches to the writer of a new connection. The lines queued for the old one are dropped.
+    #[cfg(feature = "tls")]
+    fn replace_writer(&self, writer: BoxedWriter) -> Result<(), Error> {
+        let mut state = self.lock()?;
+        state.queue = SendQueue::new(state.queue.config().clone());
+        state.writer = Some(writer);
+        Ok(())
+    }
+
+    fn close(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.closed = true;
+        }
+        self.ready.notify_all();
     }
 }
 
+type BoxedReader = Box<dyn Read + Send>;
+type BoxedWriter = Box<dyn Write + Send>;
+
 pub struct IRCClient {
-    nickname: String,
-    server: String,
-    port: u16,
-    channel: String,
-    reader: Option<BufReader<TcpStream>>,
-    writer: Option<Arc<Mutex<BufWriter<TcpStream>>>>,
+    options: ConnectionOptions,
+    /// The host and port we connected to, which STS policies are kept for.
+    server: Option<(String, u16)>,
+    #[cfg(feature = "tls")]
+    tls_config: Option<Arc<ClientConfig>>,
+    flood_control: FloodControl,
+    protocol: Arc<Mutex<Protocol>>,
+    reader: Option<BufReader<BoxedReader>>,
+    outgoing: Option<Arc<Outgoing>>,
+    plugins: Arc<Mutex<Plugins>>,
+}
+
+/// What the listener needs to follow the server's STS policy.
+struct Sts {
+    options: ConnectionOptions,
+    server: Option<(String, u16)>,
+    #[cfg(feature = "tls")]
+    tls_config: Option<Arc<ClientConfig>>,
 }
 
 impl IRCClient {
@@ -41,166 +132,448 @@ impl IRCClient {
         nickname: impl Into<String>,
         server: impl Into<String>,
         port: u16,
-    ) -> io::Result<Self> {
-        let mut client = Self::new(nickname, server, port);
-        client.initialize_connection()?;
-        Ok(client)
+    ) -> Result<Self, Error> {
+        Self::connect_with_options(ConnectionOptions::new(nickname), server, port)
     }
 
-    fn new(nickname: impl Into<String>, server: impl Into<String>, port: u16) -> Self {
-        Self {
-            nickname: nickname.into(),
-            server: server.into(),
-            port,
-            channel: "#testchannel".to_string(),
-            reader: None,
-            writer: None,
+    /// Connects without TLS. Fails with [`Error::TlsRequired`] while the host has a valid policy
+    /// in the STS store of the options, and with the `tls` feature the connection is upgraded
+    /// when the server asks for it.
+    pub fn connect_with_options(
+        options: ConnectionOptions,
+        server: impl Into<String>,
+        port: u16,
+    ) -> Result<Self, Error> {
+        let server = server.into();
+        if let Some(path) = &options.sts_policies
+            && let Some(port) = StsStore::open(path)?.port(&server)
+        {
+            error!(
+                "Refusing to connect without TLS: {} has an STS policy.",
+                server
+            );
+            return Err(Error::TlsRequired { port });
         }
+
+        let stream = TcpStream::connect((server.as_str(), port))?;
+        let mut client = Self::new(options);
+        client.server = Some((server, port));
+        client.initialize_connection(stream)?;
+        Ok(client)
     }
 
-    fn initialize_connection(&mut self) -> io::Result<()> {
-        let stream = TcpStream::connect((self.server.as_str(), self.port))?;
-        let reader = BufReader::new(stream.try_clone()?);
-        let writer = Arc::new(Mutex::new(BufWriter::new(stream)));
+    #[cfg(feature = "tls")]
+    pub fn connect_tls(
+        nickname: impl Into<String>,
+        server: impl Into<String>,
+        port: u16,
+    ) -> Result<Self, Error> {
+        Self::connect_tls_with_options(ConnectionOptions::new(nickname), server, port)
+    }
 
-        self.reader = Some(reader);
-        self.writer = Some(writer);
+    #[cfg(feature = "tls")]
+    pub fn connect_tls_with_options(
+        options: ConnectionOptions,
+        server: impl Into<String>,
+        port: u16,
+    ) -> Result<Self, Error> {
+        let server = server.into();
+        let stream = TlsStream::connect(&server, port)?;
+        let mut client = Self::new(options);
+        client.server = Some((server, port));
+        client.initialize_connection(stream)?;
+        Ok(client)
+    }
 
-        self.send_line(&format!("NICK {}", self.nickname))?;
-        self.send_line(&format!("USER {} 0 * :{}", self.nickname, self.nickname))?;
-        self.send_line(&format!("JOIN {}", self.channel))?;
+    /// Sets the TLS settings used when the server's STS policy upgrades the connection. The
+    /// default is [`TlsStream::default_config`].
+    #[cfg(feature = "tls")]
+    pub fn set_tls_config(&mut self, config: Arc<ClientConfig>) {
+        self.tls_config = Some(config);
+    }
 
-        Ok(())
+    /// Registers with the server over any transport, for example a TLS stream, a Unix socket or
+    /// a `(reader, writer)` pair such as the ends of two [`crate::memory_pipe`]s.
+    pub fn with_transport(
+        nickname: impl Into<String>,
+        transport: impl Transport,
+    ) -> Result<Self, Error> {
+        Self::with_options(ConnectionOptions::new(nickname), transport)
     }
 
-    /// The QUIT command is used to terminate a client’s connection to the server. The server
-    /// acknowledges this by replying with an ERROR message and closing the connection to the
-    /// client.
-    fn quit(&mut self) -> io::Result<()> {
-        if self.writer.is_none() {
-            return Ok(());
-        }
+    /// Registers with the server over any transport, using the given nickname fallbacks, SASL
+    /// credentials and channels.
+    pub fn with_options(
+        options: ConnectionOptions,
+        transport: impl Transport,
+    ) -> Result<Self, Error> {
+        let mut client = Self::new(options);
+        client.initialize_connection(transport)?;
+        Ok(client)
+    }
 
-        self.send_line(&format!("PART {} :Goodbye!", self.channel))?;
-        self.send_line("QUIT :Client closed")?;
+    fn new(options: ConnectionOptions) -> Self {
+        Self {
+            options: options.clone(),
+            server: None,
+            #[cfg(feature = "tls")]
+            tls_config: None,
+            flood_control: FloodControl::default(),
+            protocol: Arc::new(Mutex::new(Protocol::with_options(options))),
+            reader: None,
+            outgoing: None,
+            plugins: Arc::default(),
+        }
+    }
 
-        Ok(())
+    fn initialize_connection(&mut self, transport: impl Transport) -> Result<(), Error> {
+        let security = if transport.is_secure() {
+            Security::Tls
+        } else if cfg!(feature = "tls") && self.server.is_some() {
+            Security::Upgradable
+        } else {
+            Security::Plaintext
+        };
+        let (reader, writer) = transport.split()?;
+        let reader: BoxedReader = Box::new(reader);
+        let writer: BoxedWriter = Box::new(writer);
+        let outgoing = Arc::new(Outgoing::new(
+            self.flood_control.clone(),
+            Arc::clone(&self.plugins),
+        ));
+
+        let writer_outgoing = Arc::clone(&outgoing);
+        thread::spawn(move || Self::write_loop(BufWriter::new(writer), writer_outgoing));
+        let poll_outgoing = Arc::clone(&outgoing);
+        let protocol = Arc::clone(&self.protocol);
+        thread::spawn(move || Self::notify_loop(&protocol, &poll_outgoing));
+
+        self.reader = Some(BufReader::new(reader));
+        self.outgoing = Some(outgoing);
+
+        self.with_protocol(|protocol| {
+            protocol.set_security(security);
+            protocol.register();
+            Ok(())
+        })
     }
 
-    /// This command is used to query information about a particular user. The server SHOULD
-    /// answer this command with numeric messages with information about the nick.
-    ///
-    /// The server SHOULD end its response (to a syntactically well-formed client message) with
-    /// RPL_ENDOFWHOIS, even if it did not send any other numeric message. This allows clients to
-    /// stop waiting for new numerics. In exceptional error conditions, servers MAY not reply to a
-    /// WHOIS command. Clients SHOULD implement a hard timeout to avoid waiting for a reply which
-    /// won’t come.
-    ///
-    /// Clients MUST NOT assume all numeric messages are sent at once, as servers can interleave
-    /// other messages before the end of the WHOIS response.
-    fn whois(&mut self, nickname: impl AsRef<str>) -> io::Result<()> {
-        let nickname = nickname.as_ref().trim();
-        if nickname.is_empty() {
+    pub fn quit(&mut self) -> Result<(), Error> {
+        if self.outgoing.is_none() {
             return Ok(());
         }
 
-        self.send_line(&format!("WHOIS {}", nickname))
-    }
-
-    /// The NICK command is used to give the client a nickname or change the previous one.
-    ///
-    /// If the server receives a NICK command from a client where the desired nickname is
-    /// already in use on the network, it should issue an ERR_NICKNAMEINUSE numeric and ignore
-    /// the NICK command.
-    ///
-    /// If the server does not accept the new nickname supplied by the client as valid
-    /// (for instance, due to containing invalid characters), it should issue an ERR_ERRONEUSNICKNAME
-    /// numeric and ignore the NICK command. Servers MUST allow at least all alphanumerical
-    /// characters, square and curly brackets ([]{}), backslashes (\), and pipe (|) characters in
-    /// nicknames, and MAY disallow digits as the first character. Servers MAY allow extra
-    /// characters, as long as they do not introduce ambiguity in other commands.
-    fn change_nickname(&mut self, new_nickname: impl AsRef<str>) -> io::Result<()> {
-        let new_nickname = new_nickname.as_ref().trim();
-        if new_nickname.is_empty() {
-            return Ok(());
+        self.with_protocol(|protocol| {
+            protocol.quit();
+            Ok(())
+        })
+    }
+
+    /// Changes the burst and rate used to pace outgoing lines. Takes effect immediately, also
+    /// for lines that are already queued.
+    pub fn set_flood_control(&mut self, flood_control: FloodControl) {
+        if let Some(outgoing) = &self.outgoing
+            && let Ok(mut state) = outgoing.state.lock()
+        {
+            state.queue.set_config(flood_control.clone());
+            outgoing.ready.notify_one();
         }
 
-        self.send_line(&format!("NICK {}", new_nickname))?;
-        self.nickname = new_nickname.to_string();
-        Ok(())
+        self.flood_control = flood_control;
+    }
+
+    /// Number of lines that are waiting in the send queue.
+    pub fn queued_lines(&self) -> usize {
+        self.outgoing
+            .as_ref()
+            .and_then(|outgoing| outgoing.state.lock().ok())
+            .map_or(0, |state| state.queue.len())
     }
 
-    fn send_private_message(
+    /// Handles a line typed by the user, see [`Protocol::send_message`]. The returned response
+    /// completes with the server's reply, which the listener reads.
+    pub fn send_message(
         &mut self,
-        target: impl AsRef<str>,
         message: impl AsRef<str>,
-    ) -> io::Result<()> {
-        let target = target.as_ref().trim();
-        let message = message.as_ref().trim();
-        if target.is_empty() || message.is_empty() {
-            return Ok(());
-        }
+    ) -> Result<Response<Vec<Event>>, Error> {
+        self.with_protocol(|protocol| protocol.send_message(message))
+    }
 
-        self.send_line(&format!("PRIVMSG {} :{}", target, message))
+    /// Replies to the message `msgid`, see [`Protocol::send_reply`].
+    pub fn send_reply(
+        &mut self,
+        target: impl AsRef<str>,
+        msgid: impl AsRef<str>,
+        text: impl AsRef<str>,
+    ) -> Result<Response<Vec<Event>>, Error> {
+        self.with_protocol(|protocol| protocol.send_reply(target, msgid, text))
     }
 
-    pub fn send_message(&mut self, message: impl AsRef<str>) -> io::Result<()> {
-        let message = message.as_ref().trim();
-        if message.is_empty() {
-            return Ok(());
-        }
+    /// Reacts to the message `msgid`, see [`Protocol::send_reaction`].
+    pub fn send_reaction(
+        &mut self,
+        target: impl AsRef<str>,
+        msgid: impl AsRef<str>,
+        reaction: impl AsRef<str>,
+    ) -> Result<Response<Vec<Event>>, Error> {
+        self.with_protocol(|protocol| protocol.send_reaction(target, msgid, reaction))
+    }
 
-        if message.starts_with("/msg") {
-            let command = message.trim_start_matches("/msg").trim_start();
-            let mut parts = command.splitn(2, char::is_whitespace);
-            let target = parts.next().unwrap_or_default();
-            let msg = parts.next().unwrap_or_default();
+    /// Tells `target` whether we are typing, see [`Protocol::send_typing`].
+    pub fn send_typing(
+        &mut self,
+        target: impl AsRef<str>,
+        state: TypingState,
+    ) -> Result<(), Error> {
+        self.with_protocol(|protocol| protocol.send_typing(target, state))
+    }
 
-            self.send_private_message(target, msg)
-        } else if message.starts_with("/whois") {
-            let nickname = message.trim_start_matches("/whois").trim();
+    /// Sends a DCC request, see [`Protocol::send_dcc`].
+    pub fn send_dcc(&mut self, target: impl AsRef<str>, request: &DccRequest) -> Result<(), Error> {
+        self.with_protocol(|protocol| {
+            protocol.send_dcc(target, request);
+            Ok(())
+        })
+    }
 
-            self.whois(&nickname)
-        } else if message.starts_with("/nick") {
-            let new_nick = message.trim_start_matches("/nick").trim();
+    /// Asks the server about a user and waits for the replies, see [`Protocol::whois`]. The
+    /// replies are read by the listener, so [`IRCClient::start_listening`] must be running.
+    pub fn whois(
+        &mut self,
+        nickname: impl AsRef<str>,
+        timeout: Duration,
+    ) -> Result<WhoisInfo, Error> {
+        let response = self.with_protocol(|protocol| Ok(protocol.whois(nickname)))?;
+        response.wait_timeout(timeout)
+    }
 
-            self.change_nickname(&new_nick)
-        } else if message == "/quit" {
-            self.quit()
-        } else {
-            self.send_line(&format!("PRIVMSG {} :{}", self.channel, message))
+    /// Asks the server about the users matching a mask and waits for the replies, see
+    /// [`Protocol::who`]. Like [`IRCClient::whois`], this needs the listener to be running.
+    pub fn who(&mut self, mask: impl AsRef<str>, timeout: Duration) -> Result<Vec<User>, Error> {
+        let response = self.with_protocol(|protocol| Ok(protocol.who(mask)))?;
+        response.wait_timeout(timeout)
+    }
+
+    /// Requests message history and waits for it, see [`Protocol::chathistory`].
+    pub fn chathistory(
+        &mut self,
+        request: HistoryRequest,
+        limit: usize,
+        timeout: Duration,
+    ) -> Result<Vec<Event>, Error> {
+        let response = self.with_protocol(|protocol| Ok(protocol.chathistory(request, limit)))?;
+        response.wait_timeout(timeout)
+    }
+
+    /// Lists the conversations with history and waits for the list, see
+    /// [`Protocol::chathistory_targets`].
+    pub fn chathistory_targets(
+        &mut self,
+        start: SystemTime,
+        end: SystemTime,
+        limit: usize,
+        timeout: Duration,
+    ) -> Result<Vec<HistoryTarget>, Error> {
+        let response =
+            self.with_protocol(|protocol| Ok(protocol.chathistory_targets(start, end, limit)))?;
+        response.wait_timeout(timeout)
+    }
+
+    /// Lists the networks of a soju bouncer and waits for the list, see
+    /// [`Protocol::list_bouncer_networks`]. Like [`IRCClient::whois`], this needs the listener
+    /// to be running.
+    pub fn list_bouncer_networks(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Vec<BouncerNetwork>, Error> {
+        let response = self.with_protocol(|protocol| Ok(protocol.list_bouncer_networks()))?;
+        response.wait_timeout(timeout)
+    }
+
+    /// Adds a network to a soju bouncer and waits for its id, see
+    /// [`Protocol::add_bouncer_network`].
+    pub fn add_bouncer_network(
+        &mut self,
+        attributes: &[(&str, &str)],
+        timeout: Duration,
+    ) -> Result<String, Error> {
+        let response =
+            self.with_protocol(|protocol| Ok(protocol.add_bouncer_network(attributes)))?;
+        response.wait_timeout(timeout)
+    }
+
+    pub fn change_bouncer_network(
+        &mut self,
+        id: impl AsRef<str>,
+        attributes: &[(&str, &str)],
+    ) -> Result<(), Error> {
+        self.with_protocol(|protocol| protocol.change_bouncer_network(id, attributes))
+    }
+
+    pub fn delete_bouncer_network(&mut self, id: impl AsRef<str>) -> Result<(), Error> {
+        self.with_protocol(|protocol| protocol.delete_bouncer_network(id))
+    }
+
+    /// Asks ZNC's `*playback` for what `target` got after `since`, see [`Protocol::play_back`].
+    pub fn play_back(&mut self, target: impl AsRef<str>, since: SystemTime) -> Result<(), Error> {
+        self.with_protocol(|protocol| protocol.play_back(target, since))
+    }
+
+    /// Adds nicknames to the notify list, see [`Protocol::notify_add`].
+    pub fn notify_add<I, S>(&mut self, nicknames: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.with_protocol(|protocol| protocol.notify_add(nicknames))
+    }
+
+    pub fn notify_remove<I, S>(&mut self, nicknames: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.with_protocol(|protocol| {
+            protocol.notify_remove(nicknames);
+            Ok(())
+        })
+    }
+
+    /// Asks the server which nicknames of the notify list are online, see
+    /// [`Protocol::notify_status`].
+    pub fn notify_status(&mut self) -> Result<(), Error> {
+        self.with_protocol(|protocol| {
+            protocol.notify_status();
+            Ok(())
+        })
+    }
+
+    /// Lists the nicknames the server monitors for us and waits for the list, see
+    /// [`Protocol::monitor_list`].
+    pub fn monitor_list(&mut self, timeout: Duration) -> Result<Vec<String>, Error> {
+        let response = self.with_protocol(|protocol| Ok(protocol.monitor_list()))?;
+        response.wait_timeout(timeout)
+    }
+
+    /// A snapshot of the notify list and who on it is online.
+    pub fn notify_list(&self) -> NotifyList {
+        self.protocol
+            .lock()
+            .map(|protocol| protocol.notify_list().clone())
+            .unwrap_or_default()
+    }
+
+    /// A snapshot of the capabilities the server offers and has enabled.
+    pub fn capabilities(&self) -> Capabilities {
+        self.protocol
+            .lock()
+            .map(|protocol| protocol.capabilities().clone())
+            .unwrap_or_default()
+    }
+
+    /// A snapshot of the channels we are in and the users in them.
+    pub fn channels(&self) -> Channels {
+        self.protocol
+            .lock()
+            .map(|protocol| protocol.channels().clone())
+            .unwrap_or_default()
+    }
+
+    /// A snapshot of the networks of the soju bouncer.
+    pub fn bouncer_networks(&self) -> Vec<BouncerNetwork> {
+        self.protocol
+            .lock()
+            .map(|protocol| protocol.bouncer_networks().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// The server-time of the latest message, see [`Protocol::last_seen`].
+    pub fn last_seen(&self) -> Option<SystemTime> {
+        self.protocol
+            .lock()
+            .ok()
+            .and_then(|protocol| protocol.last_seen())
+    }
+
+    /// Adds a plugin, which sees the events and the outgoing lines from now on, after the plugins
+    /// that were added before it. See [`Plugin`] for the order.
+    pub fn add_plugin(&mut self, plugin: Box<dyn Plugin>) {
+        if let Ok(mut plugins) = self.plugins.lock() {
+            plugins.add(plugin);
+        }
+    }
+
+    /// Runs a command on the protocol state and queues the lines it produced for sending.
+    fn with_protocol<F, T>(&mut self, command: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Protocol) -> Result<T, Error>,
+    {
+        let mut protocol = self
+            .protocol
+            .lock()
+            .map_err(|_| io::Error::other("Protocol lock poisoned"))?;
+        let value = command(&mut protocol)?;
+
+        match &self.outgoing {
+            Some(outgoing) => outgoing.push_from(&mut protocol).map(|_| value),
+            None => {
+                if protocol.poll_outgoing().is_none() {
+                    return Ok(value);
+                }
+                while protocol.poll_outgoing().is_some() {}
+
+                error!("Cannot send line: Client is not connected.");
+                Err(Error::NotConnected)
+            }
         }
     }
 
-    pub fn start_listening<F>(&mut self, mut message_handler: F) -> io::Result<JoinHandle<()>>
+    pub fn start_listening<F>(&mut self, mut message_handler: F) -> Result<JoinHandle<()>, Error>
     where
-        F: FnMut(IRCEvent) -> io::Result<()> + Send + 'static,
+        F: FnMut(Event) -> io::Result<()> + Send + 'static,
     {
         let mut reader = self.reader.take().ok_or_else(|| {
             error!("Cannot start listening: Client is not connected.");
-            io::Error::new(io::ErrorKind::NotConnected, "Client is not connected.")
+            Error::NotConnected
         })?;
-        let writer = self.writer.clone().ok_or_else(|| {
+        let outgoing = self.outgoing.clone().ok_or_else(|| {
             error!("Cannot start listening: Client is not connected.");
-            io::Error::new(io::ErrorKind::NotConnected, "Client is not connected.")
+            Error::NotConnected
         })?;
+        let protocol = Arc::clone(&self.protocol);
+        let mut sts = Sts {
+            options: self.options.clone(),
+            server: self.server.clone(),
+            #[cfg(feature = "tls")]
+            tls_config: self.tls_config.clone(),
+        };
 
         Ok(thread::spawn(move || {
-            let _ = Self::listen_loop(&mut reader, writer, &mut message_handler);
+            if let Err(error) = Self::listen_loop(
+                &mut reader,
+                &protocol,
+                &outgoing,
+                &mut sts,
+                &mut message_handler,
+            ) {
+                error!("Stopped listening: {}", error);
+            }
+            outgoing.close();
         }))
     }
 
     fn listen_loop<F>(
-        reader: &mut BufReader<TcpStream>,
-        writer: Arc<Mutex<BufWriter<TcpStream>>>,
+        reader: &mut BufReader<BoxedReader>,
+        protocol: &Mutex<Protocol>,
+        outgoing: &Outgoing,
+        sts: &mut Sts,
         message_handler: &mut F,
-    ) -> io::Result<()>
+    ) -> Result<(), Error>
     where
-        F: FnMut(IRCEvent) -> io::Result<()>,
+        F: FnMut(Event) -> io::Result<()>,
     {
         info!("Started listening for IRC messages.");
 
-        let mut message_of_the_day = Vec::new();
         loop {
             let mut line = String::new();
             let read_result = reader.read_line(&mut line);
@@ -211,91 +584,48 @@ impl IRCClient {
                     break;
                 }
                 Ok(_) => {
-                    info!("Received line: {}", line.trim_end());
-
-                    let mut parser = Parser::new(&line);
-                    let message = parser.parse_message();
-
-                    match message.command.as_str() {
-                        "PING" => {
-                            debug!("Received PING, sending PONG response.");
-                            let response = format!("PONG :{}", message.params.join(" "));
-                            Self::send_line_with_writer(&writer, &response)?;
-                        }
-                        // RPL_ENDOFWHOIS
-                        "318" => {
-                            debug!("Received end of WHOIS response.");
-                        }
-                        // RPL_NAMREPLY
-                        "353" => {
-                            if let Some(names) = message.params.last() {
-                                let mut users: Vec<String> = Vec::new();
-                                for nick in names.split_whitespace() {
-                                    if !nick.is_empty() {
-                                        users.push(nick.to_string());
-                                    }
-                                }
-                                debug!("Received NAMES list: {}", users.join(", "));
-                                message_handler(IRCEvent::Users(users))?;
-                            }
-                        }
-                        // RPL_ENDOFNAMES
-                        "366" => {
-                            debug!("End of NAMES list.");
-                        }
-                        // RPL_MOTD
-                        "372" => {
-                            if let Some(motd_line) = message.params.last() {
-                                message_of_the_day.push(motd_line.to_string());
-                            }
-                        }
-                        // RPL_MOTDSTART
-                        "375" => {
-                            debug!("Start of MOTD.");
-                            message_of_the_day.clear();
-                        }
-                        // RPL_ENDOFMOTD
-                        "376" => {
-                            debug!("End of MOTD.");
-                            message_handler(IRCEvent::MessageOfTheDay(message_of_the_day.clone()))?;
-                        }
-                        // ERR_ERRONEUSNICKNAME
-                        "432" => {
-                            let nickname = message.params.get(1).cloned().unwrap_or_default();
-                            error!("Nickname '{}' is invalid.", nickname);
-                            message_handler(IRCEvent::Raw(format!(
-                                "The nickname '{}' is invalid!",
-                                nickname
-                            )))?;
-                        }
-                        // ERR_NONICKNAMEGIVEN
-                        "431" => {
-                            error!("No nickname given.");
-                            message_handler(IRCEvent::Raw(
-                                "No nickname given! Please provide a nickname.".to_string(),
-                            ))?;
-                        }
-                        // ERR_NICKNAMEINUSE
-                        "433" => {
-                            let nickname = message.params.get(1).cloned().unwrap_or_default();
-                            error!("Nickname '{}' is already in use.", nickname);
-                            message_handler(IRCEvent::Raw(format!(
-                                "The nickname '{}' is already in use!",
-                                nickname
-                            )))?;
-                        }
-                        // ERR_NICKCOLLISION
-                        "436" => {
-                            let nickname = message.params.get(1).cloned().unwrap_or_default();
-                            error!("Nickname '{}' is already in use (collision).", nickname);
-                            message_handler(IRCEvent::Raw(format!(
-                                "The nickname '{}' is already in use (collision)!",
-                                nickname
-                            )))?;
-                        }
-                        _ => {
-                            message_handler(IRCEvent::Message(message))?;
+                    let (events, mut context) = {
+                        let mut protocol = protocol
+                            .lock()
+                            .map_err(|_| io::Error::other("Protocol lock poisoned"))?;
+                        let events = protocol.handle_line(&line);
+                        outgoing.push_from(&mut protocol)?;
+                        (events, PluginContext::new(protocol.nickname()))
+                    };
+
+                    // The STS policy is followed before the plugins see the events, so that
+                    // none of them can keep the connection from being upgraded.
+                    let mut upgrade = None;
+                    let mut failed = None;
+                    let mut handled = Vec::new();
+                    for event in events {
+                        match &event.kind {
+                            IRCEvent::StsUpgrade { port } => upgrade = Some(*port),
+                            IRCEvent::StsPolicy { duration } => sts.save_policy(*duration),
+                            IRCEvent::RegistrationFailed(error) => failed = Some(error.clone()),
+                            _ => {}
                         }
+                        let mut plugins = outgoing
+                            .plugins
+                            .lock()
+                            .map_err(|_| io::Error::other("Plugin lock poisoned"))?;
+                        handled.extend(plugins.on_event(event, &mut context));
+                    }
+                    if context.has_replies() {
+                        let mut protocol = protocol
+                            .lock()
+                            .map_err(|_| io::Error::other("Protocol lock poisoned"))?;
+                        context.send_replies(&mut protocol);
+                        outgoing.push_from(&mut protocol)?;
+                    }
+                    for event in handled {
+                        message_handler(event)?;
+                    }
+                    if let Some(error) = failed {
+                        return Err(Error::from(error));
+                    }
+                    if let Some(port) = upgrade {
+                        Self::upgrade(reader, protocol, outgoing, sts, port)?;
                     }
                 }
                 Err(_) => break,
@@ -305,69 +635,168 @@ impl IRCClient {
         Ok(())
     }
 
-    fn send_line(&mut self, line: &str) -> io::Result<()> {
-        let writer = self.writer.as_ref().ok_or_else(|| {
-            error!("Cannot send line: Client is not connected.");
-            io::Error::new(io::ErrorKind::NotConnected, "Client is not connected.")
-        })?;
+    /// Reconnects over TLS to `port` as the server's STS policy asks, and registers again on the
+    /// new connection.
+    #[cfg(feature = "tls")]
+    fn upgrade(
+        reader: &mut BufReader<BoxedReader>,
+        protocol: &Mutex<Protocol>,
+        outgoing: &Outgoing,
+        sts: &mut Sts,
+        port: u16,
+    ) -> Result<(), Error> {
+        let Some((host, _)) = sts.server.take() else {
+            return Err(Error::NotConnected);
+        };
+        let config = match &sts.tls_config {
+            Some(config) => Arc::clone(config),
+            None => TlsStream::default_config()?,
+        };
+        let socket = TcpStream::connect((host.as_str(), port))?;
+        let (tls_reader, tls_writer) = TlsStream::with_config(socket, &host, config)?.split()?;
+        info!(
+            "Upgraded the connection to {} to TLS on port {}.",
+            host, port
+        );
+
+        *reader = BufReader::new(Box::new(tls_reader));
+        outgoing.replace_writer(Box::new(tls_writer))?;
+        sts.server = Some((host, port));
+
+        let mut protocol = protocol
+            .lock()
+            .map_err(|_| io::Error::other("Protocol lock poisoned"))?;
+        *protocol = Protocol::with_options(sts.options.clone());
+        protocol.set_security(Security::Tls);
+        protocol.register();
+        outgoing.push_from(&mut protocol)
+    }
 
-        debug!("Sending line: {}", line);
-        Self::send_line_with_writer(writer, line)
+    /// The protocol only asks for upgrades when the client can make them.
+    #[cfg(not(feature = "tls"))]
+    fn upgrade(
+        _reader: &mut BufReader<BoxedReader>,
+        _protocol: &Mutex<Protocol>,
+        _outgoing: &Outgoing,
+        _sts: &mut Sts,
+        _port: u16,
+    ) -> Result<(), Error> {
+        Err(Error::NotConnected)
     }
 
-    fn send_line_with_writer(
-        writer: &Arc<Mutex<BufWriter<TcpStream>>>,
-        line: &str,
-    ) -> io::Result<()> {
-        let mut writer = writer
-            .lock()
-            .map_err(|_| io::Error::other("Writer lock poisoned"))?;
+    /// Polls the notify list with ISON every [`ISON_INTERVAL`] until the connection closes.
+    fn notify_loop(protocol: &Mutex<Protocol>, outgoing: &Outgoing) {
+        loop {
+            {
+                let Ok(state) = outgoing.lock() else {
+                    return;
+                };
+                let Ok((state, _)) =
+                    outgoing
+                        .ready
+                        .wait_timeout_while(state, ISON_INTERVAL, |state| !state.closed)
+                else {
+                    return;
+                };
+                if state.closed {
+                    return;
+                }
+            }
 
+            let Ok(mut protocol) = protocol.lock() else {
+                return;
+            };
+            protocol.poll_notify_list();
+            if let Err(Error::NotConnected) = outgoing.push_from(&mut protocol) {
+                return;
+            }
+        }
+    }
+
+    fn write_loop(mut writer: BufWriter<BoxedWriter>, outgoing: Arc<Outgoing>) {
+        loop {
+            let line = {
+                let Ok(mut state) = outgoing.lock() else {
+                    return;
+                };
+
+                loop {
+                    if let Some(replacement) = state.writer.take() {
+                        writer = BufWriter::new(replacement);
+                    }
+                    if let Some(line) = state.queue.pop() {
+                        break line;
+                    }
+                    // Lines queued before closing, like a QUIT, are still sent at the usual pace.
+                    if state.closed && state.queue.is_empty() {
+                        return;
+                    }
+
+                    state = match state.queue.next_send_in() {
+                        Some(wait) => match outgoing.ready.wait_timeout(state, wait) {
+                            Ok((state, _)) => state,
+                            Err(_) => return,
+                        },
+                        None => match outgoing.ready.wait(state) {
+                            Ok(state) => state,
+                            Err(_) => return,
+                        },
+                    };
+                }
+            };
+
+            debug!("Sending line: {}", line);
+            if let Err(error) = Self::write_line(&mut writer, &line) {
+                error!("Failed to send line: {}", error);
+                outgoing.close();
+                return;
+            }
+        }
+    }
+
+    fn write_line(writer: &mut impl Write, line: &str) -> io::Result<()> {
         writer.write_all(line.as_bytes())?;
         writer.write_all(b"\r\n")?;
         writer.flush()
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn quit_without_connection_is_ok() {
-        let mut client = IRCClient::new("nick", "localhost", 6667);
+impl Sts {
+    /// Keeps the policy for the host in the STS store, when the options name one.
+    fn save_policy(&self, duration: Duration) {
+        let (Some(path), Some((host, port))) = (&self.options.sts_policies, &self.server) else {
+            return;
+        };
 
-        assert!(client.quit().is_ok());
+        let saved = StsStore::open(path).and_then(|mut store| store.update(host, *port, duration));
+        if let Err(error) = saved {
+            error!("Failed to save the STS policy of {}: {}", host, error);
+        }
     }
+}
 
-    #[test]
-    fn irc_event_debug_formats_message_variant() {
-        let event = IRCEvent::Message(Message {
-            prefix: None,
-            command: "NOTICE".to_string(),
-            params: vec!["#test".to_string(), "hello".to_string()],
-        });
-
-        assert_eq!(format!("{event:?}"), "IRCEvent::Message(cmd: NOTICE)");
+impl Drop for IRCClient {
+    fn drop(&mut self) {
+        if let Some(outgoing) = &self.outgoing {
+            outgoing.close();
+        }
     }
+}
 
-    #[test]
-    fn irc_event_debug_formats_users_variant() {
-        let event = IRCEvent::Users(vec!["alice".to_string(), "bob".to_string()]);
-
-        assert_eq!(format!("{event:?}"), "IRCEvent::Users(alice, bob)");
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
     #[test]
-    fn irc_event_debug_formats_raw_variant() {
-        let event = IRCEvent::Raw("Connected".to_string());
+    fn quit_without_connection_is_ok() {
+        let mut client = IRCClient::new(ConnectionOptions::new("nick"));
 
-        assert_eq!(format!("{event:?}"), "IRCEvent::Raw(Connected)");
+        assert!(client.quit().is_ok());
     }
 
     #[test]
     fn msg_command_without_enough_arguments_is_noop() {
-        let mut client = IRCClient::new("nick", "localhost", 6667);
+        let mut client = IRCClient::new(ConnectionOptions::new("nick"));
 
         assert!(client.send_message("/msg").is_ok());
         assert!(client.send_message("/msg alice").is_ok());
@@ -375,11 +804,11 @@ mod tests {
 
     #[test]
     fn msg_command_with_target_and_message_sends_private_message() {
-        let mut client = IRCClient::new("nick", "localhost", 6667);
+        let mut client = IRCClient::new(ConnectionOptions::new("nick"));
 
         let error = client
             .send_message("/msg alice hello there")
             .expect_err("expected NotConnected error");
-        assert_eq!(error.kind(), io::ErrorKind::NotConnected);
+        assert!(matches!(error, Error::NotConnected));
     }
 }
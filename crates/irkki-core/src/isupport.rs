@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use crate::DEFAULT_LINE_LEN;
+
+/// Tokens advertised by the server with RPL_ISUPPORT (005).
+///
+/// The server can send several 005 replies, and later ones may add new tokens or negate earlier
+/// ones with a leading `-`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ISupport {
+    tokens: HashMap<String, Option<String>>,
+}
+
+impl ISupport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Updates the tokens from the params of a RPL_ISUPPORT message. The first param is the
+    /// client nickname and the last one is the human readable "are supported by this server".
+    pub fn update(&mut self, params: &[String]) {
+        if params.len() < 3 {
+            return;
+        }
+
+        for token in &params[1..params.len() - 1] {
+            if let Some(name) = token.strip_prefix('-') {
+                self.tokens.remove(name);
+                continue;
+            }
+
+            match token.split_once('=') {
+                Some((name, value)) => {
+                    self.tokens
+                        .insert(name.to_string(), Some(Self::unescape(value)));
+                }
+                None => {
+                    self.tokens.insert(token.to_string(), None);
+                }
+            }
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.tokens.contains_key(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.tokens.get(name).and_then(|value| value.as_deref())
+    }
+
+    fn get_number(&self, name: &str) -> Option<usize> {
+        self.get(name).and_then(|value| value.parse().ok())
+    }
+
+    /// Maximum length of a line, excluding tags but including the trailing CR-LF.
+    pub fn line_len(&self) -> usize {
+        self.get_number("LINELEN")
+            .filter(|len| *len >= DEFAULT_LINE_LEN)
+            .unwrap_or(DEFAULT_LINE_LEN)
+    }
+
+    pub fn user_len(&self) -> Option<usize> {
+        self.get_number("USERLEN")
+    }
+
+    pub fn host_len(&self) -> Option<usize> {
+        self.get_number("HOSTLEN")
+    }
+
+    /// How many nicknames MONITOR may watch, from `MONITOR=100`. `None` when there is no limit
+    /// or no MONITOR at all.
+    pub fn monitor_limit(&self) -> Option<usize> {
+        self.get_number("MONITOR")
+    }
+
+    /// Whether the server relays the client-only tag `name`, given without its `+`, going by
+    /// `CLIENTTAGDENY`. `CLIENTTAGDENY=*,-draft/react` blocks all client tags but `+draft/react`.
+    pub fn client_tag_allowed(&self, name: &str) -> bool {
+        let denied: Vec<&str> = self.get("CLIENTTAGDENY").unwrap_or("").split(',').collect();
+
+        if denied.contains(&"*") {
+            denied.iter().any(|tag| tag.strip_prefix('-') == Some(name))
+        } else {
+            !denied.contains(&name)
+        }
+    }
+
+    /// The channel membership modes and their prefixes, from most to least powerful, e.g.
+    /// `[('o', '@'), ('v', '+')]` for `PREFIX=(ov)@+`.
+    pub fn prefixes(&self) -> Vec<(char, char)> {
+        let value = self.get("PREFIX").unwrap_or("(ov)@+");
+        let Some((modes, symbols)) = value
+            .strip_prefix('(')
+            .and_then(|value| value.split_once(')'))
+        else {
+            return Vec::new();
+        };
+
+        modes.chars().zip(symbols.chars()).collect()
+    }
+
+    /// The channel modes by type, from `CHANMODES=A,B,C,D`. Modes of type A and B always take a
+    /// parameter, type C only when set and type D never.
+    pub fn channel_modes(&self) -> [String; 4] {
+        let value = self.get("CHANMODES").unwrap_or("beI,k,l,imnpst");
+        let mut types = value.split(',').map(String::from);
+
+        std::array::from_fn(|_| types.next().unwrap_or_default())
+    }
+
+    /// Values can contain `\xHH` escapes, for example `\x20` for a space.
+    fn unescape(value: &str) -> String {
+        let mut bytes = Vec::with_capacity(value.len());
+        let raw = value.as_bytes();
+        let mut i = 0;
+
+        while i < raw.len() {
+            if raw[i] == b'\\'
+                && raw.get(i + 1) == Some(&b'x')
+                && let Some(hex) = value.get(i + 2..i + 4)
+                && let Ok(byte) = u8::from_str_radix(hex, 16)
+            {
+                bytes.push(byte);
+                i += 4;
+            } else {
+                bytes.push(raw[i]);
+                i += 1;
+            }
+        }
+
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixes_and_channel_modes_have_defaults() {
+        let mut isupport = ISupport::new();
+        assert_eq!(isupport.prefixes(), vec![('o', '@'), ('v', '+')]);
+
+        isupport.update(&[
+            "nick".to_string(),
+            "PREFIX=(qaohv)~&@%+".to_string(),
+            "CHANMODES=b,k,l,imnt".to_string(),
+            "are supported by this server".to_string(),
+        ]);
+
+        assert_eq!(isupport.prefixes()[0], ('q', '~'));
+        assert_eq!(isupport.prefixes().len(), 5);
+        assert_eq!(isupport.channel_modes()[3], "imnt");
+    }
+
+    fn params(tokens: &[&str]) -> Vec<String> {
+        let mut params = vec!["nick".to_string()];
+        params.extend(tokens.iter().map(|t| t.to_string()));
+        params.push("are supported by this server".to_string());
+        params
+    }
+
+    #[test]
+    fn update_adds_tokens_with_and_without_values() {
+        let mut isupport = ISupport::new();
+        isupport.update(&params(&[
+            "LINELEN=2048",
+            "WHOX",
+            "NETWORK=Example\\x20Net",
+        ]));
+
+        assert_eq!(isupport.get("LINELEN"), Some("2048"));
+        assert!(isupport.contains("WHOX"));
+        assert_eq!(isupport.get("WHOX"), None);
+        assert_eq!(isupport.get("NETWORK"), Some("Example Net"));
+        assert_eq!(isupport.line_len(), 2048);
+    }
+
+    #[test]
+    fn negated_token_is_removed() {
+        let mut isupport = ISupport::new();
+        isupport.update(&params(&["WHOX"]));
+        isupport.update(&params(&["-WHOX"]));
+
+        assert!(!isupport.contains("WHOX"));
+    }
+
+    #[test]
+    fn client_tags_are_denied_by_name_or_all_but_exempted() {
+        let mut isupport = ISupport::new();
+        assert!(isupport.client_tag_allowed("typing"));
+
+        isupport.update(&params(&["CLIENTTAGDENY=typing"]));
+        assert!(!isupport.client_tag_allowed("typing"));
+        assert!(isupport.client_tag_allowed("draft/react"));
+
+        isupport.update(&params(&["CLIENTTAGDENY=*,-draft/react"]));
+        assert!(!isupport.client_tag_allowed("typing"));
+        assert!(isupport.client_tag_allowed("draft/react"));
+    }
+
+    #[test]
+    fn line_len_defaults_to_512() {
+        let mut isupport = ISupport::new();
+        assert_eq!(isupport.line_len(), 512);
+
+        isupport.update(&params(&["LINELEN=100"]));
+        assert_eq!(isupport.line_len(), 512);
+    }
+}
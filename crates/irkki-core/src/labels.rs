@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+use crate::{Error, Event, IRCEvent, ReplyKind, Responder, Response, ServerError, response};
+
+/// The batch type servers use for a labeled reply of several messages.
+pub(crate) const LABELED_BATCH: &str = "labeled-response";
+
+/// The commands sent with a `label` tag that are waiting for their reply.
+#[derive(Default)]
+pub(crate) struct Labels {
+    labels_sent: u64,
+    pending: HashMap<String, Responder<Vec<Event>>>,
+}
+
+impl Labels {
+    /// Returns a new label and the response its reply completes.
+    pub(crate) fn expect(&mut self) -> (String, Response<Vec<Event>>) {
+        let label = format!("l{}", self.labels_sent);
+        self.labels_sent += 1;
+
+        let (responder, response) = response();
+        self.pending.insert(label.clone(), responder);
+        (label, response)
+    }
+
+    /// Completes the response of the command an event is labeled with. The reply is the single
+    /// labeled event, the events of a labeled batch, or nothing for an `ACK`. A reply with an
+    /// error numeric or a `FAIL` fails the response.
+    ///
+    /// Returns whether the event should still be delivered, which is all but the `ACK`s.
+    pub(crate) fn complete(&mut self, event: &Event) -> bool {
+        let Some(responder) = event
+            .label
+            .as_ref()
+            .and_then(|label| self.pending.remove(label))
+        else {
+            return true;
+        };
+
+        let reply = match &event.kind {
+            IRCEvent::Batch(batch) if batch.kind == LABELED_BATCH => batch.events.clone(),
+            IRCEvent::Message(message) if message.command == "ACK" => {
+                responder.complete(Ok(Vec::new()));
+                return false;
+            }
+            _ => vec![event.clone()],
+        };
+
+        let error = reply.iter().find_map(|event| match &event.kind {
+            IRCEvent::ServerError(error) => Some(error.clone()),
+            IRCEvent::StandardReply(reply) if reply.kind == ReplyKind::Fail => {
+                Some(ServerError::from(reply.clone()))
+            }
+            _ => None,
+        });
+        match error {
+            Some(error) => responder.complete(Err(Error::from(error))),
+            None => responder.complete(Ok(reply)),
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Batch, Parser, StandardReply};
+
+    fn labeled(label: &str, kind: IRCEvent) -> Event {
+        Event {
+            label: Some(label.to_string()),
+            ..Event::new(kind)
+        }
+    }
+
+    #[test]
+    fn ack_completes_with_nothing_and_is_dropped() {
+        let mut labels = Labels::default();
+        let (label, response) = labels.expect();
+        let ack = Parser::new("ACK").parse_message().unwrap();
+
+        assert!(!labels.complete(&labeled(&label, IRCEvent::Message(ack))));
+        assert_eq!(response.try_take().unwrap().unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn labeled_batch_completes_with_its_events() {
+        let mut labels = Labels::default();
+        let (label, response) = labels.expect();
+        let inner = labeled("", IRCEvent::Raw("reply".to_string()));
+        let batch = labeled(
+            &label,
+            IRCEvent::Batch(Batch {
+                kind: LABELED_BATCH.to_string(),
+                params: Vec::new(),
+                events: vec![inner.clone()],
+            }),
+        );
+
+        assert!(labels.complete(&batch));
+        assert_eq!(response.try_take().unwrap().unwrap(), vec![inner]);
+    }
+
+    #[test]
+    fn labeled_error_fails_the_response() {
+        let mut labels = Labels::default();
+        let (label, response) = labels.expect();
+        let error = ServerError {
+            code: "401".to_string(),
+            params: vec!["nobody".to_string()],
+            text: "No such nick/channel".to_string(),
+        };
+
+        labels.complete(&labeled(&label, IRCEvent::ServerError(error)));
+
+        assert!(matches!(response.try_take(), Some(Err(Error::Protocol(_)))));
+    }
+
+    #[test]
+    fn labeled_fail_fails_the_response_but_warn_does_not() {
+        let mut labels = Labels::default();
+        let (fail_label, failed) = labels.expect();
+        let (warn_label, warned) = labels.expect();
+        let reply = |line: &str| {
+            let message = Parser::new(line).parse_message().unwrap();
+            IRCEvent::StandardReply(StandardReply::from_message(&message).unwrap())
+        };
+
+        labels.complete(&labeled(
+            &fail_label,
+            reply("FAIL NICK NICKNAME_RESERVED alice :Nickname is reserved"),
+        ));
+        labels.complete(&labeled(
+            &warn_label,
+            reply("WARN REHASH CERTS_EXPIRED :Certificate has expired"),
+        ));
+
+        let Some(Err(Error::Protocol(error))) = failed.try_take() else {
+            panic!("Expected the FAIL to fail the response");
+        };
+        assert_eq!(error.code, "NICKNAME_RESERVED");
+        assert_eq!(error.params, vec!["alice"]);
+        assert_eq!(warned.try_take().unwrap().unwrap().len(), 1);
+    }
+}
@@ -13,6 +13,8 @@ pub struct Token {
     pub literal: String,
 }
 
+/// Splits a message into tokens. The positions are byte offsets into `input`, so that text
+/// outside ASCII is sliced at the boundaries of its characters.
 pub struct Lexer<'a> {
     input: &'a str,
     current_char: Option<char>,
@@ -34,13 +36,9 @@ impl<'a> Lexer<'a> {
     }
 
     fn read_char(&mut self) {
-        self.current_char = if self.read_position >= self.input.len() {
-            None
-        } else {
-            Some(self.input.chars().nth(self.read_position).unwrap())
-        };
+        self.current_char = self.char_at(self.read_position);
         self.current_position = self.read_position;
-        self.read_position += 1;
+        self.read_position += self.current_char.map_or(1, char::len_utf8);
     }
 
     fn read_string(&mut self) -> String {
@@ -52,15 +50,15 @@ impl<'a> Lexer<'a> {
             next_char = self.peek_char();
         }
 
-        self.input[start..=self.current_position].to_string()
+        self.input[start..self.read_position].to_string()
     }
 
     fn peek_char(&self) -> char {
-        if self.read_position >= self.input.len() {
-            '\0'
-        } else {
-            self.input.chars().nth(self.read_position).unwrap()
-        }
+        self.char_at(self.read_position).unwrap_or('\0')
+    }
+
+    fn char_at(&self, position: usize) -> Option<char> {
+        self.input.get(position..)?.chars().next()
     }
 
     pub fn next_token(&mut self) -> Token {
@@ -68,19 +66,12 @@ impl<'a> Lexer<'a> {
 
         match self.current_char {
             Some(c) => match c {
-                ':' => {
-                    if self.current_position == 0 && self.read_position == 1 {
-                        // Special case for leading colon in prefix
-                        token = Token {
-                            token_type: TokenType::Colon,
-                            literal: ":".to_string(),
-                        };
-                    } else {
-                        token = Token {
-                            token_type: TokenType::Word,
-                            literal: self.read_string(),
-                        };
-                    }
+                // Special case for leading colon in prefix, other colons start a word.
+                ':' if self.current_position == 0 && self.read_position == 1 => {
+                    token = Token {
+                        token_type: TokenType::Colon,
+                        literal: ":".to_string(),
+                    };
                 }
                 ' ' => {
                     token = Token {
@@ -152,6 +143,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_multibyte_characters() {
+        let input = "PRIVMSG #kanava :héllo wörld ✓\r\n";
+        let mut lexer = Lexer::new(input);
+
+        let expected_tokens = vec![
+            (TokenType::Word, "PRIVMSG"),
+            (TokenType::Space, " "),
+            (TokenType::Word, "#kanava"),
+            (TokenType::Space, " "),
+            (TokenType::Word, ":héllo"),
+            (TokenType::Space, " "),
+            (TokenType::Word, "wörld"),
+            (TokenType::Space, " "),
+            (TokenType::Word, "✓"),
+            (TokenType::CrLf, "\r\n"),
+            (TokenType::EOF, ""),
+        ];
+
+        for (expected_type, expected_literal) in expected_tokens {
+            let token = lexer.next_token();
+            assert_eq!(token.token_type, expected_type);
+            assert_eq!(token.literal, expected_literal);
+        }
+    }
+
     #[test]
     fn test_illegal_token() {
         let input = "COMMAND arg1\r";
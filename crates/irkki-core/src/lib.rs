@@ -1,7 +1,71 @@
+#[cfg(feature = "tokio")]
+mod async_client;
+mod batch;
+mod bot;
+mod bouncer;
+mod capabilities;
+mod channels;
+mod chathistory;
+mod dcc;
+mod dcc_transfer;
+mod error;
+mod event;
 mod irc_client;
+mod isupport;
+mod labels;
 mod lexer;
+mod message_split;
+mod monitor;
+mod multiline;
+mod networks;
+mod options;
 mod parser;
+mod plugin;
+mod protocol;
+mod response;
+mod sasl;
+mod send_queue;
+mod standard_replies;
+mod sts;
+mod tags;
+#[cfg(feature = "tls")]
+mod tls;
+mod transport;
+mod who;
+mod whois;
 
+#[cfg(feature = "tokio")]
+pub use async_client::*;
+pub use batch::*;
+pub use bot::*;
+pub use bouncer::*;
+pub use capabilities::*;
+pub use channels::*;
+pub use chathistory::*;
+pub use dcc::*;
+pub use dcc_transfer::*;
+pub use error::*;
+pub use event::*;
 pub use irc_client::*;
+pub use isupport::*;
+use labels::*;
 pub use lexer::*;
+pub use message_split::*;
+pub use monitor::*;
+pub use multiline::*;
+pub use networks::*;
+pub use options::*;
 pub use parser::*;
+pub use plugin::*;
+pub use protocol::*;
+pub use response::*;
+use sasl::*;
+pub use send_queue::*;
+pub use standard_replies::*;
+pub use sts::*;
+use tags::*;
+#[cfg(feature = "tls")]
+pub use tls::*;
+pub use transport::*;
+use who::*;
+pub use whois::*;
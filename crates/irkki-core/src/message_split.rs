@@ -0,0 +1,192 @@
+use crate::Error;
+
+/// Maximum length of a line, excluding tags but including the trailing CR-LF, when the server
+/// does not advertise LINELEN.
+pub const DEFAULT_LINE_LEN: usize = 512;
+
+/// Clients must not send more than 4094 bytes of tag data. Tags have their own budget and do not
+/// count against the line length.
+pub const MAX_CLIENT_TAG_LEN: usize = 4094;
+
+const DEFAULT_USER_LEN: usize = 10;
+const DEFAULT_HOST_LEN: usize = 63;
+
+/// The limits for a line we send, as it will look when the server relays it to other clients.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineLimits {
+    /// LINELEN from ISUPPORT, or 512.
+    pub line_len: usize,
+    /// Length of the `nick!user@host` source that the server puts in front of relayed messages.
+    pub source_len: usize,
+}
+
+impl LineLimits {
+    pub fn new(line_len: usize, source_len: usize) -> Self {
+        Self {
+            line_len,
+            source_len,
+        }
+    }
+
+    /// Estimates the length of our source when the server has not told us our user and host yet,
+    /// assuming the longest ones the server allows.
+    pub fn estimate_source_len(
+        nickname: &str,
+        user_len: Option<usize>,
+        host_len: Option<usize>,
+    ) -> usize {
+        nickname.len()
+            + 1
+            + user_len.unwrap_or(DEFAULT_USER_LEN)
+            + 1
+            + host_len.unwrap_or(DEFAULT_HOST_LEN)
+    }
+
+    /// Bytes left for the text in `:source COMMAND target :text\r\n`.
+    pub fn text_budget(&self, command: &str, target: &str) -> usize {
+        let overhead = 1 + self.source_len + 1 + command.len() + 1 + target.len() + 2 + 2;
+        self.line_len.saturating_sub(overhead)
+    }
+}
+
+/// Splits text into chunks of at most `max_bytes` bytes.
+///
+/// Splits are made after whitespace when possible and never inside a UTF-8 character. The
+/// whitespace stays at the end of the preceding chunk, so the chunks concatenate back into the
+/// original text.
+pub fn split_text(text: &str, max_bytes: usize) -> Vec<&str> {
+    let max_bytes = max_bytes.max(1);
+    let mut chunks = Vec::new();
+    let mut rest = text;
+
+    while rest.len() > max_bytes {
+        let mut end = max_bytes;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let split_at = if end == 0 {
+            // A single character is longer than the budget, send it anyway.
+            rest.char_indices().nth(1).map_or(rest.len(), |(i, _)| i)
+        } else {
+            rest[..end]
+                .char_indices()
+                .rev()
+                .find(|(i, c)| *i > 0 && c.is_whitespace())
+                .map_or(end, |(i, c)| i + c.len_utf8())
+        };
+
+        let (chunk, remainder) = rest.split_at(split_at);
+        chunks.push(chunk);
+        rest = remainder;
+    }
+
+    if !rest.is_empty() {
+        chunks.push(rest);
+    }
+
+    chunks
+}
+
+/// Formats `COMMAND target :text` lines that fit within the limits, splitting the text into
+/// several messages when needed. The optional tags are put in front of every line.
+pub fn split_message(
+    tags: &str,
+    command: &str,
+    target: &str,
+    text: &str,
+    limits: &LineLimits,
+) -> Result<Vec<String>, Error> {
+    if tags.len() > MAX_CLIENT_TAG_LEN {
+        return Err(Error::InvalidInput(
+            "The message tags are too long.".to_string(),
+        ));
+    }
+
+    let prefix = if tags.is_empty() {
+        String::new()
+    } else {
+        format!("@{tags} ")
+    };
+
+    let budget = limits.text_budget(command, target);
+    Ok(split_text(text, budget)
+        .into_iter()
+        .map(str::trim_end)
+        .filter(|chunk| !chunk.is_empty())
+        .map(|chunk| format!("{prefix}{command} {target} :{chunk}"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_text_is_not_split() {
+        assert_eq!(split_text("hello world", 20), vec!["hello world"]);
+    }
+
+    #[test]
+    fn split_text_prefers_whitespace() {
+        assert_eq!(
+            split_text("hello brave new world", 12),
+            vec!["hello brave ", "new world"]
+        );
+    }
+
+    #[test]
+    fn split_text_cuts_long_words() {
+        assert_eq!(split_text("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+    }
+
+    #[test]
+    fn split_text_respects_utf8_boundaries() {
+        let text = "åäöåäö";
+        let chunks = split_text(text, 5);
+
+        assert_eq!(chunks, vec!["åä", "öå", "äö"]);
+        assert_eq!(chunks.concat(), text);
+    }
+
+    #[test]
+    fn text_budget_accounts_for_source_command_and_target() {
+        let limits = LineLimits::new(512, "nick!user@host".len());
+
+        // ":nick!user@host PRIVMSG #chan :" + CRLF
+        assert_eq!(limits.text_budget("PRIVMSG", "#chan"), 512 - 31 - 2);
+    }
+
+    #[test]
+    fn estimated_source_assumes_longest_user_and_host() {
+        assert_eq!(LineLimits::estimate_source_len("nick", None, None), 79);
+        assert_eq!(
+            LineLimits::estimate_source_len("nick", Some(5), Some(20)),
+            31
+        );
+    }
+
+    #[test]
+    fn split_message_fits_every_line_in_the_limit() {
+        let limits = LineLimits::new(512, 40);
+        let text = "lorem ipsum ".repeat(100);
+
+        let lines = split_message("", "PRIVMSG", "#chan", &text, &limits).unwrap();
+
+        assert!(lines.len() > 1);
+        for line in &lines {
+            assert!(line.starts_with("PRIVMSG #chan :"));
+            assert!(1 + 40 + 1 + line.len() + 2 <= 512);
+        }
+    }
+
+    #[test]
+    fn split_message_rejects_too_long_tags() {
+        let limits = LineLimits::new(512, 40);
+        let tags = "+a=".to_string() + &"b".repeat(MAX_CLIENT_TAG_LEN);
+
+        let error = split_message(&tags, "PRIVMSG", "#chan", "hi", &limits).unwrap_err();
+
+        assert!(matches!(error, Error::InvalidInput(_)));
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::{HashSet, VecDeque};
+use std::time::Duration;
+
+use crate::{IRCEvent, Message, Responder, Response, Source, response};
+
+/// How often the clients ask with ISON which nicknames of the notify list are online, on servers
+/// without MONITOR.
+pub const ISON_INTERVAL: Duration = Duration::from_secs(60);
+
+fn key(nickname: &str) -> String {
+    nickname.to_ascii_lowercase()
+}
+
+/// The nicknames we want to know about when they come online or go offline, also when they are
+/// not in any of our channels.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NotifyList {
+    nicknames: Vec<String>,
+    online: HashSet<String>,
+}
+
+impl NotifyList {
+    /// The nicknames on the list, in the order they were added.
+    pub fn nicknames(&self) -> &[String] {
+        &self.nicknames
+    }
+
+    pub fn contains(&self, nickname: &str) -> bool {
+        self.nicknames
+            .iter()
+            .any(|listed| listed.eq_ignore_ascii_case(nickname))
+    }
+
+    pub fn is_online(&self, nickname: &str) -> bool {
+        self.online.contains(&key(nickname))
+    }
+
+    pub fn len(&self) -> usize {
+        self.nicknames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nicknames.is_empty()
+    }
+}
+
+/// Keeps the notify list up to date from the MONITOR numerics or the ISON replies, and turns
+/// them into online and offline events.
+#[derive(Default)]
+pub(crate) struct Monitor {
+    list: NotifyList,
+    /// The nicknames of the ISON requests waiting for RPL_ISON, oldest first.
+    ison_pending: VecDeque<Vec<String>>,
+    /// The `MONITOR L` requests waiting for RPL_ENDOFMONLIST.
+    listing: VecDeque<Responder<Vec<String>>>,
+    listed: Vec<String>,
+}
+
+impl Monitor {
+    pub(crate) fn list(&self) -> &NotifyList {
+        &self.list
+    }
+
+    /// Adds the nicknames that are not on the list yet, and returns them.
+    pub(crate) fn add(&mut self, nicknames: Vec<String>) -> Vec<String> {
+        let mut added: Vec<String> = Vec::new();
+        for nickname in nicknames {
+            if !self.list.contains(&nickname)
+                && !added
+                    .iter()
+                    .any(|other| other.eq_ignore_ascii_case(&nickname))
+            {
+                added.push(nickname);
+            }
+        }
+
+        self.list.nicknames.extend(added.iter().cloned());
+        added
+    }
+
+    /// Removes the nicknames that are on the list, and returns them.
+    pub(crate) fn remove(&mut self, nicknames: &[String]) -> Vec<String> {
+        let (removed, kept) = std::mem::take(&mut self.list.nicknames)
+            .into_iter()
+            .partition(|listed| {
+                nicknames
+                    .iter()
+                    .any(|nickname| nickname.eq_ignore_ascii_case(listed))
+            });
+        self.list.nicknames = kept;
+
+        for nickname in &removed {
+            self.list.online.remove(&key(nickname));
+        }
+        removed
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.list = NotifyList::default();
+    }
+
+    /// Remembers the nicknames of an ISON request, which RPL_ISON answers.
+    pub(crate) fn expect_ison(&mut self, nicknames: Vec<String>) {
+        self.ison_pending.push_back(nicknames);
+    }
+
+    pub(crate) fn expect_listing(&mut self) -> Response<Vec<String>> {
+        let (responder, response) = response();
+        self.listing.push_back(responder);
+        response
+    }
+
+    /// Handles RPL_ISON and the MONITOR numerics. Returns the events for the nicknames that came
+    /// online or went offline.
+    pub(crate) fn handle(&mut self, message: &Message) -> Vec<IRCEvent> {
+        let last = message.params.last().map_or("", String::as_str);
+        let targets = || last.split(',').filter(|target| !target.is_empty());
+
+        match message.command.as_str() {
+            // RPL_ISON lists the nicknames of the request that are online. The others are not.
+            "303" => {
+                let Some(asked) = self.ison_pending.pop_front() else {
+                    return Vec::new();
+                };
+                let online: HashSet<String> = last.split_whitespace().map(key).collect();
+
+                asked
+                    .into_iter()
+                    .filter_map(|nickname| {
+                        let is_online = online.contains(&key(&nickname));
+                        // Polling repeats itself, so only the changes become events.
+                        if is_online == self.list.is_online(&nickname) {
+                            return None;
+                        }
+                        Some(self.set_online(Source::parse(&nickname), is_online))
+                    })
+                    .collect()
+            }
+            // RPL_MONONLINE
+            "730" => targets()
+                .map(|target| self.set_online(Source::parse(target), true))
+                .collect(),
+            // RPL_MONOFFLINE
+            "731" => targets()
+                .map(|target| self.set_online(Source::parse(target), false))
+                .collect(),
+            // RPL_MONLIST
+            "732" => {
+                self.listed.extend(targets().map(str::to_string));
+                Vec::new()
+            }
+            // RPL_ENDOFMONLIST
+            "733" => {
+                let listed = std::mem::take(&mut self.listed);
+                if let Some(responder) = self.listing.pop_front() {
+                    responder.complete(Ok(listed));
+                }
+                Vec::new()
+            }
+            // ERR_MONLISTFULL <client> <limit> <targets> :Monitor list is full.
+            "734" if message.params.len() >= 3 => {
+                let nicknames: Vec<String> =
+                    message.params[2].split(',').map(str::to_string).collect();
+                self.remove(&nicknames);
+                vec![IRCEvent::MonitorListFull {
+                    limit: message.params[1].parse().unwrap_or_default(),
+                    nicknames,
+                }]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn set_online(&mut self, source: Source, online: bool) -> IRCEvent {
+        if online {
+            self.list.online.insert(key(&source.nickname));
+            IRCEvent::Online { source }
+        } else {
+            self.list.online.remove(&key(&source.nickname));
+            IRCEvent::Offline {
+                nickname: source.nickname,
+            }
+        }
+    }
+}
+
+/// Joins nicknames with `separator` into as few parameters as fit in `max_len` bytes each.
+pub(crate) fn join_within(nicknames: &[String], separator: char, max_len: usize) -> Vec<String> {
+    let mut joined: Vec<String> = Vec::new();
+    for nickname in nicknames {
+        match joined.last_mut() {
+            Some(last) if last.len() + 1 + nickname.len() <= max_len => {
+                last.push(separator);
+                last.push_str(nickname);
+            }
+            _ => joined.push(nickname.clone()),
+        }
+    }
+    joined
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+
+    fn message(line: &str) -> Message {
+        Parser::new(line).parse_message().unwrap()
+    }
+
+    fn nicknames(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn ison_replies_report_only_changes() {
+        let mut monitor = Monitor::default();
+        monitor.add(nicknames(&["alice", "bob"]));
+
+        monitor.expect_ison(nicknames(&["alice", "bob"]));
+        let first = monitor.handle(&message(":s 303 me :Alice"));
+        monitor.expect_ison(nicknames(&["alice", "bob"]));
+        let unchanged = monitor.handle(&message(":s 303 me :alice"));
+        monitor.expect_ison(nicknames(&["alice", "bob"]));
+        let changed = monitor.handle(&message(":s 303 me :bob"));
+
+        assert_eq!(
+            first,
+            vec![IRCEvent::Online {
+                source: Source::parse("alice")
+            }]
+        );
+        assert!(unchanged.is_empty());
+        assert_eq!(
+            changed,
+            vec![
+                IRCEvent::Offline {
+                    nickname: "alice".to_string()
+                },
+                IRCEvent::Online {
+                    source: Source::parse("bob")
+                },
+            ]
+        );
+        assert!(monitor.list().is_online("Bob"));
+    }
+
+    #[test]
+    fn monitor_numerics_update_the_list() {
+        let mut monitor = Monitor::default();
+        monitor.add(nicknames(&["alice", "bob", "carol"]));
+        let listing = monitor.expect_listing();
+
+        let online = monitor.handle(&message(":s 730 me :alice!a@host,bob!b@host"));
+        monitor.handle(&message(":s 731 me :bob"));
+        monitor.handle(&message(":s 732 me :alice,bob"));
+        monitor.handle(&message(":s 733 me :End of MONITOR list"));
+        let full = monitor.handle(&message(":s 734 me 2 carol :Monitor list is full."));
+
+        assert_eq!(online.len(), 2);
+        assert!(monitor.list().is_online("alice"));
+        assert!(!monitor.list().is_online("bob"));
+        assert_eq!(
+            listing.try_take().unwrap().unwrap(),
+            nicknames(&["alice", "bob"])
+        );
+        assert_eq!(
+            full,
+            vec![IRCEvent::MonitorListFull {
+                limit: 2,
+                nicknames: nicknames(&["carol"]),
+            }]
+        );
+        assert_eq!(monitor.list().nicknames(), nicknames(&["alice", "bob"]));
+    }
+
+    #[test]
+    fn nicknames_are_joined_within_the_length() {
+        let joined = join_within(&nicknames(&["alice", "bob", "carol"]), ',', 9);
+
+        assert_eq!(joined, vec!["alice,bob", "carol"]);
+    }
+}
@@ -0,0 +1,177 @@
+use crate::split_text;
+
+/// Limits advertised with the `draft/multiline` capability, e.g. `max-bytes=4096,max-lines=24`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MultilineLimits {
+    pub max_bytes: usize,
+    pub max_lines: Option<usize>,
+}
+
+impl MultilineLimits {
+    /// Parses the capability value. `max-bytes` is mandatory, `max-lines` is optional.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut max_bytes = None;
+        let mut max_lines = None;
+
+        for pair in value.split(',') {
+            match pair.split_once('=') {
+                Some(("max-bytes", bytes)) => max_bytes = bytes.parse().ok(),
+                Some(("max-lines", lines)) => max_lines = lines.parse().ok(),
+                _ => {}
+            }
+        }
+
+        Some(Self {
+            max_bytes: max_bytes?,
+            max_lines,
+        })
+    }
+}
+
+/// The batch type of multiline messages.
+pub(crate) const MULTILINE_BATCH: &str = "draft/multiline";
+/// The tag of a line that continues the previous one instead of starting a new line.
+pub(crate) const CONCAT_TAG: &str = "draft/multiline-concat";
+
+/// Builds a `draft/multiline` batch that delivers `text` to `target` as one message.
+///
+/// Every line of the text is sent as its own message in the batch. A line that is longer than
+/// `line_budget` is split into parts joined with the `draft/multiline-concat` tag, so the
+/// receiver can put it back together. Returns `None` when the text does not fit within the
+/// limits of the server.
+pub fn multiline_batch(
+    reference: &str,
+    command: &str,
+    target: &str,
+    text: &str,
+    line_budget: usize,
+    limits: &MultilineLimits,
+) -> Option<Vec<String>> {
+    if text.len() > limits.max_bytes {
+        return None;
+    }
+
+    let mut messages = Vec::new();
+    for line in text.split('\n') {
+        let chunks = split_text(line, line_budget);
+        // A blank line is sent as an empty message.
+        if chunks.is_empty() {
+            messages.push(format!("@batch={reference} {command} {target} :"));
+        }
+        for (i, chunk) in chunks.iter().enumerate() {
+            let tags = if i == 0 {
+                format!("batch={reference}")
+            } else {
+                format!("batch={reference};{CONCAT_TAG}")
+            };
+            messages.push(format!("@{tags} {command} {target} :{chunk}"));
+        }
+    }
+    if text.is_empty() || limits.max_lines.is_some_and(|max| messages.len() > max) {
+        return None;
+    }
+
+    let mut lines = Vec::with_capacity(messages.len() + 2);
+    lines.push(format!("BATCH +{reference} {MULTILINE_BATCH} {target}"));
+    lines.extend(messages);
+    lines.push(format!("BATCH -{reference}"));
+
+    Some(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_limits() {
+        assert_eq!(
+            MultilineLimits::parse("max-bytes=4096,max-lines=24"),
+            Some(MultilineLimits {
+                max_bytes: 4096,
+                max_lines: Some(24)
+            })
+        );
+        assert_eq!(
+            MultilineLimits::parse("max-bytes=4096"),
+            Some(MultilineLimits {
+                max_bytes: 4096,
+                max_lines: None
+            })
+        );
+        assert_eq!(MultilineLimits::parse("max-lines=24"), None);
+    }
+
+    #[test]
+    fn long_text_is_sent_as_concatenated_lines() {
+        let limits = MultilineLimits {
+            max_bytes: 4096,
+            max_lines: Some(10),
+        };
+
+        let lines =
+            multiline_batch("ml1", "PRIVMSG", "#chan", "hello brave world", 12, &limits).unwrap();
+
+        assert_eq!(
+            lines,
+            vec![
+                "BATCH +ml1 draft/multiline #chan",
+                "@batch=ml1 PRIVMSG #chan :hello brave ",
+                "@batch=ml1;draft/multiline-concat PRIVMSG #chan :world",
+                "BATCH -ml1",
+            ]
+        );
+    }
+
+    #[test]
+    fn each_line_is_its_own_message() {
+        let limits = MultilineLimits {
+            max_bytes: 4096,
+            max_lines: None,
+        };
+
+        let lines = multiline_batch(
+            "ml1",
+            "PRIVMSG",
+            "#chan",
+            "fn main() {\n\n    run();\n}",
+            100,
+            &limits,
+        )
+        .unwrap();
+
+        assert_eq!(
+            lines,
+            vec![
+                "BATCH +ml1 draft/multiline #chan",
+                "@batch=ml1 PRIVMSG #chan :fn main() {",
+                "@batch=ml1 PRIVMSG #chan :",
+                "@batch=ml1 PRIVMSG #chan :    run();",
+                "@batch=ml1 PRIVMSG #chan :}",
+                "BATCH -ml1",
+            ]
+        );
+    }
+
+    #[test]
+    fn text_exceeding_limits_is_rejected() {
+        let limits = MultilineLimits {
+            max_bytes: 10,
+            max_lines: Some(2),
+        };
+
+        assert!(
+            multiline_batch(
+                "ml1",
+                "PRIVMSG",
+                "#chan",
+                "a".repeat(11).as_str(),
+                4,
+                &limits
+            )
+            .is_none()
+        );
+        assert!(multiline_batch("ml1", "PRIVMSG", "#chan", "abcdefghij", 3, &limits).is_none());
+        assert!(multiline_batch("ml1", "PRIVMSG", "#chan", "a\nb\nc", 3, &limits).is_none());
+    }
+}
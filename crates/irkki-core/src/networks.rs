@@ -0,0 +1,278 @@
+use log::info;
+use std::io;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use crate::{ConnectionOptions, Error, Event, IRCClient, Plugin};
+
+/// What happened on one of the networks of a [`NetworkManager`], tagged with its id.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum NetworkEvent {
+    Event {
+        network: String,
+        event: Event,
+    },
+    /// The connection to the network has closed.
+    Disconnected {
+        network: String,
+    },
+}
+
+impl NetworkEvent {
+    /// The id of the network the event came from.
+    pub fn network(&self) -> &str {
+        match self {
+            NetworkEvent::Event { network, .. } | NetworkEvent::Disconnected { network } => network,
+        }
+    }
+}
+
+/// Makes the plugin of one network, given its id.
+type PluginFactory = Box<dyn Fn(&str) -> Box<dyn Plugin> + Send>;
+
+/// Connections to several networks at once, each known by an id of its own such as `libera`.
+/// Their events are merged into one stream of [`NetworkEvent`]s.
+pub struct NetworkManager {
+    /// The connections in the order they were added.
+    networks: Vec<(String, IRCClient)>,
+    plugins: Vec<PluginFactory>,
+    sender: mpsc::Sender<NetworkEvent>,
+    receiver: mpsc::Receiver<NetworkEvent>,
+}
+
+impl Default for NetworkManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NetworkManager {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            networks: Vec::new(),
+            plugins: Vec::new(),
+            sender,
+            receiver,
+        }
+    }
+
+    /// Connects to a network without TLS and adds it as `network`, see
+    /// [`IRCClient::connect_with_options`].
+    pub fn connect(
+        &mut self,
+        network: impl Into<String>,
+        options: ConnectionOptions,
+        server: impl Into<String>,
+        port: u16,
+    ) -> Result<(), Error> {
+        let network = network.into();
+        self.check_available(&network)?;
+        let client = IRCClient::connect_with_options(options, server, port)?;
+        self.add(network, client)
+    }
+
+    /// Connects to a network over TLS and adds it as `network`.
+    #[cfg(feature = "tls")]
+    pub fn connect_tls(
+        &mut self,
+        network: impl Into<String>,
+        options: ConnectionOptions,
+        server: impl Into<String>,
+        port: u16,
+    ) -> Result<(), Error> {
+        let network = network.into();
+        self.check_available(&network)?;
+        let client = IRCClient::connect_tls_with_options(options, server, port)?;
+        self.add(network, client)
+    }
+
+    /// Adds a plugin to every network added from now on, made for each of them by `factory`.
+    /// It comes after the plugins the client already has.
+    pub fn add_plugin<F>(&mut self, factory: F)
+    where
+        F: Fn(&str) -> Box<dyn Plugin> + Send + 'static,
+    {
+        self.plugins.push(Box::new(factory));
+    }
+
+    /// Adds a connected client as `network` and starts listening to it. Fails when the id is
+    /// already taken.
+    pub fn add(&mut self, network: impl Into<String>, mut client: IRCClient) -> Result<(), Error> {
+        let network = network.into();
+        self.check_available(&network)?;
+
+        for factory in &self.plugins {
+            client.add_plugin(factory(&network));
+        }
+
+        let sender = self.sender.clone();
+        let id = network.clone();
+        let listener = client.start_listening(move |event| {
+            let event = NetworkEvent::Event {
+                network: id.clone(),
+                event,
+            };
+            sender
+                .send(event)
+                .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e.to_string()))
+        })?;
+
+        let sender = self.sender.clone();
+        let id = network.clone();
+        thread::spawn(move || {
+            let _ = listener.join();
+            info!("Disconnected from network {}.", id);
+            let _ = sender.send(NetworkEvent::Disconnected { network: id });
+        });
+
+        self.networks.push((network, client));
+        Ok(())
+    }
+
+    fn check_available(&self, network: &str) -> Result<(), Error> {
+        if network.is_empty() {
+            return Err(Error::InvalidInput("No network id given.".to_string()));
+        }
+        if self.contains(network) {
+            return Err(Error::InvalidInput(format!(
+                "There is already a network called '{network}'."
+            )));
+        }
+        Ok(())
+    }
+
+    /// Removes a network and hands back its client, which is still connected.
+    pub fn remove(&mut self, network: &str) -> Option<IRCClient> {
+        let index = self.position(network)?;
+        Some(self.networks.remove(index).1)
+    }
+
+    pub fn contains(&self, network: &str) -> bool {
+        self.position(network).is_some()
+    }
+
+    fn position(&self, network: &str) -> Option<usize> {
+        self.networks
+            .iter()
+            .position(|(id, _)| id.eq_ignore_ascii_case(network))
+    }
+
+    /// The ids of the networks, in the order they were added.
+    pub fn networks(&self) -> impl Iterator<Item = &str> {
+        self.networks.iter().map(|(id, _)| id.as_str())
+    }
+
+    pub fn client(&self, network: &str) -> Option<&IRCClient> {
+        self.position(network).map(|index| &self.networks[index].1)
+    }
+
+    pub fn client_mut(&mut self, network: &str) -> Option<&mut IRCClient> {
+        self.position(network)
+            .map(|index| &mut self.networks[index].1)
+    }
+
+    /// The events that have arrived, without waiting for more.
+    pub fn try_events(&self) -> impl Iterator<Item = NetworkEvent> + '_ {
+        self.receiver.try_iter()
+    }
+
+    /// Waits up to `timeout` for the next event from any network.
+    pub fn next_event(&self, timeout: Duration) -> Option<NetworkEvent> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    /// Quits all networks.
+    pub fn quit_all(&mut self) -> Result<(), Error> {
+        let mut result = Ok(());
+        for (_, client) in &mut self.networks {
+            if let Err(error) = client.quit() {
+                result = Err(error);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IRCEvent, PipeWriter, memory_pipe};
+    use std::io::Write;
+
+    /// A client whose server side only sends, the lines it sends are not read.
+    fn client(nickname: &str) -> (IRCClient, PipeWriter) {
+        let (client_reader, server_writer) = memory_pipe();
+        let (_server_reader, client_writer) = memory_pipe();
+        let client = IRCClient::with_transport(nickname, (client_reader, client_writer)).unwrap();
+        (client, server_writer)
+    }
+
+    fn next(manager: &NetworkManager) -> NetworkEvent {
+        manager.next_event(Duration::from_secs(2)).unwrap()
+    }
+
+    #[test]
+    fn events_are_tagged_with_their_network() {
+        let mut manager = NetworkManager::new();
+        let (libera, mut libera_server) = client("nick");
+        let (work, mut work_server) = client("nick");
+        manager.add("libera", libera).unwrap();
+        manager.add("work", work).unwrap();
+
+        libera_server
+            .write_all(b":alice!a@host PRIVMSG #rust :hi\r\n")
+            .unwrap();
+        let event = next(&manager);
+        work_server.write_all(b"ERROR :Closing link\r\n").unwrap();
+        drop(work_server);
+        let error = next(&manager);
+        let disconnected = next(&manager);
+
+        assert_eq!(event.network(), "libera");
+        assert!(matches!(
+            event,
+            NetworkEvent::Event { event, .. } if matches!(event.kind, IRCEvent::PrivMsg { .. })
+        ));
+        assert_eq!(error.network(), "work");
+        assert_eq!(
+            disconnected,
+            NetworkEvent::Disconnected {
+                network: "work".to_string()
+            }
+        );
+        assert_eq!(
+            manager.networks().collect::<Vec<_>>(),
+            vec!["libera", "work"]
+        );
+    }
+
+    #[test]
+    fn manager_can_move_to_another_thread() {
+        fn is_send<T: Send>() {}
+        is_send::<NetworkManager>();
+    }
+
+    #[test]
+    fn network_ids_are_unique() {
+        let mut manager = NetworkManager::new();
+        let (first, _first_server) = client("nick");
+        let (second, _second_server) = client("nick");
+        manager.add("libera", first).unwrap();
+
+        assert!(matches!(
+            manager.add("Libera", second),
+            Err(Error::InvalidInput(_))
+        ));
+        let (third, _third_server) = client("nick");
+        assert!(matches!(
+            manager.add("", third),
+            Err(Error::InvalidInput(message)) if message == "No network id given."
+        ));
+        assert!(manager.client("LIBERA").is_some());
+        assert!(manager.remove("libera").is_some());
+        assert!(!manager.contains("libera"));
+    }
+}
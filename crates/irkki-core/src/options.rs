@@ -0,0 +1,128 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// What the Debug output shows in place of a password.
+const REDACTED: &str = "<redacted>";
+
+/// How the client registers with a server.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ConnectionOptions {
+    pub nickname: String,
+    /// Nicknames to try, in order, when the server rejects `nickname` during registration.
+    /// Once they are used up, underscores are added to the nickname instead.
+    pub alternate_nicknames: Vec<String>,
+    /// The username sent with USER, defaults to the nickname.
+    pub username: Option<String>,
+    /// The real name sent with USER, defaults to the nickname.
+    pub realname: Option<String>,
+    /// The server password sent with PASS, needed by private networks and bouncers like ZNC.
+    pub password: Option<String>,
+    pub services_login: ServicesLogin,
+    /// Channels to join once the connection is registered.
+    pub channels: Vec<String>,
+    /// Channels that only let in users who are logged in to an account. They are joined once
+    /// services have confirmed the login.
+    pub account_channels: Vec<String>,
+    /// The file that keeps the STS policies of servers, see [`crate::StsStore`]. Without it a
+    /// policy only upgrades the connection it was advertised on.
+    pub sts_policies: Option<PathBuf>,
+    /// The id of the soju bouncer network to bind the connection to with `BOUNCER BIND`. Without
+    /// it the connection sees the bouncer's network list instead of a network.
+    pub bouncer_network: Option<String>,
+    /// When we last read a message, for ZNC's `*playback` to only play back what came after it.
+    /// Without it the whole buffer is played back.
+    pub playback_since: Option<SystemTime>,
+}
+
+impl ConnectionOptions {
+    pub fn new(nickname: impl Into<String>) -> Self {
+        Self {
+            nickname: nickname.into(),
+            alternate_nicknames: Vec::new(),
+            username: None,
+            realname: None,
+            password: None,
+            services_login: ServicesLogin::None,
+            channels: vec!["#testchannel".to_string()],
+            account_channels: Vec::new(),
+            sts_policies: None,
+            bouncer_network: None,
+            playback_since: None,
+        }
+    }
+
+    pub(crate) fn username(&self) -> &str {
+        self.username.as_deref().unwrap_or(&self.nickname)
+    }
+
+    pub(crate) fn realname(&self) -> &str {
+        self.realname.as_deref().unwrap_or(&self.nickname)
+    }
+}
+
+/// Leaves out the password, so that the options can be logged.
+impl fmt::Debug for ConnectionOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionOptions")
+            .field("nickname", &self.nickname)
+            .field("alternate_nicknames", &self.alternate_nicknames)
+            .field("username", &self.username)
+            .field("realname", &self.realname)
+            .field("password", &self.password.as_ref().map(|_| REDACTED))
+            .field("services_login", &self.services_login)
+            .field("channels", &self.channels)
+            .field("account_channels", &self.account_channels)
+            .field("sts_policies", &self.sts_policies)
+            .field("bouncer_network", &self.bouncer_network)
+            .field("playback_since", &self.playback_since)
+            .finish()
+    }
+}
+
+/// How to log in to the account of the user with the network's services.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ServicesLogin {
+    #[default]
+    None,
+    /// Logs in with SASL PLAIN during registration, when the server supports it.
+    Sasl(Credentials),
+    /// Sends `IDENTIFY` to NickServ once registered, for networks without SASL.
+    NickServ(Credentials),
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub account: String,
+    pub password: String,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("account", &self.account)
+            .field("password", &REDACTED)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_leaves_out_the_passwords() {
+        let mut options = ConnectionOptions::new("nick");
+        options.password = Some("server-secret".to_string());
+        options.services_login = ServicesLogin::Sasl(Credentials {
+            account: "nick".to_string(),
+            password: "sasl-secret".to_string(),
+        });
+
+        let debug = format!("{options:?}");
+
+        assert!(!debug.contains("secret"));
+        assert!(debug.contains(r#"password: Some("<redacted>")"#));
+        assert!(debug.contains(r#"account: "nick""#));
+    }
+}
@@ -1,30 +1,52 @@
 use log::error;
+use std::collections::HashMap;
 
-use crate::{Lexer, Token, TokenType};
+use crate::{Error, Lexer, Token, TokenType, parse_tags};
 
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 pub struct Message {
+    /// The IRCv3 message tags, with their values unescaped.
+    pub tags: HashMap<String, String>,
     pub prefix: Option<String>,
     pub command: String,
     pub params: Vec<String>,
 }
 
+impl Message {
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.get(key).map(String::as_str)
+    }
+}
+
 pub struct Parser<'a> {
+    input: &'a str,
+    tags: Option<&'a str>,
     lexer: Lexer<'a>,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(message: &'a str) -> Self {
-        let lexer = Lexer::new(message);
+        // The tags are split off here, as the lexer only knows the rest of the message.
+        let (tags, rest) = match message.strip_prefix('@') {
+            Some(tagged) => match tagged.split_once(' ') {
+                Some((tags, rest)) => (Some(tags), rest.trim_start_matches(' ')),
+                None => (Some(tagged), ""),
+            },
+            None => (None, message),
+        };
 
-        Parser { lexer }
+        Parser {
+            input: message,
+            tags,
+            lexer: Lexer::new(rest),
+        }
     }
 
-    pub fn parse_message(&mut self) -> Message {
+    pub fn parse_message(&mut self) -> Result<Message, Error> {
         let mut token = self.lexer.next_token();
 
         // Prefix handling
-        let prefix = self.parse_prefix(&token);
+        let prefix = self.parse_prefix(&token)?;
         token = if prefix.is_some() {
             // Move to the next token after prefix
             self.lexer.next_token()
@@ -32,18 +54,26 @@ impl<'a> Parser<'a> {
             token
         };
 
-        let command = self.parse_command(&token);
+        let command = self.parse_command(&token)?;
 
-        let params = self.parse_params();
+        let params = self.parse_params()?;
 
-        Message {
+        Ok(Message {
+            tags: self.tags.map(parse_tags).unwrap_or_default(),
             prefix,
             command,
             params,
+        })
+    }
+
+    fn error(&self, reason: &str) -> Error {
+        Error::Parse {
+            line: self.input.trim_end().to_string(),
+            reason: reason.to_string(),
         }
     }
 
-    fn parse_prefix(&mut self, token: &Token) -> Option<String> {
+    fn parse_prefix(&mut self, token: &Token) -> Result<Option<String>, Error> {
         if let TokenType::Colon = token.token_type {
             let prefix_token = self.lexer.next_token();
             if prefix_token.token_type != TokenType::Word {
@@ -51,7 +81,7 @@ impl<'a> Parser<'a> {
                     "parse_prefix: Expected prefix after ':', got {}",
                     prefix_token.literal
                 );
-                panic!("Expected prefix after ':'");
+                return Err(self.error("Expected prefix after ':'"));
             }
 
             let space_token = self.lexer.next_token();
@@ -60,21 +90,21 @@ impl<'a> Parser<'a> {
                     "parse_prefix: Expected space after prefix, got {}",
                     space_token.literal
                 );
-                panic!("Expected space after prefix");
+                return Err(self.error("Expected space after prefix"));
             }
 
-            return Some(prefix_token.literal.clone());
+            return Ok(Some(prefix_token.literal.clone()));
         }
-        None
+        Ok(None)
     }
 
-    fn parse_command(&mut self, token: &Token) -> String {
+    fn parse_command(&mut self, token: &Token) -> Result<String, Error> {
         if token.token_type != TokenType::Word {
             error!(
                 "parse_command: Expected command token, got {}",
                 token.literal
             );
-            panic!("Expected command token");
+            Err(self.error("Expected command token"))
         } else if !Self::is_only_based_on_letters(&token.literal)
             && !Self::is_three_digit_number(&token.literal)
         {
@@ -82,18 +112,18 @@ impl<'a> Parser<'a> {
                 "parse_command: Command must be letters or 3 digits, got {}",
                 token.literal
             );
-            panic!("Command must consist of letters only or a number with three digits.");
+            Err(self.error("Command must consist of letters only or a number with three digits."))
         } else {
-            token.literal.clone()
+            Ok(token.literal.clone())
         }
     }
 
-    fn parse_params(&mut self) -> Vec<String> {
+    fn parse_params(&mut self) -> Result<Vec<String>, Error> {
         let mut params = Vec::new();
         let mut token = self.lexer.next_token();
 
         if token.token_type == TokenType::CrLf || token.token_type == TokenType::EOF {
-            return Vec::new();
+            return Ok(Vec::new());
         }
 
         while token.token_type == TokenType::Space {
@@ -118,20 +148,20 @@ impl<'a> Parser<'a> {
                         }
 
                         params.push(trailing);
-                        return params;
+                        return Ok(params);
                     } else {
                         params.push(param_token.literal);
                     }
                 }
-                TokenType::CrLf | TokenType::EOF => return params,
+                TokenType::CrLf | TokenType::EOF => return Ok(params),
                 _ => {
-                    panic!("Expected parameter token")
+                    return Err(self.error("Expected parameter token"));
                 }
             }
 
             token = self.lexer.next_token();
             if token.token_type == TokenType::CrLf || token.token_type == TokenType::EOF {
-                return params;
+                return Ok(params);
             }
         }
 
@@ -140,10 +170,10 @@ impl<'a> Parser<'a> {
                 "parse_params: Expected new line or end of file, got {}",
                 token.literal
             );
-            panic!("Expected new line or end of file");
+            return Err(self.error("Expected new line or end of file"));
         }
 
-        params
+        Ok(params)
     }
 
     fn is_only_based_on_letters(value: &str) -> bool {
@@ -168,7 +198,7 @@ mod tests {
         let message = ":copper.libera.chat NOTICE * :*** Checking Ident\r\n";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(
             Some("copper.libera.chat".to_string()),
@@ -181,7 +211,7 @@ mod tests {
         let message = "NOTICE * :*** Checking Ident\r\n";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(None, parsed_message.prefix);
     }
@@ -191,7 +221,7 @@ mod tests {
         let message = ":copper.libera.chat NOTICE * :*** Checking Ident\r\n";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!("NOTICE", parsed_message.command);
     }
@@ -201,11 +231,9 @@ mod tests {
         let message = ":copper.libera.chat N0T1C3 * :*** Checking Ident\r\n";
         let mut parser = Parser::new(message);
 
-        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-            parser.parse_message();
-        }));
+        let result = parser.parse_message();
 
-        assert!(result.is_err());
+        assert!(matches!(result, Err(Error::Parse { .. })));
     }
 
     #[test]
@@ -213,7 +241,7 @@ mod tests {
         let message = ":copper.libera.chat 001 copper :Welcome to the IRC server\r\n";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!("001", parsed_message.command);
     }
@@ -223,7 +251,7 @@ mod tests {
         let message = "foo bar baz asdf";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(None, parsed_message.prefix);
         assert_eq!("foo", parsed_message.command);
@@ -238,7 +266,7 @@ mod tests {
         let message = ":coolguy foo bar baz asdf";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(Some("coolguy".to_string()), parsed_message.prefix);
         assert_eq!("foo", parsed_message.command);
@@ -253,7 +281,7 @@ mod tests {
         let message = "foo bar baz :asdf quux";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(None, parsed_message.prefix);
         assert_eq!("foo", parsed_message.command);
@@ -272,7 +300,7 @@ mod tests {
         let message = "foo bar baz :";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(None, parsed_message.prefix);
         assert_eq!("foo", parsed_message.command);
@@ -287,7 +315,7 @@ mod tests {
         let message = "foo bar baz ::asdf";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(None, parsed_message.prefix);
         assert_eq!("foo", parsed_message.command);
@@ -302,7 +330,7 @@ mod tests {
         let message = ":coolguy foo bar baz :asdf quux";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(Some("coolguy".to_string()), parsed_message.prefix);
         assert_eq!("foo", parsed_message.command);
@@ -321,7 +349,7 @@ mod tests {
         let message = ":coolguy foo bar baz :  asdf quux ";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(Some("coolguy".to_string()), parsed_message.prefix);
         assert_eq!("foo", parsed_message.command);
@@ -340,7 +368,7 @@ mod tests {
         let message = ":coolguy PRIVMSG bar :lol :) ";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(Some("coolguy".to_string()), parsed_message.prefix);
         assert_eq!("PRIVMSG", parsed_message.command);
@@ -350,12 +378,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_message_with_multibyte_text() {
+        let message = ":a!b@c PRIVMSG #c :héllo wörld\r\n";
+        let mut parser = Parser::new(message);
+
+        let parsed_message = parser.parse_message().unwrap();
+
+        assert_eq!(Some("a!b@c".to_string()), parsed_message.prefix);
+        assert_eq!(
+            vec!["#c".to_string(), "héllo wörld".to_string()],
+            parsed_message.params
+        );
+    }
+
+    #[test]
+    fn parse_message_with_multibyte_command_is_an_error() {
+        let result = Parser::new(":ä PRIVMSGé #c :x\r\n").parse_message();
+
+        assert!(matches!(result, Err(Error::Parse { .. })));
+    }
+
     #[test]
     fn parse_message_with_prefix_and_empty_trailing() {
         let message = ":coolguy foo bar baz :";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(Some("coolguy".to_string()), parsed_message.prefix);
         assert_eq!("foo", parsed_message.command);
@@ -370,7 +419,7 @@ mod tests {
         let message = ":coolguy foo bar baz :  ";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(Some("coolguy".to_string()), parsed_message.prefix);
         assert_eq!("foo", parsed_message.command);
@@ -385,7 +434,7 @@ mod tests {
         let message = ":src JOIN #chan";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(Some("src".to_string()), parsed_message.prefix);
         assert_eq!("JOIN", parsed_message.command);
@@ -397,7 +446,7 @@ mod tests {
         let message = ":src JOIN :#chan";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(Some("src".to_string()), parsed_message.prefix);
         assert_eq!("JOIN", parsed_message.command);
@@ -409,7 +458,7 @@ mod tests {
         let message = ":src AWAY";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(Some("src".to_string()), parsed_message.prefix);
         assert_eq!("AWAY", parsed_message.command);
@@ -421,7 +470,7 @@ mod tests {
         let message = ":src AWAY ";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(Some("src".to_string()), parsed_message.prefix);
         assert_eq!("AWAY", parsed_message.command);
@@ -433,7 +482,7 @@ mod tests {
         let message = ":cool\tguy foo bar baz";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(Some("cool\tguy".to_string()), parsed_message.prefix);
         assert_eq!("foo", parsed_message.command);
@@ -448,7 +497,7 @@ mod tests {
         let message = ":coolguy!ag@net\x035w\x03ork.admin PRIVMSG foo :bar baz";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(
             Some("coolguy!ag@net\x035w\x03ork.admin".to_string()),
@@ -466,7 +515,7 @@ mod tests {
         let message = ":coolguy!~ag@n\x02et\x0305w\x0fork.admin PRIVMSG foo :bar baz";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(
             Some("coolguy!~ag@n\x02et\x0305w\x0fork.admin".to_string()),
@@ -484,7 +533,7 @@ mod tests {
         let message = ":irc.example.com COMMAND param1 param2 :param3 param3";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(Some("irc.example.com".to_string()), parsed_message.prefix);
         assert_eq!("COMMAND", parsed_message.command);
@@ -503,7 +552,7 @@ mod tests {
         let message = "COMMAND";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(None, parsed_message.prefix);
         assert_eq!("COMMAND", parsed_message.command);
@@ -515,7 +564,7 @@ mod tests {
         let message = ":gravel.mozilla.org 432  #momo :Erroneous Nickname: Illegal characters";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(
             Some("gravel.mozilla.org".to_string()),
@@ -536,7 +585,7 @@ mod tests {
         let message = ":gravel.mozilla.org MODE #tckk +n ";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(
             Some("gravel.mozilla.org".to_string()),
@@ -554,7 +603,7 @@ mod tests {
         let message = ":services.esper.net MODE #foo-bar +o foobar  ";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(
             Some("services.esper.net".to_string()),
@@ -576,7 +625,7 @@ mod tests {
         let message = ":SomeOp MODE #channel :+i";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(Some("SomeOp".to_string()), parsed_message.prefix);
         assert_eq!("MODE", parsed_message.command);
@@ -586,12 +635,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_message_with_tags() {
+        let message = "@time=2011-10-19T16:40:51.620Z;msgid=63E1033A051D4B41 :nick!user@host PRIVMSG #channel :Hello\r\n";
+        let mut parser = Parser::new(message);
+
+        let parsed_message = parser.parse_message().unwrap();
+
+        assert_eq!(parsed_message.tag("time"), Some("2011-10-19T16:40:51.620Z"));
+        assert_eq!(parsed_message.tag("msgid"), Some("63E1033A051D4B41"));
+        assert_eq!(Some("nick!user@host".to_string()), parsed_message.prefix);
+        assert_eq!("PRIVMSG", parsed_message.command);
+        assert_eq!(
+            vec!["#channel".to_string(), "Hello".to_string()],
+            parsed_message.params
+        );
+    }
+
+    #[test]
+    fn parse_message_with_only_tags_is_an_error() {
+        let mut parser = Parser::new("@msgid=abc\r\n");
+
+        assert!(matches!(parser.parse_message(), Err(Error::Parse { .. })));
+    }
+
     #[test]
     fn parse_message_mode_trailing_user() {
         let message = ":SomeOp MODE #channel +oo SomeUser :AnotherUser";
         let mut parser = Parser::new(message);
 
-        let parsed_message = parser.parse_message();
+        let parsed_message = parser.parse_message().unwrap();
 
         assert_eq!(Some("SomeOp".to_string()), parsed_message.prefix);
         assert_eq!("MODE", parsed_message.command);
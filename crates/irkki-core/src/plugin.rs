@@ -0,0 +1,214 @@
+use log::error;
+
+use crate::{Event, Protocol};
+
+/// A hook into a connection, for behaviour such as logging, auto-replies or filtering that
+/// should not live in the event handler.
+///
+/// A plugin sees every event before the handler and every line before it is sent, and can change
+/// or drop them. Events pass the plugins in the order they were added, and outgoing lines pass
+/// them in the reverse order, so the plugin added first is the one nearest to the server both
+/// ways.
+pub trait Plugin: Send {
+    /// Handles an event. Returns it, changed or not, for the next plugin, or `None` to drop it.
+    /// Replies queued on `context` are sent once the event has passed every plugin.
+    fn on_event(&mut self, event: Event, _context: &mut PluginContext) -> Option<Event> {
+        Some(event)
+    }
+
+    /// Handles a line on its way to the server, without its CR-LF. Returns it, changed or not,
+    /// for the next plugin, or `None` to drop it.
+    fn on_outgoing(&mut self, line: String) -> Option<String> {
+        Some(line)
+    }
+}
+
+enum Reply {
+    Message { target: String, text: String },
+    Notice { target: String, text: String },
+    Raw(String),
+}
+
+/// What a plugin knows about the connection while it handles an event, and the replies it wants
+/// to send. The replies pass the outgoing hooks of every plugin like other lines.
+pub struct PluginContext {
+    nickname: String,
+    replies: Vec<Reply>,
+}
+
+impl PluginContext {
+    /// A context for our nickname, to call the hooks of a plugin with, for example in tests.
+    pub fn new(nickname: impl Into<String>) -> Self {
+        Self {
+            nickname: nickname.into(),
+            replies: Vec::new(),
+        }
+    }
+
+    /// Our nickname on the connection.
+    pub fn nickname(&self) -> &str {
+        &self.nickname
+    }
+
+    /// Sends a PRIVMSG, split like the ones sent with `/msg` when it is too long for one line.
+    pub fn send_message(&mut self, target: impl Into<String>, text: impl Into<String>) {
+        self.replies.push(Reply::Message {
+            target: target.into(),
+            text: text.into(),
+        });
+    }
+
+    pub fn send_notice(&mut self, target: impl Into<String>, text: impl Into<String>) {
+        self.replies.push(Reply::Notice {
+            target: target.into(),
+            text: text.into(),
+        });
+    }
+
+    /// Sends a line as it is. Anything after a line break is left out.
+    pub fn send_raw(&mut self, line: impl Into<String>) {
+        let line = line.into();
+        let line = line.lines().next().unwrap_or_default();
+        if !line.is_empty() {
+            self.replies.push(Reply::Raw(line.to_string()));
+        }
+    }
+
+    pub(crate) fn has_replies(&self) -> bool {
+        !self.replies.is_empty()
+    }
+
+    /// Queues the replies on the protocol, for the client to send. A reply that fails is logged
+    /// and skipped, so that a bad reply of one plugin does not close the connection.
+    pub(crate) fn send_replies(self, protocol: &mut Protocol) {
+        for reply in self.replies {
+            let sent = match reply {
+                Reply::Message { target, text } => protocol.send_private_message(target, text),
+                Reply::Notice { target, text } => protocol.send_notice(target, text),
+                Reply::Raw(line) => {
+                    protocol.queue(line);
+                    Ok(())
+                }
+            };
+            if let Err(error) = sent {
+                error!("Failed to send the reply of a plugin: {}", error);
+            }
+        }
+    }
+}
+
+/// The plugins of a connection, in the order they were added.
+#[derive(Default)]
+pub(crate) struct Plugins {
+    plugins: Vec<Box<dyn Plugin>>,
+}
+
+impl Plugins {
+    pub(crate) fn add(&mut self, plugin: Box<dyn Plugin>) {
+        self.plugins.push(plugin);
+    }
+
+    /// Passes an event through the plugins in the order they were added.
+    pub(crate) fn on_event(&mut self, event: Event, context: &mut PluginContext) -> Option<Event> {
+        self.plugins
+            .iter_mut()
+            .try_fold(event, |event, plugin| plugin.on_event(event, context))
+    }
+
+    /// Passes a line through the plugins in the reverse order they were added.
+    pub(crate) fn on_outgoing(&mut self, line: String) -> Option<String> {
+        self.plugins
+            .iter_mut()
+            .rev()
+            .try_fold(line, |line, plugin| plugin.on_outgoing(line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IRCEvent, Parser};
+
+    /// Adds its name to the text of messages and to outgoing lines, and drops what mentions it.
+    struct Tag(&'static str);
+
+    impl Plugin for Tag {
+        fn on_event(&mut self, mut event: Event, context: &mut PluginContext) -> Option<Event> {
+            if let IRCEvent::PrivMsg { source, text, .. } = &mut event.kind {
+                if text.contains(&format!("drop {}", self.0)) {
+                    return None;
+                }
+                context.send_notice(&source.nickname, format!("seen by {}", self.0));
+                text.push_str(&format!(" {}", self.0));
+            }
+            Some(event)
+        }
+
+        fn on_outgoing(&mut self, line: String) -> Option<String> {
+            if line.contains(&format!("drop {}", self.0)) {
+                return None;
+            }
+            Some(format!("{line} {}", self.0))
+        }
+    }
+
+    fn event(line: &str) -> Event {
+        Event::new(IRCEvent::from_message(
+            Parser::new(line).parse_message().unwrap(),
+        ))
+    }
+
+    fn plugins() -> Plugins {
+        let mut plugins = Plugins::default();
+        plugins.add(Box::new(Tag("first")));
+        plugins.add(Box::new(Tag("second")));
+        plugins
+    }
+
+    #[test]
+    fn events_pass_the_plugins_in_the_order_they_were_added() {
+        let mut plugins = plugins();
+        let mut context = PluginContext::new("nick");
+
+        let passed = plugins.on_event(event(":alice!a@host PRIVMSG nick :hi"), &mut context);
+        let dropped = plugins.on_event(
+            event(":alice!a@host PRIVMSG nick :drop first"),
+            &mut context,
+        );
+
+        assert!(matches!(
+            passed.map(|event| event.kind),
+            Some(IRCEvent::PrivMsg { text, .. }) if text == "hi first second"
+        ));
+        assert!(dropped.is_none());
+
+        let mut protocol = Protocol::new("nick");
+        while protocol.poll_outgoing().is_some() {}
+        assert!(context.has_replies());
+        context.send_replies(&mut protocol);
+        let replies: Vec<String> = std::iter::from_fn(|| protocol.poll_outgoing())
+            .map(|(line, _)| line)
+            .collect();
+        assert_eq!(
+            replies,
+            vec![
+                "NOTICE alice :seen by first",
+                "NOTICE alice :seen by second"
+            ]
+        );
+    }
+
+    #[test]
+    fn outgoing_lines_pass_the_plugins_in_the_reverse_order() {
+        let mut plugins = plugins();
+
+        assert_eq!(
+            plugins.on_outgoing("PRIVMSG #rust :hi".to_string()),
+            Some("PRIVMSG #rust :hi second first".to_string())
+        );
+        assert_eq!(
+            plugins.on_outgoing("PRIVMSG #rust :drop second".to_string()),
+            None
+        );
+    }
+}
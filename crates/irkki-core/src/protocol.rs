@@ -0,0 +1,2356 @@
+use log::{debug, error, info, warn};
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+use crate::{
+    BOUNCER_NETWORKS_CAP, Batches, Bouncer, BouncerNetwork, CONCAT_TAG, Capabilities, Channels,
+    ChatHistory, ConnectionOptions, Credentials, DccRequest, Error, Event, HISTORY_BATCH,
+    HistoryRequest, HistoryTarget, IRCEvent, ISupport, LABELED_BATCH, Labels, LineLimits,
+    MULTILINE_BATCH, Message, Monitor, MultilineLimits, NotifyList, PLAYBACK_CAP, Parser, Priority,
+    ReplyKind, Response, Security, Selector, ServerError, ServicesLogin, Source, StsPolicy,
+    TARGETS_BATCH, TypingState, User, WHOX_FIELDS, WhoReplies, WhoisInfo, WhoisReplies,
+    escape_tag_value, format_attributes, join_within, multiline_batch, parse_server_time,
+    plain_authenticate_lines, playback_command, response, split_message,
+};
+
+/// Capabilities the client asks for when the server offers them.
+const REQUESTED_CAPABILITIES: &[&str] = &[
+    "account-notify",
+    "account-tag",
+    "away-notify",
+    "batch",
+    "chghost",
+    "draft/chathistory",
+    "draft/multiline",
+    "echo-message",
+    "extended-join",
+    "invite-notify",
+    "labeled-response",
+    "message-tags",
+    "server-time",
+    "setname",
+    "soju.im/bouncer-networks",
+    "soju.im/bouncer-networks-notify",
+    "znc.in/playback",
+];
+
+/// How many underscores may be added to the nickname when the server rejects it, after the
+/// alternate nicknames are used up.
+const MAX_NICKNAME_SUFFIXES: usize = 3;
+
+/// Parts of the notices NickServ sends when the login succeeded, from Atheme and Anope.
+const NICKSERV_CONFIRMATIONS: &[&str] = &[
+    "you are now identified",
+    "you are now recognized",
+    "password accepted",
+];
+
+/// Where the connection is in its registration with the server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegistrationState {
+    /// NICK and USER are sent, but the server holds the registration until the capability
+    /// negotiation has ended.
+    Negotiating,
+    /// Logging in with SASL.
+    Authenticating,
+    /// Waiting for the server to welcome us with RPL_WELCOME.
+    Registering,
+    Registered,
+}
+
+/// The protocol state of one IRC connection.
+///
+/// `Protocol` does no I/O. It is fed the lines read from the server and turns them into events,
+/// and it collects the lines that should be sent, which the caller drains with
+/// [`Protocol::poll_outgoing`]. This lets the blocking [`crate::IRCClient`] and the async client
+/// share the same handling of the protocol.
+pub struct Protocol {
+    options: ConnectionOptions,
+    registration: RegistrationState,
+    nicknames_tried: usize,
+    /// The account we are logged in to, once services have confirmed it.
+    account: Option<String>,
+    nickname: String,
+    channel: String,
+    /// Our `nick!user@host` as seen by other clients, once the server has told us.
+    source: Option<String>,
+    isupport: ISupport,
+    capabilities: Capabilities,
+    message_of_the_day: Vec<String>,
+    channels: Channels,
+    batches: Batches,
+    history: ChatHistory,
+    labels: Labels,
+    who_replies: WhoReplies,
+    whois_replies: WhoisReplies,
+    monitor: Monitor,
+    /// Whether the notify list has been sent to the server since registering.
+    notify_started: bool,
+    bouncer: Bouncer,
+    /// The server-time of the latest message we got since registering, see
+    /// [`Protocol::last_seen`].
+    last_seen: Option<SystemTime>,
+    security: Security,
+    /// The TLS port the server asked us to reconnect to with STS. The registration stops.
+    sts_upgrade: Option<u16>,
+    outgoing: VecDeque<(String, Priority)>,
+    batches_sent: u64,
+}
+
+impl Protocol {
+    pub fn new(nickname: impl Into<String>) -> Self {
+        Self::with_options(ConnectionOptions::new(nickname))
+    }
+
+    pub fn with_options(options: ConnectionOptions) -> Self {
+        Self {
+            nickname: options.nickname.clone(),
+            channel: options.channels.first().cloned().unwrap_or_default(),
+            last_seen: options.playback_since,
+            options,
+            registration: RegistrationState::Negotiating,
+            nicknames_tried: 0,
+            account: None,
+            source: None,
+            isupport: ISupport::new(),
+            capabilities: Capabilities::new(),
+            message_of_the_day: Vec::new(),
+            channels: Channels::new(),
+            batches: Batches::default(),
+            history: ChatHistory::default(),
+            labels: Labels::default(),
+            who_replies: WhoReplies::default(),
+            whois_replies: WhoisReplies::default(),
+            monitor: Monitor::default(),
+            notify_started: false,
+            bouncer: Bouncer::default(),
+            security: Security::default(),
+            sts_upgrade: None,
+            outgoing: VecDeque::new(),
+            batches_sent: 0,
+        }
+    }
+
+    pub fn nickname(&self) -> &str {
+        &self.nickname
+    }
+
+    pub fn isupport(&self) -> &ISupport {
+        &self.isupport
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    pub fn channels(&self) -> &Channels {
+        &self.channels
+    }
+
+    pub fn notify_list(&self) -> &NotifyList {
+        self.monitor.list()
+    }
+
+    pub fn registration_state(&self) -> RegistrationState {
+        self.registration
+    }
+
+    pub fn account(&self) -> Option<&str> {
+        self.account.as_deref()
+    }
+
+    /// Tells how the connection is protected, which decides what happens with the `sts`
+    /// capability: over TLS the policy is reported with [`IRCEvent::StsPolicy`], and over an
+    /// upgradable plaintext connection the registration stops with [`IRCEvent::StsUpgrade`].
+    pub fn set_security(&mut self, security: Security) {
+        self.security = security;
+    }
+
+    /// Queues the lines that register the connection with the server.
+    ///
+    /// Registration runs CAP → SASL → NICK/USER → RPL_WELCOME. NICK and USER go out right away,
+    /// since servers without CAP support register on them, while servers with CAP support wait
+    /// for CAP END before they welcome us. Channels are joined once we are welcomed.
+    pub fn register(&mut self) {
+        self.registration = RegistrationState::Negotiating;
+        self.queue("CAP LS 302");
+        if let Some(password) = self.options.password.clone() {
+            self.queue(format!("PASS {password}"));
+        }
+        self.queue(format!("NICK {}", self.nickname));
+        self.queue(format!(
+            "USER {} 0 * :{}",
+            self.options.username(),
+            self.options.realname()
+        ));
+    }
+
+    /// Takes the next line that should be sent to the server.
+    pub fn poll_outgoing(&mut self) -> Option<(String, Priority)> {
+        self.outgoing.pop_front()
+    }
+
+    pub(crate) fn queue(&mut self, line: impl Into<String>) {
+        self.queue_with_priority(line, Priority::Normal);
+    }
+
+    fn queue_with_priority(&mut self, line: impl Into<String>, priority: Priority) {
+        let line = line.into();
+        debug!("Queueing line: {}", line);
+        self.outgoing.push_back((line, priority));
+    }
+
+    /// Handles a line received from the server and returns the resulting events.
+    pub fn handle_line(&mut self, line: &str) -> Vec<Event> {
+        info!("Received line: {}", line.trim_end());
+
+        match Parser::new(line).parse_message() {
+            Ok(message) => self.handle_message(message),
+            Err(error) => {
+                warn!("Ignoring line: {}", error);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Handles a message from the server. The events are stamped with the message's
+    /// `server-time`, `msgid` and `label` tags.
+    ///
+    /// Events of batched messages are held back until the batch ends, and are then delivered
+    /// together as one [`IRCEvent::Batch`], or as a netsplit or netjoin. Replies to CHATHISTORY
+    /// requests and labeled commands complete the responses waiting for them.
+    pub fn handle_message(&mut self, message: Message) -> Vec<Event> {
+        let time = message
+            .tag("time")
+            .and_then(parse_server_time)
+            .unwrap_or_else(SystemTime::now);
+        let msgid = message.tag("msgid").map(str::to_string);
+        let reply_to = message.tag("+draft/reply").map(str::to_string);
+        let label = message.tag("label").map(str::to_string);
+        let account = message.tag("account").map(str::to_string).or_else(|| {
+            let nickname = Source::parse(message.prefix.as_deref()?).nickname;
+            self.channels.user(&nickname)?.account.clone()
+        });
+        let stamp = |kind| Event {
+            time,
+            msgid: msgid.clone(),
+            reply_to: reply_to.clone(),
+            label: label.clone(),
+            account: account.clone(),
+            kind,
+        };
+
+        if message.command == "BATCH" {
+            let finished = self.batches.handle(&message, time);
+            return self.deliver(finished.into_iter().collect());
+        }
+
+        let batch = message.tag("batch").map(str::to_string);
+        if matches!(
+            self.batches.kind(batch.as_deref()),
+            Some(HISTORY_BATCH | TARGETS_BATCH)
+        ) {
+            // Replayed messages must not change the state of the connection, and those that were
+            // already seen are dropped.
+            if msgid
+                .as_deref()
+                .is_some_and(|msgid| self.history.see(msgid))
+            {
+                return Vec::new();
+            }
+            let event = stamp(IRCEvent::from_message(message));
+            return self
+                .batches
+                .add(batch.as_deref(), event)
+                .into_iter()
+                .collect();
+        }
+        if let Some(msgid) = &msgid {
+            self.history.see(msgid);
+        }
+        if self.registration == RegistrationState::Registered
+            && matches!(message.command.as_str(), "PRIVMSG" | "NOTICE")
+            && let Some(seen) = message.tag("time").and_then(parse_server_time)
+        {
+            self.last_seen = self.last_seen.max(Some(seen));
+        }
+        // A line continuing the previous one of a multiline message is appended to it.
+        if message.tag(CONCAT_TAG).is_some()
+            && self.batches.kind(batch.as_deref()) == Some(MULTILINE_BATCH)
+            && let Some(text) = message.params.get(1)
+            && self.batches.concat(batch.as_deref(), text)
+        {
+            return Vec::new();
+        }
+
+        let events = self
+            .events_from(message)
+            .into_iter()
+            .filter_map(|kind| self.batches.add(batch.as_deref(), stamp(kind)))
+            .collect();
+        self.deliver(events)
+    }
+
+    /// Hands the events that answer CHATHISTORY requests and labeled commands to their
+    /// responses, and returns those that should be delivered.
+    fn deliver(&mut self, events: Vec<Event>) -> Vec<Event> {
+        let mut delivered = Vec::new();
+
+        for event in events {
+            let Some(mut event) = self.history.complete(event) else {
+                continue;
+            };
+            // CHATHISTORY replies to a labeled request are nested in the labeled batch.
+            if let IRCEvent::Batch(batch) = &mut event.kind
+                && batch.kind == LABELED_BATCH
+            {
+                let nested = std::mem::take(&mut batch.events);
+                batch.events = nested
+                    .into_iter()
+                    .filter_map(|event| self.history.complete(event))
+                    .collect();
+            }
+            self.bouncer.complete(&event.kind);
+            if self.labels.complete(&event) {
+                delivered.push(event);
+            }
+        }
+
+        delivered
+    }
+
+    fn events_from(&mut self, message: Message) -> Vec<IRCEvent> {
+        self.update_server_state(&message);
+        let sts = self.update_sts(&message);
+        let registered = self.update_registration(&message);
+        let logged_in = self.update_login(&message);
+
+        let mut events = Vec::new();
+        match message.command.as_str() {
+            "FAIL" | "WARN" | "NOTE" => {
+                let event = IRCEvent::from_message(message);
+                if let IRCEvent::StandardReply(reply) = &event {
+                    match reply.kind {
+                        ReplyKind::Fail => error!("Server replied with {}", reply),
+                        ReplyKind::Warn => warn!("Server replied with {}", reply),
+                        ReplyKind::Note => info!("Server replied with {}", reply),
+                    }
+                    if reply.kind == ReplyKind::Fail && reply.command == "CHATHISTORY" {
+                        self.history.fail(ServerError::from(reply.clone()));
+                    }
+                    if reply.kind == ReplyKind::Fail && reply.command == "BOUNCER" {
+                        self.bouncer.fail(reply);
+                    }
+                }
+                events.push(event);
+            }
+            "BOUNCER" => events.extend(self.bouncer.handle(&message)),
+            "PING" => {
+                debug!("Received PING, sending PONG response.");
+                let response = format!("PONG :{}", message.params.join(" "));
+                self.queue_with_priority(response, Priority::High);
+            }
+            // RPL_WHOREPLY and RPL_WHOSPCRPL
+            "352" | "354" => {
+                if let Some(reply) = self.who_replies.add(&message) {
+                    let mut user = reply.user;
+                    if !reply.has_account {
+                        user.account = self.channels.user_or_default(&user.nickname).account;
+                    }
+                    self.channels.update_user(user);
+                }
+            }
+            // RPL_ISON, RPL_MONONLINE, RPL_MONOFFLINE, RPL_MONLIST, RPL_ENDOFMONLIST and
+            // ERR_MONLISTFULL
+            "303" | "730" | "731" | "732" | "733" | "734" => {
+                events.extend(self.monitor.handle(&message));
+            }
+            // RPL_ENDOFWHO
+            "315" => {
+                let (mask, users) = self.who_replies.finish(&message);
+                events.push(IRCEvent::Who { mask, users });
+            }
+            // RPL_WHOISUSER, RPL_WHOISSERVER, RPL_WHOISOPERATOR, RPL_WHOISIDLE,
+            // RPL_ENDOFWHOIS, RPL_WHOISCHANNELS, RPL_WHOISACCOUNT and RPL_WHOISSECURE
+            "311" | "312" | "313" | "317" | "318" | "319" | "330" | "671" => {
+                if let Some(info) = self.whois_replies.handle(&message) {
+                    debug!("Received end of WHOIS response.");
+                    events.push(IRCEvent::Whois(info));
+                }
+            }
+            // RPL_NAMREPLY
+            "353" => {
+                if let Some(names) = message.params.last() {
+                    let mut users: Vec<String> = Vec::new();
+                    for nick in names.split_whitespace() {
+                        if !nick.is_empty() {
+                            users.push(nick.to_string());
+                        }
+                    }
+                    debug!("Received NAMES list: {}", users.join(", "));
+                    events.push(IRCEvent::Users(users));
+                }
+            }
+            // RPL_ENDOFNAMES
+            "366" => {
+                debug!("End of NAMES list.");
+            }
+            // RPL_MOTD
+            "372" => {
+                if let Some(motd_line) = message.params.last() {
+                    self.message_of_the_day.push(motd_line.to_string());
+                }
+            }
+            // RPL_MOTDSTART
+            "375" => {
+                debug!("Start of MOTD.");
+                self.message_of_the_day.clear();
+            }
+            // RPL_ENDOFMOTD
+            "376" => {
+                debug!("End of MOTD.");
+                events.push(IRCEvent::MessageOfTheDay(self.message_of_the_day.clone()));
+            }
+            _ => match ServerError::from_message(&message) {
+                Some(server_error) => {
+                    error!("Server replied with an error: {}", server_error);
+                    self.whois_replies.fail(&server_error);
+                    events.push(IRCEvent::ServerError(server_error));
+                }
+                None => events.push(IRCEvent::from_message(message)),
+            },
+        }
+
+        events.extend(sts);
+        events.extend(registered);
+        events.extend(logged_in);
+        events
+    }
+
+    /// Follows the STS policy offered with CAP LS or CAP NEW, see [`Protocol::set_security`].
+    fn update_sts(&mut self, message: &Message) -> Option<IRCEvent> {
+        let subcommand = message.params.get(1).map(String::as_str);
+        if message.command != "CAP" || !matches!(subcommand, Some("LS" | "NEW")) {
+            return None;
+        }
+        let policy = message
+            .params
+            .last()?
+            .split_whitespace()
+            .find_map(|cap| cap.strip_prefix("sts="))
+            .and_then(StsPolicy::parse)?;
+
+        match self.security {
+            Security::Plaintext => None,
+            Security::Upgradable => {
+                let port = policy.port?;
+                info!("Server requires TLS, upgrading to port {}.", port);
+                self.sts_upgrade = Some(port);
+                Some(IRCEvent::StsUpgrade { port })
+            }
+            Security::Tls => policy
+                .duration
+                .map(|duration| IRCEvent::StsPolicy { duration }),
+        }
+    }
+
+    /// Moves the registration along, see [`Protocol::register`]. Returns the `Registered`
+    /// event once the server has welcomed us.
+    fn update_registration(&mut self, message: &Message) -> Option<IRCEvent> {
+        let param = |i: usize| message.params.get(i).map(String::as_str);
+
+        if self.sts_upgrade.is_some() {
+            return None;
+        }
+
+        match (self.registration, message.command.as_str()) {
+            (RegistrationState::Registered, _) => {}
+            // RPL_WELCOME
+            (_, "001") => {
+                self.registration = RegistrationState::Registered;
+                info!("Registered as {}.", self.nickname);
+                for channel in self.options.channels.clone() {
+                    self.queue(format!("JOIN {channel}"));
+                }
+                // With the capability ZNC waits to be asked before playing back its buffers.
+                if self.capabilities.is_enabled(PLAYBACK_CAP) {
+                    self.queue(playback_command("*", self.last_seen));
+                }
+                if let ServicesLogin::NickServ(credentials) = &self.options.services_login {
+                    let identify = format!(
+                        "PRIVMSG NickServ :IDENTIFY {} {}",
+                        credentials.account, credentials.password
+                    );
+                    self.queue(identify);
+                }
+                if self.account.is_some() || self.options.services_login == ServicesLogin::None {
+                    self.join_account_channels();
+                }
+                return Some(IRCEvent::Registered {
+                    nickname: self.nickname.clone(),
+                });
+            }
+            // The last line of CAP LS has no `*` before the capabilities.
+            (RegistrationState::Negotiating, "CAP")
+                if param(1) == Some("LS") && param(2) != Some("*") =>
+            {
+                self.request_capabilities();
+            }
+            (RegistrationState::Negotiating, "CAP") if param(1) == Some("ACK") => {
+                if self.capabilities.is_enabled("sasl") && self.sasl_credentials().is_some() {
+                    self.registration = RegistrationState::Authenticating;
+                    self.queue("AUTHENTICATE PLAIN");
+                } else {
+                    self.end_negotiation();
+                }
+            }
+            (RegistrationState::Negotiating, "CAP") if param(1) == Some("NAK") => {
+                self.end_negotiation();
+            }
+            // ERR_UNKNOWNCOMMAND, for servers without CAP support.
+            (RegistrationState::Negotiating, "421") if param(1) == Some("CAP") => {
+                self.registration = RegistrationState::Registering;
+            }
+            (RegistrationState::Authenticating, "AUTHENTICATE") if param(0) == Some("+") => {
+                let lines = self
+                    .sasl_credentials()
+                    .map(plain_authenticate_lines)
+                    .unwrap_or_default();
+                for line in lines {
+                    self.queue(line);
+                }
+            }
+            // RPL_SASLSUCCESS and ERR_SASLALREADY end the authentication.
+            (RegistrationState::Authenticating, "903" | "907") => {
+                self.end_negotiation();
+            }
+            // ERR_NICKLOCKED, ERR_SASLFAIL, ERR_SASLTOOLONG and ERR_SASLABORTED. Going on without
+            // the login would register us without the account the user asked for.
+            (RegistrationState::Authenticating, "902" | "904" | "905" | "906") => {
+                return self.fail_registration(message);
+            }
+            // ERR_ERRONEUSNICKNAME and ERR_NICKNAMEINUSE
+            (_, "432" | "433") => match self.next_nickname() {
+                Some(nickname) => {
+                    info!("Nickname rejected, trying '{}'.", nickname);
+                    self.queue(format!("NICK {nickname}"));
+                    self.nickname = nickname;
+                }
+                None => return self.fail_registration(message),
+            },
+            // ERR_NONICKNAMEGIVEN, ERR_NICKCOLLISION and ERR_PASSWDMISMATCH are not fixed by
+            // trying again.
+            (_, "431" | "436" | "464") => return self.fail_registration(message),
+            _ => {}
+        }
+
+        None
+    }
+
+    /// Gives up the registration after an error it cannot recover from, and quits.
+    fn fail_registration(&mut self, message: &Message) -> Option<IRCEvent> {
+        let error = ServerError::from_message(message)?;
+        error!("Registration failed: {}", error);
+        self.queue("QUIT");
+        Some(IRCEvent::RegistrationFailed(error))
+    }
+
+    fn request_capabilities(&mut self) {
+        let sasl = self
+            .sasl_credentials()
+            .filter(|_| {
+                self.capabilities
+                    .value("sasl")
+                    .is_none_or(|mechanisms| mechanisms.split(',').any(|m| m == "PLAIN"))
+            })
+            .map(|_| "sasl");
+        let wanted: Vec<&str> = REQUESTED_CAPABILITIES
+            .iter()
+            .copied()
+            .chain(sasl)
+            .filter(|name| self.capabilities.is_available(name))
+            .collect();
+
+        if wanted.is_empty() {
+            self.end_negotiation();
+        } else {
+            self.queue(format!("CAP REQ :{}", wanted.join(" ")));
+        }
+    }
+
+    fn sasl_credentials(&self) -> Option<&Credentials> {
+        match &self.options.services_login {
+            ServicesLogin::Sasl(credentials) => Some(credentials),
+            _ => None,
+        }
+    }
+
+    /// Notices when services have logged us in, either with RPL_LOGGEDIN or with the notice
+    /// NickServ sends after IDENTIFY, and joins the channels that need an account.
+    fn update_login(&mut self, message: &Message) -> Option<IRCEvent> {
+        let account = match message.command.as_str() {
+            // RPL_LOGGEDIN
+            "900" => message.params.get(2).cloned(),
+            // RPL_LOGGEDOUT
+            "901" => {
+                self.account = None;
+                None
+            }
+            "NOTICE" => match &self.options.services_login {
+                ServicesLogin::NickServ(credentials) if Self::is_nickserv_confirmation(message) => {
+                    Some(credentials.account.clone())
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+
+        let account = account.filter(|_| self.account.is_none())?;
+        info!("Logged in as {}.", account);
+        self.account = Some(account.clone());
+        if self.registration == RegistrationState::Registered {
+            self.join_account_channels();
+        }
+
+        Some(IRCEvent::LoggedIn { account })
+    }
+
+    fn is_nickserv_confirmation(message: &Message) -> bool {
+        let from_nickserv = message
+            .prefix
+            .as_deref()
+            .map(Source::parse)
+            .is_some_and(|source| source.nickname.eq_ignore_ascii_case("NickServ"));
+        let text = message.params.last().map(|text| text.to_lowercase());
+
+        from_nickserv
+            && text.is_some_and(|text| {
+                NICKSERV_CONFIRMATIONS
+                    .iter()
+                    .any(|confirmation| text.contains(confirmation))
+            })
+    }
+
+    fn join_account_channels(&mut self) {
+        for channel in self.options.account_channels.clone() {
+            self.queue(format!("JOIN {channel}"));
+        }
+    }
+
+    fn end_negotiation(&mut self) {
+        // A connection is bound to a network of the bouncer before the registration ends.
+        if let Some(network) = &self.options.bouncer_network
+            && self.capabilities.is_enabled(BOUNCER_NETWORKS_CAP)
+        {
+            self.queue(format!("BOUNCER BIND {network}"));
+        }
+        self.queue("CAP END");
+        self.registration = RegistrationState::Registering;
+    }
+
+    /// The next nickname to try after the server rejected the current one: the alternate
+    /// nicknames first, then the nickname with underscores added.
+    fn next_nickname(&mut self) -> Option<String> {
+        let attempt = self.nicknames_tried;
+        self.nicknames_tried += 1;
+
+        if let Some(nickname) = self.options.alternate_nicknames.get(attempt) {
+            return Some(nickname.clone());
+        }
+
+        let suffixes = attempt - self.options.alternate_nicknames.len() + 1;
+        (suffixes <= MAX_NICKNAME_SUFFIXES)
+            .then(|| format!("{}{}", self.options.nickname, "_".repeat(suffixes)))
+    }
+
+    fn update_server_state(&mut self, message: &Message) {
+        self.channels
+            .handle(message, &self.nickname, &self.isupport);
+
+        match message.command.as_str() {
+            // RPL_WELCOME
+            "001" => {
+                if let Some(nickname) = message.params.first() {
+                    self.nickname = nickname.clone();
+                }
+            }
+            // RPL_ISUPPORT
+            "005" => self.isupport.update(&message.params),
+            // RPL_ENDOFMOTD and ERR_NOMOTD end the registration burst, after which the
+            // ISUPPORT tokens are known.
+            "376" | "422" if !self.notify_started => {
+                self.notify_started = true;
+                let nicknames = self.monitor.list().nicknames().to_vec();
+                self.watch(&nicknames);
+            }
+            // RPL_VISIBLEHOST
+            "396" => {
+                if let (Some(source), Some(host)) = (&self.source, message.params.get(1))
+                    && let Some((user, _)) = source.split_once('@')
+                {
+                    self.source = Some(format!("{user}@{host}"));
+                }
+            }
+            "CAP" => self.capabilities.update(&message.params),
+            "JOIN" => {
+                if let Some(prefix) = &message.prefix
+                    && let Some((nickname, _)) = prefix.split_once('!')
+                    && nickname.eq_ignore_ascii_case(&self.nickname)
+                    && prefix.contains('@')
+                {
+                    self.source = Some(prefix.clone());
+                }
+
+                // Learn the accounts, away states and real names of everyone in the channel.
+                if let Some(prefix) = &message.prefix
+                    && let Some(channel) = message.params.first()
+                    && Source::parse(prefix)
+                        .nickname
+                        .eq_ignore_ascii_case(&self.nickname)
+                {
+                    self.who(channel);
+                }
+            }
+            "NICK" => {
+                if let Some(prefix) = &message.prefix
+                    && let Some(nickname) = message.params.first()
+                {
+                    let source = Source::parse(prefix);
+                    if source.nickname.eq_ignore_ascii_case(&self.nickname) {
+                        self.nickname = nickname.clone();
+                        self.source = source
+                            .user
+                            .zip(source.host)
+                            .map(|(user, host)| format!("{nickname}!{user}@{host}"));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The QUIT command is used to terminate a client’s connection to the server. The server
+    /// acknowledges this by replying with an ERROR message and closing the connection to the
+    /// client.
+    pub(crate) fn quit(&mut self) {
+        self.queue_with_priority(format!("PART {} :Goodbye!", self.channel), Priority::High);
+        self.queue_with_priority("QUIT :Client closed", Priority::High);
+    }
+
+    /// This command is used to query information about a particular user. The server SHOULD
+    /// answer this command with numeric messages with information about the nick.
+    ///
+    /// The server SHOULD end its response (to a syntactically well-formed client message) with
+    /// RPL_ENDOFWHOIS, even if it did not send any other numeric message. This allows clients to
+    /// stop waiting for new numerics. In exceptional error conditions, servers MAY not reply to a
+    /// WHOIS command. Clients SHOULD implement a hard timeout to avoid waiting for a reply which
+    /// won’t come.
+    ///
+    /// Clients MUST NOT assume all numeric messages are sent at once, as servers can interleave
+    /// other messages before the end of the WHOIS response.
+    ///
+    /// The replies are collected into a [`WhoisInfo`], which is sent as an
+    /// [`IRCEvent::Whois`] and completes the returned response.
+    pub fn whois(&mut self, nickname: impl AsRef<str>) -> Response<WhoisInfo> {
+        let nickname = nickname.as_ref().trim();
+        if nickname.is_empty() {
+            let (responder, response) = response();
+            responder.complete(Err(Error::InvalidInput("No nickname given.".to_string())));
+            return response;
+        }
+
+        self.queue(format!("WHOIS {}", nickname));
+        self.whois_replies.expect(nickname)
+    }
+
+    /// The CHATHISTORY command is used to request message history from the server, such as the
+    /// messages missed while disconnected or older messages when scrolling back. At most `limit`
+    /// messages are requested, or fewer when the server allows less.
+    ///
+    /// The server replies with a `chathistory` batch, whose events complete the returned response
+    /// ordered by time. Messages that were already seen, going by their msgid, are left out.
+    pub fn chathistory(&mut self, request: HistoryRequest, limit: usize) -> Response<Vec<Event>> {
+        let limit = match self.history_limit(limit) {
+            Ok(limit) => limit,
+            Err(error) => {
+                let (responder, response) = response();
+                responder.complete(Err(error));
+                return response;
+            }
+        };
+
+        self.queue(request.command(limit));
+        self.history.expect(request.target())
+    }
+
+    /// Lists the conversations with messages between `start` and `end`, with the time of their
+    /// latest message, using CHATHISTORY TARGETS.
+    pub fn chathistory_targets(
+        &mut self,
+        start: SystemTime,
+        end: SystemTime,
+        limit: usize,
+    ) -> Response<Vec<HistoryTarget>> {
+        let limit = match self.history_limit(limit) {
+            Ok(limit) => limit,
+            Err(error) => {
+                let (responder, response) = response();
+                responder.complete(Err(error));
+                return response;
+            }
+        };
+
+        self.queue(format!(
+            "CHATHISTORY TARGETS {} {} {limit}",
+            Selector::Timestamp(start),
+            Selector::Timestamp(end)
+        ));
+        self.history.expect_targets()
+    }
+
+    /// Caps the number of messages to ask for at the server's `CHATHISTORY` limit, where 0 means
+    /// that there is none.
+    fn history_limit(&self, limit: usize) -> Result<usize, Error> {
+        if !self.capabilities.is_enabled("draft/chathistory")
+            && !self.isupport.contains("CHATHISTORY")
+        {
+            return Err(Error::InvalidInput(
+                "The server does not support CHATHISTORY.".to_string(),
+            ));
+        }
+
+        let max = self
+            .isupport
+            .get("CHATHISTORY")
+            .and_then(|max| max.parse::<usize>().ok())
+            .filter(|max| *max > 0);
+        Ok(max.map_or(limit, |max| limit.min(max)))
+    }
+
+    /// This command is used to query a list of users who match the provided mask. The server
+    /// will answer this command with zero, one or more RPL_WHOREPLY, and end the list with
+    /// RPL_ENDOFWHO.
+    ///
+    /// When the server advertises WHOX, the account of each user is asked for as well. The users
+    /// are sent as an [`IRCEvent::Who`], complete the returned response and update the
+    /// [`Channels`] model.
+    pub fn who(&mut self, mask: impl AsRef<str>) -> Response<Vec<User>> {
+        let mask = mask.as_ref().trim();
+        if mask.is_empty() {
+            let (responder, response) = response();
+            responder.complete(Err(Error::InvalidInput("No mask given.".to_string())));
+            return response;
+        }
+
+        let whox = self.isupport.contains("WHOX");
+        let (token, response) = self.who_replies.expect(mask, whox);
+        match token {
+            Some(token) => self.queue(format!("WHO {mask} {WHOX_FIELDS},{token}")),
+            None => self.queue(format!("WHO {mask}")),
+        }
+        response
+    }
+
+    /// The networks of the soju bouncer, as far as the bouncer has told us about them.
+    pub fn bouncer_networks(&self) -> impl Iterator<Item = &BouncerNetwork> {
+        self.bouncer.networks()
+    }
+
+    /// Lists the networks of a soju bouncer with `BOUNCER LISTNETWORKS`. The networks come as an
+    /// [`IRCEvent::BouncerNetworks`] and complete the returned response.
+    pub fn list_bouncer_networks(&mut self) -> Response<Vec<BouncerNetwork>> {
+        if let Err(error) = self.require_bouncer() {
+            let (responder, response) = response();
+            responder.complete(Err(error));
+            return response;
+        }
+
+        self.queue("BOUNCER LISTNETWORKS");
+        self.bouncer.expect_listing()
+    }
+
+    /// Adds a network to a soju bouncer with attributes such as `host`, `port` and `name`. The
+    /// response completes with the id of the new network, which a connection can be bound to
+    /// with [`ConnectionOptions::bouncer_network`].
+    pub fn add_bouncer_network(&mut self, attributes: &[(&str, &str)]) -> Response<String> {
+        if let Err(error) = self.require_bouncer() {
+            let (responder, response) = response();
+            responder.complete(Err(error));
+            return response;
+        }
+
+        self.queue(format!(
+            "BOUNCER ADDNETWORK {}",
+            format_attributes(attributes)
+        ));
+        self.bouncer.expect_added()
+    }
+
+    /// Changes attributes of a network of a soju bouncer, an empty value removes one.
+    pub fn change_bouncer_network(
+        &mut self,
+        id: impl AsRef<str>,
+        attributes: &[(&str, &str)],
+    ) -> Result<(), Error> {
+        self.require_bouncer()?;
+        self.queue(format!(
+            "BOUNCER CHANGENETWORK {} {}",
+            id.as_ref().trim(),
+            format_attributes(attributes)
+        ));
+        Ok(())
+    }
+
+    /// Removes a network from a soju bouncer.
+    pub fn delete_bouncer_network(&mut self, id: impl AsRef<str>) -> Result<(), Error> {
+        self.require_bouncer()?;
+        self.queue(format!("BOUNCER DELNETWORK {}", id.as_ref().trim()));
+        Ok(())
+    }
+
+    fn require_bouncer(&self) -> Result<(), Error> {
+        if !self.capabilities.is_enabled(BOUNCER_NETWORKS_CAP) {
+            return Err(Error::InvalidInput(
+                "The server is not a bouncer with networks.".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The server-time of the latest message we got, to pass as
+    /// [`ConnectionOptions::playback_since`] when connecting again.
+    pub fn last_seen(&self) -> Option<SystemTime> {
+        self.last_seen
+    }
+
+    /// Asks ZNC's `*playback` module for what `target` got after `since`, where the target `*`
+    /// stands for every buffer. Once registered this is done for every buffer since
+    /// [`Protocol::last_seen`].
+    pub fn play_back(&mut self, target: impl AsRef<str>, since: SystemTime) -> Result<(), Error> {
+        if !self.capabilities.is_enabled(PLAYBACK_CAP) {
+            return Err(Error::InvalidInput(
+                "The bouncer does not support *playback.".to_string(),
+            ));
+        }
+
+        self.queue(playback_command(target.as_ref().trim(), Some(since)));
+        Ok(())
+    }
+
+    /// Adds nicknames to the notify list, to get an [`IRCEvent::Online`] when they come online
+    /// and an [`IRCEvent::Offline`] when they leave.
+    ///
+    /// The server watches them with MONITOR when it advertises it. Otherwise the clients ask
+    /// with ISON every [`ISON_INTERVAL`], see [`Protocol::poll_notify_list`]. Adding more
+    /// nicknames than the server's MONITOR limit fails, and none of them are added.
+    ///
+    /// [`ISON_INTERVAL`]: crate::ISON_INTERVAL
+    pub fn notify_add<I, S>(&mut self, nicknames: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let nicknames = Self::nicknames(nicknames);
+        let new = nicknames
+            .iter()
+            .filter(|nickname| !self.monitor.list().contains(nickname))
+            .count();
+
+        if self.supports_monitor()
+            && let Some(limit) = self.isupport.monitor_limit()
+            && self.monitor.list().len() + new > limit
+        {
+            return Err(Error::InvalidInput(format!(
+                "The server can monitor at most {limit} nicknames."
+            )));
+        }
+
+        let added = self.monitor.add(nicknames);
+        if self.notify_started {
+            self.watch(&added);
+        }
+        Ok(())
+    }
+
+    /// Removes nicknames from the notify list.
+    pub fn notify_remove<I, S>(&mut self, nicknames: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let removed = self.monitor.remove(&Self::nicknames(nicknames));
+        if self.notify_started && self.supports_monitor() {
+            self.queue_nicknames("MONITOR -", ',', &removed);
+        }
+    }
+
+    /// Empties the notify list.
+    pub fn notify_clear(&mut self) {
+        self.monitor.clear();
+        if self.notify_started && self.supports_monitor() {
+            self.queue("MONITOR C");
+        }
+    }
+
+    /// Asks the server which nicknames of the notify list are online, with `MONITOR S` or ISON.
+    pub fn notify_status(&mut self) {
+        if !self.notify_started {
+            return;
+        }
+
+        if self.supports_monitor() {
+            self.queue("MONITOR S");
+        } else {
+            let nicknames = self.monitor.list().nicknames().to_vec();
+            self.ison(&nicknames);
+        }
+    }
+
+    /// Asks with ISON which nicknames of the notify list are online, on servers without
+    /// MONITOR. The clients call this every [`crate::ISON_INTERVAL`].
+    pub fn poll_notify_list(&mut self) {
+        if self.notify_started && !self.supports_monitor() {
+            let nicknames = self.monitor.list().nicknames().to_vec();
+            self.ison(&nicknames);
+        }
+    }
+
+    /// Lists the nicknames the server is monitoring for us with `MONITOR L`. Without MONITOR the
+    /// response completes right away with our own notify list.
+    pub fn monitor_list(&mut self) -> Response<Vec<String>> {
+        if !self.supports_monitor() {
+            let (responder, response) = response();
+            responder.complete(Ok(self.monitor.list().nicknames().to_vec()));
+            return response;
+        }
+
+        self.queue("MONITOR L");
+        self.monitor.expect_listing()
+    }
+
+    fn supports_monitor(&self) -> bool {
+        self.isupport.contains("MONITOR")
+    }
+
+    fn nicknames<I, S>(nicknames: I) -> Vec<String>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        nicknames
+            .into_iter()
+            .map(|nickname| nickname.as_ref().trim().to_string())
+            .filter(|nickname| !nickname.is_empty())
+            .collect()
+    }
+
+    /// Starts watching nicknames that were added to the notify list.
+    fn watch(&mut self, nicknames: &[String]) {
+        if self.supports_monitor() {
+            self.queue_nicknames("MONITOR +", ',', nicknames);
+        } else {
+            self.ison(nicknames);
+        }
+    }
+
+    fn ison(&mut self, nicknames: &[String]) {
+        for line in self.queue_nicknames("ISON", ' ', nicknames) {
+            self.monitor
+                .expect_ison(line.split(' ').map(str::to_string).collect());
+        }
+    }
+
+    /// Queues `command` with the nicknames, in as many lines as they need. Returns the joined
+    /// nicknames of each line.
+    fn queue_nicknames(
+        &mut self,
+        command: &str,
+        separator: char,
+        nicknames: &[String],
+    ) -> Vec<String> {
+        // The line length includes the CR-LF, and a space separates the nicknames from the
+        // command.
+        let max_len = self.isupport.line_len().saturating_sub(command.len() + 3);
+        let lines = join_within(nicknames, separator, max_len);
+        for line in &lines {
+            self.queue(format!("{command} {line}"));
+        }
+        lines
+    }
+
+    /// The NICK command is used to give the client a nickname or change the previous one.
+    ///
+    /// If the server receives a NICK command from a client where the desired nickname is
+    /// already in use on the network, it should issue an ERR_NICKNAMEINUSE numeric and ignore
+    /// the NICK command.
+    ///
+    /// If the server does not accept the new nickname supplied by the client as valid
+    /// (for instance, due to containing invalid characters), it should issue an ERR_ERRONEUSNICKNAME
+    /// numeric and ignore the NICK command. Servers MUST allow at least all alphanumerical
+    /// characters, square and curly brackets ([]{}), backslashes (\), and pipe (|) characters in
+    /// nicknames, and MAY disallow digits as the first character. Servers MAY allow extra
+    /// characters, as long as they do not introduce ambiguity in other commands.
+    fn change_nickname(&mut self, new_nickname: impl AsRef<str>) {
+        let new_nickname = new_nickname.as_ref().trim();
+        if new_nickname.is_empty() {
+            return;
+        }
+
+        self.queue(format!("NICK {}", new_nickname));
+        self.nickname = new_nickname.to_string();
+    }
+
+    pub(crate) fn send_private_message(
+        &mut self,
+        target: impl AsRef<str>,
+        message: impl AsRef<str>,
+    ) -> Result<(), Error> {
+        let target = target.as_ref().trim();
+        let message = trim_text(message.as_ref());
+        if target.is_empty() || message.is_empty() {
+            return Ok(());
+        }
+
+        self.send_text("PRIVMSG", target, message)
+    }
+
+    pub(crate) fn send_notice(
+        &mut self,
+        target: impl AsRef<str>,
+        message: impl AsRef<str>,
+    ) -> Result<(), Error> {
+        let target = target.as_ref().trim();
+        let message = trim_text(message.as_ref());
+        if target.is_empty() || message.is_empty() {
+            return Ok(());
+        }
+
+        self.send_text("NOTICE", target, message)
+    }
+
+    /// Sends a PRIVMSG or NOTICE that may be longer than what fits on one line, or have several
+    /// lines.
+    ///
+    /// The server truncates lines that exceed its limit, so long text is split on word and UTF-8
+    /// boundaries into several messages, and every line of the text becomes a message of its
+    /// own. When the `draft/multiline` capability is enabled the parts are sent as one batch
+    /// instead, which recipients show as a single message. Text over the server's `max-bytes` or
+    /// `max-lines` falls back to separate messages.
+    fn send_text(&mut self, command: &str, target: &str, text: &str) -> Result<(), Error> {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        let text = text.as_str();
+        let limits = self.line_limits();
+        let budget = limits.text_budget(command, target);
+
+        let batch = self
+            .multiline_limits()
+            .filter(|_| text.len() > budget || text.contains('\n'))
+            .and_then(|multiline| {
+                let reference = format!("ml{}", self.batches_sent);
+                multiline_batch(&reference, command, target, text, budget, &multiline)
+            });
+
+        let lines = match batch {
+            Some(lines) => {
+                self.batches_sent += 1;
+                lines
+            }
+            None => {
+                let mut lines = Vec::new();
+                for line in text.lines() {
+                    lines.extend(split_message("", command, target, line, &limits)?);
+                }
+                lines
+            }
+        };
+
+        for line in lines {
+            self.queue(line);
+        }
+
+        Ok(())
+    }
+
+    fn line_limits(&self) -> LineLimits {
+        let source_len = self.source.as_ref().map_or_else(
+            || {
+                LineLimits::estimate_source_len(
+                    &self.nickname,
+                    self.isupport.user_len(),
+                    self.isupport.host_len(),
+                )
+            },
+            String::len,
+        );
+
+        LineLimits::new(self.isupport.line_len(), source_len)
+    }
+
+    fn multiline_limits(&self) -> Option<MultilineLimits> {
+        if !self.capabilities.is_enabled("batch")
+            || !self.capabilities.is_enabled("draft/multiline")
+        {
+            return None;
+        }
+
+        self.capabilities
+            .value("draft/multiline")
+            .and_then(MultilineLimits::parse)
+    }
+
+    /// Handles a line typed by the user, which is either a message to the channel or one of the
+    /// supported `/commands`.
+    ///
+    /// When the server supports labeled-response, the command is sent with a `label` tag and
+    /// the returned response completes with the events of the server's reply. A reply with an
+    /// error fails it. Without labeled-response the response completes right away, with no
+    /// events.
+    pub fn send_message(
+        &mut self,
+        message: impl AsRef<str>,
+    ) -> Result<Response<Vec<Event>>, Error> {
+        self.send_labeled(|protocol| protocol.run_command(trim_text(message.as_ref())))
+    }
+
+    /// Sends a message to `target` in reply to the message `msgid`, with the `+draft/reply`
+    /// client tag. When the server does not relay the tag, the message is sent without it. The
+    /// response is the one of [`Protocol::send_message`].
+    pub fn send_reply(
+        &mut self,
+        target: impl AsRef<str>,
+        msgid: impl AsRef<str>,
+        text: impl AsRef<str>,
+    ) -> Result<Response<Vec<Event>>, Error> {
+        let tag = self
+            .client_tag_allowed("draft/reply")
+            .then(|| format!("+draft/reply={}", escape_tag_value(msgid.as_ref())));
+
+        self.send_labeled(|protocol| {
+            let queued = protocol.outgoing.len();
+            protocol.send_private_message(target, text)?;
+            let Some(tag) = tag else {
+                return Ok(());
+            };
+
+            // A multiline batch carries the tag on its start, split messages on every line.
+            let end = match protocol.outgoing.get(queued) {
+                Some((line, _)) if line.starts_with("BATCH +") => queued + 1,
+                _ => protocol.outgoing.len(),
+            };
+            for (line, _) in protocol.outgoing.range_mut(queued..end) {
+                Self::add_tag(line, &tag);
+            }
+            Ok(())
+        })
+    }
+
+    /// Reacts to the message `msgid` in `target`, e.g. with an emoji, using a TAGMSG with the
+    /// `+draft/react` and `+draft/reply` client tags. Fails when the server does not relay them,
+    /// and without a target, msgid or reaction.
+    pub fn send_reaction(
+        &mut self,
+        target: impl AsRef<str>,
+        msgid: impl AsRef<str>,
+        reaction: impl AsRef<str>,
+    ) -> Result<Response<Vec<Event>>, Error> {
+        let target = Self::tagmsg_target(target.as_ref())?;
+        let (msgid, reaction) = (msgid.as_ref(), reaction.as_ref());
+        if msgid.is_empty() || reaction.is_empty() {
+            return Err(Error::InvalidInput(
+                "A reaction needs a message and a reaction.".to_string(),
+            ));
+        }
+        self.require_client_tags(&["draft/reply", "draft/react"])?;
+        let tags = format!(
+            "@+draft/reply={};+draft/react={}",
+            escape_tag_value(msgid),
+            escape_tag_value(reaction)
+        );
+
+        self.send_labeled(|protocol| {
+            protocol.queue(format!("{tags} TAGMSG {target}"));
+            Ok(())
+        })
+    }
+
+    /// Tells `target` whether we are typing, using a TAGMSG with the `+typing` client tag. Fails
+    /// when the server does not relay it, and without a target.
+    ///
+    /// [`TypingState::Active`] should be sent at most every 3 seconds while typing, as the
+    /// recipients keep showing it for a few seconds after the last one.
+    pub fn send_typing(
+        &mut self,
+        target: impl AsRef<str>,
+        state: TypingState,
+    ) -> Result<(), Error> {
+        let target = Self::tagmsg_target(target.as_ref())?;
+        self.require_client_tags(&["typing"])?;
+        self.queue(format!("@+typing={} TAGMSG {target}", state.as_str()));
+        Ok(())
+    }
+
+    fn tagmsg_target(target: &str) -> Result<&str, Error> {
+        let target = target.trim();
+        if target.is_empty() {
+            return Err(Error::InvalidInput("No target given.".to_string()));
+        }
+        Ok(target)
+    }
+
+    /// Sends a DCC request to `target` in a CTCP, see [`crate::DccTransfers`].
+    pub fn send_dcc(&mut self, target: impl AsRef<str>, request: &DccRequest) {
+        self.queue(format!(
+            "PRIVMSG {} :\x01DCC {request}\x01",
+            target.as_ref().trim()
+        ));
+    }
+
+    /// Whether the server relays the client-only tag `name`: message-tags has to be enabled and
+    /// the tag must not be in `CLIENTTAGDENY`.
+    fn client_tag_allowed(&self, name: &str) -> bool {
+        self.capabilities.is_enabled("message-tags") && self.isupport.client_tag_allowed(name)
+    }
+
+    fn require_client_tags(&self, names: &[&str]) -> Result<(), Error> {
+        match names.iter().find(|name| !self.client_tag_allowed(name)) {
+            Some(name) => Err(Error::InvalidInput(format!(
+                "The server does not relay the +{name} tag."
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Adds a tag to a line that may already have tags.
+    fn add_tag(line: &mut String, tag: &str) {
+        *line = match line.strip_prefix('@') {
+            Some(tagged) => format!("@{tag};{tagged}"),
+            None => format!("@{tag} {line}"),
+        };
+    }
+
+    /// Sends what `send` queues, labeled when the server supports labeled-response, see
+    /// [`Protocol::send_message`].
+    fn send_labeled<F>(&mut self, send: F) -> Result<Response<Vec<Event>>, Error>
+    where
+        F: FnOnce(&mut Self) -> Result<(), Error>,
+    {
+        let queued = self.outgoing.len();
+        send(self)?;
+
+        let (label, response) =
+            if self.capabilities.is_enabled("labeled-response") && self.outgoing.len() > queued {
+                self.labels.expect()
+            } else {
+                let (responder, response) = response();
+                responder.complete(Ok(Vec::new()));
+                return Ok(response);
+            };
+
+        // A multiline batch is labeled on its start. A message that is split into several lines
+        // is labeled on the last one, so that the reply comes after all of them.
+        let index = if self.outgoing[queued].0.starts_with("BATCH +") {
+            queued
+        } else {
+            self.outgoing.len() - 1
+        };
+        Self::add_tag(&mut self.outgoing[index].0, &format!("label={label}"));
+
+        Ok(response)
+    }
+
+    fn run_command(&mut self, message: &str) -> Result<(), Error> {
+        if message.is_empty() {
+            return Ok(());
+        }
+        // Only text keeps its indentation, commands may be typed after some spaces.
+        let message = match message.trim_start() {
+            command if command.starts_with('/') => command,
+            _ => message,
+        };
+
+        if message.starts_with("/msg") {
+            let (target, msg) = split_target(message.trim_start_matches("/msg"));
+
+            self.send_private_message(target, msg)
+        } else if message.starts_with("/notice") {
+            let (target, msg) = split_target(message.trim_start_matches("/notice"));
+
+            self.send_notice(target, msg)
+        } else if message.starts_with("/whois") {
+            let nickname = message.trim_start_matches("/whois").trim();
+
+            self.whois(nickname);
+            Ok(())
+        } else if message.starts_with("/who") {
+            let mask = message.trim_start_matches("/who").trim();
+
+            self.who(mask);
+            Ok(())
+        } else if message.starts_with("/nick") {
+            let new_nick = message.trim_start_matches("/nick").trim();
+
+            self.change_nickname(new_nick);
+            Ok(())
+        } else if message == "/quit" {
+            self.quit();
+            Ok(())
+        } else if self.channel.is_empty() {
+            Err(Error::InvalidInput(
+                "There is no channel to send the message to.".to_string(),
+            ))
+        } else {
+            let channel = self.channel.clone();
+            self.send_text("PRIVMSG", &channel, message)
+        }
+    }
+}
+
+/// Trims the trailing whitespace and the leading blank lines of text typed by the user. The
+/// indentation of the first line is kept, as it matters for pasted code.
+fn trim_text(text: &str) -> &str {
+    let text = text.trim_end();
+    let first_char = text.len() - text.trim_start().len();
+    let first_line = text[..first_char]
+        .rfind('\n')
+        .map_or(0, |newline| newline + 1);
+    &text[first_line..]
+}
+
+/// Splits the arguments of `/msg` and `/notice` into the target and the text.
+fn split_target(arguments: &str) -> (&str, &str) {
+    let arguments = arguments.trim_start();
+    match arguments.find(char::is_whitespace) {
+        Some(end) => (
+            &arguments[..end],
+            arguments[end..].trim_start_matches([' ', '\t']),
+        ),
+        None => (arguments, ""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Credentials;
+    use std::time::Duration;
+
+    fn handle(protocol: &mut Protocol, line: &str) -> Vec<IRCEvent> {
+        protocol
+            .handle_line(line)
+            .into_iter()
+            .map(|event| event.kind)
+            .collect()
+    }
+
+    fn outgoing(protocol: &mut Protocol) -> Vec<String> {
+        std::iter::from_fn(|| protocol.poll_outgoing())
+            .map(|(line, _)| line)
+            .collect()
+    }
+
+    #[test]
+    fn register_queues_cap_nick_and_user() {
+        let mut protocol = Protocol::new("nick");
+        protocol.register();
+
+        assert_eq!(
+            outgoing(&mut protocol),
+            vec!["CAP LS 302", "NICK nick", "USER nick 0 * :nick"]
+        );
+    }
+
+    #[test]
+    fn channels_are_joined_after_welcome() {
+        let mut protocol = Protocol::new("nick");
+        protocol.register();
+        outgoing(&mut protocol);
+
+        protocol.handle_line(":server CAP * LS :multi-prefix\r\n");
+        assert_eq!(outgoing(&mut protocol), vec!["CAP END"]);
+
+        let events = handle(&mut protocol, ":server 001 nick :Welcome\r\n");
+        assert_eq!(
+            events.last(),
+            Some(&IRCEvent::Registered {
+                nickname: "nick".to_string()
+            })
+        );
+        assert_eq!(outgoing(&mut protocol), vec!["JOIN #testchannel"]);
+        assert_eq!(protocol.registration_state(), RegistrationState::Registered);
+    }
+
+    #[test]
+    fn sasl_plain_runs_before_cap_end() {
+        let mut options = ConnectionOptions::new("nick");
+        options.services_login = ServicesLogin::Sasl(Credentials {
+            account: "jilles".to_string(),
+            password: "sesame".to_string(),
+        });
+        let mut protocol = Protocol::with_options(options);
+        protocol.register();
+        outgoing(&mut protocol);
+
+        protocol.handle_line(":server CAP * LS * :multi-prefix batch\r\n");
+        assert!(outgoing(&mut protocol).is_empty());
+        protocol.handle_line(":server CAP * LS :sasl=PLAIN,EXTERNAL\r\n");
+        assert_eq!(outgoing(&mut protocol), vec!["CAP REQ :batch sasl"]);
+
+        protocol.handle_line(":server CAP * ACK :batch sasl\r\n");
+        assert_eq!(outgoing(&mut protocol), vec!["AUTHENTICATE PLAIN"]);
+        protocol.handle_line("AUTHENTICATE +\r\n");
+        assert_eq!(
+            outgoing(&mut protocol),
+            vec!["AUTHENTICATE amlsbGVzAGppbGxlcwBzZXNhbWU="]
+        );
+        protocol.handle_line(":server 903 nick :SASL authentication successful\r\n");
+        assert_eq!(outgoing(&mut protocol), vec!["CAP END"]);
+    }
+
+    #[test]
+    fn password_is_sent_before_nick() {
+        let mut options = ConnectionOptions::new("nick");
+        options.password = Some("secret".to_string());
+        let mut protocol = Protocol::with_options(options);
+
+        protocol.register();
+
+        assert_eq!(
+            outgoing(&mut protocol),
+            vec![
+                "CAP LS 302",
+                "PASS secret",
+                "NICK nick",
+                "USER nick 0 * :nick"
+            ]
+        );
+    }
+
+    #[test]
+    fn account_channels_wait_for_nickserv_confirmation() {
+        let mut options = ConnectionOptions::new("nick");
+        options.services_login = ServicesLogin::NickServ(Credentials {
+            account: "nick".to_string(),
+            password: "secret".to_string(),
+        });
+        options.account_channels = vec!["#private".to_string()];
+        let mut protocol = Protocol::with_options(options);
+
+        protocol.handle_line(":server 001 nick :Welcome\r\n");
+        assert_eq!(
+            outgoing(&mut protocol),
+            vec![
+                "JOIN #testchannel",
+                "PRIVMSG NickServ :IDENTIFY nick secret"
+            ]
+        );
+
+        protocol.handle_line(":NickServ!NickServ@services. NOTICE nick :Invalid password\r\n");
+        assert!(outgoing(&mut protocol).is_empty());
+
+        let events = handle(
+            &mut protocol,
+            ":NickServ!NickServ@services. NOTICE nick :You are now identified for \x02nick\x02.\r\n",
+        );
+        assert_eq!(
+            events.last(),
+            Some(&IRCEvent::LoggedIn {
+                account: "nick".to_string()
+            })
+        );
+        assert_eq!(outgoing(&mut protocol), vec!["JOIN #private"]);
+        assert_eq!(protocol.account(), Some("nick"));
+    }
+
+    #[test]
+    fn sasl_login_joins_account_channels_on_welcome() {
+        let mut options = ConnectionOptions::new("nick");
+        options.channels = Vec::new();
+        options.account_channels = vec!["#private".to_string()];
+
+        let mut protocol = Protocol::with_options(options);
+        protocol
+            .handle_line(":server 900 nick nick!user@host nick :You are now logged in as nick\r\n");
+        protocol.handle_line(":server 001 nick :Welcome\r\n");
+
+        assert_eq!(outgoing(&mut protocol), vec!["JOIN #private"]);
+    }
+
+    #[test]
+    fn rejected_nickname_falls_back_to_alternates_then_suffixes() {
+        let mut options = ConnectionOptions::new("nick");
+        options.alternate_nicknames = vec!["other".to_string()];
+        let mut protocol = Protocol::with_options(options);
+        protocol.register();
+        outgoing(&mut protocol);
+
+        protocol.handle_line(":server 433 * nick :Nickname is already in use\r\n");
+        assert_eq!(outgoing(&mut protocol), vec!["NICK other"]);
+        protocol.handle_line(":server 432 * other :Erroneous nickname\r\n");
+        assert_eq!(outgoing(&mut protocol), vec!["NICK nick_"]);
+
+        let events = handle(&mut protocol, ":server 001 nick_ :Welcome\r\n");
+        assert_eq!(
+            events.last(),
+            Some(&IRCEvent::Registered {
+                nickname: "nick_".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn nickname_fallback_gives_up() {
+        let mut protocol = Protocol::new("nick");
+        for _ in 0..MAX_NICKNAME_SUFFIXES {
+            protocol.handle_line(":server 433 * nick :Nickname is already in use\r\n");
+        }
+        outgoing(&mut protocol);
+
+        let events = handle(
+            &mut protocol,
+            ":server 433 * nick___ :Nickname is already in use\r\n",
+        );
+
+        let Some(IRCEvent::RegistrationFailed(error)) = events.last() else {
+            panic!("no RegistrationFailed in {events:?}");
+        };
+        assert!(matches!(Error::from(error.clone()), Error::Registration(_)));
+        assert_eq!(outgoing(&mut protocol), vec!["QUIT"]);
+    }
+
+    #[test]
+    fn failed_sasl_login_fails_the_registration() {
+        let mut options = ConnectionOptions::new("nick");
+        options.services_login = ServicesLogin::Sasl(Credentials {
+            account: "jilles".to_string(),
+            password: "wrong".to_string(),
+        });
+        let mut protocol = Protocol::with_options(options);
+        protocol.register();
+        protocol.handle_line(":server CAP * LS :sasl\r\n");
+        protocol.handle_line(":server CAP * ACK :sasl\r\n");
+        protocol.handle_line("AUTHENTICATE +\r\n");
+        outgoing(&mut protocol);
+
+        let events = handle(
+            &mut protocol,
+            ":server 904 nick :SASL authentication failed\r\n",
+        );
+
+        let Some(IRCEvent::RegistrationFailed(error)) = events.last() else {
+            panic!("no RegistrationFailed in {events:?}");
+        };
+        assert!(matches!(Error::from(error.clone()), Error::Auth(_)));
+        assert_eq!(outgoing(&mut protocol), vec!["QUIT"]);
+    }
+
+    #[test]
+    fn wrong_password_fails_the_registration() {
+        let mut options = ConnectionOptions::new("nick");
+        options.password = Some("wrong".to_string());
+        let mut protocol = Protocol::with_options(options);
+        protocol.register();
+        outgoing(&mut protocol);
+
+        let events = handle(&mut protocol, ":server 464 * :Password incorrect\r\n");
+
+        let Some(IRCEvent::RegistrationFailed(error)) = events.last() else {
+            panic!("no RegistrationFailed in {events:?}");
+        };
+        assert!(matches!(Error::from(error.clone()), Error::Auth(_)));
+        assert_eq!(outgoing(&mut protocol), vec!["QUIT"]);
+    }
+
+    #[test]
+    fn ping_queues_high_priority_pong() {
+        let mut protocol = Protocol::new("nick");
+
+        let events = handle(&mut protocol, "PING :stub\r\n");
+
+        assert!(events.is_empty());
+        assert_eq!(
+            protocol.poll_outgoing(),
+            Some(("PONG :stub".to_string(), Priority::High))
+        );
+    }
+
+    #[test]
+    fn motd_is_collected_into_one_event() {
+        let mut protocol = Protocol::new("nick");
+
+        protocol.handle_line(":server 375 nick :- server Message of the day -\r\n");
+        protocol.handle_line(":server 372 nick :- hello\r\n");
+        let events = handle(&mut protocol, ":server 376 nick :End of /MOTD command.\r\n");
+
+        assert_eq!(
+            events,
+            vec![IRCEvent::MessageOfTheDay(vec!["- hello".to_string()])]
+        );
+    }
+
+    #[test]
+    fn message_tag_capabilities_are_requested() {
+        let mut protocol = Protocol::new("nick");
+        protocol.register();
+        outgoing(&mut protocol);
+
+        protocol.handle_line(":server CAP * LS :message-tags multi-prefix server-time\r\n");
+
+        assert_eq!(
+            outgoing(&mut protocol),
+            vec!["CAP REQ :message-tags server-time"]
+        );
+    }
+
+    #[test]
+    fn presence_capabilities_are_requested() {
+        let mut protocol = Protocol::new("nick");
+        protocol.register();
+        outgoing(&mut protocol);
+
+        protocol.handle_line(
+            ":server CAP * LS :away-notify account-notify chghost extended-join invite-notify setname\r\n",
+        );
+
+        assert_eq!(
+            outgoing(&mut protocol),
+            vec!["CAP REQ :account-notify away-notify chghost extended-join invite-notify setname"]
+        );
+    }
+
+    #[test]
+    fn sts_policy_depends_on_the_security_of_the_connection() {
+        let offer = ":server CAP * LS :sts=port=6697,duration=300 batch\r\n";
+
+        let mut plaintext = Protocol::new("nick");
+        plaintext.register();
+        outgoing(&mut plaintext);
+        assert!(!handle(&mut plaintext, offer).iter().any(|event| matches!(
+            event,
+            IRCEvent::StsUpgrade { .. } | IRCEvent::StsPolicy { .. }
+        )));
+        assert_eq!(outgoing(&mut plaintext), vec!["CAP REQ :batch"]);
+
+        let mut upgradable = Protocol::new("nick");
+        upgradable.set_security(Security::Upgradable);
+        upgradable.register();
+        outgoing(&mut upgradable);
+        assert!(handle(&mut upgradable, offer).contains(&IRCEvent::StsUpgrade { port: 6697 }));
+        assert!(outgoing(&mut upgradable).is_empty());
+
+        let mut tls = Protocol::new("nick");
+        tls.set_security(Security::Tls);
+        tls.register();
+        outgoing(&mut tls);
+        assert!(handle(&mut tls, offer).contains(&IRCEvent::StsPolicy {
+            duration: Duration::from_secs(300)
+        }));
+        assert_eq!(outgoing(&mut tls), vec!["CAP REQ :batch"]);
+    }
+
+    #[test]
+    fn bouncer_connection_is_bound_and_played_back_since_last_read() {
+        let mut options = ConnectionOptions::new("nick");
+        options.bouncer_network = Some("42".to_string());
+        options.playback_since = Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000));
+        let mut protocol = Protocol::with_options(options);
+        protocol.register();
+        outgoing(&mut protocol);
+
+        protocol.handle_line(":soju CAP * LS :soju.im/bouncer-networks znc.in/playback\r\n");
+        assert_eq!(
+            outgoing(&mut protocol),
+            vec!["CAP REQ :soju.im/bouncer-networks znc.in/playback"]
+        );
+        protocol.handle_line(":soju CAP * ACK :soju.im/bouncer-networks znc.in/playback\r\n");
+        assert_eq!(outgoing(&mut protocol), vec!["BOUNCER BIND 42", "CAP END"]);
+
+        protocol.handle_line(":soju 001 nick :Welcome\r\n");
+        assert_eq!(
+            outgoing(&mut protocol),
+            vec![
+                "JOIN #testchannel",
+                "PRIVMSG *playback :PLAY * 1700000000.000"
+            ]
+        );
+
+        protocol.handle_line(
+            "@time=2023-11-14T22:15:00.000Z :alice!a@host PRIVMSG #testchannel :hi\r\n",
+        );
+        assert_eq!(
+            protocol.last_seen(),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_100))
+        );
+    }
+
+    #[test]
+    fn bouncer_networks_are_listed() {
+        let mut protocol = Protocol::new("nick");
+        assert!(
+            protocol
+                .list_bouncer_networks()
+                .try_take()
+                .unwrap()
+                .is_err()
+        );
+        protocol.handle_line(":soju CAP nick ACK :batch soju.im/bouncer-networks\r\n");
+        outgoing(&mut protocol);
+
+        let listing = protocol.list_bouncer_networks();
+        assert_eq!(outgoing(&mut protocol), vec!["BOUNCER LISTNETWORKS"]);
+        protocol.handle_line(":soju BATCH +n soju.im/bouncer-networks\r\n");
+        protocol.handle_line("@batch=n :soju BOUNCER NETWORK 1 name=Libera;state=connected\r\n");
+        protocol.handle_line("@batch=n :soju BOUNCER NETWORK 2 host=irc.example.com\r\n");
+        let events = handle(&mut protocol, ":soju BATCH -n\r\n");
+
+        let networks = listing.try_take().unwrap().unwrap();
+        assert_eq!(
+            networks
+                .iter()
+                .map(BouncerNetwork::name)
+                .collect::<Vec<_>>(),
+            vec!["Libera", "irc.example.com"]
+        );
+        assert_eq!(events, vec![IRCEvent::BouncerNetworks(networks)]);
+        assert_eq!(protocol.bouncer_networks().count(), 2);
+
+        let added = protocol.add_bouncer_network(&[("host", "irc.oftc.net"), ("name", "OFTC")]);
+        protocol
+            .change_bouncer_network("2", &[("nickname", "nick_")])
+            .unwrap();
+        assert_eq!(
+            outgoing(&mut protocol),
+            vec![
+                "BOUNCER ADDNETWORK host=irc.oftc.net;name=OFTC",
+                "BOUNCER CHANGENETWORK 2 nickname=nick_"
+            ]
+        );
+        protocol.handle_line(":soju BOUNCER ADDNETWORK 3\r\n");
+        assert_eq!(added.try_take().unwrap().unwrap(), "3");
+    }
+
+    #[test]
+    fn events_carry_server_time_and_msgid() {
+        let mut protocol = Protocol::new("nick");
+
+        let tagged = protocol.handle_line(
+            "@time=2011-10-19T16:40:51.620Z;msgid=abc :alice!al@host PRIVMSG #testchannel :hi\r\n",
+        );
+        let before = SystemTime::now();
+        let untagged = protocol.handle_line(":alice!al@host PRIVMSG #testchannel :hi\r\n");
+
+        assert_eq!(
+            tagged[0].time,
+            SystemTime::UNIX_EPOCH + std::time::Duration::from_millis(1_319_042_451_620)
+        );
+        assert_eq!(tagged[0].msgid.as_deref(), Some("abc"));
+        assert!(untagged[0].time >= before);
+        assert_eq!(untagged[0].msgid, None);
+    }
+
+    #[test]
+    fn batched_messages_are_delivered_when_the_batch_ends() {
+        let mut protocol = Protocol::new("nick");
+        protocol.handle_line(":nick!u@h JOIN #rust\r\n");
+        protocol.handle_line(":server 353 nick = #rust :alice bob nick\r\n");
+
+        assert!(
+            handle(
+                &mut protocol,
+                ":server BATCH +1 netsplit irc.hub other.host\r\n"
+            )
+            .is_empty()
+        );
+        assert!(
+            handle(
+                &mut protocol,
+                "@batch=1 :alice!a@h QUIT :irc.hub other.host\r\n"
+            )
+            .is_empty()
+        );
+        assert!(
+            handle(
+                &mut protocol,
+                "@batch=1 :bob!b@h QUIT :irc.hub other.host\r\n"
+            )
+            .is_empty()
+        );
+        let events = handle(&mut protocol, ":server BATCH -1\r\n");
+
+        assert!(matches!(
+            &events[..],
+            [IRCEvent::Netsplit { remote, users, .. }] if remote == "other.host" && users.len() == 2
+        ));
+        assert_eq!(protocol.channels().channel("#rust").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn chathistory_returns_unseen_messages_in_order() {
+        let mut protocol = Protocol::new("nick");
+        protocol.handle_line(":server 005 nick CHATHISTORY=20 :are supported by this server\r\n");
+        protocol.handle_line(":nick!u@h JOIN #rust\r\n");
+        outgoing(&mut protocol);
+        protocol.handle_line("@msgid=live :alice!a@h PRIVMSG #rust :seen live\r\n");
+
+        let response = protocol.chathistory(
+            HistoryRequest::Latest {
+                target: "#rust".to_string(),
+                after: None,
+            },
+            100,
+        );
+        assert_eq!(
+            outgoing(&mut protocol),
+            vec!["CHATHISTORY LATEST #rust * 20"]
+        );
+        for line in [
+            ":server BATCH +h chathistory #rust",
+            "@batch=h;msgid=old;time=2020-01-01T00:00:00.000Z :alice!a@h PRIVMSG #rust :old",
+            "@batch=h;msgid=live;time=2020-01-01T00:01:00.000Z :alice!a@h PRIVMSG #rust :seen live",
+            "@batch=h;msgid=left;time=2019-12-31T23:00:00.000Z :bob!b@h PART #rust",
+        ] {
+            assert!(protocol.handle_line(line).is_empty());
+        }
+        assert!(protocol.handle_line(":server BATCH -h\r\n").is_empty());
+
+        let events = response.try_take().unwrap().unwrap();
+        let msgids: Vec<_> = events.iter().map(|event| event.msgid.as_deref()).collect();
+        assert_eq!(msgids, vec![Some("left"), Some("old")]);
+        assert!(protocol.channels().channel("#rust").is_some());
+    }
+
+    #[test]
+    fn chathistory_needs_server_support() {
+        let mut protocol = Protocol::new("nick");
+
+        let response = protocol.chathistory(
+            HistoryRequest::Before {
+                target: "#rust".to_string(),
+                selector: Selector::MsgId("abc".to_string()),
+            },
+            10,
+        );
+
+        assert!(matches!(
+            response.try_take(),
+            Some(Err(Error::InvalidInput(_)))
+        ));
+        assert!(outgoing(&mut protocol).is_empty());
+    }
+
+    #[test]
+    fn labeled_command_is_answered_by_its_reply() {
+        let mut protocol = Protocol::new("nick");
+        protocol.handle_line(":server CAP nick ACK :echo-message labeled-response\r\n");
+        outgoing(&mut protocol);
+
+        let response = protocol.send_message("hello").unwrap();
+        assert_eq!(
+            outgoing(&mut protocol),
+            vec!["@label=l0 PRIVMSG #testchannel :hello"]
+        );
+        let events = protocol.handle_line("@label=l0 :nick!u@h PRIVMSG #testchannel :hello\r\n");
+
+        let reply = response.try_take().unwrap().unwrap();
+        assert_eq!(reply, events);
+        assert!(matches!(
+            &events[0].kind,
+            IRCEvent::PrivMsg { source, text, .. } if source.nickname == "nick" && text == "hello"
+        ));
+    }
+
+    #[test]
+    fn labeled_errors_fail_the_response_and_acks_are_dropped() {
+        let mut protocol = Protocol::new("nick");
+        protocol.handle_line(":server CAP nick ACK :labeled-response\r\n");
+        outgoing(&mut protocol);
+
+        let failed = protocol.send_message("/msg nobody hi").unwrap();
+        let acked = protocol.send_message("/nick other").unwrap();
+        assert_eq!(
+            outgoing(&mut protocol),
+            vec!["@label=l0 PRIVMSG nobody :hi", "@label=l1 NICK other"]
+        );
+        protocol.handle_line("@label=l0 :server BATCH +b labeled-response\r\n");
+        protocol.handle_line("@batch=b :server 401 nick nobody :No such nick/channel\r\n");
+        protocol.handle_line(":server BATCH -b\r\n");
+
+        assert!(handle(&mut protocol, "@label=l1 :server ACK\r\n").is_empty());
+        assert!(matches!(acked.try_take(), Some(Ok(events)) if events.is_empty()));
+        assert!(matches!(failed.try_take(), Some(Err(Error::Protocol(_)))));
+    }
+
+    #[test]
+    fn labeled_fail_becomes_a_standard_reply_for_its_command() {
+        let mut protocol = Protocol::new("nick");
+        protocol.handle_line(":server CAP nick ACK :labeled-response\r\n");
+        outgoing(&mut protocol);
+
+        let response = protocol.send_message("/nick alice").unwrap();
+        assert_eq!(outgoing(&mut protocol), vec!["@label=l0 NICK alice"]);
+        let events = protocol.handle_line(
+            "@label=l0 :server FAIL NICK NICKNAME_RESERVED alice :Nickname is reserved\r\n",
+        );
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].label.as_deref(), Some("l0"));
+        let IRCEvent::StandardReply(reply) = &events[0].kind else {
+            panic!("Expected a standard reply");
+        };
+        assert_eq!(
+            (reply.kind, reply.command.as_str(), reply.code.as_str()),
+            (ReplyKind::Fail, "NICK", "NICKNAME_RESERVED")
+        );
+        assert!(matches!(response.try_take(), Some(Err(Error::Protocol(_)))));
+    }
+
+    #[test]
+    fn client_tags_are_sent_with_tagmsg_and_replies() {
+        let mut protocol = Protocol::new("nick");
+        protocol.handle_line(":server CAP nick ACK :message-tags\r\n");
+        outgoing(&mut protocol);
+
+        protocol
+            .send_typing("#testchannel", TypingState::Active)
+            .unwrap();
+        protocol.send_reaction("#testchannel", "abc", "👍").unwrap();
+        protocol.send_reply("alice", "a;b", "sure thing").unwrap();
+
+        assert_eq!(
+            outgoing(&mut protocol),
+            vec![
+                "@+typing=active TAGMSG #testchannel",
+                "@+draft/reply=abc;+draft/react=👍 TAGMSG #testchannel",
+                "@+draft/reply=a\\:b PRIVMSG alice :sure thing",
+            ]
+        );
+    }
+
+    #[test]
+    fn client_tags_need_a_target_and_a_message() {
+        let mut protocol = Protocol::new("nick");
+        protocol.handle_line(":server CAP nick ACK :message-tags\r\n");
+        outgoing(&mut protocol);
+
+        assert!(matches!(
+            protocol.send_typing("  ", TypingState::Active),
+            Err(Error::InvalidInput(_))
+        ));
+        assert!(matches!(
+            protocol.send_reaction("", "abc", "👍"),
+            Err(Error::InvalidInput(_))
+        ));
+        assert!(matches!(
+            protocol.send_reaction("#testchannel", "", "👍"),
+            Err(Error::InvalidInput(_))
+        ));
+        assert!(outgoing(&mut protocol).is_empty());
+    }
+
+    #[test]
+    fn denied_client_tags_are_not_sent() {
+        let mut protocol = Protocol::new("nick");
+        assert!(
+            protocol
+                .send_typing("#testchannel", TypingState::Active)
+                .is_err()
+        );
+
+        protocol.handle_line(":server CAP nick ACK :message-tags\r\n");
+        protocol.handle_line(":server 005 nick CLIENTTAGDENY=*,-typing :are supported\r\n");
+        outgoing(&mut protocol);
+
+        assert!(protocol.send_reaction("#testchannel", "abc", "👍").is_err());
+        protocol.send_reply("alice", "abc", "hi").unwrap();
+        protocol
+            .send_typing("#testchannel", TypingState::Done)
+            .unwrap();
+        assert_eq!(
+            outgoing(&mut protocol),
+            vec!["PRIVMSG alice :hi", "@+typing=done TAGMSG #testchannel"]
+        );
+    }
+
+    #[test]
+    fn replies_carry_the_msgid_they_reply_to() {
+        let mut protocol = Protocol::new("nick");
+
+        let events = protocol
+            .handle_line("@msgid=b;+draft/reply=a :alice!al@host PRIVMSG #testchannel :yes\r\n");
+
+        assert_eq!(events[0].reply_to.as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn unlabeled_command_completes_right_away() {
+        let mut protocol = Protocol::new("nick");
+
+        let response = protocol.send_message("hello").unwrap();
+
+        assert_eq!(response.try_take().unwrap().unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn privmsg_becomes_a_typed_event() {
+        let mut protocol = Protocol::new("nick");
+
+        let events = handle(
+            &mut protocol,
+            ":alice!al@host PRIVMSG #testchannel :hi all\r\n",
+        );
+
+        assert_eq!(
+            events,
+            vec![IRCEvent::PrivMsg {
+                source: Source::parse("alice!al@host"),
+                target: "#testchannel".to_string(),
+                text: "hi all".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn own_nick_change_is_followed() {
+        let mut protocol = Protocol::new("nick");
+
+        protocol.handle_line(":nick!user@host NICK :newnick\r\n");
+
+        assert_eq!(protocol.nickname(), "newnick");
+    }
+
+    #[test]
+    fn error_numeric_becomes_server_error_event() {
+        let mut protocol = Protocol::new("nick");
+
+        let events = handle(
+            &mut protocol,
+            ":server 433 * nick :Nickname is already in use\r\n",
+        );
+
+        assert_eq!(
+            events,
+            vec![IRCEvent::ServerError(ServerError {
+                code: "433".to_string(),
+                params: vec!["nick".to_string()],
+                text: "Nickname is already in use".to_string(),
+            })]
+        );
+    }
+
+    #[test]
+    fn malformed_line_is_ignored() {
+        let mut protocol = Protocol::new("nick");
+
+        assert!(protocol.handle_line(":server N0T1C3 * :hi\r\n").is_empty());
+    }
+
+    #[test]
+    fn whois_replies_become_one_event() {
+        let mut protocol = Protocol::new("nick");
+        let response = protocol.whois("alice");
+        assert_eq!(outgoing(&mut protocol), vec!["WHOIS alice"]);
+
+        assert!(
+            protocol
+                .handle_line(":s 311 nick alice al host * :Alice\r\n")
+                .is_empty()
+        );
+        protocol.handle_line(":bob!b@h PRIVMSG #testchannel :in between\r\n");
+        let events = handle(&mut protocol, ":s 318 nick alice :End of /WHOIS list.\r\n");
+
+        let info = response.try_take().unwrap().unwrap();
+        assert_eq!(info.realname.as_deref(), Some("Alice"));
+        assert_eq!(events, vec![IRCEvent::Whois(info)]);
+    }
+
+    #[test]
+    fn joining_sends_whox_and_tracks_accounts() {
+        let mut protocol = Protocol::new("nick");
+        protocol.handle_line(":server 005 nick WHOX :are supported by this server\r\n");
+
+        protocol.handle_line(":nick!user@host JOIN #rust\r\n");
+        assert_eq!(outgoing(&mut protocol), vec!["WHO #rust %tcuhnfar,1"]);
+        protocol.handle_line(":server 353 nick = #rust :@alice nick\r\n");
+        protocol.handle_line(":server 354 nick 1 #rust al host alice G alice_acct :Alice\r\n");
+        let events = handle(&mut protocol, ":server 315 nick #rust :End of WHO list\r\n");
+
+        assert!(
+            matches!(&events[..], [IRCEvent::Who { mask, users }] if mask == "#rust" && users.len() == 1)
+        );
+        let alice = protocol.channels().user("alice").unwrap();
+        assert_eq!(alice.account.as_deref(), Some("alice_acct"));
+        assert!(alice.away);
+        assert_eq!(
+            protocol
+                .channels()
+                .channel("#rust")
+                .unwrap()
+                .member("alice")
+                .unwrap()
+                .prefixes,
+            "@"
+        );
+
+        let tagged = protocol.handle_line("@account=other :bob!b@host PRIVMSG #rust :hi\r\n");
+        let known = protocol.handle_line(":alice!al@host PRIVMSG #rust :hi\r\n");
+        assert_eq!(tagged[0].account.as_deref(), Some("other"));
+        assert_eq!(known[0].account.as_deref(), Some("alice_acct"));
+    }
+
+    #[test]
+    fn notify_list_uses_monitor_within_its_limit() {
+        let mut protocol = Protocol::new("nick");
+        protocol.notify_add(["alice", "bob"]).unwrap();
+        protocol.handle_line(":server 005 nick MONITOR=3 :are supported by this server\r\n");
+        assert!(outgoing(&mut protocol).is_empty());
+
+        protocol.handle_line(":server 376 nick :End of /MOTD command.\r\n");
+        assert_eq!(outgoing(&mut protocol), vec!["MONITOR + alice,bob"]);
+        let events = handle(&mut protocol, ":server 730 nick :alice!al@host\r\n");
+
+        assert_eq!(
+            events,
+            vec![IRCEvent::Online {
+                source: Source::parse("alice!al@host")
+            }]
+        );
+        assert!(protocol.notify_add(["carol", "dave"]).is_err());
+        assert_eq!(protocol.notify_list().nicknames(), ["alice", "bob"]);
+        protocol.notify_remove(["Alice"]);
+        assert_eq!(outgoing(&mut protocol), vec!["MONITOR - alice"]);
+        protocol.poll_notify_list();
+        assert!(outgoing(&mut protocol).is_empty());
+    }
+
+    #[test]
+    fn notify_list_falls_back_to_ison() {
+        let mut protocol = Protocol::new("nick");
+        protocol.handle_line(":server 422 nick :MOTD File is missing\r\n");
+        protocol.notify_add(["alice", "bob"]).unwrap();
+        assert_eq!(outgoing(&mut protocol), vec!["ISON alice bob"]);
+
+        let online = handle(&mut protocol, ":server 303 nick :alice\r\n");
+        protocol.poll_notify_list();
+        assert_eq!(outgoing(&mut protocol), vec!["ISON alice bob"]);
+        let offline = handle(&mut protocol, ":server 303 nick :\r\n");
+
+        assert_eq!(
+            online,
+            vec![IRCEvent::Online {
+                source: Source::parse("alice")
+            }]
+        );
+        assert_eq!(
+            offline,
+            vec![IRCEvent::Offline {
+                nickname: "alice".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn plain_who_without_whox() {
+        let mut protocol = Protocol::new("nick");
+
+        let response = protocol.who("alice");
+        assert_eq!(outgoing(&mut protocol), vec!["WHO alice"]);
+        protocol.handle_line(":server 352 nick * al host s alice H :0 Alice\r\n");
+        protocol.handle_line(":server 315 nick alice :End of WHO list\r\n");
+
+        let users = response.try_take().unwrap().unwrap();
+        assert_eq!(users[0].nickname, "alice");
+    }
+
+    #[test]
+    fn long_message_is_split_using_the_learned_source() {
+        let mut protocol = Protocol::new("nick");
+        protocol.handle_line(":server 005 nick LINELEN=512 :are supported by this server\r\n");
+        protocol.handle_line(":nick!user@host JOIN #testchannel\r\n");
+
+        protocol.send_message("word ".repeat(200)).unwrap();
+        let lines = outgoing(&mut protocol);
+
+        assert!(lines.len() > 1);
+        for line in &lines {
+            assert!(":nick!user@host ".len() + line.len() + 2 <= 512);
+        }
+    }
+
+    #[test]
+    fn pasted_lines_are_sent_as_one_multiline_message() {
+        let mut protocol = Protocol::new("nick");
+        protocol.handle_line(
+            ":server CAP nick LS :batch draft/multiline=max-bytes=4096,max-lines=10\r\n",
+        );
+        protocol.handle_line(":server CAP nick ACK :batch draft/multiline\r\n");
+        outgoing(&mut protocol);
+
+        protocol
+            .send_message("/msg alice \n    indented\r\nnext\n\n")
+            .unwrap();
+
+        assert_eq!(
+            outgoing(&mut protocol),
+            vec![
+                "BATCH +ml0 draft/multiline alice",
+                "@batch=ml0 PRIVMSG alice :    indented",
+                "@batch=ml0 PRIVMSG alice :next",
+                "BATCH -ml0",
+            ]
+        );
+    }
+
+    #[test]
+    fn pasted_lines_are_separate_messages_without_multiline() {
+        let mut protocol = Protocol::new("nick");
+
+        protocol.send_message("/msg alice first\n\nsecond").unwrap();
+
+        assert_eq!(
+            outgoing(&mut protocol),
+            vec!["PRIVMSG alice :first", "PRIVMSG alice :second"]
+        );
+    }
+
+    #[test]
+    fn incoming_multiline_batch_becomes_one_message() {
+        let mut protocol = Protocol::new("nick");
+
+        for line in [
+            "@msgid=m1 :alice!al@host BATCH +ml draft/multiline #testchannel",
+            "@batch=ml :alice!al@host PRIVMSG #testchannel :hello brave ",
+            "@batch=ml;draft/multiline-concat :alice!al@host PRIVMSG #testchannel :world",
+            "@batch=ml :alice!al@host PRIVMSG #testchannel :second line",
+        ] {
+            assert!(protocol.handle_line(line).is_empty());
+        }
+        let events = protocol.handle_line(":alice!al@host BATCH -ml");
+
+        assert_eq!(events[0].msgid.as_deref(), Some("m1"));
+        assert_eq!(
+            events[0].kind,
+            IRCEvent::PrivMsg {
+                source: Source::parse("alice!al@host"),
+                target: "#testchannel".to_string(),
+                text: "hello brave world\nsecond line".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn long_message_uses_multiline_batch_when_enabled() {
+        let mut protocol = Protocol::new("nick");
+        protocol.handle_line(
+            ":server CAP nick LS :batch draft/multiline=max-bytes=4096,max-lines=10\r\n",
+        );
+        protocol.handle_line(":server CAP nick ACK :batch draft/multiline\r\n");
+        outgoing(&mut protocol);
+
+        protocol.send_message("word ".repeat(200)).unwrap();
+        let lines = outgoing(&mut protocol);
+
+        assert_eq!(lines[0], "BATCH +ml0 draft/multiline #testchannel");
+        assert!(lines[1].starts_with("@batch=ml0 PRIVMSG #testchannel :"));
+        assert!(lines[2].starts_with("@batch=ml0;draft/multiline-concat PRIVMSG"));
+        assert_eq!(lines.last().map(String::as_str), Some("BATCH -ml0"));
+    }
+}
@@ -0,0 +1,190 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use crate::Error;
+
+/// The answer to a request sent to the server, such as a WHOIS.
+///
+/// The reply arrives on the listener, so the caller either blocks on
+/// [`Response::wait_timeout`] or awaits the response as a future. Servers may never reply, so
+/// async callers should put a timeout around it as well.
+pub struct Response<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// The half of a [`Response`] kept by the protocol, which completes it when the reply is in. A
+/// responder that is dropped without a reply fails the response with [`Error::NotConnected`].
+pub(crate) struct Responder<T> {
+    shared: Arc<Shared<T>>,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    ready: Condvar,
+}
+
+struct State<T> {
+    result: Option<Result<T, Error>>,
+    completed: bool,
+    waker: Option<Waker>,
+}
+
+pub(crate) fn response<T>() -> (Responder<T>, Response<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            result: None,
+            completed: false,
+            waker: None,
+        }),
+        ready: Condvar::new(),
+    });
+
+    (
+        Responder {
+            shared: Arc::clone(&shared),
+        },
+        Response { shared },
+    )
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<T> Response<T> {
+    /// Blocks until the reply is in, or fails with [`Error::Timeout`].
+    pub fn wait_timeout(self, timeout: Duration) -> Result<T, Error> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+
+        loop {
+            if let Some(result) = state.result.take() {
+                return result;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::Timeout);
+            }
+            state = match self.shared.ready.wait_timeout(state, deadline - now) {
+                Ok((state, _)) => state,
+                Err(poisoned) => poisoned.into_inner().0,
+            };
+        }
+    }
+
+    /// Takes the reply if it is already in.
+    pub fn try_take(&self) -> Option<Result<T, Error>> {
+        self.shared.lock().result.take()
+    }
+}
+
+impl<T> std::fmt::Debug for Response<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let completed = self.shared.lock().completed;
+        f.debug_struct("Response")
+            .field("completed", &completed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> Future for Response<T> {
+    type Output = Result<T, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.shared.lock();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Responder<T> {
+    pub(crate) fn complete(self, result: Result<T, Error>) {
+        self.set(result);
+    }
+
+    /// Whether the response was dropped, for example because its caller timed out, so that
+    /// nobody waits for the reply any more.
+    pub(crate) fn is_abandoned(&self) -> bool {
+        Arc::strong_count(&self.shared) == 1
+    }
+
+    fn set(&self, result: Result<T, Error>) {
+        let mut state = self.shared.lock();
+        if state.completed {
+            return;
+        }
+        state.result = Some(result);
+        state.completed = true;
+        self.shared.ready.notify_all();
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for Responder<T> {
+    fn drop(&mut self) {
+        if Arc::strong_count(&self.shared) > 1 {
+            self.set(Err(Error::NotConnected));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn wait_returns_completed_value() {
+        let (responder, response) = response();
+
+        thread::spawn(move || responder.complete(Ok(42)));
+
+        assert_eq!(response.wait_timeout(Duration::from_secs(2)).unwrap(), 42);
+    }
+
+    #[test]
+    fn wait_times_out_without_reply() {
+        let (_responder, response) = response::<u32>();
+
+        let result = response.wait_timeout(Duration::from_millis(10));
+
+        assert!(matches!(result, Err(Error::Timeout)));
+    }
+
+    #[test]
+    fn response_that_timed_out_is_abandoned() {
+        let (responder, response) = response::<u32>();
+        assert!(!responder.is_abandoned());
+
+        let _ = response.wait_timeout(Duration::ZERO);
+
+        assert!(responder.is_abandoned());
+    }
+
+    #[test]
+    fn dropped_responder_fails_the_response() {
+        let (responder, response) = response::<u32>();
+
+        drop(responder);
+
+        assert!(matches!(
+            response.try_take(),
+            Some(Err(Error::NotConnected))
+        ));
+    }
+}
@@ -0,0 +1,97 @@
+use crate::Credentials;
+
+/// The largest chunk of a base64 encoded AUTHENTICATE payload that fits on one line.
+const AUTHENTICATE_CHUNK_LEN: usize = 400;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Builds the AUTHENTICATE lines that answer the server's `AUTHENTICATE +` for the PLAIN
+/// mechanism.
+///
+/// The payload is `account NUL account NUL password` in base64, sent in chunks of 400 bytes. A
+/// payload that is a multiple of 400 bytes long is finished with `AUTHENTICATE +`.
+pub(crate) fn plain_authenticate_lines(credentials: &Credentials) -> Vec<String> {
+    let payload = format!(
+        "{}\0{}\0{}",
+        credentials.account, credentials.account, credentials.password
+    );
+    let encoded = base64_encode(payload.as_bytes());
+
+    let mut lines: Vec<String> = encoded
+        .as_bytes()
+        .chunks(AUTHENTICATE_CHUNK_LEN)
+        .map(|chunk| format!("AUTHENTICATE {}", String::from_utf8_lossy(chunk)))
+        .collect();
+    if encoded.len().is_multiple_of(AUTHENTICATE_CHUNK_LEN) {
+        lines.push("AUTHENTICATE +".to_string());
+    }
+
+    lines
+}
+
+fn base64_encode(input: &[u8]) -> String {
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let triple = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (triple >> (18 - 6 * i)) & 0x3f;
+                output.push(BASE64_ALPHABET[index as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_matches_rfc_4648_vectors() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn plain_payload_is_account_account_password() {
+        let credentials = Credentials {
+            account: "jilles".to_string(),
+            password: "sesame".to_string(),
+        };
+
+        assert_eq!(
+            plain_authenticate_lines(&credentials),
+            vec!["AUTHENTICATE amlsbGVzAGppbGxlcwBzZXNhbWU="]
+        );
+    }
+
+    #[test]
+    fn payload_of_exactly_one_chunk_ends_with_plus() {
+        // 2 + 1 + 2 + 1 + 294 = 300 bytes encode to exactly 400 characters.
+        let credentials = Credentials {
+            account: "ab".to_string(),
+            password: "p".repeat(294),
+        };
+
+        let lines = plain_authenticate_lines(&credentials);
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].len(), "AUTHENTICATE ".len() + 400);
+        assert_eq!(lines[1], "AUTHENTICATE +");
+    }
+}
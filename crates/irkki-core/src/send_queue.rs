@@ -0,0 +1,354 @@
+use log::warn;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Source of the current time for a [`SendQueue`]. Tests can drive the queue with a fake clock.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Lines with a high priority, such as PONG and QUIT, are sent before any queued normal lines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    High,
+    Normal,
+}
+
+/// What to do with a normal priority line when the queue is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Reject the line that is being queued.
+    DropNewest,
+    /// Make room by discarding the oldest queued normal priority line.
+    DropOldest,
+}
+
+/// What became of a line given to [`SendQueue::push`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Pushed {
+    Queued,
+    /// The line was queued, and the oldest normal priority line was dropped to make room.
+    DroppedOldest(String),
+    /// The queue was full, so the line was dropped.
+    Rejected,
+}
+
+/// Token bucket settings for outgoing lines.
+///
+/// Up to `burst` lines can be sent at once, after which one more line may be sent every `rate`.
+/// A burst of zero counts as one, as nothing could ever be sent otherwise.
+/// Most servers disconnect clients for "Excess Flood" when they send faster than roughly one line
+/// every two seconds over a longer period.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FloodControl {
+    pub burst: u32,
+    pub rate: Duration,
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for FloodControl {
+    fn default() -> Self {
+        Self {
+            burst: 5,
+            rate: Duration::from_secs(2),
+            capacity: 512,
+            overflow: OverflowPolicy::DropNewest,
+        }
+    }
+}
+
+pub struct SendQueue<C: Clock = SystemClock> {
+    config: FloodControl,
+    clock: C,
+    tokens: u32,
+    last_refill: Instant,
+    high: VecDeque<String>,
+    normal: VecDeque<String>,
+}
+
+impl SendQueue<SystemClock> {
+    pub fn new(config: FloodControl) -> Self {
+        Self::with_clock(config, SystemClock)
+    }
+}
+
+impl FloodControl {
+    fn clamped(self) -> Self {
+        Self {
+            burst: self.burst.max(1),
+            ..self
+        }
+    }
+}
+
+impl<C: Clock> SendQueue<C> {
+    pub fn with_clock(config: FloodControl, clock: C) -> Self {
+        let config = config.clamped();
+        let last_refill = clock.now();
+        Self {
+            tokens: config.burst,
+            config,
+            clock,
+            last_refill,
+            high: VecDeque::new(),
+            normal: VecDeque::new(),
+        }
+    }
+
+    pub fn config(&self) -> &FloodControl {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: FloodControl) {
+        let config = config.clamped();
+        self.tokens = self.tokens.min(config.burst);
+        self.config = config;
+    }
+
+    /// Number of lines waiting to be sent.
+    pub fn len(&self) -> usize {
+        self.high.len() + self.normal.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.high.is_empty() && self.normal.is_empty()
+    }
+
+    /// Queues a line, and tells whether it or another line was dropped to respect the capacity.
+    ///
+    /// High priority lines are never dropped, they may exceed the capacity of the queue.
+    pub fn push(&mut self, line: impl Into<String>, priority: Priority) -> Pushed {
+        let line = line.into();
+
+        if priority == Priority::High {
+            self.high.push_back(line);
+            return Pushed::Queued;
+        }
+
+        if self.len() < self.config.capacity {
+            self.normal.push_back(line);
+            return Pushed::Queued;
+        }
+
+        let oldest = match self.config.overflow {
+            OverflowPolicy::DropNewest => None,
+            OverflowPolicy::DropOldest => self.normal.pop_front(),
+        };
+        match oldest {
+            Some(oldest) => {
+                warn!("Send queue is full, dropping line: {}", oldest);
+                self.normal.push_back(line);
+                Pushed::DroppedOldest(oldest)
+            }
+            None => {
+                warn!("Send queue is full, dropping line: {}", line);
+                Pushed::Rejected
+            }
+        }
+    }
+
+    /// Takes the next line to send if the token bucket allows it.
+    pub fn pop(&mut self) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+
+        self.refill();
+        if self.tokens == 0 {
+            return None;
+        }
+
+        let line = self.high.pop_front().or_else(|| self.normal.pop_front())?;
+        self.tokens -= 1;
+        Some(line)
+    }
+
+    /// How long to wait before the next line may be sent, or `None` when the queue is empty.
+    pub fn next_send_in(&mut self) -> Option<Duration> {
+        if self.is_empty() {
+            return None;
+        }
+
+        self.refill();
+        if self.tokens > 0 {
+            return Some(Duration::ZERO);
+        }
+
+        let elapsed = self.clock.now().saturating_duration_since(self.last_refill);
+        Some(self.config.rate.saturating_sub(elapsed))
+    }
+
+    fn refill(&mut self) {
+        let now = self.clock.now();
+        if self.config.rate.is_zero() {
+            self.tokens = self.config.burst;
+            self.last_refill = now;
+            return;
+        }
+
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let earned = elapsed.as_nanos() / self.config.rate.as_nanos();
+        if earned == 0 {
+            return;
+        }
+
+        let earned = u32::try_from(earned).unwrap_or(u32::MAX);
+        self.tokens = self.tokens.saturating_add(earned).min(self.config.burst);
+        if self.tokens == self.config.burst {
+            self.last_refill = now;
+        } else {
+            self.last_refill += self.config.rate * earned;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[derive(Clone)]
+    struct FakeClock(Rc<Cell<Instant>>);
+
+    impl FakeClock {
+        fn new() -> Self {
+            Self(Rc::new(Cell::new(Instant::now())))
+        }
+
+        fn advance(&self, duration: Duration) {
+            self.0.set(self.0.get() + duration);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.0.get()
+        }
+    }
+
+    fn queue(
+        burst: u32,
+        capacity: usize,
+        overflow: OverflowPolicy,
+    ) -> (SendQueue<FakeClock>, FakeClock) {
+        let clock = FakeClock::new();
+        let config = FloodControl {
+            burst,
+            rate: Duration::from_secs(2),
+            capacity,
+            overflow,
+        };
+        (SendQueue::with_clock(config, clock.clone()), clock)
+    }
+
+    #[test]
+    fn burst_is_sent_immediately_then_rate_limited() {
+        let (mut queue, clock) = queue(2, 10, OverflowPolicy::DropNewest);
+        for i in 0..4 {
+            queue.push(format!("PRIVMSG #c :{i}"), Priority::Normal);
+        }
+
+        assert_eq!(queue.pop().as_deref(), Some("PRIVMSG #c :0"));
+        assert_eq!(queue.pop().as_deref(), Some("PRIVMSG #c :1"));
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.next_send_in(), Some(Duration::from_secs(2)));
+
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.next_send_in(), Some(Duration::from_millis(500)));
+
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(queue.pop().as_deref(), Some("PRIVMSG #c :2"));
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn tokens_do_not_accumulate_beyond_burst() {
+        let (mut queue, clock) = queue(2, 10, OverflowPolicy::DropNewest);
+        clock.advance(Duration::from_secs(60));
+        for i in 0..3 {
+            queue.push(format!("line {i}"), Priority::Normal);
+        }
+
+        assert!(queue.pop().is_some());
+        assert!(queue.pop().is_some());
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn high_priority_lines_jump_the_queue() {
+        let (mut queue, _clock) = queue(5, 10, OverflowPolicy::DropNewest);
+        queue.push("PRIVMSG #c :one", Priority::Normal);
+        queue.push("PRIVMSG #c :two", Priority::Normal);
+        queue.push("PONG :server", Priority::High);
+
+        assert_eq!(queue.pop().as_deref(), Some("PONG :server"));
+        assert_eq!(queue.pop().as_deref(), Some("PRIVMSG #c :one"));
+        assert_eq!(queue.pop().as_deref(), Some("PRIVMSG #c :two"));
+        assert_eq!(queue.next_send_in(), None);
+    }
+
+    #[test]
+    fn drop_newest_rejects_line_when_full() {
+        let (mut queue, _clock) = queue(5, 2, OverflowPolicy::DropNewest);
+        assert_eq!(queue.push("one", Priority::Normal), Pushed::Queued);
+        assert_eq!(queue.push("two", Priority::Normal), Pushed::Queued);
+
+        assert_eq!(queue.push("three", Priority::Normal), Pushed::Rejected);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop().as_deref(), Some("one"));
+    }
+
+    #[test]
+    fn drop_oldest_discards_front_of_queue_when_full() {
+        let (mut queue, _clock) = queue(5, 2, OverflowPolicy::DropOldest);
+        queue.push("same", Priority::Normal);
+        queue.push("two", Priority::Normal);
+
+        assert_eq!(
+            queue.push("same", Priority::Normal),
+            Pushed::DroppedOldest("same".to_string())
+        );
+        assert_eq!(queue.pop().as_deref(), Some("two"));
+        assert_eq!(queue.pop().as_deref(), Some("same"));
+    }
+
+    #[test]
+    fn burst_of_zero_still_sends() {
+        let (mut queue, clock) = queue(0, 10, OverflowPolicy::DropNewest);
+        queue.push("one", Priority::Normal);
+        queue.push("two", Priority::Normal);
+
+        assert_eq!(queue.pop().as_deref(), Some("one"));
+        assert_eq!(queue.pop(), None);
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(queue.pop().as_deref(), Some("two"));
+
+        queue.set_config(FloodControl {
+            burst: 0,
+            ..FloodControl::default()
+        });
+        assert_eq!(queue.config().burst, 1);
+    }
+
+    #[test]
+    fn high_priority_lines_are_never_dropped() {
+        let (mut queue, _clock) = queue(5, 1, OverflowPolicy::DropNewest);
+        queue.push("one", Priority::Normal);
+
+        assert_eq!(queue.push("QUIT :bye", Priority::High), Pushed::Queued);
+        assert_eq!(queue.len(), 2);
+    }
+}
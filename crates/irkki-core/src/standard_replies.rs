@@ -0,0 +1,127 @@
+use std::fmt;
+
+use crate::{Message, ServerError};
+
+/// How severe a standard reply is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplyKind {
+    /// `FAIL`, the command failed.
+    Fail,
+    /// `WARN`, the command worked but something is off.
+    Warn,
+    /// `NOTE`, information about the command.
+    Note,
+}
+
+impl fmt::Display for ReplyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplyKind::Fail => write!(f, "FAIL"),
+            ReplyKind::Warn => write!(f, "WARN"),
+            ReplyKind::Note => write!(f, "NOTE"),
+        }
+    }
+}
+
+/// An IRCv3 standard reply: `FAIL <command> <code> [<context>...] :<description>`, and the same
+/// for `WARN` and `NOTE`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StandardReply {
+    pub kind: ReplyKind,
+    /// The command the reply is about, or `*` when it is not about a command.
+    pub command: String,
+    /// The machine readable code, e.g. `ACCOUNT_REQUIRED`.
+    pub code: String,
+    pub context: Vec<String>,
+    /// The human readable description from the server.
+    pub description: String,
+}
+
+impl StandardReply {
+    /// Returns the reply of a `FAIL`, `WARN` or `NOTE` message.
+    pub fn from_message(message: &Message) -> Option<Self> {
+        let kind = match message.command.as_str() {
+            "FAIL" => ReplyKind::Fail,
+            "WARN" => ReplyKind::Warn,
+            "NOTE" => ReplyKind::Note,
+            _ => return None,
+        };
+        let [command, code, context @ .., description] = &message.params[..] else {
+            return None;
+        };
+
+        Some(Self {
+            kind,
+            command: command.clone(),
+            code: code.clone(),
+            context: context.to_vec(),
+            description: description.clone(),
+        })
+    }
+}
+
+impl fmt::Display for StandardReply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.kind, self.command, self.code)?;
+        for param in &self.context {
+            write!(f, " {param}")?;
+        }
+        write!(f, " :{}", self.description)
+    }
+}
+
+/// A `FAIL` as the error of the command it answers, keeping its code, context and description.
+impl From<StandardReply> for ServerError {
+    fn from(reply: StandardReply) -> Self {
+        Self {
+            code: reply.code,
+            params: reply.context,
+            text: reply.description,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+
+    fn reply(line: &str) -> Option<StandardReply> {
+        StandardReply::from_message(&Parser::new(line).parse_message().unwrap())
+    }
+
+    #[test]
+    fn fail_keeps_its_context() {
+        let reply = reply(
+            ":server FAIL CHATHISTORY INVALID_TARGET LATEST #nope :Messages could not be retrieved",
+        )
+        .unwrap();
+
+        assert_eq!(reply.kind, ReplyKind::Fail);
+        assert_eq!(reply.command, "CHATHISTORY");
+        assert_eq!(reply.code, "INVALID_TARGET");
+        assert_eq!(reply.context, vec!["LATEST", "#nope"]);
+        assert_eq!(reply.description, "Messages could not be retrieved");
+        assert_eq!(
+            reply.to_string(),
+            "FAIL CHATHISTORY INVALID_TARGET LATEST #nope :Messages could not be retrieved"
+        );
+    }
+
+    #[test]
+    fn warn_and_note_without_context() {
+        let warn = reply(":server WARN REHASH CERTS_EXPIRED :Certificate has expired").unwrap();
+        let note = reply(":server NOTE * OPER_MESSAGE :The message").unwrap();
+
+        assert_eq!(warn.kind, ReplyKind::Warn);
+        assert!(warn.context.is_empty());
+        assert_eq!(note.kind, ReplyKind::Note);
+        assert_eq!(note.command, "*");
+    }
+
+    #[test]
+    fn too_short_reply_is_not_parsed() {
+        assert_eq!(reply(":server FAIL :Oops"), None);
+        assert_eq!(reply(":server PRIVMSG #chan :hi"), None);
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How the connection to the server is protected, which decides what happens with the STS
+/// policy the server advertises.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Security {
+    /// Plaintext without a way to move to TLS, so STS policies are ignored.
+    #[default]
+    Plaintext,
+    /// Plaintext, and the client reconnects over TLS when the server asks for it.
+    Upgradable,
+    Tls,
+}
+
+/// The longest an STS policy is kept, however long the server asks for. Longer durations are
+/// shortened to it.
+pub(crate) const MAX_STS_DURATION: Duration = Duration::from_secs(10 * 365 * 24 * 60 * 60);
+
+/// The value of the `sts` capability, e.g. `port=6697,duration=2592000`.
+///
+/// Over plaintext only the port counts: the client must reconnect over TLS to it. Over TLS only
+/// the duration counts: for that long the client must not connect to the host without TLS.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StsPolicy {
+    pub port: Option<u16>,
+    pub duration: Option<Duration>,
+    /// Whether the server allows the policy to be preloaded into clients.
+    pub preload: bool,
+}
+
+impl StsPolicy {
+    /// Parses the comma separated keys of the capability value. Unknown keys are ignored, and
+    /// the duration is capped at ten years.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut policy = Self {
+            port: None,
+            duration: None,
+            preload: false,
+        };
+
+        for key in value.split(',') {
+            match key.split_once('=') {
+                Some(("port", port)) => policy.port = Some(port.parse().ok()?),
+                Some(("duration", seconds)) => {
+                    let duration = Duration::from_secs(seconds.parse().ok()?);
+                    policy.duration = Some(duration.min(MAX_STS_DURATION));
+                }
+                None if key == "preload" => policy.preload = true,
+                _ => {}
+            }
+        }
+
+        Some(policy)
+    }
+}
+
+/// The STS policies of the hosts we have connected to over TLS, kept in a file so that later
+/// connections honour them.
+///
+/// Each line of the file is `<host> <port> <expiry>`, with the expiry in seconds since the Unix
+/// epoch.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StsStore {
+    path: Option<PathBuf>,
+    policies: HashMap<String, (u16, SystemTime)>,
+}
+
+impl StsStore {
+    /// A store that is not saved anywhere.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the policies from `path`. A file that does not exist yet is an empty store.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error),
+        };
+
+        let policies = contents
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let host = fields.next()?;
+                let port = fields.next()?.parse().ok()?;
+                let expiry =
+                    UNIX_EPOCH.checked_add(Duration::from_secs(fields.next()?.parse().ok()?))?;
+                Some((host.to_string(), (port, expiry)))
+            })
+            .collect();
+
+        Ok(Self {
+            path: Some(path),
+            policies,
+        })
+    }
+
+    /// The TLS port to use for `host`, while its policy has not expired.
+    pub fn port(&self, host: &str) -> Option<u16> {
+        self.policies
+            .get(&host.to_ascii_lowercase())
+            .filter(|(_, expiry)| *expiry > SystemTime::now())
+            .map(|(port, _)| *port)
+    }
+
+    /// Sets the policy of `host` to expire `duration` from now, at most ten years, and saves the
+    /// store. A duration of zero removes the policy.
+    pub fn update(&mut self, host: &str, port: u16, duration: Duration) -> io::Result<()> {
+        let host = host.to_ascii_lowercase();
+        let expiry = SystemTime::now().checked_add(duration.min(MAX_STS_DURATION));
+        match expiry {
+            Some(expiry) if !duration.is_zero() => {
+                self.policies.insert(host, (port, expiry));
+            }
+            _ => {
+                self.policies.remove(&host);
+            }
+        }
+        self.save()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let now = SystemTime::now();
+        let mut lines: Vec<String> = self
+            .policies
+            .iter()
+            .filter(|(_, (_, expiry))| *expiry > now)
+            .map(|(host, (port, expiry))| {
+                let seconds = expiry.duration_since(UNIX_EPOCH).unwrap_or_default();
+                format!("{host} {port} {}\n", seconds.as_secs())
+            })
+            .collect();
+        lines.sort();
+
+        write_atomically(path, &lines.concat())
+    }
+}
+
+/// Writes to a temporary file next to `path` first, so a crash never leaves half a file.
+fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)?;
+    }
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, contents)?;
+    fs::rename(temporary, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temporary_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("irkki-{name}-{}.sts", std::process::id()))
+    }
+
+    #[test]
+    fn policy_keys_are_parsed() {
+        let policy = StsPolicy::parse("port=6697,duration=300,preload,unknown=1").unwrap();
+
+        assert_eq!(policy.port, Some(6697));
+        assert_eq!(policy.duration, Some(Duration::from_secs(300)));
+        assert!(policy.preload);
+        assert_eq!(StsPolicy::parse("port=tls"), None);
+        assert_eq!(
+            StsPolicy::parse("duration=18446744073709551615")
+                .unwrap()
+                .duration,
+            Some(MAX_STS_DURATION)
+        );
+    }
+
+    #[test]
+    fn store_keeps_policies_until_they_expire() {
+        let mut store = StsStore::new();
+        store
+            .update("IRC.example.com", 6697, Duration::from_secs(60))
+            .unwrap();
+        store.policies.insert(
+            "old.example.com".to_string(),
+            (6697, SystemTime::now() - Duration::from_secs(1)),
+        );
+
+        assert_eq!(store.port("irc.example.com"), Some(6697));
+        assert_eq!(store.port("old.example.com"), None);
+
+        store
+            .update("long.example.com", 6697, Duration::MAX)
+            .unwrap();
+        assert_eq!(store.port("long.example.com"), Some(6697));
+
+        store
+            .update("irc.example.com", 6697, Duration::ZERO)
+            .unwrap();
+        assert_eq!(store.port("irc.example.com"), None);
+    }
+
+    #[test]
+    fn store_is_saved_and_loaded() {
+        let path = temporary_path("store");
+        let _ = fs::remove_file(&path);
+
+        let mut store = StsStore::open(&path).unwrap();
+        store
+            .update("irc.example.com", 6697, Duration::from_secs(60))
+            .unwrap();
+        let loaded = StsStore::open(&path).unwrap();
+        fs::write(&path, "tampered.example.com 6697 18446744073709551615\n").unwrap();
+        let tampered = StsStore::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(tampered.port("tampered.example.com"), None);
+        assert_eq!(loaded.port("irc.example.com"), Some(6697));
+        assert_eq!(loaded.port("other.example.com"), None);
+    }
+}
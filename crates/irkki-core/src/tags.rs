@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Parses the tags of a message, the part between the `@` and the first space, into keys and
+/// unescaped values. Tags without a value get an empty one, as the specification treats the two
+/// the same.
+pub(crate) fn parse_tags(raw: &str) -> HashMap<String, String> {
+    raw.split(';')
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| match tag.split_once('=') {
+            Some((key, value)) => (key.to_string(), unescape_tag_value(value)),
+            None => (tag.to_string(), String::new()),
+        })
+        .collect()
+}
+
+fn unescape_tag_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        // A backslash at the end of the value is dropped.
+        match chars.next() {
+            Some(':') => unescaped.push(';'),
+            Some('s') => unescaped.push(' '),
+            Some('r') => unescaped.push('\r'),
+            Some('n') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => {}
+        }
+    }
+
+    unescaped
+}
+
+/// Escapes a tag value for sending, the inverse of the unescaping in [`parse_tags`].
+pub(crate) fn escape_tag_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            ';' => escaped.push_str(r"\:"),
+            ' ' => escaped.push_str(r"\s"),
+            '\\' => escaped.push_str(r"\\"),
+            '\r' => escaped.push_str(r"\r"),
+            '\n' => escaped.push_str(r"\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Parses the `time` tag of the server-time extension, such as `2011-10-19T16:40:51.620Z`.
+pub(crate) fn parse_server_time(value: &str) -> Option<SystemTime> {
+    let (date, time) = value.strip_suffix('Z')?.split_once('T')?;
+
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut time = time.splitn(3, ':').map(str::parse::<u64>);
+    let (hours, minutes, seconds) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hours > 23 || minutes > 59 {
+        return None;
+    }
+    // Seconds may be 60 for a leap second.
+    if seconds > 60 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    let nanos = format!("{:0<9}", &fraction[..fraction.len().min(9)])
+        .parse::<u32>()
+        .ok()?;
+
+    Some(
+        UNIX_EPOCH
+            + Duration::from_secs(days * 86_400 + hours * 3_600 + minutes * 60 + seconds)
+            + Duration::from_nanos(nanos.into()),
+    )
+}
+
+/// Formats a time the way the server-time extension does, such as `2011-10-19T16:40:51.620Z`.
+pub(crate) fn format_server_time(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        seconds / 3_600 % 24,
+        seconds / 60 % 60,
+        seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// The date of a day since 1970-01-01, the inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar, after Howard Hinnant's
+/// `days_from_civil`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (month + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_are_split_and_unescaped() {
+        let tags = parse_tags(r"msgid=abc;+example.com/flag;note=a\sb\:c\\d\");
+
+        assert_eq!(tags["msgid"], "abc");
+        assert_eq!(tags["+example.com/flag"], "");
+        assert_eq!(tags["note"], r"a b;c\d");
+    }
+
+    #[test]
+    fn escaped_tag_values_are_parsed_back() {
+        let value = "a b;c\\d";
+
+        assert_eq!(escape_tag_value(value), r"a\sb\:c\\d");
+        assert_eq!(
+            parse_tags(&format!("k={}", escape_tag_value(value)))["k"],
+            value
+        );
+    }
+
+    #[test]
+    fn server_time_is_parsed_as_utc() {
+        let time = parse_server_time("2011-10-19T16:40:51.620Z").unwrap();
+
+        assert_eq!(
+            time.duration_since(UNIX_EPOCH).unwrap(),
+            Duration::from_millis(1_319_042_451_620)
+        );
+        assert_eq!(parse_server_time("1970-01-01T00:00:00Z"), Some(UNIX_EPOCH));
+    }
+
+    #[test]
+    fn server_time_is_formatted_back() {
+        for value in ["2011-10-19T16:40:51.620Z", "2000-02-29T00:00:00.000Z"] {
+            assert_eq!(format_server_time(parse_server_time(value).unwrap()), value);
+        }
+    }
+
+    #[test]
+    fn invalid_server_time_is_rejected() {
+        assert_eq!(parse_server_time("2011-10-19 16:40:51Z"), None);
+        assert_eq!(parse_server_time("2011-13-19T16:40:51Z"), None);
+        assert_eq!(parse_server_time("2011-10-19T16:40:51"), None);
+    }
+}
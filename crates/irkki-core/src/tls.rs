@@ -0,0 +1,155 @@
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::Transport;
+
+const TLS_READ_BUFFER: usize = 16 * 1024;
+
+/// A TLS connection to an IRC server.
+///
+/// rustls keeps the state of the session in one `ClientConnection`, so the reading and writing
+/// halves share it behind a mutex and each use their own clone of the socket. The reader never
+/// holds the lock while it waits for data from the server.
+pub struct TlsStream {
+    connection: Arc<Mutex<ClientConnection>>,
+    socket: TcpStream,
+}
+
+impl TlsStream {
+    /// Connects to the server and verifies its certificate against the Mozilla root
+    /// certificates.
+    pub fn connect(server: &str, port: u16) -> io::Result<Self> {
+        let socket = TcpStream::connect((server, port))?;
+        Self::with_config(socket, server, Self::default_config()?)
+    }
+
+    /// Runs the TLS handshake over an already connected socket.
+    pub fn with_config(
+        mut socket: TcpStream,
+        server_name: &str,
+        config: Arc<ClientConfig>,
+    ) -> io::Result<Self> {
+        let server_name = ServerName::try_from(server_name.to_string())
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        let mut connection =
+            ClientConnection::new(config, server_name).map_err(io::Error::other)?;
+
+        while connection.is_handshaking() {
+            connection.complete_io(&mut socket)?;
+        }
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+            socket,
+        })
+    }
+
+    pub fn default_config() -> io::Result<Arc<ClientConfig>> {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(io::Error::other)?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        Ok(Arc::new(config))
+    }
+}
+
+impl Transport for TlsStream {
+    type Reader = TlsReader;
+    type Writer = TlsWriter;
+
+    fn split(self) -> io::Result<(Self::Reader, Self::Writer)> {
+        let reader = TlsReader {
+            connection: Arc::clone(&self.connection),
+            socket: self.socket.try_clone()?,
+            incoming: vec![0; TLS_READ_BUFFER],
+        };
+        let writer = TlsWriter {
+            connection: self.connection,
+            socket: self.socket,
+        };
+
+        Ok((reader, writer))
+    }
+
+    fn is_secure(&self) -> bool {
+        true
+    }
+}
+
+fn lock(connection: &Mutex<ClientConnection>) -> io::Result<MutexGuard<'_, ClientConnection>> {
+    connection
+        .lock()
+        .map_err(|_| io::Error::other("TLS connection lock poisoned"))
+}
+
+fn write_pending(connection: &mut ClientConnection, socket: &mut TcpStream) -> io::Result<()> {
+    while connection.wants_write() {
+        connection.write_tls(socket)?;
+    }
+    Ok(())
+}
+
+pub struct TlsReader {
+    connection: Arc<Mutex<ClientConnection>>,
+    socket: TcpStream,
+    /// The encrypted bytes read from the socket, kept between reads to not allocate for each.
+    incoming: Vec<u8>,
+}
+
+impl Read for TlsReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match lock(&self.connection)?.reader().read(buf) {
+                Ok(n) => return Ok(n),
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => {}
+                Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(0),
+                Err(error) => return Err(error),
+            }
+
+            let n = self.socket.read(&mut self.incoming)?;
+            if n == 0 {
+                return Ok(0);
+            }
+
+            let mut connection = lock(&self.connection)?;
+            let mut received = &self.incoming[..n];
+            while !received.is_empty() {
+                connection.read_tls(&mut received)?;
+                connection
+                    .process_new_packets()
+                    .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+            }
+            write_pending(&mut connection, &mut self.socket)?;
+        }
+    }
+}
+
+pub struct TlsWriter {
+    connection: Arc<Mutex<ClientConnection>>,
+    socket: TcpStream,
+}
+
+impl Write for TlsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut connection = lock(&self.connection)?;
+        let n = connection.writer().write(buf)?;
+        write_pending(&mut connection, &mut self.socket)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut connection = lock(&self.connection)?;
+        connection.writer().flush()?;
+        write_pending(&mut connection, &mut self.socket)?;
+        self.socket.flush()
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+/// A byte stream to an IRC server that the client can run over.
+///
+/// The client reads on its listener thread while lines are written from another thread, so a
+/// transport is split into a reading and a writing half. Any `(reader, writer)` pair is a
+/// transport, which makes it easy to bring your own.
+pub trait Transport {
+    type Reader: Read + Send + 'static;
+    type Writer: Write + Send + 'static;
+
+    fn split(self) -> io::Result<(Self::Reader, Self::Writer)>;
+
+    /// Whether the transport is encrypted, which decides how the client treats the server's STS
+    /// policy.
+    fn is_secure(&self) -> bool {
+        false
+    }
+}
+
+impl Transport for TcpStream {
+    type Reader = TcpStream;
+    type Writer = TcpStream;
+
+    fn split(self) -> io::Result<(Self::Reader, Self::Writer)> {
+        Ok((self.try_clone()?, self))
+    }
+}
+
+#[cfg(unix)]
+impl Transport for std::os::unix::net::UnixStream {
+    type Reader = std::os::unix::net::UnixStream;
+    type Writer = std::os::unix::net::UnixStream;
+
+    fn split(self) -> io::Result<(Self::Reader, Self::Writer)> {
+        Ok((self.try_clone()?, self))
+    }
+}
+
+impl<R, W> Transport for (R, W)
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    type Reader = R;
+    type Writer = W;
+
+    fn split(self) -> io::Result<(Self::Reader, Self::Writer)> {
+        Ok(self)
+    }
+}
+
+/// Creates an in-memory pipe. Bytes written to the writer can be read from the reader, and the
+/// reader sees the end of the stream once the writer is dropped.
+///
+/// Two pipes make a connection that lets tests talk to a client without opening any sockets.
+pub fn memory_pipe() -> (PipeReader, PipeWriter) {
+    let shared = Arc::new(Pipe {
+        state: Mutex::new(PipeState {
+            buffer: VecDeque::new(),
+            closed: false,
+        }),
+        ready: Condvar::new(),
+    });
+
+    (
+        PipeReader {
+            pipe: Arc::clone(&shared),
+        },
+        PipeWriter { pipe: shared },
+    )
+}
+
+struct Pipe {
+    state: Mutex<PipeState>,
+    ready: Condvar,
+}
+
+struct PipeState {
+    buffer: VecDeque<u8>,
+    closed: bool,
+}
+
+impl Pipe {
+    fn lock(&self) -> io::Result<MutexGuard<'_, PipeState>> {
+        self.state
+            .lock()
+            .map_err(|_| io::Error::other("Pipe lock poisoned"))
+    }
+}
+
+pub struct PipeReader {
+    pipe: Arc<Pipe>,
+}
+
+pub struct PipeWriter {
+    pipe: Arc<Pipe>,
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.pipe.lock()?;
+        while state.buffer.is_empty() && !state.closed {
+            state = self
+                .pipe
+                .ready
+                .wait(state)
+                .map_err(|_| io::Error::other("Pipe lock poisoned"))?;
+        }
+
+        let n = buf.len().min(state.buffer.len());
+        for (slot, byte) in buf.iter_mut().zip(state.buffer.drain(..n)) {
+            *slot = byte;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.pipe.lock()?;
+        state.buffer.extend(buf);
+        self.pipe.ready.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.pipe.state.lock() {
+            state.closed = true;
+        }
+        self.pipe.ready.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::thread;
+
+    #[test]
+    fn memory_pipe_delivers_written_bytes() {
+        let (reader, mut writer) = memory_pipe();
+
+        writer.write_all(b"PING :one\r\nPING :two\r\n").unwrap();
+        drop(writer);
+
+        let lines: Vec<String> = BufReader::new(reader).lines().map(Result::unwrap).collect();
+        assert_eq!(lines, vec!["PING :one", "PING :two"]);
+    }
+
+    #[test]
+    fn memory_pipe_reader_waits_for_writer() {
+        let (mut reader, mut writer) = memory_pipe();
+
+        let handle = thread::spawn(move || {
+            let mut buffer = [0; 4];
+            let n = reader.read(&mut buffer).unwrap();
+            buffer[..n].to_vec()
+        });
+        writer.write_all(b"ok").unwrap();
+
+        assert_eq!(handle.join().unwrap(), b"ok");
+    }
+
+    #[test]
+    fn tuple_is_a_transport() {
+        let (reader, writer) = memory_pipe();
+
+        assert!((reader, writer).split().is_ok());
+    }
+}
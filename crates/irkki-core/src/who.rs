@@ -0,0 +1,237 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::{Error, Message, Responder, Response, User, response};
+
+/// How long a WHO request waits for RPL_ENDOFWHO before it is given up.
+const WHO_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The WHOX fields we ask for: token, channel, username, host, nickname, flags, account and
+/// real name. The replies list them in this order.
+pub(crate) const WHOX_FIELDS: &str = "%tcuhnfar";
+
+/// A user from a WHO reply, and whether the reply said which account they are logged in to.
+/// Only WHOX replies do, plain WHO leaves the account unknown.
+pub(crate) struct WhoReply {
+    pub(crate) user: User,
+    pub(crate) has_account: bool,
+}
+
+/// Collects the WHO and WHOX replies up to RPL_ENDOFWHO.
+///
+/// WHOX replies carry the token of their request, and RPL_ENDOFWHO the mask. Plain WHO replies
+/// carry neither, so they belong to the plain request for their channel, or else to the oldest
+/// plain request. Requests that never end are given up after [`WHO_TIMEOUT`], so that they do
+/// not take the replies of later ones.
+#[derive(Default)]
+pub(crate) struct WhoReplies {
+    waiting: VecDeque<WhoRequest>,
+    tokens_used: u16,
+}
+
+struct WhoRequest {
+    mask: String,
+    token: Option<String>,
+    users: Vec<User>,
+    sent: Instant,
+    responder: Responder<Vec<User>>,
+}
+
+impl WhoReplies {
+    /// Returns the token to send with a WHOX request, or `None` for a plain WHO, and the
+    /// response that will be completed with the users.
+    pub(crate) fn expect(
+        &mut self,
+        mask: &str,
+        whox: bool,
+    ) -> (Option<String>, Response<Vec<User>>) {
+        self.expire();
+        let token = whox.then(|| {
+            // WHOX tokens are at most three digits.
+            self.tokens_used = self.tokens_used % 999 + 1;
+            self.tokens_used.to_string()
+        });
+        let (responder, response) = response();
+        self.waiting.push_back(WhoRequest {
+            mask: mask.to_string(),
+            token: token.clone(),
+            users: Vec::new(),
+            sent: Instant::now(),
+            responder,
+        });
+
+        (token, response)
+    }
+
+    /// Fails the requests that have waited longer than [`WHO_TIMEOUT`].
+    fn expire(&mut self) {
+        let (expired, waiting) = std::mem::take(&mut self.waiting)
+            .into_iter()
+            .partition(|request| request.sent.elapsed() >= WHO_TIMEOUT);
+        self.waiting = waiting;
+
+        for request in expired {
+            request.responder.complete(Err(Error::Timeout));
+        }
+    }
+
+    /// Parses a RPL_WHOREPLY or RPL_WHOSPCRPL and keeps the user for the current request.
+    pub(crate) fn add(&mut self, message: &Message) -> Option<WhoReply> {
+        let params = &message.params;
+        let reply = match message.command.as_str() {
+            // <client> <channel> <username> <host> <server> <nick> <flags> :<hopcount> <realname>
+            "352" if params.len() >= 8 => WhoReply {
+                user: User {
+                    nickname: params[5].clone(),
+                    username: Some(params[2].clone()),
+                    host: Some(params[3].clone()),
+                    realname: params[7].split_once(' ').map(|(_, name)| name.to_string()),
+                    account: None,
+                    away: params[6].starts_with('G'),
+                },
+                has_account: false,
+            },
+            // <client> <token> <channel> <username> <host> <nick> <flags> <account> :<realname>
+            "354" if params.len() >= 9 => {
+                if !self
+                    .waiting
+                    .iter()
+                    .any(|request| request.token.as_deref() == Some(params[1].as_str()))
+                {
+                    return None;
+                }
+
+                WhoReply {
+                    user: User {
+                        nickname: params[5].clone(),
+                        username: Some(params[3].clone()),
+                        host: Some(params[4].clone()),
+                        realname: Some(params[8].clone()),
+                        account: Some(params[7].clone()).filter(|account| account != "0"),
+                        away: params[6].starts_with('G'),
+                    },
+                    has_account: true,
+                }
+            }
+            _ => return None,
+        };
+
+        let request = match message.command.as_str() {
+            "354" => self
+                .waiting
+                .iter_mut()
+                .find(|request| request.token.as_deref() == Some(params[1].as_str())),
+            _ => {
+                let channel = &params[1];
+                let index = self
+                    .waiting
+                    .iter()
+                    .position(|r| r.token.is_none() && r.mask.eq_ignore_ascii_case(channel))
+                    .or_else(|| self.waiting.iter().position(|r| r.token.is_none()));
+                index.and_then(|index| self.waiting.get_mut(index))
+            }
+        };
+        if let Some(request) = request {
+            request.users.push(reply.user.clone());
+        }
+        Some(reply)
+    }
+
+    /// Ends the request for the mask of RPL_ENDOFWHO, or the oldest one when no request has
+    /// that mask, and returns the mask and the users.
+    pub(crate) fn finish(&mut self, message: &Message) -> (String, Vec<User>) {
+        let mask = message.params.get(1).cloned().unwrap_or_default();
+        let index = self
+            .waiting
+            .iter()
+            .position(|request| request.mask.eq_ignore_ascii_case(&mask))
+            .unwrap_or_default();
+
+        let Some(request) = self.waiting.remove(index) else {
+            return (mask, Vec::new());
+        };
+        request.responder.complete(Ok(request.users.clone()));
+        (mask, request.users)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+
+    fn message(line: &str) -> Message {
+        Parser::new(line).parse_message().unwrap()
+    }
+
+    #[test]
+    fn who_replies_are_collected_until_end_of_who() {
+        let mut replies = WhoReplies::default();
+        let (token, response) = replies.expect("#rust", false);
+        assert_eq!(token, None);
+
+        let reply = replies
+            .add(&message(
+                ":s 352 me #rust al host s alice G :0 Alice Liddell",
+            ))
+            .unwrap();
+        assert!(reply.user.away && !reply.has_account);
+        replies.add(&message(":s 352 me #rust b host s bob H@ :0 Bob"));
+        let (mask, users) = replies.finish(&message(":s 315 me #rust :End of WHO list"));
+
+        assert_eq!(mask, "#rust");
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0].realname.as_deref(), Some("Alice Liddell"));
+        assert_eq!(response.try_take().unwrap().unwrap(), users);
+    }
+
+    #[test]
+    fn whox_replies_carry_account_and_token() {
+        let mut replies = WhoReplies::default();
+        let (token, _response) = replies.expect("#rust", true);
+        let token = token.unwrap();
+
+        let other = ":s 354 me 999 #rust al host alice H alice :Alice";
+        assert!(replies.add(&message(other)).is_none());
+        let line = format!(":s 354 me {token} #rust al host alice H alice_acct :Alice");
+        let reply = replies.add(&message(&line)).unwrap();
+        let line = format!(":s 354 me {token} #rust b host bob G 0 :Bob");
+        let logged_out = replies.add(&message(&line)).unwrap();
+
+        assert!(reply.has_account);
+        assert_eq!(reply.user.account.as_deref(), Some("alice_acct"));
+        assert_eq!(logged_out.user.account, None);
+        assert!(logged_out.user.away);
+    }
+
+    #[test]
+    fn replies_are_matched_by_token_and_mask() {
+        let mut replies = WhoReplies::default();
+        let (_, lost) = replies.expect("#lost", true);
+        let (token, response) = replies.expect("alice", true);
+        let token = token.unwrap();
+
+        let line = format!(":s 354 me {token} #rust al host alice H alice_acct :Alice");
+        assert!(replies.add(&message(&line)).is_some());
+        let (mask, users) = replies.finish(&message(":s 315 me alice :End of WHO list"));
+
+        assert_eq!(mask, "alice");
+        assert_eq!(response.try_take().unwrap().unwrap(), users);
+        assert_eq!(users.len(), 1);
+        assert!(lost.try_take().is_none());
+    }
+
+    #[test]
+    fn requests_without_an_end_expire() {
+        let mut replies = WhoReplies::default();
+        let (_, lost) = replies.expect("#lost", false);
+        replies.waiting[0].sent -= WHO_TIMEOUT;
+
+        let (_, response) = replies.expect("#rust", false);
+        replies.add(&message(":s 352 me #rust al host s alice G :0 Alice"));
+        replies.finish(&message(":s 315 me #rust :End of WHO list"));
+
+        assert!(matches!(lost.try_take(), Some(Err(Error::Timeout))));
+        assert_eq!(response.try_take().unwrap().unwrap().len(), 1);
+    }
+}
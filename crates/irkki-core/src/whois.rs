@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use crate::{Error, Message, Responder, Response, ServerError, response};
+
+/// What the server told us about a user in reply to WHOIS.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WhoisInfo {
+    pub nickname: String,
+    pub username: Option<String>,
+    pub host: Option<String>,
+    pub realname: Option<String>,
+    /// The server the user is connected to.
+    pub server: Option<String>,
+    pub server_info: Option<String>,
+    pub operator: bool,
+    pub idle: Option<Duration>,
+    pub signon: Option<SystemTime>,
+    /// The channels the user is in, with their prefix such as `@#channel`.
+    pub channels: Vec<String>,
+    /// The account the user is logged in to.
+    pub account: Option<String>,
+    /// Whether the user is connected over TLS.
+    pub secure: bool,
+}
+
+/// Collects the WHOIS replies per nickname until RPL_ENDOFWHOIS.
+///
+/// Servers can send other messages, and the replies for other WHOIS requests, in between, so
+/// the replies are matched to the nickname they are about rather than to the last request.
+#[derive(Default)]
+pub(crate) struct WhoisReplies {
+    replies: HashMap<String, WhoisInfo>,
+    waiting: Vec<(String, Responder<WhoisInfo>)>,
+}
+
+impl WhoisReplies {
+    /// Returns the response that will be completed when the replies for the nickname are in.
+    /// Requests whose caller has stopped waiting are dropped here, so they do not pile up.
+    pub(crate) fn expect(&mut self, nickname: &str) -> Response<WhoisInfo> {
+        self.waiting
+            .retain(|(_, responder)| !responder.is_abandoned());
+        let (responder, response) = response();
+        self.waiting
+            .push((nickname.to_ascii_lowercase(), responder));
+        response
+    }
+
+    /// Adds a WHOIS reply and returns the collected info once RPL_ENDOFWHOIS has arrived.
+    pub(crate) fn handle(&mut self, message: &Message) -> Option<WhoisInfo> {
+        let params = &message.params;
+        let nickname = params.get(1)?;
+        let key = nickname.to_ascii_lowercase();
+        let param = |i: usize| params.get(i).cloned();
+
+        if message.command == "318" {
+            let info = self.replies.remove(&key);
+            let result = info.clone().ok_or_else(|| ServerError {
+                code: message.command.clone(),
+                params: vec![nickname.clone()],
+                text: params.last().cloned().unwrap_or_default(),
+            });
+            self.complete(&key, result);
+            return info;
+        }
+
+        let info = self.replies.entry(key).or_insert_with(|| WhoisInfo {
+            nickname: nickname.clone(),
+            ..WhoisInfo::default()
+        });
+        match message.command.as_str() {
+            // RPL_WHOISUSER
+            "311" => {
+                info.username = param(2);
+                info.host = param(3);
+                info.realname = param(5);
+            }
+            // RPL_WHOISSERVER
+            "312" => {
+                info.server = param(2);
+                info.server_info = param(3);
+            }
+            // RPL_WHOISOPERATOR
+            "313" => info.operator = true,
+            // RPL_WHOISIDLE
+            "317" => {
+                let seconds = |i: usize| params.get(i).and_then(|value| value.parse().ok());
+                info.idle = seconds(2).map(Duration::from_secs);
+                info.signon = seconds(3)
+                    .filter(|_| params.len() > 4)
+                    .map(|signon| SystemTime::UNIX_EPOCH + Duration::from_secs(signon));
+            }
+            // RPL_WHOISCHANNELS, may be sent more than once.
+            "319" => {
+                if let Some(channels) = params.get(2) {
+                    info.channels
+                        .extend(channels.split_whitespace().map(String::from));
+                }
+            }
+            // RPL_WHOISACCOUNT
+            "330" => info.account = param(2),
+            // RPL_WHOISSECURE
+            "671" => info.secure = true,
+            _ => {}
+        }
+
+        None
+    }
+
+    /// Fails the requests for a nickname that does not exist.
+    pub(crate) fn fail(&mut self, error: &ServerError) {
+        // ERR_NOSUCHNICK and ERR_NOSUCHSERVER
+        if !matches!(error.code.as_str(), "401" | "402") {
+            return;
+        }
+        if let Some(nickname) = error.params.first() {
+            let key = nickname.to_ascii_lowercase();
+            self.replies.remove(&key);
+            self.complete(&key, Err(error.clone()));
+        }
+    }
+
+    fn complete(&mut self, key: &str, result: Result<WhoisInfo, ServerError>) {
+        let (done, waiting) = std::mem::take(&mut self.waiting)
+            .into_iter()
+            .partition(|(nickname, _)| nickname == key);
+        self.waiting = waiting;
+
+        for (_, responder) in done {
+            responder.complete(result.clone().map_err(Error::Protocol));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Parser;
+
+    fn handle(replies: &mut WhoisReplies, line: &str) -> Option<WhoisInfo> {
+        replies.handle(&Parser::new(line).parse_message().unwrap())
+    }
+
+    #[test]
+    fn replies_are_collected_until_end_of_whois() {
+        let mut replies = WhoisReplies::default();
+        let response = replies.expect("Alice");
+
+        assert_eq!(
+            handle(
+                &mut replies,
+                ":s 311 me alice al host.example * :Alice Liddell"
+            ),
+            None
+        );
+        handle(
+            &mut replies,
+            ":s 312 me alice irc.example.com :Example server",
+        );
+        handle(&mut replies, ":s 319 me alice :@#rust +#irc");
+        handle(&mut replies, ":s 319 me alice :#more");
+        handle(
+            &mut replies,
+            ":s 317 me alice 42 1700000000 :seconds idle, signon time",
+        );
+        handle(&mut replies, ":s 330 me alice alice_acct :is logged in as");
+        handle(
+            &mut replies,
+            ":s 671 me alice :is using a secure connection",
+        );
+        handle(&mut replies, ":s 313 me alice :is an IRC operator");
+        let info = handle(&mut replies, ":s 318 me alice :End of /WHOIS list.").unwrap();
+
+        assert_eq!(info.nickname, "alice");
+        assert_eq!(info.username.as_deref(), Some("al"));
+        assert_eq!(info.host.as_deref(), Some("host.example"));
+        assert_eq!(info.realname.as_deref(), Some("Alice Liddell"));
+        assert_eq!(info.server.as_deref(), Some("irc.example.com"));
+        assert_eq!(info.channels, vec!["@#rust", "+#irc", "#more"]);
+        assert_eq!(info.idle, Some(Duration::from_secs(42)));
+        assert_eq!(
+            info.signon,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        );
+        assert_eq!(info.account.as_deref(), Some("alice_acct"));
+        assert!(info.secure && info.operator);
+        assert_eq!(response.try_take().unwrap().unwrap(), info);
+    }
+
+    #[test]
+    fn interleaved_replies_for_two_nicknames() {
+        let mut replies = WhoisReplies::default();
+        let bob = replies.expect("bob");
+
+        handle(&mut replies, ":s 311 me alice al host * :Alice");
+        handle(&mut replies, ":s 311 me bob b host * :Bob");
+        let alice = handle(&mut replies, ":s 318 me alice :End of /WHOIS list.").unwrap();
+        assert!(bob.try_take().is_none());
+        let info = handle(&mut replies, ":s 318 me bob :End of /WHOIS list.").unwrap();
+
+        assert_eq!(alice.realname.as_deref(), Some("Alice"));
+        assert_eq!(bob.try_take().unwrap().unwrap(), info);
+    }
+
+    #[test]
+    fn requests_that_timed_out_are_dropped() {
+        let mut replies = WhoisReplies::default();
+        let _ = replies.expect("alice").wait_timeout(Duration::ZERO);
+        drop(replies.expect("bob"));
+
+        let carol = replies.expect("carol");
+        handle(&mut replies, ":s 311 me carol c host * :Carol");
+        let info = handle(&mut replies, ":s 318 me carol :End of /WHOIS list.").unwrap();
+
+        assert_eq!(replies.waiting.len(), 0);
+        assert_eq!(carol.try_take().unwrap().unwrap(), info);
+    }
+
+    #[test]
+    fn no_such_nick_fails_the_request() {
+        let mut replies = WhoisReplies::default();
+        let response = replies.expect("ghost");
+
+        replies.fail(&ServerError {
+            code: "401".to_string(),
+            params: vec!["ghost".to_string()],
+            text: "No such nick/channel".to_string(),
+        });
+
+        assert!(matches!(
+            response.try_take(),
+            Some(Err(Error::Protocol(error))) if error.code == "401"
+        ));
+    }
+}
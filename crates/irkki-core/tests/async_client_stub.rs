@@ -0,0 +1,137 @@
+#![cfg(feature = "tokio")]
+
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::time::timeout;
+
+use irkki_core::{AsyncIRCClient, FloodControl, IRCEvent, OverflowPolicy};
+
+async fn spawn_stub_server() -> (u16, oneshot::Receiver<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let (tx, rx) = oneshot::channel();
+
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let mut received = Vec::new();
+
+        for _ in 0..3 {
+            received.push(lines.next_line().await.unwrap().unwrap());
+        }
+
+        writer.write_all(b"PING :stub\r\n").await.unwrap();
+        received.push(lines.next_line().await.unwrap().unwrap());
+
+        writer
+            .write_all(b":server 001 nick :welcome\r\n")
+            .await
+            .unwrap();
+
+        let _ = tx.send(received);
+    });
+
+    (port, rx)
+}
+
+#[tokio::test]
+async fn async_client_handles_ping_and_receives_message() {
+    let (port, rx) = spawn_stub_server().await;
+
+    let (_client, mut events) = AsyncIRCClient::connect("nick", "127.0.0.1", port)
+        .await
+        .unwrap();
+
+    let received = timeout(Duration::from_secs(2), rx).await.unwrap().unwrap();
+    assert!(received[0].starts_with("CAP LS"));
+    assert!(received[1].starts_with("NICK "));
+    assert!(received[2].starts_with("USER "));
+    assert_eq!(received[3], "PONG :stub");
+
+    let event = timeout(Duration::from_secs(2), events.next())
+        .await
+        .unwrap()
+        .unwrap();
+    let IRCEvent::Message(m) = event.kind else {
+        panic!("Expected a Message event");
+    };
+
+    assert_eq!(m.command, "001");
+}
+
+#[tokio::test]
+async fn hundreds_of_connections_run_on_one_thread() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let connections = 200;
+
+    tokio::spawn(async move {
+        for _ in 0..connections {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                stream
+                    .write_all(b":server 001 nick :welcome\r\n")
+                    .await
+                    .unwrap();
+                let mut buffer = [0; 64];
+                while tokio::io::AsyncReadExt::read(&mut stream, &mut buffer)
+                    .await
+                    .is_ok_and(|n| n > 0)
+                {}
+            });
+        }
+    });
+
+    let mut clients = Vec::new();
+    for i in 0..connections {
+        clients.push(
+            AsyncIRCClient::connect(format!("nick{i}"), "127.0.0.1", port)
+                .await
+                .unwrap(),
+        );
+    }
+
+    for (_client, events) in &mut clients {
+        let event = timeout(Duration::from_secs(5), events.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(event.kind, IRCEvent::Message(m) if m.command == "001"));
+    }
+}
+
+#[tokio::test]
+async fn full_send_queue_makes_senders_wait() {
+    let (client_reader, _server_writer) = tokio::io::duplex(1024);
+    let (_server_reader, client_writer) = tokio::io::duplex(64 * 1024);
+    let flood_control = FloodControl {
+        burst: 1,
+        rate: Duration::from_secs(3600),
+        capacity: 2,
+        overflow: OverflowPolicy::DropNewest,
+    };
+    let (client, _events) =
+        AsyncIRCClient::with_transport("nick", client_reader, client_writer, flood_control)
+            .await
+            .unwrap();
+
+    // CAP LS is sent at once, NICK and USER fill the queue, and the rest waits in the channel.
+    let sending = async {
+        loop {
+            client.send_message("hi").await.unwrap();
+        }
+    };
+    assert!(timeout(Duration::from_millis(200), sending).await.is_err());
+    assert_eq!(client.queued_lines(), 2);
+
+    // A bigger queue takes the waiting lines, so there is room again.
+    client.set_flood_control(FloodControl::default());
+    timeout(Duration::from_secs(1), client.send_message("hi"))
+        .await
+        .unwrap()
+        .unwrap();
+}
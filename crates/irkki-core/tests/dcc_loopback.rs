@@ -0,0 +1,367 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use irkki_core::{DccConfig, DccEvent, DccRequest, DccTransfers};
+
+fn directory(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("irkki-dcc-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+    path
+}
+
+fn transfers(directory: &PathBuf) -> (DccTransfers, mpsc::Receiver<DccEvent>) {
+    let mut config = DccConfig::new(directory, IpAddr::V4(Ipv4Addr::LOCALHOST));
+    config.bind = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+    config.timeout = Duration::from_secs(5);
+    DccTransfers::new(config)
+}
+
+/// Offers `size` and sends `bytes` to whoever connects, like a sender that does not keep to its
+/// offer.
+fn sender(bytes: usize, size: Option<u64>) -> DccRequest {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = stream.write_all(&contents()[..bytes]);
+        let _ = stream.shutdown(std::net::Shutdown::Write);
+        let _ = stream.read_to_end(&mut Vec::new());
+    });
+
+    DccRequest::Send {
+        filename: "logs.tgz".to_string(),
+        address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        port,
+        size,
+        token: None,
+    }
+}
+
+/// A file big enough to take several chunks.
+fn contents() -> Vec<u8> {
+    (0..200_000u32).map(|i| (i % 251) as u8).collect()
+}
+
+fn wait_until_done(events: &mpsc::Receiver<DccEvent>) -> (DccEvent, usize) {
+    let mut progress = 0;
+    loop {
+        match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+            DccEvent::Progress { .. } => progress += 1,
+            event => return (event, progress),
+        }
+    }
+}
+
+#[test]
+fn file_is_sent_to_a_connecting_receiver() {
+    let (alice_dir, bob_dir) = (directory("active-alice"), directory("active-bob"));
+    let path = alice_dir.join("logs.tgz");
+    fs::write(&path, contents()).unwrap();
+    let (mut alice, alice_events) = transfers(&alice_dir);
+    let (mut bob, bob_events) = transfers(&bob_dir);
+
+    let (_, offer) = alice.offer_file("bob", &path).unwrap();
+    let (id, reply) = bob.accept_file("alice", &offer).unwrap();
+
+    assert_eq!(reply, None);
+    let (received, progress) = wait_until_done(&bob_events);
+    assert_eq!(
+        received,
+        DccEvent::Completed {
+            id,
+            path: bob_dir.join("logs.tgz")
+        }
+    );
+    assert!(progress > 1);
+    assert!(matches!(
+        wait_until_done(&alice_events).0,
+        DccEvent::Completed { .. }
+    ));
+    assert_eq!(fs::read(bob_dir.join("logs.tgz")).unwrap(), contents());
+}
+
+#[test]
+fn finished_offer_cannot_be_resumed() {
+    let (alice_dir, bob_dir) = (directory("finished-alice"), directory("finished-bob"));
+    let path = alice_dir.join("logs.tgz");
+    fs::write(&path, contents()).unwrap();
+    let (mut alice, alice_events) = transfers(&alice_dir);
+    let (mut bob, bob_events) = transfers(&bob_dir);
+
+    let (_, offer) = alice.offer_file("bob", &path).unwrap();
+    bob.accept_file("alice", &offer).unwrap();
+    wait_until_done(&bob_events);
+    wait_until_done(&alice_events);
+
+    let DccRequest::Send { filename, port, .. } = offer else {
+        unreachable!()
+    };
+    let resume = DccRequest::Resume {
+        filename,
+        port,
+        position: 0,
+        token: None,
+    };
+    assert_eq!(alice.handle("bob", &resume).unwrap(), None);
+}
+
+#[test]
+fn passive_download_only_accepts_the_sender() {
+    let bob_dir = directory("sender-bob");
+    let (mut bob, _bob_events) = transfers(&bob_dir);
+    let offer = DccRequest::Send {
+        filename: "logs.tgz".to_string(),
+        address: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+        port: 0,
+        size: Some(10),
+        token: Some("1".to_string()),
+    };
+
+    let (_, answer) = bob.accept_file("alice", &offer).unwrap();
+    let Some(DccRequest::Send { port, .. }) = answer else {
+        panic!("no answer to the passive offer");
+    };
+    let mut stranger = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+    stranger
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    assert_eq!(stranger.read(&mut [0; 1]).unwrap(), 0);
+}
+
+#[test]
+fn passive_offer_is_answered_and_connected_to() {
+    let (alice_dir, bob_dir) = (directory("passive-alice"), directory("passive-bob"));
+    let path = alice_dir.join("logs.tgz");
+    fs::write(&path, contents()).unwrap();
+    let (mut alice, alice_events) = transfers(&alice_dir);
+    let (mut bob, bob_events) = transfers(&bob_dir);
+
+    let (_, offer) = alice.offer_file_passive("bob", &path).unwrap();
+    assert!(offer.is_passive());
+    let (_, answer) = bob.accept_file("alice", &offer).unwrap();
+    let answer = answer.unwrap();
+    assert!(matches!(&answer, DccRequest::Send { port, token: Some(_), .. } if *port != 0));
+    assert_eq!(alice.handle("bob", &answer).unwrap(), None);
+
+    assert!(matches!(
+        wait_until_done(&bob_events).0,
+        DccEvent::Completed { .. }
+    ));
+    assert!(matches!(
+        wait_until_done(&alice_events).0,
+        DccEvent::Completed { .. }
+    ));
+    assert_eq!(fs::read(bob_dir.join("logs.tgz")).unwrap(), contents());
+}
+
+#[test]
+fn partial_file_is_resumed() {
+    let (alice_dir, bob_dir) = (directory("resume-alice"), directory("resume-bob"));
+    let path = alice_dir.join("logs.tgz");
+    fs::write(&path, contents()).unwrap();
+    fs::write(bob_dir.join("logs.tgz.part"), &contents()[..70_000]).unwrap();
+    let (mut alice, alice_events) = transfers(&alice_dir);
+    let (mut bob, bob_events) = transfers(&bob_dir);
+
+    let (_, offer) = alice.offer_file("bob", &path).unwrap();
+    let (_, resume) = bob.accept_file("alice", &offer).unwrap();
+    let resume = resume.unwrap();
+    assert!(matches!(
+        resume,
+        DccRequest::Resume {
+            position: 70_000,
+            ..
+        }
+    ));
+    let accept = alice.handle("bob", &resume).unwrap().unwrap();
+    let DccRequest::Accept {
+        filename,
+        port,
+        position: 70_000,
+        token,
+    } = accept.clone()
+    else {
+        panic!("no ACCEPT at 70000 bytes: {accept:?}");
+    };
+    for position in [0, 1_000] {
+        let elsewhere = DccRequest::Accept {
+            filename: filename.clone(),
+            port,
+            position,
+            token: token.clone(),
+        };
+        assert_eq!(bob.handle("alice", &elsewhere).unwrap(), None);
+    }
+    assert!(bob_events.try_recv().is_err());
+    assert_eq!(bob.handle("alice", &accept).unwrap(), None);
+
+    let first = bob_events.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(matches!(first, DccEvent::Progress { transferred, .. } if transferred > 70_000));
+    assert!(matches!(
+        wait_until_done(&bob_events).0,
+        DccEvent::Completed { .. }
+    ));
+    assert!(matches!(
+        wait_until_done(&alice_events).0,
+        DccEvent::Completed { .. }
+    ));
+    assert_eq!(fs::read(bob_dir.join("logs.tgz")).unwrap(), contents());
+    assert!(!bob_dir.join("logs.tgz.part").exists());
+}
+
+#[test]
+fn existing_files_are_not_overwritten() {
+    let (alice_dir, bob_dir) = (directory("existing-alice"), directory("existing-bob"));
+    let path = alice_dir.join("logs.tgz");
+    fs::write(&path, contents()).unwrap();
+    fs::write(bob_dir.join("logs.tgz"), b"keep me").unwrap();
+    fs::write(bob_dir.join("logs (1).tgz"), &contents()[..70_000]).unwrap();
+    let (mut alice, _alice_events) = transfers(&alice_dir);
+    let (mut bob, bob_events) = transfers(&bob_dir);
+
+    let (_, offer) = alice.offer_file("bob", &path).unwrap();
+    let (id, reply) = bob.accept_file("alice", &offer).unwrap();
+
+    assert_eq!(reply, None);
+    assert_eq!(
+        wait_until_done(&bob_events).0,
+        DccEvent::Completed {
+            id,
+            path: bob_dir.join("logs (2).tgz")
+        }
+    );
+    assert_eq!(fs::read(bob_dir.join("logs.tgz")).unwrap(), b"keep me");
+    assert_eq!(
+        fs::read(bob_dir.join("logs (1).tgz")).unwrap(),
+        &contents()[..70_000]
+    );
+    assert_eq!(fs::read(bob_dir.join("logs (2).tgz")).unwrap(), contents());
+}
+
+#[test]
+fn bytes_past_the_offered_size_are_left_out() {
+    let bob_dir = directory("oversized-bob");
+    let (mut bob, bob_events) = transfers(&bob_dir);
+
+    let (id, _) = bob
+        .accept_file("alice", &sender(100_000, Some(1_000)))
+        .unwrap();
+
+    assert_eq!(
+        wait_until_done(&bob_events).0,
+        DccEvent::Completed {
+            id,
+            path: bob_dir.join("logs.tgz")
+        }
+    );
+    assert_eq!(
+        fs::read(bob_dir.join("logs.tgz")).unwrap(),
+        &contents()[..1_000]
+    );
+}
+
+#[test]
+fn offer_without_a_size_stops_at_the_limit() {
+    let bob_dir = directory("unsized-bob");
+    let mut config = DccConfig::new(&bob_dir, IpAddr::V4(Ipv4Addr::LOCALHOST));
+    config.size_limit = 1_000;
+    let (mut bob, bob_events) = DccTransfers::new(config);
+
+    let (id, _) = bob.accept_file("alice", &sender(100_000, None)).unwrap();
+
+    assert!(matches!(
+        wait_until_done(&bob_events).0,
+        DccEvent::Failed { id: failed, .. } if failed == id
+    ));
+    assert!(fs::metadata(bob_dir.join("logs.tgz.part")).unwrap().len() <= 1_000);
+}
+
+#[test]
+fn offered_path_is_saved_inside_the_download_directory() {
+    let (alice_dir, bob_dir) = (directory("traversal-alice"), directory("traversal-bob"));
+    let path = alice_dir.join("passwd");
+    fs::write(&path, b"not really").unwrap();
+    let (mut alice, _alice_events) = transfers(&alice_dir);
+    let (mut bob, bob_events) = transfers(&bob_dir);
+
+    let (_, offer) = alice.offer_file("bob", &path).unwrap();
+    let DccRequest::Send {
+        address,
+        port,
+        size,
+        ..
+    } = offer
+    else {
+        panic!("Expected a SEND offer");
+    };
+    let hostile = DccRequest::Send {
+        filename: "../../passwd".to_string(),
+        address,
+        port,
+        size,
+        token: None,
+    };
+    bob.accept_file("alice", &hostile).unwrap();
+
+    assert!(matches!(
+        wait_until_done(&bob_events).0,
+        DccEvent::Completed { path, .. } if path == bob_dir.join("passwd")
+    ));
+    let unnamed = DccRequest::Send {
+        filename: "..".to_string(),
+        address,
+        port,
+        size,
+        token: None,
+    };
+    assert!(bob.accept_file("alice", &unnamed).is_err());
+}
+
+#[test]
+fn chat_lines_go_both_ways() {
+    let (alice_dir, bob_dir) = (directory("chat-alice"), directory("chat-bob"));
+    let (mut alice, alice_events) = transfers(&alice_dir);
+    let (mut bob, bob_events) = transfers(&bob_dir);
+
+    let (alice_id, offer) = alice.offer_chat().unwrap();
+    let bob_id = bob.accept_chat(&offer).unwrap();
+    let next = |events: &mpsc::Receiver<DccEvent>| events.recv_timeout(Duration::from_secs(5));
+    assert_eq!(
+        next(&alice_events).unwrap(),
+        DccEvent::ChatConnected { id: alice_id }
+    );
+    assert_eq!(
+        next(&bob_events).unwrap(),
+        DccEvent::ChatConnected { id: bob_id }
+    );
+
+    alice.send_chat(alice_id, "hello bob").unwrap();
+    bob.send_chat(bob_id, "hi alice").unwrap();
+    assert_eq!(
+        next(&bob_events).unwrap(),
+        DccEvent::ChatLine {
+            id: bob_id,
+            text: "hello bob".to_string()
+        }
+    );
+    assert_eq!(
+        next(&alice_events).unwrap(),
+        DccEvent::ChatLine {
+            id: alice_id,
+            text: "hi alice".to_string()
+        }
+    );
+
+    alice.close_chat(alice_id);
+    assert_eq!(
+        next(&bob_events).unwrap(),
+        DccEvent::ChatClosed { id: bob_id }
+    );
+    assert!(bob.send_chat(bob_id, "still there?").is_err());
+}
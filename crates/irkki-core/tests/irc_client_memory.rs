@@ -0,0 +1,240 @@
+use std::io::{BufRead, BufReader, Write};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use irkki_core::{
+    ConnectionOptions, Error, Event, FloodControl, IRCClient, IRCEvent, PipeReader, PipeWriter,
+    Plugin, PluginContext, memory_pipe,
+};
+
+struct StubServer {
+    lines: BufReader<PipeReader>,
+    writer: PipeWriter,
+}
+
+impl StubServer {
+    fn read_line(&mut self) -> String {
+        let mut line = String::new();
+        self.lines.read_line(&mut line).unwrap();
+        line.trim_end().to_string()
+    }
+
+    fn send(&mut self, line: &str) {
+        self.writer.write_all(line.as_bytes()).unwrap();
+        self.writer.write_all(b"\r\n").unwrap();
+    }
+}
+
+fn connect() -> (IRCClient, StubServer) {
+    let (client_reader, server_writer) = memory_pipe();
+    let (server_reader, client_writer) = memory_pipe();
+
+    let client = IRCClient::with_transport("nick", (client_reader, client_writer)).unwrap();
+    let server = StubServer {
+        lines: BufReader::new(server_reader),
+        writer: server_writer,
+    };
+
+    (client, server)
+}
+
+#[test]
+fn client_registers_and_answers_ping_over_memory_pipes() {
+    let (mut client, mut server) = connect();
+    let (event_tx, event_rx) = mpsc::channel();
+    let listener = client
+        .start_listening(move |event| {
+            let _ = event_tx.send(event.kind);
+            Ok(())
+        })
+        .unwrap();
+
+    assert_eq!(server.read_line(), "CAP LS 302");
+    assert_eq!(server.read_line(), "NICK nick");
+    assert_eq!(server.read_line(), "USER nick 0 * :nick");
+
+    server.send(":server CAP * LS :multi-prefix");
+    assert_eq!(server.read_line(), "CAP END");
+
+    server.send("PING :stub");
+    assert_eq!(server.read_line(), "PONG :stub");
+
+    server.send(":server 001 nick :welcome");
+    let event = event_rx.recv_timeout(Duration::from_secs(2)).unwrap();
+    assert!(matches!(event, IRCEvent::Message(m) if m.command == "CAP"));
+    let event = event_rx.recv_timeout(Duration::from_secs(2)).unwrap();
+    assert!(matches!(event, IRCEvent::Message(m) if m.command == "001"));
+    let event = event_rx.recv_timeout(Duration::from_secs(2)).unwrap();
+    assert_eq!(
+        event,
+        IRCEvent::Registered {
+            nickname: "nick".to_string()
+        }
+    );
+    assert_eq!(server.read_line(), "JOIN #testchannel");
+
+    drop(server);
+    listener.join().unwrap();
+}
+
+#[test]
+fn client_registers_with_alternate_nickname() {
+    let (client_reader, server_writer) = memory_pipe();
+    let (server_reader, client_writer) = memory_pipe();
+    let mut options = ConnectionOptions::new("nick");
+    options.alternate_nicknames = vec!["nick2".to_string()];
+    options.channels = vec!["#a".to_string(), "#b".to_string()];
+    let mut client = IRCClient::with_options(options, (client_reader, client_writer)).unwrap();
+    let mut server = StubServer {
+        lines: BufReader::new(server_reader),
+        writer: server_writer,
+    };
+    let (event_tx, event_rx) = mpsc::channel();
+    client
+        .start_listening(move |event| {
+            let _ = event_tx.send(event.kind);
+            Ok(())
+        })
+        .unwrap();
+    for _ in 0..3 {
+        server.read_line();
+    }
+
+    server.send(":server 421 * CAP :Unknown command");
+    server.send(":server 433 * nick :Nickname is already in use");
+    assert_eq!(server.read_line(), "NICK nick2");
+    server.send(":server 001 nick2 :welcome");
+
+    assert_eq!(server.read_line(), "JOIN #a");
+    assert_eq!(server.read_line(), "JOIN #b");
+    let registered = event_rx
+        .iter()
+        .find(|event| matches!(event, IRCEvent::Registered { .. }));
+    assert_eq!(
+        registered,
+        Some(IRCEvent::Registered {
+            nickname: "nick2".to_string()
+        })
+    );
+}
+
+#[test]
+fn client_sends_long_messages_in_parts() {
+    let (mut client, mut server) = connect();
+    for _ in 0..3 {
+        server.read_line();
+    }
+
+    client
+        .send_message("/msg alice ".to_string() + &"word ".repeat(150))
+        .unwrap();
+
+    let first = server.read_line();
+    let second = server.read_line();
+    assert!(first.starts_with("PRIVMSG alice :word"));
+    assert!(second.starts_with("PRIVMSG alice :word"));
+    assert!(first.len() < 512 && second.len() < 512);
+}
+
+#[test]
+fn whois_waits_for_the_replies() {
+    let (mut client, mut server) = connect();
+    client.start_listening(|_| Ok(())).unwrap();
+    for _ in 0..3 {
+        server.read_line();
+    }
+
+    let whois = std::thread::spawn(move || client.whois("alice", Duration::from_secs(2)));
+    assert_eq!(server.read_line(), "WHOIS alice");
+    server.send(":server 311 nick alice al host.example * :Alice");
+    server.send(":bob!b@host PRIVMSG nick :interleaved");
+    server.send(":server 330 nick alice alice :is logged in as");
+    server.send(":server 318 nick alice :End of /WHOIS list.");
+
+    let info = whois.join().unwrap().unwrap();
+    assert_eq!(info.realname.as_deref(), Some("Alice"));
+    assert_eq!(info.account.as_deref(), Some("alice"));
+}
+
+#[test]
+fn whois_times_out_without_replies() {
+    let (mut client, mut server) = connect();
+    client.start_listening(|_| Ok(())).unwrap();
+    for _ in 0..3 {
+        server.read_line();
+    }
+
+    let result = client.whois("alice", Duration::from_millis(50));
+
+    assert!(matches!(result, Err(Error::Timeout)));
+}
+
+/// Answers `!ping` in the channel it was sent to, hides the `!ping` itself and shouts what we
+/// send.
+struct PingPong;
+
+impl Plugin for PingPong {
+    fn on_event(&mut self, event: Event, context: &mut PluginContext) -> Option<Event> {
+        match &event.kind {
+            IRCEvent::PrivMsg { target, text, .. } if text == "!ping" => {
+                context.send_message(target, "pong");
+                None
+            }
+            _ => Some(event),
+        }
+    }
+
+    fn on_outgoing(&mut self, line: String) -> Option<String> {
+        match line.strip_prefix("PRIVMSG #rust :") {
+            Some(text) => Some(format!("PRIVMSG #rust :{}", text.to_uppercase())),
+            None => Some(line),
+        }
+    }
+}
+
+#[test]
+fn plugins_reply_to_events_and_change_outgoing_lines() {
+    let (mut client, mut server) = connect();
+    client.add_plugin(Box::new(PingPong));
+    let (event_tx, event_rx) = mpsc::channel();
+    client
+        .start_listening(move |event| {
+            let _ = event_tx.send(event.kind);
+            Ok(())
+        })
+        .unwrap();
+    for _ in 0..3 {
+        server.read_line();
+    }
+
+    server.send(":alice!a@host PRIVMSG #rust :!ping");
+    server.send(":alice!a@host PRIVMSG #rust :hi");
+
+    assert_eq!(server.read_line(), "PRIVMSG #rust :PONG");
+    let event = event_rx.recv_timeout(Duration::from_secs(2)).unwrap();
+    assert!(matches!(event, IRCEvent::PrivMsg { text, .. } if text == "hi"));
+}
+
+#[test]
+fn quit_is_sent_after_the_burst_even_when_the_client_is_dropped() {
+    let (mut client, mut server) = connect();
+    assert_eq!(server.read_line(), "CAP LS 302");
+    assert_eq!(server.read_line(), "NICK nick");
+    assert_eq!(server.read_line(), "USER nick 0 * :nick");
+    client.set_flood_control(FloodControl {
+        burst: 1,
+        rate: Duration::from_millis(100),
+        ..FloodControl::default()
+    });
+
+    for _ in 0..3 {
+        client.send_message("hi").unwrap();
+    }
+    client.quit().unwrap();
+    drop(client);
+
+    let sent: Vec<String> = std::iter::from_fn(|| Some(server.read_line()))
+        .take_while(|line| !line.is_empty())
+        .collect();
+    assert!(sent.iter().any(|line| line.starts_with("QUIT")), "{sent:?}");
+}
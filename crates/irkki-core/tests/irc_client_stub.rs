@@ -55,15 +55,15 @@ fn client_handles_ping_and_receives_message() {
     let (event_tx, event_rx) = mpsc::channel();
     let listener = client
         .start_listening(move |event| {
-            let _ = event_tx.send(event);
+            let _ = event_tx.send(event.kind);
             Ok(())
         })
         .unwrap();
 
     let received = rx.recv_timeout(Duration::from_secs(2)).unwrap();
-    assert!(received[0].starts_with("NICK "));
-    assert!(received[1].starts_with("USER "));
-    assert!(received[2].starts_with("JOIN "));
+    assert!(received[0].starts_with("CAP LS"));
+    assert!(received[1].starts_with("NICK "));
+    assert!(received[2].starts_with("USER "));
     assert_eq!(received[3], "PONG :stub");
 
     let event = event_rx.recv_timeout(Duration::from_secs(2)).unwrap();
@@ -0,0 +1,157 @@
+#![cfg(feature = "tls")]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::Duration;
+
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::{ClientConfig, RootCertStore, ServerConfig, ServerConnection, StreamOwned};
+
+use irkki_core::{ConnectionOptions, Error, IRCClient, IRCEvent, StsStore};
+
+fn provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn certificate() -> (CertificateDer<'static>, PrivateKeyDer<'static>) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let key = PrivatePkcs8KeyDer::from(certified.signing_key.serialize_der());
+
+    (certified.cert.der().clone(), key.into())
+}
+
+fn read_lines(reader: &mut impl BufRead, count: usize) -> Vec<String> {
+    (0..count)
+        .map(|_| {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            line.trim_end().to_string()
+        })
+        .collect()
+}
+
+/// Advertises `sts=port=<tls_port>` and reports whether the client hung up afterwards.
+fn spawn_plaintext_stub_server(tls_port: u16) -> (u16, mpsc::Receiver<(Vec<String>, bool)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(2)));
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let received = read_lines(&mut reader, 3);
+
+        let offer = format!(":server CAP * LS :sts=port={tls_port},duration=300 batch\r\n");
+        stream.write_all(offer.as_bytes()).unwrap();
+
+        let mut rest = String::new();
+        let closed = matches!(reader.read_line(&mut rest), Ok(0));
+        let _ = tx.send((received, closed));
+    });
+
+    (port, rx)
+}
+
+/// Advertises `sts=duration=300` over TLS and welcomes the client once it ends the negotiation.
+fn spawn_tls_stub_server(
+    certificate: CertificateDer<'static>,
+    key: PrivateKeyDer<'static>,
+) -> (u16, mpsc::Receiver<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let config = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![certificate], key)
+        .unwrap();
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let (socket, _) = listener.accept().unwrap();
+        let _ = socket.set_read_timeout(Some(Duration::from_secs(2)));
+        let connection = ServerConnection::new(Arc::new(config)).unwrap();
+        let mut stream = BufReader::new(StreamOwned::new(connection, socket));
+        let mut received = read_lines(&mut stream, 3);
+
+        stream
+            .get_mut()
+            .write_all(b":server CAP * LS :sts=port=1,duration=300\r\n")
+            .unwrap();
+        received.extend(read_lines(&mut stream, 1));
+
+        stream
+            .get_mut()
+            .write_all(b":server 001 nick :welcome\r\n")
+            .unwrap();
+        stream.get_mut().conn.send_close_notify();
+        let _ = stream.get_mut().flush();
+
+        let _ = tx.send(received);
+    });
+
+    (port, rx)
+}
+
+fn policy_path() -> PathBuf {
+    std::env::temp_dir().join(format!("irkki-sts-stub-{}.sts", std::process::id()))
+}
+
+#[test]
+fn sts_upgrades_to_tls_and_refuses_plaintext_afterwards() {
+    let path = policy_path();
+    let _ = std::fs::remove_file(&path);
+    let (certificate, key) = certificate();
+    let (tls_port, tls_rx) = spawn_tls_stub_server(certificate.clone(), key);
+    let (plain_port, plain_rx) = spawn_plaintext_stub_server(tls_port);
+
+    let mut roots = RootCertStore::empty();
+    roots.add(certificate).unwrap();
+    let config = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let mut options = ConnectionOptions::new("nick");
+    options.channels.clear();
+    options.sts_policies = Some(path.clone());
+    let mut client =
+        IRCClient::connect_with_options(options.clone(), "localhost", plain_port).unwrap();
+    client.set_tls_config(Arc::new(config));
+
+    let (event_tx, event_rx) = mpsc::channel();
+    let listener = client
+        .start_listening(move |event| {
+            let _ = event_tx.send(event.kind);
+            Ok(())
+        })
+        .unwrap();
+
+    let (plain_received, closed) = plain_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(plain_received[0], "CAP LS 302");
+    assert!(closed, "the plaintext connection should be closed");
+
+    let tls_received = tls_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(tls_received[0], "CAP LS 302");
+    assert_eq!(tls_received[1], "NICK nick");
+    assert_eq!(tls_received[3], "CAP END");
+
+    let events: Vec<IRCEvent> = event_rx.iter().collect();
+    listener.join().unwrap();
+    assert!(events.contains(&IRCEvent::StsUpgrade { port: tls_port }));
+    assert!(events.contains(&IRCEvent::StsPolicy {
+        duration: Duration::from_secs(300)
+    }));
+
+    let store = StsStore::open(&path).unwrap();
+    assert_eq!(store.port("localhost"), Some(tls_port));
+
+    let refused = IRCClient::connect_with_options(options, "localhost", plain_port);
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(refused, Err(Error::TlsRequired { port }) if port == tls_port));
+}
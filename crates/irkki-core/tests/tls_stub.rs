@@ -0,0 +1,106 @@
+#![cfg(feature = "tls")]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::Duration;
+
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::{ClientConfig, RootCertStore, ServerConfig, ServerConnection, StreamOwned};
+
+use irkki_core::{IRCClient, IRCEvent, TlsStream};
+
+fn provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn certificate() -> (CertificateDer<'static>, PrivateKeyDer<'static>) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let key = PrivatePkcs8KeyDer::from(certified.signing_key.serialize_der());
+
+    (certified.cert.der().clone(), key.into())
+}
+
+fn spawn_tls_stub_server(
+    certificate: CertificateDer<'static>,
+    key: PrivateKeyDer<'static>,
+) -> (u16, mpsc::Receiver<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let config = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![certificate], key)
+        .unwrap();
+
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        let (socket, _) = listener.accept().unwrap();
+        let _ = socket.set_read_timeout(Some(Duration::from_secs(2)));
+        let connection = ServerConnection::new(Arc::new(config)).unwrap();
+        let mut stream = BufReader::new(StreamOwned::new(connection, socket));
+        let mut received = Vec::new();
+
+        for _ in 0..3 {
+            let mut line = String::new();
+            stream.read_line(&mut line).unwrap();
+            received.push(line.trim_end().to_string());
+        }
+
+        stream.get_mut().write_all(b"PING :tls\r\n").unwrap();
+        let mut line = String::new();
+        stream.read_line(&mut line).unwrap();
+        received.push(line.trim_end().to_string());
+
+        stream
+            .get_mut()
+            .write_all(b":server 001 nick :welcome\r\n")
+            .unwrap();
+        stream.get_mut().conn.send_close_notify();
+        let _ = stream.get_mut().flush();
+
+        let _ = tx.send(received);
+    });
+
+    (port, rx)
+}
+
+#[test]
+fn client_runs_over_tls_transport() {
+    let (certificate, key) = certificate();
+    let (port, rx) = spawn_tls_stub_server(certificate.clone(), key);
+
+    let mut roots = RootCertStore::empty();
+    roots.add(certificate).unwrap();
+    let config = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let socket = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let stream = TlsStream::with_config(socket, "localhost", Arc::new(config)).unwrap();
+    let mut client = IRCClient::with_transport("nick", stream).unwrap();
+
+    let (event_tx, event_rx) = mpsc::channel();
+    let listener = client
+        .start_listening(move |event| {
+            let _ = event_tx.send(event.kind);
+            Ok(())
+        })
+        .unwrap();
+
+    let received = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(received[0], "CAP LS 302");
+    assert_eq!(received[1], "NICK nick");
+    assert_eq!(received[2], "USER nick 0 * :nick");
+    assert_eq!(received[3], "PONG :tls");
+
+    let event = event_rx.recv_timeout(Duration::from_secs(2)).unwrap();
+    assert!(matches!(event, IRCEvent::Message(m) if m.command == "001"));
+
+    listener.join().unwrap();
+}